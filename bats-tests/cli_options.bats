@@ -74,7 +74,55 @@ load helpers/test_helpers
     assert_file_not_exists "$DST_DIR/delete_test/remove.txt"
 }
 
-@test "server mode with port option" {
+@test "dry-run flag changes nothing" {
+    create_test_structure "$SRC_DIR/dry_run" \
+        "new.txt:New file"
+    create_test_structure "$DST_DIR/dry_run" \
+        "extra.txt:Extraneous file"
+
+    run_rsynx --dry-run --delete "$SRC_DIR/dry_run" "$DST_DIR/dry_run"
+    assert_success
+    assert_output_contains "create"
+    assert_output_contains "delete"
+    assert_output_contains "(dry run)"
+    assert_file_not_exists "$DST_DIR/dry_run/new.txt"
+    assert_file_exists "$DST_DIR/dry_run/extra.txt"
+}
+
+@test "interactive flag fails safe without a terminal" {
+    create_test_structure "$SRC_DIR/interactive" \
+        "new.txt:New file"
+    create_test_structure "$DST_DIR/interactive" \
+        "extra.txt:Extraneous file"
+
+    run_rsynx --interactive --delete "$SRC_DIR/interactive" "$DST_DIR/interactive" < /dev/null
+    assert_failure
+    assert_output_contains "nothing was changed"
+    assert_file_not_exists "$DST_DIR/interactive/new.txt"
+    assert_file_exists "$DST_DIR/interactive/extra.txt"
+
+    # Without anything destructive to confirm, no terminal is needed
+    run_rsynx --interactive "$SRC_DIR/interactive" "$DST_DIR/interactive" < /dev/null
+    assert_success
+    assert_file_exists "$DST_DIR/interactive/new.txt"
+}
+
+@test "batch-from syncs every listed pair" {
+    create_test_file "$SRC_DIR/batch1.txt" "First batch file"
+    create_test_file "$SRC_DIR/batch2.txt" "Second batch file"
+
+    printf '%s %s\n' "$SRC_DIR/batch1.txt" "$DST_DIR/batch1.txt" \
+        "$SRC_DIR/missing.txt" "$DST_DIR/missing.txt" \
+        "$SRC_DIR/batch2.txt" "$DST_DIR/batch2.txt" > "$TEST_DIR/batch.list"
+
+    run_rsynx --batch-from "$TEST_DIR/batch.list" --jobs 2
+    [ "$status" -eq 5 ]
+    assert_output_contains "Batch complete: 2 of 3 entries synced"
+    assert_files_equal "$SRC_DIR/batch1.txt" "$DST_DIR/batch1.txt"
+    assert_files_equal "$SRC_DIR/batch2.txt" "$DST_DIR/batch2.txt"
+}
+
+@test "daemon with port option" {
     start_server 7886
     sleep 0.5
     stop_server
@@ -154,4 +202,166 @@ load helpers/test_helpers
     run_rsynx "$SRC_DIR/dir_arg" "$DST_DIR/dir_arg"
     assert_success
     assert_file_exists "$DST_DIR/dir_arg/test.txt"
-}
\ No newline at end of file
+}
+@test "progress flags" {
+    create_test_file "$SRC_DIR/progress.txt" "Progress flag test"
+
+    run_rsynx --no-progress "$SRC_DIR/progress.txt" "$DST_DIR/progress1.txt"
+    assert_success
+    [[ "$output" != *"[#"* ]]
+
+    run_rsynx --no-progress --progress "$SRC_DIR/progress.txt" "$DST_DIR/progress2.txt"
+    assert_success
+
+    assert_files_equal "$DST_DIR/progress1.txt" "$DST_DIR/progress2.txt"
+}
+
+@test "signature, delta and patch subcommands" {
+    create_test_file "$SRC_DIR/basis.txt" "Offline delta basis content"
+    create_test_file "$SRC_DIR/new.txt" "Offline delta basis content, edited"
+
+    run_rsynx signature --block-size 8 "$SRC_DIR/basis.txt" "$DST_DIR/basis.sig"
+    assert_success
+    run_rsynx delta "$DST_DIR/basis.sig" "$SRC_DIR/new.txt" "$DST_DIR/new.delta"
+    assert_success
+    run_rsynx patch "$SRC_DIR/basis.txt" "$DST_DIR/new.delta" "$DST_DIR/patched.txt"
+    assert_success
+
+    assert_files_equal "$SRC_DIR/new.txt" "$DST_DIR/patched.txt"
+}
+
+@test "log file records file actions" {
+    create_test_file "$SRC_DIR/logged.txt" "Log file test"
+
+    run_rsynx --log-file "$DST_DIR/rsynx.log" "$SRC_DIR/logged.txt" "$DST_DIR/logged.txt"
+    assert_success
+    assert_file_exists "$DST_DIR/rsynx.log"
+    grep -q "INFO" "$DST_DIR/rsynx.log"
+    grep -q "Sync complete" "$DST_DIR/rsynx.log"
+
+    run_rsynx --log-file "$DST_DIR/rsynx.log" "$SRC_DIR/missing.txt" "$DST_DIR/missing.txt"
+    assert_failure
+    grep -q "ERROR" "$DST_DIR/rsynx.log"
+}
+
+@test "stats summary" {
+    create_test_file "$SRC_DIR/stats.txt" "Stats summary test"
+
+    run_rsynx --stats "$SRC_DIR/stats.txt" "$DST_DIR/stats.txt"
+    assert_success
+    assert_output_contains "Number of files: 1"
+    assert_output_contains "Literal data:"
+    assert_output_contains "Throughput:"
+}
+
+@test "itemize changes" {
+    create_test_structure "$SRC_DIR/itemize" \
+        "new.txt:Itemized file"
+
+    run_rsynx -i -r "$SRC_DIR/itemize" "$DST_DIR/itemize"
+    assert_success
+    assert_output_contains ">f+++++++++ new.txt"
+
+    rm "$SRC_DIR/itemize/new.txt"
+    run_rsynx -i -r --delete "$SRC_DIR/itemize" "$DST_DIR/itemize"
+    assert_success
+    assert_output_contains "*deleting   new.txt"
+}
+
+@test "verbosity levels" {
+    create_test_structure "$SRC_DIR/verbosity" \
+        "file.txt:Verbosity test"
+
+    run_rsynx -q "$SRC_DIR/verbosity" "$DST_DIR/verbosity"
+    assert_success
+    [[ "$output" != *"Transferred:"* ]]
+
+    create_test_file "$SRC_DIR/verbosity/new.txt" "Another file"
+    run_rsynx -v "$SRC_DIR/verbosity" "$DST_DIR/verbosity"
+    assert_success
+    assert_output_contains "created $DST_DIR/verbosity/new.txt"
+    assert_output_contains "Transferred:"
+
+    run_rsynx -v -q "$SRC_DIR/verbosity" "$DST_DIR/verbosity"
+    assert_failure
+}
+
+@test "every repeats the sync" {
+    create_test_structure "$SRC_DIR/every" \
+        "file.txt:Scheduled file"
+
+    run_rsynx --every 0 -r "$SRC_DIR/every" "$DST_DIR/every"
+    assert_failure
+    assert_output_contains "expected an interval"
+
+    run_rsynx --every 1s --dry-run -r "$SRC_DIR/every" "$DST_DIR/every"
+    assert_failure
+
+    cd "$ORIGINAL_DIR"
+    run timeout 5 $RSYNX_BIN --every 1s -r "$SRC_DIR/every" "$DST_DIR/every"
+    [ "$status" -eq 124 ]
+    assert_file_exists "$DST_DIR/every/file.txt"
+}
+
+@test "snapshot backups" {
+    create_test_structure "$SRC_DIR/snapshot" \
+        "file.txt:Snapshot file"
+
+    run_rsynx --snapshot "$SRC_DIR/snapshot" "$DST_DIR/snapshots"
+    assert_success
+    assert_output_contains "Snapshot"
+
+    sleep 1
+    run_rsynx --snapshot --keep-snapshots 1 "$SRC_DIR/snapshot" "$DST_DIR/snapshots"
+    assert_success
+    [ "$(ls "$DST_DIR/snapshots" | wc -l)" -eq 1 ]
+    assert_files_equal "$SRC_DIR/snapshot/file.txt" "$DST_DIR/snapshots"/*/file.txt
+
+    run_rsynx --keep-snapshots 1 "$SRC_DIR/snapshot" "$DST_DIR/snapshots"
+    assert_failure
+}
+
+@test "content-addressed store round trip" {
+    create_test_structure "$SRC_DIR/store" \
+        "one.txt:Same content" \
+        "sub/two.txt:Same content"
+
+    run_rsynx -r "$SRC_DIR/store" "store://$DST_DIR/store"
+    assert_success
+    assert_output_contains "Deduplicated: 12 bytes"
+
+    run_rsynx -r "store://$DST_DIR/store" "$DST_DIR/restored"
+    assert_success
+    assert_files_equal "$SRC_DIR/store/one.txt" "$DST_DIR/restored/one.txt"
+    assert_files_equal "$SRC_DIR/store/sub/two.txt" "$DST_DIR/restored/sub/two.txt"
+}
+
+@test "sftp transport needs a remote destination" {
+    create_test_structure "$SRC_DIR/sftp" "file.txt:SFTP file"
+
+    run_rsynx --transport sftp "$SRC_DIR/sftp" "$DST_DIR/sftp"
+    assert_failure
+
+    run_rsynx --identity "$SRC_DIR/sftp/file.txt" "$SRC_DIR/sftp" "$DST_DIR/sftp"
+    assert_failure
+}
+
+@test "manifest export and verify" {
+    create_test_structure "$SRC_DIR/manifest" \
+        "file.txt:Listed file" \
+        "sub/nested.txt:Nested file"
+
+    run_rsynx manifest "$SRC_DIR/manifest" -o "$DST_DIR/tree.mtree"
+    assert_success
+    grep -q "^./sub/nested.txt type=file size=" "$DST_DIR/tree.mtree"
+
+    run_rsynx -r -m "$SRC_DIR/manifest" "$DST_DIR/manifest"
+    assert_success
+    run_rsynx manifest --verify "$DST_DIR/tree.mtree" "$DST_DIR/manifest"
+    assert_success
+
+    echo "Changed" > "$DST_DIR/manifest/file.txt"
+    run_rsynx manifest --verify "$DST_DIR/tree.mtree" --content-only "$DST_DIR/manifest"
+    [ "$status" -eq 3 ]
+    assert_output_contains "changed (size, sha256): file.txt"
+}
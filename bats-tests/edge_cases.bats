@@ -67,7 +67,7 @@ load helpers/test_helpers
     mkdir -p "$deep_path"
     create_test_file "$deep_path/deep_file.txt" "Deep nesting test"
     
-    run_rsynx "$SRC_DIR/level1" "$DST_DIR/level1"
+    run_rsynx -r "$SRC_DIR/level1" "$DST_DIR/level1"
     assert_success
     assert_file_exists "$DST_DIR/level1/level2/level3/level4/level5/deep_file.txt"
     [[ "$(get_file_content "$DST_DIR/level1/level2/level3/level4/level5/deep_file.txt")" == "Deep nesting test" ]]
@@ -178,4 +178,21 @@ load helpers/test_helpers
     assert_file_exists "$DST_DIR/concurrent1.txt"
     assert_file_exists "$DST_DIR/concurrent2.txt"
     assert_file_exists "$DST_DIR/concurrent3.txt"
-}
\ No newline at end of file
+}
+@test "exit codes distinguish failure kinds" {
+    create_test_file "$SRC_DIR/exit.txt" "Exit code test"
+
+    # Usage error: no destination
+    run_rsynx "$SRC_DIR/exit.txt"
+    [ "$status" -eq 2 ]
+
+    # Nothing listening on the port
+    run_rsynx "$SRC_DIR/exit.txt" "127.0.0.1:$DST_DIR/exit.txt" --port 1
+    [ "$status" -eq 4 ]
+
+    # A socket can't be synced, but the regular file still is
+    python3 -c "import socket; socket.socket(socket.AF_UNIX).bind('$SRC_DIR/exit.sock')"
+    run_rsynx "$SRC_DIR" "$DST_DIR"
+    [ "$status" -eq 6 ]
+    assert_files_equal "$SRC_DIR/exit.txt" "$DST_DIR/exit.txt"
+}
@@ -79,7 +79,7 @@ start_server() {
     local block_size="${2:-1024}"
     
     cd "$ORIGINAL_DIR"
-    $RSYNX_BIN --server --port "$port" --block-size "$block_size" &
+    $RSYNX_BIN daemon --port "$port" --block-size "$block_size" &
     SERVER_PID=$!
     
     # Wait for server to start
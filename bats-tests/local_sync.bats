@@ -54,7 +54,7 @@ load helpers/test_helpers
         "subdir/" \
         "subdir/file3.txt:Content 3"
     
-    run_rsynx "$SRC_DIR/testdir" "$DST_DIR/testdir"
+    run_rsynx -r "$SRC_DIR/testdir" "$DST_DIR/testdir"
     assert_success
     assert_output_contains "Transferred:"
     
@@ -67,6 +67,36 @@ load helpers/test_helpers
     assert_files_equal "$SRC_DIR/testdir/subdir/file3.txt" "$DST_DIR/testdir/subdir/file3.txt"
 }
 
+@test "sync directory without recursion copies only top-level files" {
+    create_test_structure "$SRC_DIR/flatdir" \
+        "file1.txt:Content 1" \
+        "subdir/" \
+        "subdir/file2.txt:Content 2"
+
+    run_rsynx "$SRC_DIR/flatdir" "$DST_DIR/flatdir"
+    assert_success
+
+    assert_file_exists "$DST_DIR/flatdir/file1.txt"
+    assert_file_not_exists "$DST_DIR/flatdir/subdir/file2.txt"
+}
+
+@test "sync directory in archive mode keeps symlinks and modes" {
+    create_test_structure "$SRC_DIR/archdir" \
+        "file1.txt:Content 1" \
+        "subdir/" \
+        "subdir/file2.txt:Content 2"
+    chmod 750 "$SRC_DIR/archdir/file1.txt"
+    ln -s file1.txt "$SRC_DIR/archdir/link.txt"
+
+    run_rsynx -a "$SRC_DIR/archdir" "$DST_DIR/archdir"
+    assert_success
+
+    assert_files_equal "$SRC_DIR/archdir/subdir/file2.txt" "$DST_DIR/archdir/subdir/file2.txt"
+    [[ -L "$DST_DIR/archdir/link.txt" ]]
+    [[ "$(readlink "$DST_DIR/archdir/link.txt")" == "file1.txt" ]]
+    [[ "$(stat -c %a "$DST_DIR/archdir/file1.txt")" == "750" ]]
+}
+
 @test "sync with metadata preservation" {
     create_test_file "$SRC_DIR/meta.txt" "Test metadata"
     chmod 755 "$SRC_DIR/meta.txt"
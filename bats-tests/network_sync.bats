@@ -137,4 +137,53 @@ load helpers/test_helpers
     [[ "$(get_file_content "$DST_DIR/seq3.txt")" == "Sequential file 3" ]]
     
     stop_server
-}
\ No newline at end of file
+}
+@test "network sync with port in the destination" {
+    create_test_file "$SRC_DIR/port_spec.txt" "Port in spec"
+
+    start_server 7895 1024
+
+    run_rsynx "$SRC_DIR/port_spec.txt" "127.0.0.1:7895:$DST_DIR/port_spec1.txt"
+    assert_success
+
+    run_rsynx "$SRC_DIR/port_spec.txt" "rsynx://127.0.0.1:7895$DST_DIR/port_spec2.txt"
+    assert_success
+
+    assert_files_equal "$SRC_DIR/port_spec.txt" "$DST_DIR/port_spec1.txt"
+    assert_files_equal "$SRC_DIR/port_spec.txt" "$DST_DIR/port_spec2.txt"
+
+    stop_server
+}
+
+@test "network sync into a daemon module" {
+    create_test_file "$SRC_DIR/module.txt" "Module content"
+    mkdir -p "$DST_DIR/module_root"
+
+    $RSYNX_BIN daemon --port 7896 --module "files=$DST_DIR/module_root" &
+    SERVER_PID=$!
+    sleep 1
+
+    run_rsynx "$SRC_DIR/module.txt" "127.0.0.1:7896::files/module.txt"
+    assert_success
+    assert_files_equal "$SRC_DIR/module.txt" "$DST_DIR/module_root/module.txt"
+
+    run_rsynx "$SRC_DIR/module.txt" "127.0.0.1:7896::files/../escaped.txt"
+    assert_failure
+    assert_file_not_exists "$DST_DIR/escaped.txt"
+
+    run_rsynx "$SRC_DIR/module.txt" "127.0.0.1:7896:$DST_DIR/direct.txt"
+    assert_failure
+
+    stop_server
+}
+
+@test "probe reports server capabilities" {
+    start_server 7899
+
+    run_rsynx probe 127.0.0.1:7899
+    assert_success
+    assert_output_contains "Protocol version: 1"
+    assert_output_contains "Compression: gzip"
+
+    stop_server
+}
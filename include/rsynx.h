@@ -0,0 +1,147 @@
+#ifndef RSYNX_H
+#define RSYNX_H
+
+/* Generated by cbindgen from src/ffi.rs; do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Result of an rsynx call; on anything but `Ok`, `rsynx_last_error` describes the failure.
+ */
+typedef enum RsynxStatus {
+  RSYNX_STATUS_OK = 0,
+  RSYNX_STATUS_ERROR = 1,
+  RSYNX_STATUS_INVALID_ARGUMENT = 2,
+  RSYNX_STATUS_CHECKSUM_MISMATCH = 3,
+} RsynxStatus;
+
+/**
+ * Opaque delta between a signature and a new file.
+ */
+typedef struct RsynxDelta RsynxDelta;
+
+/**
+ * Opaque block signature of a basis file.
+ */
+typedef struct RsynxSignature RsynxSignature;
+
+/**
+ * Called with the bytes handled so far in the current file and its total size.
+ */
+typedef void (*RsynxProgressFn)(uint64_t bytes_done, uint64_t bytes_total, void *user_data);
+
+/**
+ * Byte counts of a finished sync or patch.
+ */
+typedef struct RsynxStats {
+  uint64_t new_bytes;
+  uint64_t reused_bytes;
+} RsynxStats;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Message describing the last failed call on this thread, or null if none failed. The
+ * string stays valid until the next failing call on the same thread.
+ */
+const char *rsynx_last_error(void);
+
+/**
+ * Sync a local file or directory. A `block_size` of 0 uses the default, `progress` may be
+ * null, and `stats` receives the byte counts when non-null.
+ *
+ * # Safety
+ * `source` and `destination` must be NUL-terminated strings and `stats` null or writable.
+ */
+enum RsynxStatus rsynx_sync_local(const char *source,
+                                  const char *destination,
+                                  size_t block_size,
+                                  RsynxProgressFn progress,
+                                  void *user_data,
+                                  struct RsynxStats *stats);
+
+/**
+ * Compute the signature of `basis_path`, or return null on failure.
+ *
+ * # Safety
+ * `basis_path` must be a NUL-terminated string.
+ */
+struct RsynxSignature *rsynx_signature_new(const char *basis_path, size_t block_size);
+
+/**
+ * Read a signature saved with `rsynx_signature_save`, or return null on failure.
+ *
+ * # Safety
+ * `path` must be a NUL-terminated string.
+ */
+struct RsynxSignature *rsynx_signature_load(const char *path);
+
+/**
+ * Write a signature in rsynx's native format.
+ *
+ * # Safety
+ * `signature` must come from this library and `path` be a NUL-terminated string.
+ */
+enum RsynxStatus rsynx_signature_save(const struct RsynxSignature *signature, const char *path);
+
+/**
+ * # Safety
+ * `signature` must be null or come from this library, and not be used afterwards.
+ */
+void rsynx_signature_free(struct RsynxSignature *signature);
+
+/**
+ * Compute the delta from the file described by `signature` to `new_path`, or return null
+ * on failure.
+ *
+ * # Safety
+ * `signature` must come from this library and `new_path` be a NUL-terminated string.
+ */
+struct RsynxDelta *rsynx_delta_new(const struct RsynxSignature *signature, const char *new_path);
+
+/**
+ * Read a delta saved with `rsynx_delta_save`, or return null on failure.
+ *
+ * # Safety
+ * `path` must be a NUL-terminated string.
+ */
+struct RsynxDelta *rsynx_delta_load(const char *path);
+
+/**
+ * Write a delta in rsynx's native format.
+ *
+ * # Safety
+ * `delta` must come from this library and `path` be a NUL-terminated string.
+ */
+enum RsynxStatus rsynx_delta_save(const struct RsynxDelta *delta, const char *path);
+
+/**
+ * Rebuild `output_path` from `basis_path` and `delta`; `stats` receives the byte counts
+ * when non-null.
+ *
+ * # Safety
+ * `delta` must come from this library, the paths be NUL-terminated strings and `stats` null
+ * or writable.
+ */
+enum RsynxStatus rsynx_delta_apply(const char *basis_path,
+                                   const struct RsynxDelta *delta,
+                                   const char *output_path,
+                                   struct RsynxStats *stats);
+
+/**
+ * # Safety
+ * `delta` must be null or come from this library, and not be used afterwards.
+ */
+void rsynx_delta_free(struct RsynxDelta *delta);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RSYNX_H */
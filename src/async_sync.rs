@@ -0,0 +1,1062 @@
+use crate::conflict::STATE_FILE_NAME;
+use crate::daemon::auth_response;
+use crate::delta::{BlockIndex, DeltaGenerator, DeltaOp, Signature};
+use crate::error::{IoContext, Result, SyncError};
+use crate::events::SyncEvent;
+use crate::local_sync::LocalSyncer;
+use crate::lock::{DestinationLock, LOCK_FILE_NAME};
+use crate::network_sync::{NetworkSyncer, Received, RemoteMetadata};
+use crate::pathname;
+use crate::plan::{PlanReason, TransferOrder};
+use crate::probe::ServerInfo;
+use crate::progress::{Phase, ProgressReporter};
+use crate::protocol::{
+    self, Instruction, MAX_INSTRUCTION_SIZE, MAX_LINE_LEN, Request, RequestLine, TableLine,
+};
+use crate::sync::{Block, FileAction, Syncer, TransferResult, permission_bits};
+use filetime::{FileTime, set_file_times};
+use flate2::read::GzDecoder;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::VecDeque,
+    fs::Metadata,
+    io::{self, Read, SeekFrom},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    fs::{self, File},
+    io::{
+        AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite,
+        AsyncWriteExt, BufReader, BufWriter,
+    },
+    net::{TcpListener, TcpStream},
+    task,
+};
+use tracing::{error, info, warn};
+
+/// Size of the chunks read from files and fed to the delta generator.
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// How long `serve_async` waits after a failed accept, so running out of file descriptors
+/// doesn't turn into a busy loop.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+impl LocalSyncer {
+    /// Async variant of `sync` for files and directories, reading and writing through
+    /// `tokio::fs`. Options that need the blocking machinery of `sync` are refused with
+    /// `SyncError::ConflictingOptions`; see `check_async_options`.
+    pub async fn sync_async(&self) -> Result<TransferResult> {
+        self.validate()?;
+        self.check_async_options()?;
+        info!("Local syncing...");
+        let src_path = self.source.as_path();
+        let dst_path = self.destination.as_path();
+        let result = match fs::metadata(src_path).await {
+            Ok(meta) if meta.is_file() => {
+                let _lock = DestinationLock::acquire(dst_path)?;
+                self.sync_file_async(src_path, dst_path, &meta).await?
+            }
+            Ok(meta) if meta.is_dir() => {
+                fs::create_dir_all(dst_path).await?;
+                let _lock = DestinationLock::acquire(dst_path)?;
+                self.sync_dir_async().await?
+            }
+            _ => {
+                return Err(SyncError::UnsupportedSource {
+                    path: src_path.to_path_buf(),
+                    reason: "only regular files and directories can be synced",
+                });
+            }
+        };
+        info!("Local sync completed");
+        Ok(result)
+    }
+
+    /// Refuse the options `sync_async` doesn't implement: deleting, links, ownership,
+    /// checkpoints, partial files, conflicts, backups, journals, atomic or ordered syncs,
+    /// dedup, preallocation, direct I/O, throttling and Unicode normalization.
+    fn check_async_options(&self) -> Result<(), SyncError> {
+        let options = &self.syncer.options;
+        let unsupported = [
+            (
+                "delete",
+                options.delete_extraneous || options.delete_to.is_some(),
+            ),
+            ("preserve_links", options.preserve_links),
+            ("preserve_owner", options.preserve_owner),
+            ("checkpoint", options.checkpoint),
+            ("partial", options.partial || options.partial_dir.is_some()),
+            ("link_dest", options.link_dest.is_some()),
+            ("conflicts", options.conflicts.is_some()),
+            ("backup_dir", options.backup_dir.is_some()),
+            ("journal", options.journal.is_some()),
+            ("atomic", options.atomic),
+            ("order", options.order != TransferOrder::AsFound),
+            ("dedup", options.dedup),
+            ("preallocate", options.preallocate),
+            ("direct_io", options.direct_io),
+            ("bwlimit", options.bwlimit.is_some()),
+            ("disk_limit", options.disk_limit.is_some()),
+            ("unicode_normalize", options.unicode_normalize.is_some()),
+        ];
+        match unsupported.iter().find(|(_, set)| *set) {
+            Some((name, _)) => Err(SyncError::ConflictingOptions(format!(
+                "{} isn't supported by async syncs",
+                name
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Walk the source tree, syncing every file that isn't unchanged. Directories are queued
+    /// rather than recursed into, which async functions can't do without boxing.
+    async fn sync_dir_async(&self) -> Result<TransferResult> {
+        let filter = &self.syncer.options.filter;
+        let mut result = TransferResult::default();
+        let mut dirs = vec![(self.source.clone(), self.destination.clone())];
+        while let Some((src_dir, dst_dir)) = dirs.pop() {
+            info!("Syncing directory: {:?} -> {:?}", src_dir, dst_dir);
+            fs::create_dir_all(&dst_dir).await?;
+            let mut entries = fs::read_dir(&src_dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                self.syncer.check_cancelled()?;
+                let file_name = entry.file_name();
+                if file_name == LOCK_FILE_NAME || file_name == STATE_FILE_NAME {
+                    continue;
+                }
+                let path = entry.path();
+                let dest_path = dst_dir.join(&file_name);
+                let meta = fs::metadata(&path).await.ok();
+                let relative = path.strip_prefix(&self.source).unwrap_or(&path);
+                if !filter.is_empty()
+                    && filter.is_excluded(relative, meta.as_ref().is_some_and(Metadata::is_dir))
+                {
+                    info!("Skipping excluded entry: {:?}", path);
+                    continue;
+                }
+                match meta {
+                    Some(meta) if meta.is_file() => {
+                        if quick_check(&self.syncer, &meta, &path, &dest_path).await?
+                            == PlanReason::Unchanged
+                        {
+                            info!("Skipping unchanged file: {:?}", path);
+                            self.syncer.emit(|| SyncEvent::FileSkipped {
+                                path: dest_path.clone(),
+                            });
+                            result.merge(TransferResult::for_file(
+                                &dest_path,
+                                FileAction::Skipped,
+                                0,
+                                meta.len() as usize,
+                            ));
+                        } else {
+                            result.merge(self.sync_file_async(&path, &dest_path, &meta).await?);
+                        }
+                    }
+                    Some(meta) if meta.is_dir() && self.syncer.options.recursive => {
+                        dirs.push((path, dest_path));
+                    }
+                    Some(meta) if meta.is_dir() => {
+                        info!("Skipping directory in non-recursive mode: {:?}", path);
+                    }
+                    _ => {
+                        warn!("Skipping unsupported file type: {:?}", path);
+                        result.unsupported_files += 1;
+                    }
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Sync one file, reporting its start, completion or failure as events.
+    async fn sync_file_async(
+        &self,
+        src_path: &Path,
+        dst_path: &Path,
+        src_meta: &Metadata,
+    ) -> Result<TransferResult> {
+        self.syncer.emit(|| SyncEvent::FileStarted {
+            path: dst_path.to_path_buf(),
+            size: src_meta.len(),
+        });
+        match self.transfer_file_async(src_path, dst_path, src_meta).await {
+            Ok(result) => {
+                for record in &result.files {
+                    self.syncer
+                        .emit(|| SyncEvent::FileCompleted(record.clone()));
+                }
+                Ok(result)
+            }
+            Err(e) => {
+                self.syncer.emit(|| SyncEvent::Error {
+                    path: dst_path.to_path_buf(),
+                    message: e.to_string(),
+                });
+                Err(e)
+            }
+        }
+    }
+
+    /// Rebuild `dst_path` from `src_path` in a temp file, reusing the blocks of the existing
+    /// destination, then move it into place.
+    async fn transfer_file_async(
+        &self,
+        src_path: &Path,
+        dst_path: &Path,
+        src_meta: &Metadata,
+    ) -> Result<TransferResult> {
+        info!("Syncing file: {:?} -> {:?}", src_path, dst_path);
+        let src_size = src_meta.len();
+        let progress = ProgressReporter::new(
+            &self.syncer,
+            src_path,
+            src_size,
+            format!("Syncing {}", src_path.display()),
+        );
+        progress.update(Phase::Signature, 0);
+        let (action, basis, blocks) = match fs::metadata(dst_path).await {
+            Ok(meta) if meta.is_file() => (
+                FileAction::Updated,
+                Some(File::open(dst_path).await?),
+                block_checksums(&self.syncer, dst_path).await?,
+            ),
+            _ => (FileAction::Created, None, Vec::new()),
+        };
+        let signature = Signature::new(self.syncer.options.block_size, blocks);
+        let index = BlockIndex::new(&signature);
+
+        let temp_path = self.temp_path(dst_path);
+        // A stale temp file, or a symlink in its place, is replaced rather than written through
+        let _ = fs::remove_file(&temp_path).await;
+        let reconstructed = self
+            .reconstruct_async(src_path, dst_path, &index, basis, &temp_path, &progress)
+            .await;
+        let (literal_bytes, reused_bytes, checksum) = match reconstructed {
+            Ok(reconstructed) => reconstructed,
+            Err(e) => {
+                let _ = fs::remove_file(&temp_path).await;
+                return Err(e);
+            }
+        };
+        if self.syncer.options.preserve_metadata {
+            fs::set_permissions(&temp_path, src_meta.permissions())
+                .await
+                .with_context(|| {
+                    format!(
+                        "Failed to set permissions for temporary file: {:?}",
+                        temp_path
+                    )
+                })?;
+            set_times(
+                &temp_path,
+                FileTime::from_last_access_time(src_meta),
+                FileTime::from_last_modification_time(src_meta),
+            )
+            .await
+            .with_context(|| {
+                format!(
+                    "Failed to set file times for temporary file: {:?}",
+                    temp_path
+                )
+            })?;
+        }
+        move_into_place(&temp_path, dst_path).await?;
+
+        if self.syncer.options.verify {
+            progress.update(Phase::Verify, src_size);
+            let actual = file_checksum(dst_path).await?;
+            if actual != checksum {
+                return Err(SyncError::ChecksumMismatch {
+                    path: dst_path.to_path_buf(),
+                    expected: hex::encode(checksum),
+                    actual: hex::encode(actual),
+                });
+            }
+        }
+        progress.finish(format!(
+            "Synced {} ({} bytes)",
+            src_path.display(),
+            src_size
+        ));
+        Ok(TransferResult::for_file(
+            dst_path,
+            action,
+            literal_bytes,
+            reused_bytes,
+        ))
+    }
+
+    /// Write the delta of `src_path` against `index`, the signature of `basis`, to a new file
+    /// at `temp_path`. Returns the literal and reused byte counts and the source's checksum.
+    async fn reconstruct_async(
+        &self,
+        src_path: &Path,
+        dst_path: &Path,
+        index: &BlockIndex<'_>,
+        mut basis: Option<File>,
+        temp_path: &Path,
+        progress: &ProgressReporter<'_>,
+    ) -> Result<(usize, usize, [u8; 32])> {
+        let source = File::open(src_path)
+            .await
+            .with_context(|| format!("Failed to open source file: {:?}", src_path))?;
+        let output = File::create(temp_path)
+            .await
+            .with_context(|| format!("Failed to create temporary file: {:?}", temp_path))?;
+        let mut output = BufWriter::with_capacity(READ_CHUNK_SIZE, output);
+        let mut delta = DeltaStream::new(index, source);
+        let mut literal_bytes = 0;
+        let mut reused_bytes = 0;
+        let mut block = Vec::new();
+        while let Some(op) = delta.next_op(&self.syncer).await? {
+            match op {
+                DeltaOp::Literal(data) => {
+                    output.write_all(&data).await?;
+                    literal_bytes += data.len();
+                }
+                DeltaOp::Copy { offset, len } => {
+                    // Blocks only come from the signature of an existing basis
+                    let basis = basis.as_mut().expect("COPY without a basis file");
+                    basis.seek(SeekFrom::Start(offset)).await?;
+                    block.resize(len, 0);
+                    basis.read_exact(&mut block).await?;
+                    output.write_all(&block).await?;
+                    reused_bytes += len;
+                    self.syncer.emit(|| SyncEvent::BlockMatched {
+                        path: dst_path.to_path_buf(),
+                        offset,
+                        len,
+                    });
+                }
+            }
+            progress.update(Phase::Transfer, (literal_bytes + reused_bytes) as u64);
+        }
+        // Waits for the writes still running on the blocking pool as well
+        output.flush().await?;
+        Ok((literal_bytes, reused_bytes, delta.checksum()))
+    }
+}
+
+impl NetworkSyncer {
+    /// Async variant of `sync`, connecting over TCP to `remote_address:remote_port`. The source
+    /// is read through `tokio::fs` and the protocol runs on a tokio `TcpStream`. `bwlimit` and
+    /// `disk_limit` pace transfers by sleeping, so they are refused here.
+    pub async fn sync_async(&self) -> Result<TransferResult> {
+        self.validate()?;
+        if self.syncer.options.bwlimit.is_some() || self.syncer.options.disk_limit.is_some() {
+            return Err(SyncError::ConflictingOptions(
+                "bwlimit and disk_limit aren't supported by async syncs".to_string(),
+            ));
+        }
+        let peer = format!("{}:{}", self.remote_address, self.remote_port);
+        let stream = TcpStream::connect((self.remote_address.as_str(), self.remote_port))
+            .await
+            .map_err(|source| SyncError::Connect {
+                peer: peer.clone(),
+                source,
+            })?;
+        info!("Connected to remote server at {}", peer);
+        self.run_client_async(stream).await
+    }
+
+    /// Client side of the protocol, like `run_client`.
+    async fn run_client_async(&self, stream: TcpStream) -> Result<TransferResult> {
+        let mut reader = BufReader::new(stream);
+        let src_path = self.source.as_path();
+        let src_meta = match fs::metadata(src_path).await {
+            Ok(meta) if meta.is_file() => meta,
+            _ => {
+                return Err(SyncError::UnsupportedSource {
+                    path: src_path.to_path_buf(),
+                    reason: "only regular files can be synced over the network",
+                });
+            }
+        };
+        let file_size = src_meta.len();
+        let src_filename = src_path
+            .file_name()
+            .ok_or_else(|| SyncError::UnsupportedSource {
+                path: src_path.to_path_buf(),
+                reason: "source has no file name",
+            })?;
+
+        let progress = ProgressReporter::new(
+            &self.syncer,
+            src_path,
+            file_size,
+            format!("Network sync: {}", src_filename.to_string_lossy()),
+        );
+        progress.update(Phase::Signature, 0);
+        self.syncer.emit(|| SyncEvent::FileStarted {
+            path: self.destination.clone(),
+            size: file_size,
+        });
+        if let Some(module) = &self.module {
+            self.open_module_async(&mut reader, module).await?;
+        }
+        // Format: FILE <src_name_len> <dst_path_len> <filesize> followed by the bytes of both
+        let src_name = pathname::as_bytes(Path::new(src_filename))?;
+        let destination = pathname::as_bytes(&self.destination)?;
+        let stream = reader.get_mut();
+        write_line(
+            stream,
+            &format!(
+                "FILE {} {} {}",
+                src_name.len(),
+                destination.len(),
+                file_size
+            ),
+        )
+        .await?;
+        stream.write_all(src_name).await?;
+        stream.write_all(destination).await?;
+
+        let (action, blocks) = match read_block_table(&mut reader).await? {
+            Some(blocks) => (FileAction::Updated, blocks),
+            None => (FileAction::Created, Vec::new()),
+        };
+        let signature = Signature::new(self.syncer.options.block_size, blocks);
+        let index = BlockIndex::new(&signature);
+
+        let src_file = File::open(src_path)
+            .await
+            .with_context(|| format!("Failed to open source file: {:?}", src_path))?;
+        let mut delta = DeltaStream::new(&index, src_file);
+        let mut writer = BufWriter::new(reader.get_mut());
+        let mut pos: u64 = 0;
+        let mut reused_bytes = 0usize;
+        let mut compression_saved_bytes = 0usize;
+        while let Some(op) = delta.next_op(&self.syncer).await? {
+            match op {
+                DeltaOp::Literal(data) => {
+                    match self.syncer.compress_frame(&data)? {
+                        Some(compressed) => {
+                            write_line(&mut writer, &format!("ZDATA {}", compressed.len())).await?;
+                            writer.write_all(&compressed).await?;
+                            compression_saved_bytes += data.len() - compressed.len();
+                        }
+                        None => {
+                            write_line(&mut writer, &format!("DATA {}", data.len())).await?;
+                            writer.write_all(&data).await?;
+                        }
+                    }
+                    pos += data.len() as u64;
+                }
+                DeltaOp::Copy { offset, len } => {
+                    write_line(&mut writer, &format!("COPY {} {}", offset, len)).await?;
+                    pos += len as u64;
+                    reused_bytes += len;
+                    self.syncer.emit(|| SyncEvent::BlockMatched {
+                        path: self.destination.clone(),
+                        offset,
+                        len,
+                    });
+                }
+            }
+            progress.update(Phase::Transfer, pos);
+        }
+        let source_checksum = delta.checksum();
+        if self.syncer.options.preserve_metadata {
+            let atime = FileTime::from_last_access_time(&src_meta);
+            let mtime = FileTime::from_last_modification_time(&src_meta);
+            let meta = format!(
+                "META {:o} {} {} {} {}",
+                permission_bits(&src_meta),
+                atime.unix_seconds(),
+                atime.nanoseconds(),
+                mtime.unix_seconds(),
+                mtime.nanoseconds()
+            );
+            write_line(&mut writer, &meta).await?;
+        }
+        if self.syncer.options.verify {
+            let verify = format!("VERIFY {}", hex::encode(source_checksum));
+            write_line(&mut writer, &verify).await?;
+        }
+        write_line(&mut writer, "DONE").await?;
+        writer.flush().await?;
+        drop(writer);
+
+        if self.syncer.options.verify {
+            progress.update(Phase::Verify, file_size);
+            let reply = read_line(&mut reader).await?.unwrap_or_default();
+            if let Some(actual) = reply.strip_prefix("MISMATCH ") {
+                return Err(SyncError::ChecksumMismatch {
+                    path: self.destination.clone(),
+                    expected: hex::encode(source_checksum),
+                    actual: actual.to_string(),
+                });
+            } else if reply != "VERIFIED" {
+                return Err(SyncError::Protocol(format!(
+                    "Invalid verification response from server: {}",
+                    reply
+                )));
+            }
+        }
+
+        progress.finish(format!(
+            "Network sync complete: {} ({} bytes)",
+            src_filename.to_string_lossy(),
+            file_size
+        ));
+        let mut result = TransferResult::for_file(
+            &self.destination,
+            action,
+            (file_size as usize).saturating_sub(reused_bytes),
+            reused_bytes,
+        );
+        result.compression_saved_bytes = compression_saved_bytes;
+        self.syncer
+            .emit(|| SyncEvent::FileCompleted(result.files[0].clone()));
+        Ok(result)
+    }
+
+    /// Ask the daemon for `module`, like `open_module`.
+    async fn open_module_async(
+        &self,
+        reader: &mut BufReader<TcpStream>,
+        module: &str,
+    ) -> Result<()> {
+        write_line(reader.get_mut(), &format!("MODULE {}", module)).await?;
+        let mut reply = read_line(reader).await?.unwrap_or_default();
+        if let Some(challenge) = reply.strip_prefix("CHALLENGE ") {
+            let Some((user, secret)) = &self.credentials else {
+                return Err(SyncError::Refused(format!(
+                    "module {:?} requires authentication",
+                    module
+                )));
+            };
+            let response = auth_response(challenge, secret);
+            write_line(reader.get_mut(), &format!("AUTH {} {}", user, response)).await?;
+            reply = read_line(reader).await?.unwrap_or_default();
+        }
+        match reply.as_str() {
+            "OK" => Ok(()),
+            reply => match reply.strip_prefix("ERROR ") {
+                Some(reason) => Err(SyncError::Refused(reason.to_string())),
+                None => Err(SyncError::Protocol(format!(
+                    "Invalid response to MODULE: {}",
+                    reply
+                ))),
+            },
+        }
+    }
+
+    /// Serve clients from `listener`, handling connections concurrently. A failed accept or
+    /// connection is logged, and the next client is served.
+    pub async fn serve_async(listener: TcpListener, block_size: usize) -> Result<()> {
+        Self::validate_server_block_size(block_size)?;
+        loop {
+            let (stream, addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    error!("Failed to accept connection: {}", e);
+                    tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                    continue;
+                }
+            };
+            info!("Accepted connection from {:?}", addr);
+            tokio::spawn(async move {
+                match Self::handle_connection_async(stream, block_size).await {
+                    Ok(result) => info!(
+                        "Transfer completed successfully for client {:?}: {} bytes transferred, {} bytes reused",
+                        addr, result.new_bytes, result.reused_bytes
+                    ),
+                    Err(e) => error!("Error handling connection from {:?}: {}", addr, e),
+                }
+            });
+        }
+    }
+
+    /// Serve a single client from `listener`.
+    pub async fn serve_once_async(
+        listener: TcpListener,
+        block_size: usize,
+    ) -> Result<TransferResult> {
+        Self::validate_server_block_size(block_size)?;
+        let (stream, addr) = listener.accept().await?;
+        info!("Accepted connection from {:?}", addr);
+        Self::handle_connection_async(stream, block_size).await
+    }
+
+    /// Run the server side of the protocol for one client, like `handle_connection`, writing
+    /// wherever the client asks. Listings aren't served.
+    pub async fn handle_connection_async(
+        stream: TcpStream,
+        block_size: usize,
+    ) -> Result<TransferResult> {
+        let mut reader = BufReader::new(stream);
+        match read_request(&mut reader).await? {
+            Request::Probe => {
+                let mut info = ServerInfo::local();
+                info.features.retain(|feature| feature != "list");
+                let mut reply = Vec::new();
+                info.write_to(&mut reply)?;
+                reader.get_mut().write_all(&reply).await?;
+                Ok(TransferResult::default())
+            }
+            Request::File {
+                destination, size, ..
+            } => receive_file(&mut reader, destination, size, block_size).await,
+            Request::List { .. } => {
+                let reason = "listings aren't served by async servers";
+                let _ = write_line(reader.get_mut(), &format!("ERROR {}", reason)).await;
+                Err(SyncError::Refused(reason.to_string()))
+            }
+            request => Err(SyncError::Protocol(format!(
+                "Unexpected request: {:?}",
+                request
+            ))),
+        }
+    }
+}
+
+/// A delta generated from an async reader, handing out one instruction at a time like
+/// `Syncer::stream_delta` hands them to its callback.
+struct DeltaStream<'a, R> {
+    /// `None` once the reader is exhausted and the generator finished.
+    generator: Option<DeltaGenerator<'a>>,
+    reader: R,
+    hasher: Sha256,
+    chunk: Vec<u8>,
+    pending: VecDeque<DeltaOp>,
+}
+
+impl<'a, R: AsyncRead + Unpin> DeltaStream<'a, R> {
+    fn new(index: &'a BlockIndex<'a>, reader: R) -> Self {
+        Self {
+            generator: Some(DeltaGenerator::new(index)),
+            reader,
+            hasher: Sha256::new(),
+            chunk: vec![0; READ_CHUNK_SIZE],
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// The next instruction, or `None` once the whole reader is covered.
+    async fn next_op(&mut self, syncer: &Syncer) -> Result<Option<DeltaOp>> {
+        loop {
+            if let Some(op) = self.pending.pop_front() {
+                return Ok(Some(op));
+            }
+            let Some(generator) = self.generator.as_mut() else {
+                return Ok(None);
+            };
+            let read = match self.reader.read(&mut self.chunk).await {
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            syncer.check_cancelled()?;
+            let pending = &mut self.pending;
+            let mut emit = |op| {
+                pending.push_back(op);
+                Ok::<_, SyncError>(())
+            };
+            if read == 0 {
+                if let Some(generator) = self.generator.take() {
+                    generator.finish(&mut emit)?;
+                }
+            } else {
+                self.hasher.update(&self.chunk[..read]);
+                generator.feed(&self.chunk[..read], &mut emit)?;
+            }
+        }
+    }
+
+    /// SHA-256 of everything read.
+    fn checksum(self) -> [u8; 32] {
+        self.hasher.finalize().into()
+    }
+}
+
+/// Serve a FILE request for `destination`, like `NetworkSyncer::receive_file` without a root,
+/// preallocation or read-only mode.
+async fn receive_file(
+    reader: &mut BufReader<TcpStream>,
+    destination: PathBuf,
+    filesize: u64,
+    block_size: usize,
+) -> Result<TransferResult> {
+    let target = destination.as_path();
+    let _lock = match DestinationLock::acquire(target) {
+        Ok(lock) => lock,
+        Err(e) => {
+            let _ = write_line(reader.get_mut(), &format!("ERROR {}", e)).await;
+            return Err(e);
+        }
+    };
+    let exists = fs::try_exists(target).await.unwrap_or(false);
+    let action = if exists {
+        FileAction::Updated
+    } else {
+        FileAction::Created
+    };
+    let mut syncer = Syncer::new();
+    syncer.options.block_size = block_size;
+
+    let temp_path = target.with_extension("tmp");
+    // A stale temp file, or a symlink in its place, is replaced rather than written through
+    let _ = fs::remove_file(&temp_path).await;
+    let mut temp_file = File::create(&temp_path).await?;
+    let received = receive_into(reader, &syncer, &mut temp_file, target, exists, filesize).await;
+    // Waits for the writes still running on the blocking pool as well
+    let received = match received {
+        Ok(received) => temp_file
+            .flush()
+            .await
+            .map(|_| received)
+            .map_err(Into::into),
+        Err(e) => Err(e),
+    };
+    drop(temp_file);
+    let received = match received {
+        Ok(received) => received,
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path).await;
+            return Err(e);
+        }
+    };
+
+    if let Some(metadata) = &received.metadata {
+        set_file_mode(&temp_path, metadata.mode)
+            .await
+            .with_context(|| {
+                format!(
+                    "Failed to set permissions for temporary file: {:?}",
+                    temp_path
+                )
+            })?;
+        set_times(&temp_path, metadata.atime, metadata.mtime)
+            .await
+            .with_context(|| {
+                format!(
+                    "Failed to set file times for temporary file: {:?}",
+                    temp_path
+                )
+            })?;
+    }
+    fs::rename(&temp_path, target).await?;
+    if let Some(expected) = received.checksum {
+        let actual = file_checksum(target).await?;
+        if actual != expected {
+            write_line(
+                reader.get_mut(),
+                &format!("MISMATCH {}", hex::encode(actual)),
+            )
+            .await?;
+            return Err(SyncError::ChecksumMismatch {
+                path: target.to_path_buf(),
+                expected: hex::encode(expected),
+                actual: hex::encode(actual),
+            });
+        }
+        write_line(reader.get_mut(), "VERIFIED").await?;
+    }
+    Ok(TransferResult::for_file(
+        target,
+        action,
+        received.literal_bytes,
+        received.reused_bytes,
+    ))
+}
+
+/// Send the block table of `target`, or NOBLK unless it `exists`, then apply the client's
+/// instructions to `temp_file` until DONE.
+async fn receive_into(
+    reader: &mut BufReader<TcpStream>,
+    syncer: &Syncer,
+    temp_file: &mut File,
+    target: &Path,
+    exists: bool,
+    filesize: u64,
+) -> Result<Received> {
+    let mut table = BufWriter::new(reader.get_mut());
+    if exists {
+        for block in block_checksums(syncer, target).await? {
+            let line = format!(
+                "BLK {} {} {} {}",
+                block.offset,
+                block.size,
+                block.weak_checksum,
+                hex::encode(block.strong_checksum)
+            );
+            write_line(&mut table, &line).await?;
+        }
+        write_line(&mut table, "BLKEND").await?;
+    } else {
+        write_line(&mut table, "NOBLK").await?;
+    }
+    table.flush().await?;
+
+    let mut old_file = match exists {
+        true => Some(File::open(target).await?),
+        false => None,
+    };
+    let mut received = Received::default();
+    loop {
+        let line = read_line(reader)
+            .await?
+            .ok_or_else(|| SyncError::Protocol("Connection closed before DONE".to_string()))?;
+        match protocol::parse_instruction(&line)? {
+            Instruction::Done => break,
+            Instruction::Data(length) => {
+                let copied = tokio::io::copy(&mut (&mut *reader).take(length), temp_file).await?;
+                if copied != length {
+                    return Err(SyncError::Protocol(
+                        "Connection closed inside DATA payload".to_string(),
+                    ));
+                }
+                received.literal_bytes += copied as usize;
+            }
+            Instruction::ZData(length) => {
+                let mut compressed = Vec::new();
+                (&mut *reader)
+                    .take(length)
+                    .read_to_end(&mut compressed)
+                    .await?;
+                if compressed.len() as u64 != length {
+                    return Err(SyncError::Protocol(
+                        "Connection closed inside ZDATA payload".to_string(),
+                    ));
+                }
+                // Cap the inflated size too, so a tiny payload can't expand without bound
+                let mut inflated = Vec::new();
+                GzDecoder::new(&compressed[..])
+                    .take(MAX_INSTRUCTION_SIZE + 1)
+                    .read_to_end(&mut inflated)
+                    .with_context(|| "Failed to decompress ZDATA payload")?;
+                if inflated.len() as u64 > MAX_INSTRUCTION_SIZE {
+                    return Err(SyncError::Protocol(format!(
+                        "ZDATA payload inflates beyond {} bytes",
+                        MAX_INSTRUCTION_SIZE
+                    )));
+                }
+                temp_file.write_all(&inflated).await?;
+                received.literal_bytes += inflated.len();
+            }
+            Instruction::Copy { offset, length } => {
+                let Some(f) = old_file.as_mut() else {
+                    return Err(SyncError::Protocol(
+                        "COPY command received but no old file available".to_string(),
+                    ));
+                };
+                f.seek(SeekFrom::Start(offset)).await?;
+                let copied = tokio::io::copy(&mut f.take(length), temp_file).await?;
+                if copied != length {
+                    return Err(SyncError::Protocol(format!(
+                        "COPY {} {} reads past the end of {:?}",
+                        offset, length, target
+                    )));
+                }
+                received.reused_bytes += copied as usize;
+            }
+            Instruction::Verify(checksum) => received.checksum = Some(checksum),
+            Instruction::Meta { mode, atime, mtime } => {
+                // Setuid, setgid and sticky bits are dropped, so a client can't plant a
+                // setuid program owned by the server's user
+                let mode = mode & 0o777;
+                received.metadata = Some(RemoteMetadata { mode, atime, mtime });
+            }
+        }
+        if (received.literal_bytes + received.reused_bytes) as u64 > filesize {
+            return Err(SyncError::Protocol(format!(
+                "Client sent more than the announced {} bytes",
+                filesize
+            )));
+        }
+    }
+    Ok(received)
+}
+
+/// Read one protocol line; see `protocol::read_line`.
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<String>> {
+    let mut line = Vec::new();
+    reader
+        .take(MAX_LINE_LEN as u64)
+        .read_until(b'\n', &mut line)
+        .await?;
+    protocol::decode_line(line)
+}
+
+/// Write one protocol line.
+async fn write_line<W: AsyncWrite + Unpin>(writer: &mut W, line: &str) -> io::Result<()> {
+    writer.write_all(line.as_bytes()).await?;
+    writer.write_all(b"\n").await
+}
+
+/// Whether `dst` already matches the source at `src`, described by `src_meta`; see
+/// `Syncer::quick_check`.
+async fn quick_check(
+    syncer: &Syncer,
+    src_meta: &Metadata,
+    src: &Path,
+    dst: &Path,
+) -> Result<PlanReason> {
+    let dst_meta = match fs::metadata(dst).await {
+        Ok(meta) if meta.is_file() => meta,
+        _ => return Ok(PlanReason::Missing),
+    };
+    if src_meta.len() != dst_meta.len() {
+        return Ok(PlanReason::SizeChanged);
+    }
+    if syncer.options.checksum {
+        if file_checksum(src).await? == file_checksum(dst).await? {
+            return Ok(PlanReason::Unchanged);
+        }
+        return Ok(PlanReason::ChecksumChanged);
+    }
+    if syncer.options.ignore_times {
+        return Ok(PlanReason::TimesIgnored);
+    }
+    if syncer.mtimes_match(
+        FileTime::from_last_modification_time(src_meta),
+        FileTime::from_last_modification_time(&dst_meta),
+    ) {
+        Ok(PlanReason::Unchanged)
+    } else {
+        Ok(PlanReason::MtimeChanged)
+    }
+}
+
+/// Read a request line and the paths following it; see `protocol::read_request`.
+async fn read_request<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Request> {
+    let line = read_line(reader)
+        .await?
+        .ok_or_else(|| SyncError::Protocol("Connection closed before a request".to_string()))?;
+    Ok(match protocol::parse_request_line(&line)? {
+        RequestLine::Complete(request) => request,
+        RequestLine::List { hash, path_len } => Request::List {
+            hash,
+            path: read_path(reader, path_len).await?,
+        },
+        RequestLine::File {
+            source_name_len,
+            destination_len,
+            size,
+        } => Request::File {
+            source_name: read_path(reader, source_name_len).await?,
+            destination: read_path(reader, destination_len).await?,
+            size,
+        },
+    })
+}
+
+/// Read the `len` bytes of a path sent after a command line; see `protocol::decode_path`.
+async fn read_path<R: AsyncRead + Unpin>(reader: &mut R, len: usize) -> Result<PathBuf> {
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes).await?;
+    protocol::decode_path(bytes)
+}
+
+/// Read a server's block table; see `protocol::read_block_table`.
+async fn read_block_table<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<Vec<Block>>> {
+    let mut blocks = Vec::new();
+    loop {
+        let line = read_line(reader).await?.ok_or_else(|| {
+            SyncError::Protocol("Connection closed inside the block table".to_string())
+        })?;
+        match protocol::parse_table_line(&line, blocks.len())? {
+            TableLine::Missing => return Ok(None),
+            TableLine::End => return Ok(Some(blocks)),
+            TableLine::Block(block) => blocks.push(block),
+        }
+    }
+}
+
+/// Block checksums of `path`, like `Syncer::calculate_checksums`.
+async fn block_checksums(syncer: &Syncer, path: &Path) -> Result<Vec<Block>> {
+    let file = File::open(path)
+        .await
+        .with_context(|| format!("Failed to calculate signature for {:?}", path))?;
+    let mut file = BufReader::with_capacity(READ_CHUNK_SIZE, file);
+    let mut blocks = Vec::new();
+    let mut offset: u64 = 0;
+    let mut buffer = vec![0; syncer.options.block_size];
+    loop {
+        let mut read = 0;
+        while read < buffer.len() {
+            match file.read(&mut buffer[read..]).await? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        if read == 0 {
+            return Ok(blocks);
+        }
+        let data = &buffer[..read];
+        blocks.push(Block {
+            offset,
+            size: read,
+            weak_checksum: syncer.calculate_weak_checksum(data),
+            strong_checksum: syncer.calculate_strong_checksum(data),
+        });
+        offset += read as u64;
+    }
+}
+
+/// SHA-256 of a whole file, like `Syncer::calculate_file_checksum`.
+async fn file_checksum(path: &Path) -> Result<[u8; 32]> {
+    let mut file = File::open(path)
+        .await
+        .with_context(|| format!("Failed to open file: {:?}", path))?;
+    let mut hasher = Sha256::new();
+    let mut chunk = vec![0; READ_CHUNK_SIZE];
+    loop {
+        match file.read(&mut chunk).await? {
+            0 => return Ok(hasher.finalize().into()),
+            read => hasher.update(&chunk[..read]),
+        }
+    }
+}
+
+/// `set_file_times` on the blocking pool, where `tokio::fs` runs its calls too.
+async fn set_times(path: &Path, atime: FileTime, mtime: FileTime) -> io::Result<()> {
+    let path = path.to_path_buf();
+    task::spawn_blocking(move || set_file_times(path, atime, mtime))
+        .await
+        .map_err(io::Error::other)?
+}
+
+#[cfg(unix)]
+async fn set_file_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).await
+}
+
+#[cfg(not(unix))]
+async fn set_file_mode(path: &Path, mode: u32) -> io::Result<()> {
+    let mut permissions = fs::metadata(path).await?.permissions();
+    permissions.set_readonly(mode & 0o222 == 0);
+    fs::set_permissions(path, permissions).await
+}
+
+/// Rename a finished temp file over `dst_path`, like `LocalSyncer::move_into_place`.
+async fn move_into_place(temp_path: &Path, dst_path: &Path) -> Result<()> {
+    match fs::rename(temp_path, dst_path).await {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {}
+        result => {
+            return result
+                .with_context(|| format!("Failed to move {:?} to {:?}", temp_path, dst_path));
+        }
+    }
+    let staged = dst_path.with_extension("tmp");
+    let temp_meta = fs::metadata(temp_path).await?;
+    fs::copy(temp_path, &staged)
+        .await
+        .with_context(|| format!("Failed to copy {:?} to {:?}", temp_path, staged))?;
+    set_times(
+        &staged,
+        FileTime::from_last_access_time(&temp_meta),
+        FileTime::from_last_modification_time(&temp_meta),
+    )
+    .await?;
+    fs::rename(&staged, dst_path)
+        .await
+        .with_context(|| format!("Failed to move {:?} to {:?}", staged, dst_path))?;
+    fs::remove_file(temp_path)
+        .await
+        .with_context(|| format!("Failed to remove temporary file: {:?}", temp_path))
+}
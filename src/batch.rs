@@ -0,0 +1,80 @@
+use crate::error::{Result, SyncError};
+use std::{
+    io::BufRead,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+};
+
+/// One independent sync listed in a batch: a source and a local or remote destination.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchEntry {
+    pub source: String,
+    pub destination: String,
+}
+
+/// Read one `SOURCE DESTINATION` pair per line. The two are separated by a tab, so paths may
+/// contain spaces, or by whitespace when the line has no tab. Blank lines and lines starting
+/// with `#` are skipped.
+pub fn read_entries<R: BufRead>(reader: R) -> Result<Vec<BatchEntry>> {
+    let mut entries = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = if trimmed.contains('\t') {
+            trimmed
+                .split('\t')
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+                .collect()
+        } else {
+            trimmed.split_whitespace().collect()
+        };
+        let [source, destination] = fields[..] else {
+            return Err(SyncError::Format(format!(
+                "Batch line {}: expected a source and a destination, got {:?}",
+                index + 1,
+                trimmed
+            )));
+        };
+        entries.push(BatchEntry {
+            source: source.to_string(),
+            destination: destination.to_string(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Run `sync` on every entry with up to `jobs` running at once, returning the results in the
+/// order of `entries`. A failing entry doesn't stop the others.
+pub fn run<R, F>(entries: &[BatchEntry], jobs: usize, sync: F) -> Vec<R>
+where
+    R: Send,
+    F: Fn(&BatchEntry) -> R + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results: Vec<Mutex<Option<R>>> = entries.iter().map(|_| Mutex::new(None)).collect();
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, entries.len().max(1)) {
+            scope.spawn(|| {
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(entry) = entries.get(index) else {
+                        break;
+                    };
+                    let result = sync(entry);
+                    *results[index].lock().unwrap() = Some(result);
+                }
+            });
+        }
+    });
+    results
+        .into_iter()
+        .map(|slot| slot.into_inner().unwrap().expect("every entry was run"))
+        .collect()
+}
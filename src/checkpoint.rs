@@ -0,0 +1,140 @@
+use crate::error::{IoContext, Result};
+use crate::pathname;
+use filetime::FileTime;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+/// Name of the state file kept in the destination root while a directory sync is running.
+pub const CHECKPOINT_FILE_NAME: &str = ".rsynx-checkpoint";
+
+/// Size and modification time of a source file, used to tell whether a partially written
+/// temp file was built from the same content we are about to resume from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceStamp {
+    pub len: u64,
+    pub mtime: FileTime,
+}
+
+impl SourceStamp {
+    pub fn of(path: &Path) -> Result<Self> {
+        let meta = fs::metadata(path)
+            .with_context(|| format!("Failed to get metadata for source file: {:?}", path))?;
+        Ok(Self {
+            len: meta.len(),
+            mtime: FileTime::from_last_modification_time(&meta),
+        })
+    }
+}
+
+/// Append-only log of directory sync progress.
+///
+/// Each line is either `done <path>` for a finished entry or
+/// `partial <offset> <len> <mtime_secs> <mtime_nanos> <path>` for a file whose temp copy is
+/// complete up to `offset`. Paths are relative to the sync root, written with
+/// `pathname::escape`; later lines win.
+pub struct Checkpoint {
+    path: PathBuf,
+    file: File,
+    completed: HashSet<PathBuf>,
+    partial: HashMap<PathBuf, (u64, SourceStamp)>,
+}
+
+impl Checkpoint {
+    /// Open the checkpoint in `dst_root`, replaying whatever an interrupted run left behind.
+    pub fn open(dst_root: &Path) -> Result<Self> {
+        let path = dst_root.join(CHECKPOINT_FILE_NAME);
+        let mut completed = HashSet::new();
+        let mut partial = HashMap::new();
+        if path.exists() {
+            let reader = BufReader::new(
+                File::open(&path)
+                    .with_context(|| format!("Failed to open checkpoint: {:?}", path))?,
+            );
+            for line in reader.lines() {
+                let line = line?;
+                // A torn final line from a crash is simply ignored
+                if let Some(key) = line.strip_prefix("done ").and_then(pathname::unescape) {
+                    partial.remove(&key);
+                    completed.insert(key);
+                } else if let Some((key, offset, stamp)) = parse_partial(&line) {
+                    partial.insert(key, (offset, stamp));
+                }
+            }
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open checkpoint: {:?}", path))?;
+        Ok(Self {
+            path,
+            file,
+            completed,
+            partial,
+        })
+    }
+
+    pub fn is_completed(&self, key: &Path) -> bool {
+        self.completed.contains(key)
+    }
+
+    /// Offset to resume `key` from, if a previous run recorded progress for the same source.
+    pub fn resume_offset(&self, key: &Path, stamp: &SourceStamp) -> Option<u64> {
+        self.partial
+            .get(key)
+            .filter(|(_, recorded)| recorded == stamp)
+            .map(|(offset, _)| *offset)
+    }
+
+    /// Record that the temp file for `key` holds final content up to `offset`. The caller
+    /// must have flushed that content to disk first.
+    pub fn record_partial(&mut self, key: &Path, offset: u64, stamp: &SourceStamp) -> Result<()> {
+        writeln!(
+            self.file,
+            "partial {} {} {} {} {}",
+            offset,
+            stamp.len,
+            stamp.mtime.unix_seconds(),
+            stamp.mtime.nanoseconds(),
+            pathname::escape(key)
+        )?;
+        self.file.sync_data()?;
+        self.partial.insert(key.to_path_buf(), (offset, *stamp));
+        Ok(())
+    }
+
+    pub fn record_completed(&mut self, key: &Path) -> Result<()> {
+        writeln!(self.file, "done {}", pathname::escape(key))?;
+        self.partial.remove(key);
+        self.completed.insert(key.to_path_buf());
+        Ok(())
+    }
+
+    /// Remove the state file once the whole sync has succeeded.
+    pub fn finish(self) -> Result<()> {
+        drop(self.file);
+        fs::remove_file(&self.path)
+            .with_context(|| format!("Failed to remove checkpoint: {:?}", self.path))
+    }
+}
+
+fn parse_partial(line: &str) -> Option<(PathBuf, u64, SourceStamp)> {
+    let mut parts = line.strip_prefix("partial ")?.splitn(5, ' ');
+    let offset = parts.next()?.parse().ok()?;
+    let len = parts.next()?.parse().ok()?;
+    let seconds = parts.next()?.parse().ok()?;
+    let nanos = parts.next()?.parse().ok()?;
+    let key = pathname::unescape(parts.next()?)?;
+    Some((
+        key,
+        offset,
+        SourceStamp {
+            len,
+            mtime: FileTime::from_unix_time(seconds, nanos),
+        },
+    ))
+}
@@ -0,0 +1,101 @@
+use crate::error::{IoContext, Result, SyncError};
+use serde::Deserialize;
+use std::{collections::BTreeMap, env, fs, path::Path, path::PathBuf};
+
+/// Default options read from a TOML config file, plus named profiles layered on top:
+///
+/// ```toml
+/// block-size = 4096
+/// exclude = ["*.tmp"]
+///
+/// [profiles.backups]
+/// destination = "backup-host:/srv/backups"
+/// compress = true
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    #[serde(flatten)]
+    pub defaults: ConfigOptions,
+    pub profiles: BTreeMap<String, ConfigOptions>,
+}
+
+/// Settings a config file or profile may supply; unset fields fall through to the next layer.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ConfigOptions {
+    pub block_size: Option<usize>,
+    pub compress: Option<bool>,
+    pub metadata: Option<bool>,
+    pub recursive: Option<bool>,
+    pub delete: Option<bool>,
+    pub checksum: Option<bool>,
+    pub modify_window: Option<u64>,
+    /// Rate in the CLI's `--bwlimit` syntax, such as `"500k"`.
+    pub bwlimit: Option<String>,
+    /// Rate in the same syntax for `--disk-limit`.
+    pub disk_limit: Option<String>,
+    pub port: Option<u16>,
+    /// Exclude patterns, applied after any given on the command line.
+    pub exclude: Vec<String>,
+    /// Destination used when the command line gives only a source.
+    pub destination: Option<String>,
+    /// URL the status and totals of each run are posted to, as `--notify-url`.
+    pub notify_url: Option<String>,
+    /// Shell command run after each run, as `--notify-cmd`.
+    pub notify_cmd: Option<String>,
+}
+
+impl ConfigOptions {
+    /// These options with every field set in `overrides` replaced; excludes are appended.
+    pub fn merge(mut self, overrides: ConfigOptions) -> Self {
+        self.block_size = overrides.block_size.or(self.block_size);
+        self.compress = overrides.compress.or(self.compress);
+        self.metadata = overrides.metadata.or(self.metadata);
+        self.recursive = overrides.recursive.or(self.recursive);
+        self.delete = overrides.delete.or(self.delete);
+        self.checksum = overrides.checksum.or(self.checksum);
+        self.modify_window = overrides.modify_window.or(self.modify_window);
+        self.bwlimit = overrides.bwlimit.or(self.bwlimit);
+        self.disk_limit = overrides.disk_limit.or(self.disk_limit);
+        self.port = overrides.port.or(self.port);
+        self.exclude.extend(overrides.exclude);
+        self.destination = overrides.destination.or(self.destination);
+        self.notify_url = overrides.notify_url.or(self.notify_url);
+        self.notify_cmd = overrides.notify_cmd.or(self.notify_cmd);
+        self
+    }
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/rsynx/config.toml`, falling back to `~/.config/rsynx/config.toml`.
+    pub fn default_path() -> Option<PathBuf> {
+        let base = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(base.join("rsynx").join("config.toml"))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        toml::from_str(contents).map_err(|e| SyncError::Config(e.to_string()))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {:?}", path))?;
+        Self::parse(&contents)
+    }
+
+    /// The defaults with `profile`, if given, applied on top.
+    pub fn resolve(&self, profile: Option<&str>) -> Result<ConfigOptions> {
+        let Some(name) = profile else {
+            return Ok(self.defaults.clone());
+        };
+        let overrides = self
+            .profiles
+            .get(name)
+            .ok_or_else(|| SyncError::Config(format!("no profile named {:?}", name)))?;
+        Ok(self.defaults.clone().merge(overrides.clone()))
+    }
+}
@@ -0,0 +1,168 @@
+use crate::checkpoint::SourceStamp;
+use crate::error::{IoContext, Result};
+use crate::pathname;
+use filetime::FileTime;
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fmt,
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Name of the file in the destination root recording its files as syncs left them.
+pub const STATE_FILE_NAME: &str = ".rsynx-state";
+
+/// What a sync does with a destination file changed since the last sync when it would
+/// overwrite or delete it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Leave the destination's version in place; it stays a conflict on later runs.
+    Skip,
+    /// Replace or delete it as if it hadn't changed.
+    Overwrite,
+    /// Move it aside to `<name>.conflict-<n>` first, where later syncs leave it alone.
+    Rename,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(ConflictPolicy::Skip),
+            "overwrite" => Ok(ConflictPolicy::Overwrite),
+            "rename" => Ok(ConflictPolicy::Rename),
+            _ => Err(format!(
+                "unknown conflict policy {:?}; expected skip, overwrite or rename",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ConflictPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ConflictPolicy::Skip => "skip",
+            ConflictPolicy::Overwrite => "overwrite",
+            ConflictPolicy::Rename => "rename",
+        })
+    }
+}
+
+/// Size and modification time of each destination file as the last sync left it, by path
+/// relative to the destination root.
+///
+/// The state file has a `<len> <mtime_secs> <mtime_nanos> <path>` line per file, with the
+/// path written by `pathname::escape`.
+#[derive(Debug, Default)]
+pub struct SyncState {
+    path: PathBuf,
+    files: HashMap<PathBuf, SourceStamp>,
+}
+
+impl SyncState {
+    /// Read the state recorded in `dst_root`; empty when no earlier sync recorded one.
+    pub fn load(dst_root: &Path) -> Result<Self> {
+        let path = dst_root.join(STATE_FILE_NAME);
+        let mut files = HashMap::new();
+        if path.exists() {
+            let reader = BufReader::new(
+                File::open(&path)
+                    .with_context(|| format!("Failed to open sync state: {:?}", path))?,
+            );
+            for line in reader.lines() {
+                if let Some((key, stamp)) = parse_line(&line?) {
+                    files.insert(key, stamp);
+                }
+            }
+        }
+        Ok(Self { path, files })
+    }
+
+    /// Whether `path`, recorded as `key`, was changed since the last sync. Files the state
+    /// doesn't know of, and files that are gone, haven't been.
+    pub fn changed_since(&self, key: &Path, path: &Path) -> bool {
+        match (self.files.get(key), SourceStamp::of(path)) {
+            (Some(recorded), Ok(current)) => *recorded != current,
+            _ => false,
+        }
+    }
+
+    /// Remember `path` as it is now under `key`.
+    pub fn record(&mut self, key: &Path, path: &Path) -> Result<()> {
+        self.files.insert(key.to_path_buf(), SourceStamp::of(path)?);
+        Ok(())
+    }
+
+    /// Stop tracking `key`, such as after deleting it.
+    pub fn forget(&mut self, key: &Path) {
+        self.files.remove(key);
+    }
+
+    /// Write the state back to the destination, replacing the file atomically.
+    pub fn save(&self) -> Result<()> {
+        let temp_path = self.path.with_extension("tmp");
+        let mut keys: Vec<_> = self.files.keys().collect();
+        keys.sort();
+        let mut writer = BufWriter::new(
+            File::create(&temp_path)
+                .with_context(|| format!("Failed to write sync state: {:?}", temp_path))?,
+        );
+        for key in keys {
+            let stamp = &self.files[key];
+            writeln!(
+                writer,
+                "{} {} {} {}",
+                stamp.len,
+                stamp.mtime.unix_seconds(),
+                stamp.mtime.nanoseconds(),
+                pathname::escape(key)
+            )?;
+        }
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&temp_path, &self.path)
+            .with_context(|| format!("Failed to write sync state: {:?}", self.path))
+    }
+}
+
+/// Where a conflicting `path` is moved by `ConflictPolicy::Rename`: the first free
+/// `<name>.conflict-<n>`.
+pub fn conflict_path(path: &Path) -> PathBuf {
+    (1..)
+        .map(|n| {
+            let mut name = path.as_os_str().to_os_string();
+            name.push(format!(".conflict-{}", n));
+            PathBuf::from(name)
+        })
+        .find(|candidate| fs::symlink_metadata(candidate).is_err())
+        .expect("some numbered name is free")
+}
+
+/// Whether `name` is that of a copy moved aside by `ConflictPolicy::Rename`.
+pub fn is_conflict_copy(name: &OsStr) -> bool {
+    const MARKER: &[u8] = b".conflict-";
+    let name = name.as_encoded_bytes();
+    let digits = name.iter().rev().take_while(|b| b.is_ascii_digit()).count();
+    let stem_len = name.len() - digits;
+    digits > 0 && name[..stem_len].ends_with(MARKER) && stem_len > MARKER.len()
+}
+
+fn parse_line(line: &str) -> Option<(PathBuf, SourceStamp)> {
+    let mut parts = line.splitn(4, ' ');
+    let len = parts.next()?.parse().ok()?;
+    let seconds = parts.next()?.parse().ok()?;
+    let nanos = parts.next()?.parse().ok()?;
+    let key = pathname::unescape(parts.next()?)?;
+    Some((
+        key,
+        SourceStamp {
+            len,
+            mtime: FileTime::from_unix_time(seconds, nanos),
+        },
+    ))
+}
@@ -0,0 +1,602 @@
+use alloc::{format, string::String, vec::Vec};
+use blake2::{Blake2b, digest::consts::U32};
+use hashbrown::HashMap;
+use md4::Md4;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Largest literal run emitted as a single instruction, so deltas can be streamed in bounded memory.
+pub const MAX_LITERAL_SIZE: usize = 64 * 1024;
+
+/// Version written into serde-serialized signatures; bumped whenever their layout changes.
+pub const SIGNATURE_SERDE_VERSION: u32 = 1;
+
+const ROLLSUM_CHAR_OFFSET: u32 = 31;
+const RABINKARP_SEED: u32 = 1;
+const RABINKARP_MULT: u32 = 0x0810_4225;
+const RABINKARP_ADJ: u32 = 0x0810_4224;
+
+/// rsynx's Adler-style weak checksum.
+pub fn weak_checksum(data: &[u8]) -> u32 {
+    let mut a: u32 = 0;
+    let mut b: u32 = 0;
+
+    for &byte in data {
+        a = a.wrapping_add(byte as u32);
+        b = b.wrapping_add(a);
+    }
+    (a & 0xffff) | ((b & 0xffff) << 16)
+}
+
+/// Slide a `weak_checksum` window of `len` bytes by one byte.
+pub fn update_weak_checksum(old_byte: u8, new_byte: u8, old_sum: u32, len: usize) -> u32 {
+    let a_old = old_sum & 0xffff;
+    let b_old = (old_sum >> 16) & 0xffff;
+    let a_new = a_old
+        .wrapping_sub(old_byte as u32)
+        .wrapping_add(new_byte as u32);
+    let b_new = b_old
+        .wrapping_sub((len as u32).wrapping_mul(old_byte as u32))
+        .wrapping_add(a_new);
+    (a_new & 0xffff) | ((b_new & 0xffff) << 16)
+}
+
+/// SHA-256 strong checksum.
+pub fn strong_checksum(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+/// librsync's rollsum: an Adler-style checksum with every byte offset by 31.
+pub fn rollsum(data: &[u8]) -> u32 {
+    let mut s1: u32 = 0;
+    let mut s2: u32 = 0;
+    for &byte in data {
+        s1 = s1.wrapping_add(byte as u32 + ROLLSUM_CHAR_OFFSET);
+        s2 = s2.wrapping_add(s1);
+    }
+    (s1 & 0xffff) | ((s2 & 0xffff) << 16)
+}
+
+/// Slide a rollsum window by one byte.
+pub fn rollsum_rotate(old_byte: u8, new_byte: u8, sum: u32, len: usize) -> u32 {
+    let s1 = (sum & 0xffff)
+        .wrapping_sub(old_byte as u32)
+        .wrapping_add(new_byte as u32);
+    let s2 = ((sum >> 16) & 0xffff)
+        .wrapping_add(s1)
+        .wrapping_sub((len as u32).wrapping_mul(old_byte as u32 + ROLLSUM_CHAR_OFFSET));
+    (s1 & 0xffff) | ((s2 & 0xffff) << 16)
+}
+
+/// librsync's RabinKarp polynomial rolling hash, the default since librsync 2.2.
+pub fn rabinkarp(data: &[u8]) -> u32 {
+    data.iter().fold(RABINKARP_SEED, |hash, &byte| {
+        hash.wrapping_mul(RABINKARP_MULT).wrapping_add(byte as u32)
+    })
+}
+
+/// Multiplier needed to roll a RabinKarp window of `len` bytes.
+pub fn rabinkarp_factor(len: usize) -> u32 {
+    RABINKARP_MULT.wrapping_pow(len as u32)
+}
+
+/// Slide a RabinKarp window by one byte, given `factor = rabinkarp_factor(len)`.
+pub fn rabinkarp_rotate(old_byte: u8, new_byte: u8, hash: u32, factor: u32) -> u32 {
+    hash.wrapping_mul(RABINKARP_MULT)
+        .wrapping_add(new_byte as u32)
+        .wrapping_sub(factor.wrapping_mul(old_byte as u32 + RABINKARP_ADJ))
+}
+
+/// MD4 strong sum, zero-padded to the 32 bytes stored per block.
+pub fn md4(data: &[u8]) -> [u8; 32] {
+    let mut sum = [0u8; 32];
+    sum[..16].copy_from_slice(&Md4::digest(data));
+    sum
+}
+
+/// BLAKE2b-256 strong sum as used by librsync.
+pub fn blake2(data: &[u8]) -> [u8; 32] {
+    Blake2b::<U32>::digest(data).into()
+}
+
+/// Rolling checksum used to find candidate blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WeakHash {
+    /// rsynx's Adler-style checksum.
+    Adler,
+    /// librsync's rollsum.
+    RollSum,
+    /// librsync's RabinKarp hash.
+    RabinKarp,
+}
+
+/// Strong checksum used to confirm a candidate block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StrongHash {
+    Sha256,
+    Md4,
+    Blake2,
+}
+
+impl StrongHash {
+    /// Full length of the digest in bytes.
+    pub fn digest_len(self) -> usize {
+        match self {
+            StrongHash::Sha256 | StrongHash::Blake2 => 32,
+            StrongHash::Md4 => 16,
+        }
+    }
+
+    /// Checksum of a block, zero-padded to 32 bytes.
+    pub fn checksum(self, data: &[u8]) -> [u8; 32] {
+        match self {
+            StrongHash::Sha256 => strong_checksum(data),
+            StrongHash::Md4 => md4(data),
+            StrongHash::Blake2 => blake2(data),
+        }
+    }
+}
+
+/// Checksums of one block of a basis file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Block {
+    pub offset: u64,
+    pub size: usize,
+    pub weak_checksum: u32,
+    pub strong_checksum: [u8; 32],
+}
+
+/// Block checksums of a basis file, enough to compute a delta against it without the file itself.
+///
+/// With serde it is written as a versioned record, so cached or transmitted signatures from an
+/// incompatible release are rejected instead of misread.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(into = "VersionedSignature", try_from = "VersionedSignature")]
+pub struct Signature {
+    pub block_size: usize,
+    pub weak_hash: WeakHash,
+    pub strong_hash: StrongHash,
+    /// Number of leading strong checksum bytes recorded per block.
+    pub strong_len: usize,
+    pub blocks: Vec<Block>,
+}
+
+/// Serde representation of a `Signature`, tagged with `SIGNATURE_SERDE_VERSION`.
+#[derive(Serialize, Deserialize)]
+struct VersionedSignature {
+    version: u32,
+    block_size: usize,
+    weak_hash: WeakHash,
+    strong_hash: StrongHash,
+    strong_len: usize,
+    blocks: Vec<Block>,
+}
+
+impl From<Signature> for VersionedSignature {
+    fn from(signature: Signature) -> Self {
+        Self {
+            version: SIGNATURE_SERDE_VERSION,
+            block_size: signature.block_size,
+            weak_hash: signature.weak_hash,
+            strong_hash: signature.strong_hash,
+            strong_len: signature.strong_len,
+            blocks: signature.blocks,
+        }
+    }
+}
+
+impl TryFrom<VersionedSignature> for Signature {
+    type Error = String;
+
+    fn try_from(signature: VersionedSignature) -> Result<Self, String> {
+        if signature.version != SIGNATURE_SERDE_VERSION {
+            return Err(format!(
+                "unsupported signature version {} (expected {})",
+                signature.version, SIGNATURE_SERDE_VERSION
+            ));
+        }
+        if signature.strong_len > signature.strong_hash.digest_len() {
+            return Err(format!(
+                "strong checksum length {} exceeds the digest length",
+                signature.strong_len
+            ));
+        }
+        Ok(Self {
+            block_size: signature.block_size,
+            weak_hash: signature.weak_hash,
+            strong_hash: signature.strong_hash,
+            strong_len: signature.strong_len,
+            blocks: signature.blocks,
+        })
+    }
+}
+
+impl Signature {
+    /// A signature using rsynx's native checksums.
+    pub fn new(block_size: usize, blocks: Vec<Block>) -> Self {
+        Self {
+            block_size,
+            weak_hash: WeakHash::Adler,
+            strong_hash: StrongHash::Sha256,
+            strong_len: StrongHash::Sha256.digest_len(),
+            blocks,
+        }
+    }
+
+    /// Signature of an in-memory basis, split into blocks of `block_size` bytes.
+    pub fn from_bytes(
+        basis: &[u8],
+        block_size: usize,
+        weak_hash: WeakHash,
+        strong_hash: StrongHash,
+    ) -> Self {
+        let blocks = if block_size == 0 {
+            Vec::new()
+        } else {
+            basis
+                .chunks(block_size)
+                .enumerate()
+                .map(|(i, data)| Block {
+                    offset: (i * block_size) as u64,
+                    size: data.len(),
+                    weak_checksum: weak_hash.checksum(data),
+                    strong_checksum: strong_hash.checksum(data),
+                })
+                .collect()
+        };
+        Self {
+            block_size,
+            weak_hash,
+            strong_hash,
+            strong_len: strong_hash.digest_len(),
+            blocks,
+        }
+    }
+}
+
+impl WeakHash {
+    /// Checksum of a whole window.
+    pub fn checksum(self, data: &[u8]) -> u32 {
+        match self {
+            WeakHash::Adler => weak_checksum(data),
+            WeakHash::RollSum => rollsum(data),
+            WeakHash::RabinKarp => rabinkarp(data),
+        }
+    }
+
+    /// Slide a window of `len` bytes by one byte; `roll_factor` is only used by RabinKarp
+    /// and must be `rabinkarp_factor(len)`.
+    pub fn roll(self, old_byte: u8, new_byte: u8, weak: u32, len: usize, roll_factor: u32) -> u32 {
+        match self {
+            WeakHash::Adler => update_weak_checksum(old_byte, new_byte, weak, len),
+            WeakHash::RollSum => rollsum_rotate(old_byte, new_byte, weak, len),
+            WeakHash::RabinKarp => rabinkarp_rotate(old_byte, new_byte, weak, roll_factor),
+        }
+    }
+}
+
+/// A single reconstruction instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeltaOp {
+    /// Bytes written verbatim to the output.
+    Literal(Vec<u8>),
+    /// Bytes copied from the given range of the basis file.
+    Copy { offset: u64, len: usize },
+}
+
+/// Instructions that turn a basis file into a new file.
+#[derive(Debug, Clone, Default)]
+pub struct Delta {
+    pub ops: Vec<DeltaOp>,
+    /// SHA-256 of the file the delta reconstructs, used to verify the result when applied.
+    pub checksum: Option<[u8; 32]>,
+}
+
+impl Delta {
+    /// Number of bytes carried verbatim in the delta.
+    pub fn literal_bytes(&self) -> usize {
+        self.ops
+            .iter()
+            .map(|op| match op {
+                DeltaOp::Literal(data) => data.len(),
+                DeltaOp::Copy { .. } => 0,
+            })
+            .sum()
+    }
+
+    /// Number of bytes reused from the basis file.
+    pub fn copied_bytes(&self) -> usize {
+        self.ops
+            .iter()
+            .map(|op| match op {
+                DeltaOp::Literal(_) => 0,
+                DeltaOp::Copy { len, .. } => *len,
+            })
+            .sum()
+    }
+
+    /// Size of the file produced by applying this delta.
+    pub fn target_len(&self) -> u64 {
+        (self.literal_bytes() + self.copied_bytes()) as u64
+    }
+
+    /// Delta that turns the basis described by `signature` into `data`.
+    pub fn from_bytes(signature: &Signature, data: &[u8]) -> Self {
+        let index = BlockIndex::new(signature);
+        let mut generator = DeltaGenerator::new(&index);
+        let mut ops = Vec::new();
+        let mut emit = |op| {
+            ops.push(op);
+            Ok::<(), core::convert::Infallible>(())
+        };
+        let _ = generator.feed(data, &mut emit);
+        let _ = generator.finish(&mut emit);
+        Self {
+            ops,
+            checksum: Some(strong_checksum(data)),
+        }
+    }
+}
+
+/// Signature blocks indexed by weak checksum.
+pub struct BlockIndex<'a> {
+    signature: &'a Signature,
+    lookup: HashMap<u32, Vec<usize>>,
+}
+
+impl<'a> BlockIndex<'a> {
+    pub fn new(signature: &'a Signature) -> Self {
+        let mut lookup: HashMap<u32, Vec<usize>> = HashMap::default();
+        for (index, block) in signature.blocks.iter().enumerate() {
+            lookup.entry(block.weak_checksum).or_default().push(index);
+        }
+        Self { signature, lookup }
+    }
+
+    pub fn signature(&self) -> &'a Signature {
+        self.signature
+    }
+
+    fn contains(&self, weak: u32) -> bool {
+        self.lookup.contains_key(&weak)
+    }
+
+    fn candidates(&self, weak: u32) -> impl Iterator<Item = &'a Block> + '_ {
+        let blocks = &self.signature.blocks;
+        self.lookup
+            .get(&weak)
+            .into_iter()
+            .flatten()
+            .map(move |&index| &blocks[index])
+    }
+}
+
+/// A window of the new file whose weak checksum appears in the signature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WeakHit {
+    /// Offset of the window in the new file.
+    pub offset: u64,
+    pub weak: u32,
+}
+
+/// First matching stage: rolls the weak checksum across every window of the new file and
+/// reports the windows that hit the signature. It never needs to know which hits end up
+/// matching, so it can run ahead of the strong checksum stage on another thread.
+pub struct WeakScanner<'a> {
+    index: &'a BlockIndex<'a>,
+    /// Bytes from the last evaluated window onwards, carried over into the next chunk.
+    carry: Vec<u8>,
+    /// Offset of `carry[0]` in the new file.
+    carry_offset: u64,
+    /// Weak checksum of the window starting at `carry[0]`, once it has been evaluated.
+    weak: Option<u32>,
+    roll_factor: u32,
+}
+
+impl<'a> WeakScanner<'a> {
+    pub fn new(index: &'a BlockIndex<'a>) -> Self {
+        Self {
+            index,
+            carry: Vec::new(),
+            carry_offset: 0,
+            weak: None,
+            roll_factor: rabinkarp_factor(index.signature.block_size),
+        }
+    }
+
+    /// Scan the next chunk of the new file, appending its hits to `hits` in offset order.
+    pub fn scan(&mut self, data: &[u8], hits: &mut Vec<WeakHit>) {
+        let signature = self.index.signature;
+        let block_size = signature.block_size;
+        if block_size == 0 || signature.blocks.is_empty() {
+            return;
+        }
+        self.carry.extend_from_slice(data);
+        if self.carry.len() < block_size {
+            return;
+        }
+
+        let mut weak = match self.weak {
+            Some(weak) => weak,
+            None => {
+                let weak = signature.weak_hash.checksum(&self.carry[..block_size]);
+                if self.index.contains(weak) {
+                    hits.push(WeakHit {
+                        offset: self.carry_offset,
+                        weak,
+                    });
+                }
+                weak
+            }
+        };
+        let mut start = 0;
+        while start + block_size < self.carry.len() {
+            weak = signature.weak_hash.roll(
+                self.carry[start],
+                self.carry[start + block_size],
+                weak,
+                block_size,
+                self.roll_factor,
+            );
+            start += 1;
+            if self.index.contains(weak) {
+                hits.push(WeakHit {
+                    offset: self.carry_offset + start as u64,
+                    weak,
+                });
+            }
+        }
+        self.weak = Some(weak);
+        self.carry.drain(..start);
+        self.carry_offset += start as u64;
+    }
+}
+
+/// Second matching stage: confirms weak hits with the strong checksum and turns the new file
+/// into literal and copy instructions.
+pub struct BlockMatcher<'a> {
+    index: &'a BlockIndex<'a>,
+    /// Bytes of the new file from `buf_offset` onwards.
+    buf: Vec<u8>,
+    buf_offset: u64,
+    /// Start of the pending literal run.
+    literal_start: u64,
+    /// End of the last matched block; hits before it overlap a match and are ignored.
+    next_allowed: u64,
+    /// Basis offset just past the last copied block, preferred for the next match so that
+    /// reconstruction keeps reading the basis sequentially.
+    next_basis_offset: Option<u64>,
+}
+
+impl<'a> BlockMatcher<'a> {
+    pub fn new(index: &'a BlockIndex<'a>) -> Self {
+        Self {
+            index,
+            buf: Vec::new(),
+            buf_offset: 0,
+            literal_start: 0,
+            next_allowed: 0,
+            next_basis_offset: None,
+        }
+    }
+
+    /// Process the next chunk of the new file along with the hits the scanner found in it.
+    pub fn process<F, E>(&mut self, data: &[u8], hits: &[WeakHit], emit: &mut F) -> Result<(), E>
+    where
+        F: FnMut(DeltaOp) -> Result<(), E>,
+    {
+        self.buf.extend_from_slice(data);
+        let block_size = self.index.signature.block_size.max(1);
+
+        for hit in hits {
+            if hit.offset < self.next_allowed {
+                continue;
+            }
+            if let Some(block) = self.find_match(hit) {
+                let (offset, len) = (block.offset, block.size);
+                self.flush_literal(hit.offset, emit)?;
+                emit(DeltaOp::Copy { offset, len })?;
+                self.next_basis_offset = Some(offset + len as u64);
+                self.next_allowed = hit.offset + block_size as u64;
+                self.literal_start = self.next_allowed;
+            }
+        }
+
+        // Every window starting before `settled` has been scanned, so bytes before it that
+        // aren't part of a match are definitely literal
+        let end = self.buf_offset + self.buf.len() as u64;
+        let settled = (end + 1).saturating_sub(block_size as u64);
+        if settled.saturating_sub(self.literal_start) >= MAX_LITERAL_SIZE as u64 {
+            self.flush_literal(settled, emit)?;
+        }
+
+        let keep_from = self.literal_start.min(settled).max(self.buf_offset);
+        self.buf.drain(..(keep_from - self.buf_offset) as usize);
+        self.buf_offset = keep_from;
+        Ok(())
+    }
+
+    /// Emit whatever is left once the whole new file has been processed.
+    pub fn finish<F, E>(mut self, emit: &mut F) -> Result<(), E>
+    where
+        F: FnMut(DeltaOp) -> Result<(), E>,
+    {
+        let end = self.buf_offset + self.buf.len() as u64;
+        self.flush_literal(end, emit)
+    }
+
+    /// Confirm a weak hit with the strong checksum. Only blocks exactly as long as the window
+    /// qualify, so a short final block can never stand in for a full one; among several
+    /// qualifying blocks the one continuing the previous copy wins.
+    fn find_match(&self, hit: &WeakHit) -> Option<&'a Block> {
+        let signature = self.index.signature;
+        let start = (hit.offset - self.buf_offset) as usize;
+        let window = self.buf.get(start..start + signature.block_size)?;
+        let strong = signature.strong_hash.checksum(window);
+        let strong_len = signature.strong_len;
+        let matches = self.index.candidates(hit.weak).filter(|block| {
+            block.size == window.len()
+                && block.strong_checksum[..strong_len] == strong[..strong_len]
+        });
+        let mut first = None;
+        for block in matches {
+            if Some(block.offset) == self.next_basis_offset {
+                return Some(block);
+            }
+            first.get_or_insert(block);
+        }
+        first
+    }
+
+    fn flush_literal<F, E>(&mut self, end: u64, emit: &mut F) -> Result<(), E>
+    where
+        F: FnMut(DeltaOp) -> Result<(), E>,
+    {
+        while self.literal_start < end {
+            let chunk_end = end.min(self.literal_start + MAX_LITERAL_SIZE as u64);
+            let from = (self.literal_start - self.buf_offset) as usize;
+            let to = (chunk_end - self.buf_offset) as usize;
+            emit(DeltaOp::Literal(self.buf[from..to].to_vec()))?;
+            self.literal_start = chunk_end;
+        }
+        Ok(())
+    }
+}
+
+/// Push-based delta generator: feed it the new file in arbitrary chunks and it emits the
+/// instructions needed to rebuild it from the basis described by the index.
+pub struct DeltaGenerator<'a> {
+    scanner: WeakScanner<'a>,
+    matcher: BlockMatcher<'a>,
+    hits: Vec<WeakHit>,
+}
+
+impl<'a> DeltaGenerator<'a> {
+    pub fn new(index: &'a BlockIndex<'a>) -> Self {
+        Self {
+            scanner: WeakScanner::new(index),
+            matcher: BlockMatcher::new(index),
+            hits: Vec::new(),
+        }
+    }
+
+    /// Feed the next chunk of the new file.
+    pub fn feed<F, E>(&mut self, data: &[u8], emit: &mut F) -> Result<(), E>
+    where
+        F: FnMut(DeltaOp) -> Result<(), E>,
+    {
+        self.hits.clear();
+        self.scanner.scan(data, &mut self.hits);
+        self.matcher.process(data, &self.hits, emit)
+    }
+
+    /// Emit whatever is left once the whole new file has been fed.
+    pub fn finish<F, E>(self, emit: &mut F) -> Result<(), E>
+    where
+        F: FnMut(DeltaOp) -> Result<(), E>,
+    {
+        self.matcher.finish(emit)
+    }
+}
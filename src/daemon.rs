@@ -0,0 +1,552 @@
+use crate::error::{IoContext, Result, SyncError};
+use crate::metrics::{self, Metrics};
+use crate::network_sync::NetworkSyncer;
+use crate::probe::{ModuleInfo, ServerInfo};
+use crate::protocol::{self, Request};
+use crate::sync::TransferResult;
+use crate::transport::{Acceptor, Transport};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    io::{BufReader, Write},
+    net::{IpAddr, SocketAddr, TcpListener},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::Instant,
+};
+use tracing::{error, info, warn};
+
+/// Settings of a long-running server, usually read from a TOML file:
+///
+/// ```toml
+/// address = "0.0.0.0"
+/// port = 7878
+/// metrics-address = "127.0.0.1:9178"
+/// preallocate = true
+///
+/// [modules.backups]
+/// path = "/srv/backups"
+/// auth-users = ["alice"]
+/// secrets-file = "/etc/rsynx.secrets"
+/// hosts-allow = ["10.0.0.0/8", "::1"]
+/// max-connections = 4
+///
+/// [modules.reference]
+/// path = "/srv/reference"
+/// read-only = true
+/// ```
+///
+/// Without modules, clients may write to any path the server can; with them, clients must
+/// name a module (`host::module/path`) and stay inside its directory.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct DaemonConfig {
+    pub address: String,
+    pub port: u16,
+    pub block_size: usize,
+    /// Tracing filter, such as `info` or `rsynx=debug`; `RUST_LOG` is used when unset.
+    pub log_level: Option<String>,
+    /// `host:port` to serve Prometheus metrics on at `/metrics`; off when unset.
+    pub metrics_address: Option<String>,
+    /// Reserve the full size of each received file before accepting its data, refusing it
+    /// up front when the disk is too full.
+    pub preallocate: bool,
+    /// Refuse every write, serving only listings, so reference data can be exposed to many
+    /// clients safely.
+    pub read_only: bool,
+    pub modules: BTreeMap<String, Module>,
+}
+
+/// A directory exported by the daemon under a name.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Module {
+    pub path: PathBuf,
+    /// Description shown in logs.
+    pub comment: Option<String>,
+    /// Users allowed to use the module; when empty, no authentication is needed.
+    pub auth_users: Vec<String>,
+    /// File of `user:secret` lines for `auth_users`. It must not be readable by other users.
+    pub secrets_file: Option<PathBuf>,
+    /// Clients allowed to use the module; when empty, any client not denied may. Clients whose
+    /// address isn't known, such as over Unix sockets, are refused once this is set.
+    pub hosts_allow: Vec<HostPattern>,
+    /// Clients refused even when `hosts_allow` matches them.
+    pub hosts_deny: Vec<HostPattern>,
+    /// Refuse writes to the module, serving only listings.
+    pub read_only: bool,
+    /// Clients served by the module at once; more are refused until one finishes.
+    pub max_connections: Option<usize>,
+}
+
+impl Module {
+    /// Whether a client at `peer` may use the module under `hosts_allow` and `hosts_deny`.
+    pub fn admits(&self, peer: Option<IpAddr>) -> bool {
+        let matches = |patterns: &[HostPattern]| {
+            peer.is_some_and(|ip| patterns.iter().any(|pattern| pattern.matches(ip)))
+        };
+        !matches(&self.hosts_deny) && (self.hosts_allow.is_empty() || matches(&self.hosts_allow))
+    }
+}
+
+/// An address or network in CIDR notation, such as `192.168.1.7`, `10.0.0.0/8` or `fd00::/8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct HostPattern {
+    network: IpAddr,
+    prefix: u32,
+}
+
+impl HostPattern {
+    pub fn matches(&self, ip: IpAddr) -> bool {
+        // IPv4 clients of dual-stack listeners show up as IPv4-mapped IPv6 addresses
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for HostPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("invalid host pattern {:?}; expected an address or CIDR", s);
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+        let address: IpAddr = address.parse().map_err(|_| invalid())?;
+        let bits: u32 = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|&p| p <= bits)
+                .ok_or_else(invalid)?,
+            None => bits,
+        };
+        // Written as IPv4-mapped, match it like the IPv4 network it is
+        let network = address.to_canonical();
+        let prefix = match network.is_ipv4() && address.is_ipv6() {
+            true => prefix.saturating_sub(96),
+            false => prefix,
+        };
+        Ok(Self { network, prefix })
+    }
+}
+
+impl TryFrom<String> for HostPattern {
+    type Error = String;
+
+    fn try_from(s: String) -> std::result::Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for HostPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            address: "0.0.0.0".to_string(),
+            port: 7878,
+            block_size: 1024,
+            log_level: None,
+            metrics_address: None,
+            preallocate: false,
+            read_only: false,
+            modules: BTreeMap::new(),
+        }
+    }
+}
+
+impl DaemonConfig {
+    pub fn parse(contents: &str) -> Result<Self> {
+        toml::from_str(contents).map_err(|e| SyncError::Config(e.to_string()))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read daemon config: {:?}", path))?;
+        Self::parse(&contents)
+    }
+
+    /// Export `path` as module `name`, without authentication.
+    pub fn with_module(mut self, name: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        self.modules.insert(
+            name.into(),
+            Module {
+                path: path.into(),
+                ..Default::default()
+            },
+        );
+        self
+    }
+}
+
+/// Response a client proves knowledge of `secret` with: the hex SHA-256 of the challenge
+/// followed by the secret.
+pub fn auth_response(challenge: &str, secret: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(challenge.as_bytes());
+    hasher.update(secret.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// A server accepting network syncs into configured modules, with optional challenge-response
+/// authentication per module.
+pub struct Daemon {
+    config: DaemonConfig,
+    /// Secrets of each module's users, read at startup.
+    secrets: HashMap<String, HashMap<String, String>>,
+    /// Clients each module is serving right now.
+    active: HashMap<String, AtomicUsize>,
+    metrics: Arc<Metrics>,
+}
+
+impl Daemon {
+    /// Check `config` and read the modules' secrets files.
+    pub fn new(config: DaemonConfig) -> Result<Self> {
+        NetworkSyncer::validate_server_block_size(config.block_size)?;
+        let mut secrets = HashMap::new();
+        for (name, module) in &config.modules {
+            if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '/') {
+                return Err(SyncError::Config(format!("invalid module name {:?}", name)));
+            }
+            if !module.path.is_dir() {
+                return Err(SyncError::Config(format!(
+                    "path {:?} of module {:?} is not a directory",
+                    module.path, name
+                )));
+            }
+            if module.max_connections == Some(0) {
+                return Err(SyncError::Config(format!(
+                    "max-connections of module {:?} must be at least 1",
+                    name
+                )));
+            }
+            if module.auth_users.is_empty() {
+                continue;
+            }
+            let Some(secrets_file) = &module.secrets_file else {
+                return Err(SyncError::Config(format!(
+                    "module {:?} has auth-users but no secrets-file",
+                    name
+                )));
+            };
+            secrets.insert(name.clone(), read_secrets(secrets_file)?);
+        }
+        let active = config
+            .modules
+            .keys()
+            .map(|name| (name.clone(), AtomicUsize::new(0)))
+            .collect();
+        Ok(Self {
+            config,
+            secrets,
+            active,
+            metrics: Arc::new(Metrics::new()),
+        })
+    }
+
+    pub fn config(&self) -> &DaemonConfig {
+        &self.config
+    }
+
+    /// Counters of the connections served so far.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Listen on the configured address and port and serve clients until accepting fails,
+    /// serving metrics from a background thread if a metrics address is configured.
+    pub fn serve(&self) -> Result<()> {
+        if let Some(address) = &self.config.metrics_address {
+            self.metrics.serve(metrics::bind(address)?);
+        }
+        let address = (self.config.address.as_str(), self.config.port);
+        let listener = TcpListener::bind(address).with_context(|| {
+            format!(
+                "Failed to bind to address: {}:{}",
+                self.config.address, self.config.port
+            )
+        })?;
+        info!(
+            "Daemon listening on {}:{} with {} module(s)",
+            self.config.address,
+            self.config.port,
+            self.config.modules.len()
+        );
+        self.serve_on(&listener)
+    }
+
+    /// Serve clients from any acceptor until it fails, each on its own thread; a failing
+    /// client doesn't stop the daemon. Clients still being served when accepting fails are
+    /// finished first.
+    pub fn serve_on<A>(&self, acceptor: &A) -> Result<()>
+    where
+        A: Acceptor,
+        A::Transport: Send,
+    {
+        thread::scope(|scope| {
+            loop {
+                let (transport, addr) = acceptor.accept()?;
+                info!("Accepted connection from {:?}", addr);
+                scope.spawn(move || {
+                    match self.handle_connection_from(transport, peer_ip(&addr)) {
+                        Ok(result) => info!(
+                            "Transfer completed successfully for client {:?}: {} bytes transferred, {} bytes reused",
+                            addr, result.new_bytes, result.reused_bytes
+                        ),
+                        Err(e) => error!("Error handling connection from {:?}: {}", addr, e),
+                    }
+                });
+            }
+        })
+    }
+
+    /// Serve a single client from any acceptor.
+    pub fn serve_once_on<A: Acceptor>(&self, acceptor: &A) -> Result<TransferResult> {
+        let (transport, addr) = acceptor.accept()?;
+        info!("Accepted connection from {:?}", addr);
+        self.handle_connection_from(transport, peer_ip(&addr))
+    }
+
+    /// Run the server side of the protocol for one client whose address isn't known; see
+    /// `handle_connection_from`.
+    pub fn handle_connection<T: Transport>(&self, transport: T) -> Result<TransferResult> {
+        self.handle_connection_from(transport, None)
+    }
+
+    /// Run the server side of the protocol for one client at `peer`, starting with the module
+    /// handshake when the client names a module. Probes are answered with `server_info`.
+    pub fn handle_connection_from<T: Transport>(
+        &self,
+        transport: T,
+        peer: Option<IpAddr>,
+    ) -> Result<TransferResult> {
+        self.metrics.start_connection();
+        let started = Instant::now();
+        let outcome = self.serve_client(transport, peer);
+        self.metrics.finish_connection(&outcome, started.elapsed());
+        outcome
+    }
+
+    fn serve_client<T: Transport>(
+        &self,
+        transport: T,
+        peer: Option<IpAddr>,
+    ) -> Result<TransferResult> {
+        let mut reader = BufReader::new(transport);
+        let block_size = self.config.block_size;
+        let request = match protocol::read_request(&mut reader)? {
+            Request::Probe => {
+                self.server_info().write_to(reader.get_mut())?;
+                return Ok(TransferResult::default());
+            }
+            Request::Module(name) => {
+                let (module, _slot) = self.open_module(&mut reader, &name, peer)?;
+                let request = protocol::read_request(&mut reader)?;
+                return NetworkSyncer::serve_request(
+                    &mut reader,
+                    request,
+                    block_size,
+                    Some(&module.path),
+                    self.config.preallocate,
+                    self.config.read_only || module.read_only,
+                );
+            }
+            request => request,
+        };
+        if !self.config.modules.is_empty() {
+            return Err(refuse(
+                reader.get_mut(),
+                "this server only accepts module destinations (host::module/path)",
+            ));
+        }
+        // Without a module there is no directory to confine a listing to, and it would hash
+        // anything the daemon can read
+        if matches!(request, Request::List { .. }) {
+            return Err(refuse(
+                reader.get_mut(),
+                "listings are only served from modules (host::module/path)",
+            ));
+        }
+        NetworkSyncer::serve_request(
+            &mut reader,
+            request,
+            block_size,
+            None,
+            self.config.preallocate,
+            self.config.read_only,
+        )
+    }
+
+    /// What a probe reports: this build's capabilities and the configured modules.
+    pub fn server_info(&self) -> ServerInfo {
+        let modules = self
+            .config
+            .modules
+            .iter()
+            .map(|(name, module)| ModuleInfo {
+                name: name.clone(),
+                auth: !module.auth_users.is_empty(),
+                comment: module.comment.clone(),
+            })
+            .collect();
+        let mut info = ServerInfo::local().with_modules(modules);
+        if self.config.read_only {
+            info.features.push("read-only".to_string());
+        }
+        info
+    }
+
+    /// Look up module `name`, check the client at `peer` against its host lists and
+    /// connection limit, and authenticate it if the module requires so, answering `OK` on
+    /// success. The client counts towards the limit until the returned slot is dropped.
+    fn open_module<T: Transport>(
+        &self,
+        reader: &mut BufReader<T>,
+        name: &str,
+        peer: Option<IpAddr>,
+    ) -> Result<(&Module, ModuleSlot<'_>)> {
+        let (Some(module), Some(active)) = (self.config.modules.get(name), self.active.get(name))
+        else {
+            return Err(refuse(
+                reader.get_mut(),
+                &format!("unknown module {:?}", name),
+            ));
+        };
+        if !module.admits(peer) {
+            warn!("Refused client {:?} for module {:?}", peer, name);
+            return Err(refuse(
+                reader.get_mut(),
+                &format!("access to module {:?} denied", name),
+            ));
+        }
+        let slot = ModuleSlot::acquire(active, module.max_connections).ok_or_else(|| {
+            refuse(
+                reader.get_mut(),
+                &format!("too many connections to module {:?}", name),
+            )
+        })?;
+        if let Some(users) = self.secrets.get(name) {
+            let challenge = hex::encode(rand::random::<[u8; 16]>());
+            writeln!(reader.get_mut(), "CHALLENGE {}", challenge)?;
+            reader.get_mut().flush()?;
+            let line = protocol::read_line(reader)?.unwrap_or_default();
+            let mut parts = line.split_whitespace();
+            let (user, response) = match (parts.next(), parts.next(), parts.next()) {
+                (Some("AUTH"), Some(user), Some(response)) => (user, response),
+                _ => return Err(refuse(reader.get_mut(), "expected AUTH")),
+            };
+            let authorized = module.auth_users.iter().any(|allowed| allowed == user)
+                && users.get(user).is_some_and(|secret| {
+                    constant_time_eq(&auth_response(&challenge, secret), response)
+                });
+            if !authorized {
+                warn!(
+                    "Authentication failed for user {:?} on module {:?}",
+                    user, name
+                );
+                return Err(refuse(
+                    reader.get_mut(),
+                    &format!("authentication failed for module {:?}", name),
+                ));
+            }
+            info!("User {:?} authenticated for module {:?}", user, name);
+        }
+        writeln!(reader.get_mut(), "OK")?;
+        reader.get_mut().flush()?;
+        Ok((module, slot))
+    }
+}
+
+/// A client counted among a module's active connections until dropped.
+struct ModuleSlot<'a>(&'a AtomicUsize);
+
+impl<'a> ModuleSlot<'a> {
+    /// Count another client in `active`, unless it already has `max` of them.
+    fn acquire(active: &'a AtomicUsize, max: Option<usize>) -> Option<Self> {
+        active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                max.is_none_or(|max| count < max).then_some(count + 1)
+            })
+            .ok()?;
+        Some(Self(active))
+    }
+}
+
+impl Drop for ModuleSlot<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// The IP address in an acceptor's description of a peer, for TCP clients.
+fn peer_ip(addr: &str) -> Option<IpAddr> {
+    addr.parse::<SocketAddr>()
+        .map(|addr| addr.ip())
+        .or_else(|_| addr.parse())
+        .ok()
+}
+
+/// Tell the client why its request is refused, returning the matching error.
+fn refuse<W: Write>(writer: &mut W, reason: &str) -> SyncError {
+    let _ = writeln!(writer, "ERROR {}", reason).and_then(|_| writer.flush());
+    SyncError::Refused(reason.to_string())
+}
+
+/// Read `user:secret` lines, refusing files other users can read.
+fn read_secrets(path: &Path) -> Result<HashMap<String, String>> {
+    let meta =
+        fs::metadata(path).with_context(|| format!("Failed to read secrets file: {:?}", path))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if meta.permissions().mode() & 0o077 != 0 {
+            return Err(SyncError::Config(format!(
+                "secrets file {:?} must not be accessible by group or others",
+                path
+            )));
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = meta;
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read secrets file: {:?}", path))?;
+    Ok(contents
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(user, secret)| (user.trim().to_string(), secret.to_string()))
+        .collect())
+}
+
+/// Compare two strings without exiting early on the first difference.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
@@ -0,0 +1,142 @@
+#[cfg(unix)]
+use crate::checkpoint::CHECKPOINT_FILE_NAME;
+#[cfg(unix)]
+use crate::conflict::STATE_FILE_NAME;
+use crate::error::Result;
+#[cfg(unix)]
+use crate::error::{IoContext, SyncError};
+#[cfg(unix)]
+use crate::lock::LOCK_FILE_NAME;
+use crate::sync::Syncer;
+use std::path::Path;
+#[cfg(unix)]
+use std::{collections::HashMap, fs, os::unix::fs::MetadataExt, path::PathBuf};
+#[cfg(unix)]
+use tracing::info;
+use tracing::warn;
+#[cfg(unix)]
+use walkdir::WalkDir;
+
+/// What files must share to be linked: SHA-256, device, mode, owner and group.
+#[cfg(unix)]
+type LinkKey = ([u8; 32], u64, u32, u32, u32);
+
+/// What `link_duplicates` reclaimed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DedupReport {
+    /// Files replaced by a hard link to an identical one.
+    pub linked_files: usize,
+    /// Space those files no longer take up.
+    pub reclaimed_bytes: u64,
+}
+
+/// Replace files beneath `root` that are byte-for-byte identical to another with hard links to
+/// it, reading them through `syncer` so its disk limit applies.
+///
+/// Files are grouped by size and then SHA-256, and only linked when they share a filesystem,
+/// permissions and owner, since linked files share those too. Empty files and rsynx's own
+/// state files are left alone. Each replacement is atomic: a new link is renamed over the
+/// duplicate.
+#[cfg(unix)]
+pub fn link_duplicates(root: &Path, syncer: &Syncer) -> Result<DedupReport> {
+    let mut by_size: HashMap<u64, Vec<(PathBuf, fs::Metadata)>> = HashMap::new();
+    for entry in WalkDir::new(root).follow_links(false) {
+        let entry = entry.map_err(|e| SyncError::Io {
+            context: format!("Failed to walk {:?}", root),
+            source: e.into(),
+        })?;
+        let name = entry.file_name();
+        if !entry.file_type().is_file()
+            || name == LOCK_FILE_NAME
+            || name == STATE_FILE_NAME
+            || name == CHECKPOINT_FILE_NAME
+        {
+            continue;
+        }
+        let meta = entry.metadata().map_err(|e| SyncError::Io {
+            context: format!("Failed to read metadata of {:?}", entry.path()),
+            source: e.into(),
+        })?;
+        if meta.len() > 0 {
+            by_size
+                .entry(meta.len())
+                .or_default()
+                .push((entry.into_path(), meta));
+        }
+    }
+
+    let mut report = DedupReport::default();
+    let mut sizes: Vec<_> = by_size
+        .into_iter()
+        .filter(|(_, files)| files.len() > 1)
+        .collect();
+    sizes.sort_by_key(|(size, _)| *size);
+    for (_, mut files) in sizes {
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+        // Files already linked together are hashed once, under the first path seen
+        let mut by_hash: HashMap<LinkKey, (PathBuf, fs::Metadata)> = HashMap::new();
+        let mut hashed: HashMap<(u64, u64), [u8; 32]> = HashMap::new();
+        for (path, meta) in files {
+            let inode = (meta.dev(), meta.ino());
+            let hash = match hashed.get(&inode) {
+                Some(hash) => *hash,
+                None => {
+                    let hash = syncer.calculate_file_checksum(&path)?;
+                    hashed.insert(inode, hash);
+                    hash
+                }
+            };
+            let key = (hash, meta.dev(), meta.mode(), meta.uid(), meta.gid());
+            let Some((original, original_meta)) = by_hash.get(&key) else {
+                by_hash.insert(key, (path, meta));
+                continue;
+            };
+            if original_meta.ino() == meta.ino() {
+                continue;
+            }
+            match replace_with_link(original, &path) {
+                Ok(()) => {
+                    info!("Hard-linked duplicate {:?} to {:?}", path, original);
+                    report.linked_files += 1;
+                    // The data is only freed with the duplicate's last link
+                    if meta.nlink() == 1 {
+                        report.reclaimed_bytes += meta.len();
+                    }
+                }
+                // Too many links, say: keep the copy
+                Err(e) => warn!("Failed to hard-link {:?} to {:?}: {}", path, original, e),
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Without inode numbers, files already linked together can't be told apart from copies, so
+/// nothing is deduplicated.
+#[cfg(not(unix))]
+pub fn link_duplicates(root: &Path, _syncer: &Syncer) -> Result<DedupReport> {
+    warn!(
+        "Skipping deduplication of {:?}: hard links are only detected on unix",
+        root
+    );
+    Ok(DedupReport::default())
+}
+
+/// Atomically replace `duplicate` with a hard link to `original`.
+#[cfg(unix)]
+fn replace_with_link(original: &Path, duplicate: &Path) -> Result<()> {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(duplicate.file_name().unwrap_or_default());
+    name.push(".rsynx-link");
+    let temp = duplicate.with_file_name(name);
+    let _ = fs::remove_file(&temp);
+    fs::hard_link(original, &temp)
+        .with_context(|| format!("Failed to link {:?} to {:?}", temp, original))?;
+    fs::rename(&temp, duplicate).map_err(|e| {
+        let _ = fs::remove_file(&temp);
+        SyncError::Io {
+            context: format!("Failed to replace {:?}", duplicate),
+            source: e,
+        }
+    })
+}
@@ -0,0 +1,781 @@
+pub use crate::core::{
+    BlockIndex, BlockMatcher, Delta, DeltaGenerator, DeltaOp, MAX_LITERAL_SIZE,
+    SIGNATURE_SERDE_VERSION, Signature, StrongHash, WeakHash, WeakHit, WeakScanner,
+};
+use crate::error::{IoContext, Result, SyncError};
+use crate::rdiff;
+use crate::sync::{Block, Syncer, TransferResult};
+use crate::throttle::{DiskThrottled, SharedRateLimiter};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+use tracing::instrument;
+
+/// Size of the chunks read from the new file while generating a delta.
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Serialization format for signatures and deltas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeltaFormat {
+    /// rsynx's own format, carrying a checksum of the reconstructed file.
+    #[default]
+    Native,
+    /// librsync's format, interoperable with rdiff and rdiff-backup.
+    Rdiff,
+}
+
+impl DeltaFormat {
+    /// Checksums used for signatures generated in this format.
+    pub fn default_hashes(self) -> (WeakHash, StrongHash) {
+        match self {
+            DeltaFormat::Native => (WeakHash::Adler, StrongHash::Sha256),
+            DeltaFormat::Rdiff => (WeakHash::RabinKarp, StrongHash::Blake2),
+        }
+    }
+}
+
+impl Syncer {
+    /// Compute the block signature of a basis file.
+    #[instrument(skip(self))]
+    pub fn generate_signature(&self, path: &Path) -> Result<Signature> {
+        let blocks = self.calculate_checksums(path)?;
+        Ok(Signature::new(self.options.block_size, blocks))
+    }
+
+    /// Compute the block signature of a basis file using the checksums of the given format,
+    /// e.g. so the signature can be handed to rdiff.
+    pub fn generate_signature_as(&self, path: &Path, format: DeltaFormat) -> Result<Signature> {
+        if format == DeltaFormat::Native {
+            return self.generate_signature(path);
+        }
+        let file = self
+            .open_input(path)
+            .with_context(|| format!("Failed to open basis file: {:?}", path))?;
+        self.signature_from_reader(io::BufReader::new(file), format)
+    }
+
+    /// Compute the block signature of basis data read from `reader`.
+    pub fn signature_from_reader<R: Read>(
+        &self,
+        mut reader: R,
+        format: DeltaFormat,
+    ) -> Result<Signature> {
+        let (weak_hash, strong_hash) = format.default_hashes();
+        let mut blocks = Vec::new();
+        let mut buffer = vec![0u8; self.options.block_size];
+        let mut offset: u64 = 0;
+        loop {
+            let mut size = 0;
+            while size < self.options.block_size {
+                match reader.read(&mut buffer[size..]) {
+                    Ok(0) => break,
+                    Ok(read) => size += read,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            if size == 0 {
+                break;
+            }
+            let data = &buffer[..size];
+            blocks.push(Block {
+                offset,
+                size,
+                weak_checksum: weak_hash.checksum(data),
+                strong_checksum: strong_hash.checksum(data),
+            });
+            offset += size as u64;
+        }
+        Ok(Signature {
+            block_size: self.options.block_size,
+            weak_hash,
+            strong_hash,
+            strong_len: strong_hash.digest_len(),
+            blocks,
+        })
+    }
+
+    /// Native signature of an in-memory basis.
+    pub fn signature_of(&self, basis: &[u8]) -> Result<Signature> {
+        self.signature_from_reader(basis, DeltaFormat::Native)
+    }
+
+    /// Compute the delta that turns the file described by `signature` into `new_file`.
+    pub fn generate_delta(&self, signature: &Signature, new_file: &Path) -> Result<Delta> {
+        let file = File::open(new_file)
+            .with_context(|| format!("Failed to open new file: {:?}", new_file))?;
+        self.delta_from_reader(signature, self.disk_io(file))
+    }
+
+    /// Compute the delta that turns the data described by `signature` into everything read
+    /// from `reader`.
+    pub fn delta_from_reader<R: Read>(&self, signature: &Signature, reader: R) -> Result<Delta> {
+        let mut ops = Vec::new();
+        let checksum = self.stream_delta(signature, reader, |op| {
+            ops.push(op);
+            Ok(())
+        })?;
+        Ok(Delta {
+            ops,
+            checksum: Some(checksum),
+        })
+    }
+
+    /// Compute the delta that turns the data described by `signature` into `data`.
+    pub fn delta_of(&self, signature: &Signature, data: &[u8]) -> Result<Delta> {
+        self.delta_from_reader(signature, data)
+    }
+
+    /// Generate a delta from any reader, handing each instruction to `emit` as soon as it is known.
+    /// Returns the SHA-256 of everything read, i.e. of the file the delta reconstructs.
+    pub fn stream_delta<R, F>(
+        &self,
+        signature: &Signature,
+        mut reader: R,
+        mut emit: F,
+    ) -> Result<[u8; 32]>
+    where
+        R: Read,
+        F: FnMut(DeltaOp) -> Result<()>,
+    {
+        let index = BlockIndex::new(signature);
+        let mut generator = DeltaGenerator::new(&index);
+        let mut hasher = Sha256::new();
+        let mut chunk = vec![0u8; READ_CHUNK_SIZE];
+        loop {
+            let read = match reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            self.check_cancelled()?;
+            hasher.update(&chunk[..read]);
+            generator.feed(&chunk[..read], &mut emit)?;
+        }
+        generator.finish(&mut emit)?;
+        Ok(hasher.finalize().into())
+    }
+
+    /// Rebuild a file from its basis and a delta. The output is written to a temporary file,
+    /// verified against the delta's checksum (when present) and then renamed into place.
+    pub fn apply_delta(
+        &self,
+        basis: &Path,
+        delta: &Delta,
+        output: &Path,
+    ) -> Result<TransferResult> {
+        let mut reconstruction =
+            Reconstruction::create(basis, output, self.options.disk_limit.clone())?;
+        for op in &delta.ops {
+            if let Err(e) = self
+                .check_cancelled()
+                .and_then(|_| reconstruction.apply(op))
+            {
+                reconstruction.abort();
+                return Err(e);
+            }
+        }
+        reconstruction.commit(output, delta.checksum)
+    }
+
+    /// Write the data reconstructed from `basis` and `delta` to `output`, failing with
+    /// `SyncError::ChecksumMismatch` when it doesn't hash to the delta's checksum. The output
+    /// has already been written by then, so callers decide whether to keep it.
+    pub fn apply_delta_to<B, W>(&self, basis: B, delta: &Delta, output: W) -> Result<TransferResult>
+    where
+        B: Read + Seek,
+        W: Write,
+    {
+        let mut applier = DeltaApplier::new(basis, output);
+        for op in &delta.ops {
+            self.check_cancelled()?;
+            applier.apply(op)?;
+        }
+        applier.finish(Path::new(STREAM_OUTPUT), delta.checksum)
+    }
+
+    /// Reconstruct in-memory data from its basis and a delta.
+    pub fn patch_bytes(&self, basis: &[u8], delta: &Delta) -> Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(delta.target_len() as usize);
+        self.apply_delta_to(io::Cursor::new(basis), delta, &mut output)?;
+        Ok(output)
+    }
+
+    /// Rebuild a file from its basis and a serialized delta stream (see [`Delta::write_to`]),
+    /// applying instructions as they are read so the delta never has to fit in memory.
+    pub fn patch<R: Read>(&self, basis: &Path, delta: R, output: &Path) -> Result<TransferResult> {
+        let mut reader = DeltaReader::new(delta)?;
+        let mut reconstruction =
+            Reconstruction::create(basis, output, self.options.disk_limit.clone())?;
+        loop {
+            let op = match reader.next_op() {
+                Ok(Some(op)) => op,
+                Ok(None) => break,
+                Err(e) => {
+                    reconstruction.abort();
+                    return Err(e);
+                }
+            };
+            if let Err(e) = self
+                .check_cancelled()
+                .and_then(|_| reconstruction.apply(&op))
+            {
+                reconstruction.abort();
+                return Err(e);
+            }
+        }
+        reconstruction.commit(output, reader.checksum())
+    }
+
+    /// Rebuild a file from its basis and the instructions `next_op` produces one at a time
+    /// until it returns `None`, for sources that fetch literal data as they go and check it
+    /// themselves.
+    #[cfg(feature = "http")]
+    pub(crate) fn apply_ops<F>(
+        &self,
+        basis: &Path,
+        output: &Path,
+        mut next_op: F,
+    ) -> Result<TransferResult>
+    where
+        F: FnMut() -> Result<Option<DeltaOp>>,
+    {
+        let mut reconstruction =
+            Reconstruction::create(basis, output, self.options.disk_limit.clone())?;
+        loop {
+            let applied = self.check_cancelled().and_then(|_| match next_op()? {
+                Some(op) => reconstruction.apply(&op).map(|_| true),
+                None => Ok(false),
+            });
+            match applied {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    reconstruction.abort();
+                    return Err(e);
+                }
+            }
+        }
+        reconstruction.commit(output, None)
+    }
+}
+
+/// Name used for the output in errors from reconstructions that don't write to a file.
+const STREAM_OUTPUT: &str = "<stream>";
+
+/// Applies delta instructions, copying from any seekable basis into any writer and hashing
+/// what it writes.
+struct DeltaApplier<B, W> {
+    basis: B,
+    writer: W,
+    hasher: Sha256,
+    literal_bytes: usize,
+    copied_bytes: usize,
+}
+
+impl<B: Read + Seek, W: Write> DeltaApplier<B, W> {
+    fn new(basis: B, writer: W) -> Self {
+        Self {
+            basis,
+            writer,
+            hasher: Sha256::new(),
+            literal_bytes: 0,
+            copied_bytes: 0,
+        }
+    }
+
+    fn apply(&mut self, op: &DeltaOp) -> Result<()> {
+        match op {
+            DeltaOp::Literal(data) => {
+                self.writer.write_all(data)?;
+                self.hasher.update(data);
+                self.literal_bytes += data.len();
+            }
+            DeltaOp::Copy { offset, len } => {
+                self.basis.seek(SeekFrom::Start(*offset))?;
+                let mut remaining = *len;
+                let mut buf = vec![0u8; remaining.min(READ_CHUNK_SIZE)];
+                while remaining > 0 {
+                    let chunk = &mut buf[..remaining.min(READ_CHUNK_SIZE)];
+                    self.basis.read_exact(chunk).with_context(|| {
+                        format!("Basis is too short for COPY at offset {}", offset)
+                    })?;
+                    self.writer.write_all(chunk)?;
+                    self.hasher.update(&*chunk);
+                    remaining -= chunk.len();
+                }
+                self.copied_bytes += len;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush the output and check it against `expected`, naming it `output` in errors.
+    fn finish(mut self, output: &Path, expected: Option<[u8; 32]>) -> Result<TransferResult> {
+        self.writer.flush()?;
+        let actual: [u8; 32] = self.hasher.finalize().into();
+        if let Some(expected) = expected.filter(|expected| *expected != actual) {
+            return Err(SyncError::ChecksumMismatch {
+                path: output.to_path_buf(),
+                expected: hex::encode(expected),
+                actual: hex::encode(actual),
+            });
+        }
+        Ok(TransferResult {
+            new_bytes: self.literal_bytes,
+            reused_bytes: self.copied_bytes,
+            ..Default::default()
+        })
+    }
+}
+
+/// Basis file opened on first use, so deltas made only of literals don't need one.
+struct LazyBasis<'a> {
+    path: &'a Path,
+    file: Option<DiskThrottled<File>>,
+    limiter: Option<SharedRateLimiter>,
+}
+
+impl LazyBasis<'_> {
+    fn file(&mut self) -> io::Result<&mut DiskThrottled<File>> {
+        match self.file {
+            Some(ref mut file) => Ok(file),
+            None => {
+                let file = File::open(self.path).map_err(|e| {
+                    io::Error::new(
+                        e.kind(),
+                        format!("Failed to open basis file {:?}: {}", self.path, e),
+                    )
+                })?;
+                Ok(self
+                    .file
+                    .insert(DiskThrottled::new(file, self.limiter.clone())))
+            }
+        }
+    }
+}
+
+impl Read for LazyBasis<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file()?.read(buf)
+    }
+}
+
+impl Seek for LazyBasis<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file()?.seek(pos)
+    }
+}
+
+/// Output file being rebuilt from a basis into a temporary file.
+struct Reconstruction<'a> {
+    temp_path: PathBuf,
+    applier: DeltaApplier<LazyBasis<'a>, BufWriter<DiskThrottled<File>>>,
+}
+
+impl<'a> Reconstruction<'a> {
+    /// Start rebuilding `output`, pacing reads of `basis` and writes of the temporary file
+    /// by `limiter` when one is set.
+    fn create(basis: &'a Path, output: &Path, limiter: Option<SharedRateLimiter>) -> Result<Self> {
+        let temp_path = output.with_extension("tmp");
+        let temp_file = File::create(&temp_path)
+            .with_context(|| format!("Failed to create temporary file: {:?}", temp_path))?;
+        let temp_file = DiskThrottled::new(temp_file, limiter.clone());
+        let basis = LazyBasis {
+            path: basis,
+            file: None,
+            limiter,
+        };
+        Ok(Self {
+            temp_path,
+            applier: DeltaApplier::new(basis, BufWriter::new(temp_file)),
+        })
+    }
+
+    fn apply(&mut self, op: &DeltaOp) -> Result<()> {
+        self.applier.apply(op)
+    }
+
+    fn commit(self, output: &Path, expected: Option<[u8; 32]>) -> Result<TransferResult> {
+        let result = match self.applier.finish(output, expected) {
+            Ok(result) => result,
+            Err(e) => {
+                let _ = fs::remove_file(&self.temp_path);
+                return Err(e);
+            }
+        };
+        fs::rename(&self.temp_path, output)
+            .inspect_err(|_| {
+                let _ = fs::remove_file(&self.temp_path);
+            })
+            .with_context(|| format!("Failed to move reconstructed file to {:?}", output))?;
+        Ok(result)
+    }
+
+    fn abort(self) {
+        drop(self.applier);
+        let _ = fs::remove_file(&self.temp_path);
+    }
+}
+
+/// Native signature layout: magic and version, the checksum kinds, strong sum length,
+/// block size and block count, then `<u64 offset> <u32 size> <u32 weak> <strong>` per block.
+const SIGNATURE_MAGIC: &[u8; 4] = b"RSXS";
+const SIGNATURE_VERSION: u8 = 1;
+
+/// Native delta layout: magic and version, then a sequence of `LITERAL <u32 len> <bytes>` /
+/// `COPY <u64 offset> <u32 len>` records, then `END` followed by an optional SHA-256 of the
+/// reconstructed file. Integers are big-endian throughout.
+const DELTA_MAGIC: &[u8; 4] = b"RSXD";
+const DELTA_VERSION: u8 = 1;
+const OP_END: u8 = 0;
+const OP_LITERAL: u8 = 1;
+const OP_COPY: u8 = 2;
+
+impl WeakHash {
+    fn to_byte(self) -> u8 {
+        match self {
+            WeakHash::Adler => 0,
+            WeakHash::RollSum => 1,
+            WeakHash::RabinKarp => 2,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self> {
+        match byte {
+            0 => Ok(WeakHash::Adler),
+            1 => Ok(WeakHash::RollSum),
+            2 => Ok(WeakHash::RabinKarp),
+            _ => Err(SyncError::Format(format!(
+                "Unknown weak checksum kind: {}",
+                byte
+            ))),
+        }
+    }
+}
+
+impl StrongHash {
+    fn to_byte(self) -> u8 {
+        match self {
+            StrongHash::Sha256 => 0,
+            StrongHash::Md4 => 1,
+            StrongHash::Blake2 => 2,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self> {
+        match byte {
+            0 => Ok(StrongHash::Sha256),
+            1 => Ok(StrongHash::Md4),
+            2 => Ok(StrongHash::Blake2),
+            _ => Err(SyncError::Format(format!(
+                "Unknown strong checksum kind: {}",
+                byte
+            ))),
+        }
+    }
+}
+
+impl Signature {
+    /// Serialize the signature so a delta can be computed against it elsewhere.
+    pub fn write_to<W: Write>(&self, mut writer: W, format: DeltaFormat) -> Result<()> {
+        match format {
+            DeltaFormat::Native => {
+                writer.write_all(SIGNATURE_MAGIC)?;
+                writer.write_all(&[
+                    SIGNATURE_VERSION,
+                    self.weak_hash.to_byte(),
+                    self.strong_hash.to_byte(),
+                    self.strong_len as u8,
+                ])?;
+                writer.write_all(&(self.block_size as u32).to_be_bytes())?;
+                writer.write_all(&(self.blocks.len() as u64).to_be_bytes())?;
+                for block in &self.blocks {
+                    writer.write_all(&block.offset.to_be_bytes())?;
+                    writer.write_all(&(block.size as u32).to_be_bytes())?;
+                    writer.write_all(&block.weak_checksum.to_be_bytes())?;
+                    writer.write_all(&block.strong_checksum[..self.strong_len])?;
+                }
+            }
+            DeltaFormat::Rdiff => rdiff::write_signature(self, &mut writer)?,
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Deserialize a signature, detecting the native or rdiff format from its magic number.
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Signature> {
+        let mut magic = [0u8; 4];
+        reader
+            .read_exact(&mut magic)
+            .with_context(|| "Failed to read signature header")?;
+        if rdiff::is_signature_magic(u32::from_be_bytes(magic)) {
+            return rdiff::read_signature(u32::from_be_bytes(magic), &mut reader);
+        }
+        if &magic != SIGNATURE_MAGIC {
+            return Err(SyncError::Format(
+                "Not an rsynx or rdiff signature".to_string(),
+            ));
+        }
+
+        let mut header = [0u8; 16];
+        reader
+            .read_exact(&mut header)
+            .with_context(|| "Truncated signature header")?;
+        if header[0] != SIGNATURE_VERSION {
+            return Err(SyncError::Format(format!(
+                "Unsupported signature version: {}",
+                header[0]
+            )));
+        }
+        let weak_hash = WeakHash::from_byte(header[1])?;
+        let strong_hash = StrongHash::from_byte(header[2])?;
+        let strong_len = header[3] as usize;
+        if strong_len == 0 || strong_len > strong_hash.digest_len() {
+            return Err(SyncError::Format(format!(
+                "Invalid strong checksum length: {}",
+                strong_len
+            )));
+        }
+        let block_size = u32::from_be_bytes(header[4..8].try_into().unwrap()) as usize;
+        let count = u64::from_be_bytes(header[8..16].try_into().unwrap());
+
+        let mut blocks = Vec::new();
+        let mut record = [0u8; 16];
+        for _ in 0..count {
+            reader
+                .read_exact(&mut record)
+                .with_context(|| "Truncated signature")?;
+            let mut strong_checksum = [0u8; 32];
+            reader
+                .read_exact(&mut strong_checksum[..strong_len])
+                .with_context(|| "Truncated signature")?;
+            blocks.push(Block {
+                offset: u64::from_be_bytes(record[0..8].try_into().unwrap()),
+                size: u32::from_be_bytes(record[8..12].try_into().unwrap()) as usize,
+                weak_checksum: u32::from_be_bytes(record[12..16].try_into().unwrap()),
+                strong_checksum,
+            });
+        }
+        Ok(Signature {
+            block_size,
+            weak_hash,
+            strong_hash,
+            strong_len,
+            blocks,
+        })
+    }
+}
+
+impl Delta {
+    /// Serialize the delta so it can be shipped out-of-band and applied with [`Syncer::patch`].
+    /// The rdiff format has no room for the target checksum, so it is dropped.
+    pub fn write_to<W: Write>(&self, mut writer: W, format: DeltaFormat) -> Result<()> {
+        match format {
+            DeltaFormat::Native => self.write_native(&mut writer)?,
+            DeltaFormat::Rdiff => {
+                rdiff::write_delta_header(&mut writer)?;
+                for op in &self.ops {
+                    rdiff::write_delta_op(&mut writer, op)?;
+                }
+                rdiff::write_delta_end(&mut writer)?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn write_native<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(DELTA_MAGIC)?;
+        writer.write_all(&[DELTA_VERSION])?;
+        for op in &self.ops {
+            match op {
+                DeltaOp::Literal(data) => {
+                    let len = u32::try_from(data.len()).map_err(|_| {
+                        SyncError::Format("Literal too large to serialize".to_string())
+                    })?;
+                    writer.write_all(&[OP_LITERAL])?;
+                    writer.write_all(&len.to_be_bytes())?;
+                    writer.write_all(data)?;
+                }
+                DeltaOp::Copy { offset, len } => {
+                    let len = u32::try_from(*len).map_err(|_| {
+                        SyncError::Format("Copy too large to serialize".to_string())
+                    })?;
+                    writer.write_all(&[OP_COPY])?;
+                    writer.write_all(&offset.to_be_bytes())?;
+                    writer.write_all(&len.to_be_bytes())?;
+                }
+            }
+        }
+        writer.write_all(&[OP_END])?;
+        match self.checksum {
+            Some(checksum) => {
+                writer.write_all(&[1])?;
+                writer.write_all(&checksum)?;
+            }
+            None => writer.write_all(&[0])?,
+        }
+        Ok(())
+    }
+
+    /// Deserialize a delta, detecting the native or rdiff format from its magic number.
+    pub fn read_from<R: Read>(reader: R) -> Result<Delta> {
+        let mut reader = DeltaReader::new(reader)?;
+        let mut delta = Delta::default();
+        while let Some(op) = reader.next_op()? {
+            delta.ops.push(op);
+        }
+        delta.checksum = reader.checksum();
+        Ok(delta)
+    }
+}
+
+/// Incremental reader for serialized delta streams in either format. Long literals are
+/// split into chunks of at most [`MAX_LITERAL_SIZE`] bytes.
+pub struct DeltaReader<R> {
+    reader: R,
+    format: DeltaFormat,
+    checksum: Option<[u8; 32]>,
+    literal_remaining: u64,
+    done: bool,
+}
+
+impl<R: Read> DeltaReader<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        reader
+            .read_exact(&mut magic)
+            .with_context(|| "Failed to read delta header")?;
+        let format = if u32::from_be_bytes(magic) == rdiff::DELTA_MAGIC {
+            DeltaFormat::Rdiff
+        } else if &magic == DELTA_MAGIC {
+            let mut version = [0u8; 1];
+            reader
+                .read_exact(&mut version)
+                .with_context(|| "Failed to read delta header")?;
+            if version[0] != DELTA_VERSION {
+                return Err(SyncError::Format(format!(
+                    "Unsupported delta version: {}",
+                    version[0]
+                )));
+            }
+            DeltaFormat::Native
+        } else {
+            return Err(SyncError::Format(
+                "Not an rsynx or rdiff delta stream".to_string(),
+            ));
+        };
+        Ok(Self {
+            reader,
+            format,
+            checksum: None,
+            literal_remaining: 0,
+            done: false,
+        })
+    }
+
+    /// Format detected from the stream header.
+    pub fn format(&self) -> DeltaFormat {
+        self.format
+    }
+
+    /// Read the next instruction, or `None` once the end marker has been reached.
+    pub fn next_op(&mut self) -> Result<Option<DeltaOp>> {
+        if self.literal_remaining > 0 {
+            return self.read_literal_chunk().map(Some);
+        }
+        if self.done {
+            return Ok(None);
+        }
+        match self.format {
+            DeltaFormat::Native => self.next_native_op(),
+            DeltaFormat::Rdiff => match rdiff::read_command(&mut self.reader)? {
+                rdiff::Command::End => {
+                    self.done = true;
+                    Ok(None)
+                }
+                rdiff::Command::Literal(len) => {
+                    self.literal_remaining = len;
+                    self.read_literal_chunk().map(Some)
+                }
+                rdiff::Command::Copy { offset, len } => Ok(Some(DeltaOp::Copy {
+                    offset,
+                    len: usize::try_from(len)
+                        .map_err(|_| SyncError::Format(format!("Copy length {} too large", len)))?,
+                })),
+            },
+        }
+    }
+
+    fn next_native_op(&mut self) -> Result<Option<DeltaOp>> {
+        let mut tag = [0u8; 1];
+        self.reader
+            .read_exact(&mut tag)
+            .with_context(|| "Truncated delta stream")?;
+        match tag[0] {
+            OP_LITERAL => {
+                self.literal_remaining = self.read_u32()? as u64;
+                self.read_literal_chunk().map(Some)
+            }
+            OP_COPY => {
+                let mut offset = [0u8; 8];
+                self.reader
+                    .read_exact(&mut offset)
+                    .with_context(|| "Truncated delta stream")?;
+                let len = self.read_u32()? as usize;
+                Ok(Some(DeltaOp::Copy {
+                    offset: u64::from_be_bytes(offset),
+                    len,
+                }))
+            }
+            OP_END => {
+                self.done = true;
+                let mut flag = [0u8; 1];
+                self.reader
+                    .read_exact(&mut flag)
+                    .with_context(|| "Truncated delta stream")?;
+                if flag[0] == 1 {
+                    let mut checksum = [0u8; 32];
+                    self.reader
+                        .read_exact(&mut checksum)
+                        .with_context(|| "Truncated delta checksum")?;
+                    self.checksum = Some(checksum);
+                }
+                Ok(None)
+            }
+            tag => Err(SyncError::Format(format!(
+                "Unknown delta instruction: {}",
+                tag
+            ))),
+        }
+    }
+
+    fn read_literal_chunk(&mut self) -> Result<DeltaOp> {
+        let len = self.literal_remaining.min(MAX_LITERAL_SIZE as u64) as usize;
+        let mut data = vec![0u8; len];
+        self.reader
+            .read_exact(&mut data)
+            .with_context(|| "Truncated literal in delta stream")?;
+        self.literal_remaining -= len as u64;
+        Ok(DeltaOp::Literal(data))
+    }
+
+    /// Checksum of the reconstructed file, available once the stream has been fully read.
+    /// Always `None` for rdiff deltas.
+    pub fn checksum(&self) -> Option<[u8; 32]> {
+        self.checksum
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let mut buf = [0u8; 4];
+        self.reader
+            .read_exact(&mut buf)
+            .with_context(|| "Truncated delta stream")?;
+        Ok(u32::from_be_bytes(buf))
+    }
+}
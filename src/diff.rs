@@ -0,0 +1,159 @@
+use crate::filter::Filter;
+use crate::manifest::{EntryKind, ManifestEntry};
+use crate::plan::PlanReason;
+use crate::sync::Syncer;
+use filetime::FileTime;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    path::Path,
+};
+
+/// Where an entry of two compared trees is out of sync.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffStatus {
+    /// Only the first tree has it.
+    OnlyInA,
+    /// Only the second tree has it.
+    OnlyInB,
+    /// Both have it, with different content, time, link target or type.
+    Differs,
+}
+
+/// An entry that isn't the same on both sides, by its path relative to the compared roots.
+/// A directory only one side has stands for everything in it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffEntry {
+    pub path: String,
+    pub status: DiffStatus,
+    /// Why the two copies differ; `None` unless `status` is `Differs`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<PlanReason>,
+    pub is_dir: bool,
+}
+
+impl fmt::Display for DiffEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let suffix = if self.is_dir { "/" } else { "" };
+        match (self.status, self.reason) {
+            (DiffStatus::OnlyInA, _) => write!(f, "only-a  {}{}", self.path, suffix),
+            (DiffStatus::OnlyInB, _) => write!(f, "only-b  {}{}", self.path, suffix),
+            (DiffStatus::Differs, Some(reason)) => {
+                write!(f, "differs {}{} ({})", self.path, suffix, reason)
+            }
+            (DiffStatus::Differs, None) => write!(f, "differs {}{}", self.path, suffix),
+        }
+    }
+}
+
+impl Syncer {
+    /// Compare the listings of two trees, as built by `manifest::build`, the way a sync from
+    /// `a` to `b` would: files differ by size, then by hash with the `checksum` option (both
+    /// listings need hashes then) or else by modification time within the `modify_window`.
+    /// Directories only differ by type. Entries the filter excludes are left out.
+    pub fn diff_listings(&self, a: &[ManifestEntry], b: &[ManifestEntry]) -> Vec<DiffEntry> {
+        let filter = &self.options.filter;
+        let mut b: BTreeMap<&str, &ManifestEntry> = included(b, filter)
+            .map(|entry| (entry.path.as_str(), entry))
+            .collect();
+        let mut differences = Vec::new();
+        for entry in included(a, filter) {
+            let reason = match b.remove(entry.path.as_str()) {
+                Some(other) => match self.entry_check(entry, other) {
+                    PlanReason::Unchanged => continue,
+                    reason => Some(reason),
+                },
+                None => None,
+            };
+            differences.push(DiffEntry {
+                path: entry.path.clone(),
+                status: if reason.is_some() {
+                    DiffStatus::Differs
+                } else {
+                    DiffStatus::OnlyInA
+                },
+                reason,
+                is_dir: entry.kind == EntryKind::Dir,
+            });
+        }
+        differences.extend(b.into_values().map(|entry| DiffEntry {
+            path: entry.path.clone(),
+            status: DiffStatus::OnlyInB,
+            reason: None,
+            is_dir: entry.kind == EntryKind::Dir,
+        }));
+        differences.sort_by(|x, y| x.path.cmp(&y.path));
+        without_nested(differences)
+    }
+
+    /// The quick check of `quick_check` and `link_check`, on listed entries.
+    fn entry_check(&self, a: &ManifestEntry, b: &ManifestEntry) -> PlanReason {
+        if a.kind != b.kind {
+            return PlanReason::TypeChanged;
+        }
+        match a.kind {
+            EntryKind::Dir => PlanReason::Unchanged,
+            EntryKind::Link if a.link != b.link => PlanReason::TargetChanged,
+            EntryKind::Link => PlanReason::Unchanged,
+            EntryKind::File if a.size != b.size => PlanReason::SizeChanged,
+            EntryKind::File if self.options.checksum => {
+                if a.sha256 == b.sha256 {
+                    PlanReason::Unchanged
+                } else {
+                    PlanReason::ChecksumChanged
+                }
+            }
+            EntryKind::File => {
+                let mtime =
+                    |entry: &ManifestEntry| FileTime::from_unix_time(entry.mtime, entry.mtime_nsec);
+                if self.mtimes_match(mtime(a), mtime(b)) {
+                    PlanReason::Unchanged
+                } else {
+                    PlanReason::MtimeChanged
+                }
+            }
+        }
+    }
+}
+
+/// The entries of `listing` that neither `filter` nor an excluded directory above them
+/// leaves out.
+fn included<'a>(
+    listing: &'a [ManifestEntry],
+    filter: &'a Filter,
+) -> impl Iterator<Item = &'a ManifestEntry> {
+    listing.iter().filter(move |entry| {
+        filter.is_empty()
+            || Path::new(&entry.path)
+                .ancestors()
+                .filter(|path| !path.as_os_str().is_empty())
+                .enumerate()
+                .all(|(depth, path)| {
+                    let is_dir = depth > 0 || entry.kind == EntryKind::Dir;
+                    !filter.is_excluded(path, is_dir)
+                })
+    })
+}
+
+/// Drop entries inside a directory that is itself only on one side, or on one side of a
+/// type change.
+fn without_nested(differences: Vec<DiffEntry>) -> Vec<DiffEntry> {
+    let mut one_sided = HashSet::new();
+    differences
+        .into_iter()
+        .filter(|entry| {
+            let nested = Path::new(&entry.path)
+                .ancestors()
+                .skip(1)
+                .any(|dir| one_sided.contains(dir));
+            let contains_one_sided = (entry.is_dir && entry.status != DiffStatus::Differs)
+                || entry.reason == Some(PlanReason::TypeChanged);
+            if !nested && contains_one_sided {
+                one_sided.insert(Path::new(&entry.path).to_path_buf());
+            }
+            !nested
+        })
+        .collect()
+}
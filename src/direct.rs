@@ -0,0 +1,218 @@
+use memmap2::MmapMut;
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+/// Offset, length and address alignment `O_DIRECT` reads are made with; a multiple of the
+/// logical block size of common devices.
+pub const ALIGNMENT: usize = 4096;
+
+/// Size of the aligned buffer uncached reads go through.
+const BUFFER_SIZE: usize = 1024 * 1024;
+
+/// How a `DirectReader` reaches its file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadMode {
+    /// Plain reads through the page cache.
+    Cached,
+    /// `O_DIRECT` reads, bypassing the page cache.
+    Direct,
+    /// Buffered reads that drop the pages read from the cache, where `O_DIRECT` isn't
+    /// supported.
+    Dropping,
+}
+
+/// Reader over a local file that, when uncached, keeps the file out of the page cache, so
+/// scanning a file much larger than memory doesn't evict everything else cached on the host.
+///
+/// Uncached reads go through an aligned buffer, so callers may seek and read any range.
+/// Filesystems refusing `O_DIRECT`, such as tmpfs, fall back to buffered reads followed by
+/// `POSIX_FADV_DONTNEED`; platforms other than Linux always read through the cache.
+pub struct DirectReader {
+    file: File,
+    path: PathBuf,
+    mode: ReadMode,
+    buffer: Vec<u8>,
+    /// Where `buffer`'s aligned window starts.
+    align: usize,
+    /// File offset of the buffered data.
+    buffered_at: u64,
+    buffered_len: usize,
+    pos: u64,
+}
+
+impl DirectReader {
+    /// Open `path`, bypassing the page cache when `uncached` is set.
+    pub fn open(path: &Path, uncached: bool) -> io::Result<Self> {
+        let (file, mode) = match uncached {
+            false => (File::open(path)?, ReadMode::Cached),
+            true => open_uncached(path)?,
+        };
+        let buffer = match mode {
+            ReadMode::Cached => Vec::new(),
+            _ => vec![0; BUFFER_SIZE + ALIGNMENT],
+        };
+        let align = buffer.as_ptr().align_offset(ALIGNMENT).min(ALIGNMENT);
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+            mode,
+            buffer,
+            align,
+            buffered_at: 0,
+            buffered_len: 0,
+            pos: 0,
+        })
+    }
+
+    pub fn mode(&self) -> ReadMode {
+        self.mode
+    }
+
+    pub fn get_ref(&self) -> &File {
+        &self.file
+    }
+
+    /// Fill the buffer with the aligned window holding `pos`.
+    fn fill(&mut self) -> io::Result<()> {
+        let start = self.pos - self.pos % ALIGNMENT as u64;
+        let window = &mut self.buffer[self.align..self.align + BUFFER_SIZE];
+        let read = match read_at(&self.file, window, start) {
+            // Some filesystems only refuse O_DIRECT once it's used
+            Err(e) if self.mode == ReadMode::Direct && is_unsupported(&e) => {
+                self.file = File::open(&self.path)?;
+                self.mode = ReadMode::Dropping;
+                read_at(&self.file, window, start)?
+            }
+            read => read?,
+        };
+        if self.mode == ReadMode::Dropping {
+            drop_cached(&self.file, start, read as u64);
+        }
+        self.buffered_at = start;
+        self.buffered_len = read;
+        Ok(())
+    }
+}
+
+impl Read for DirectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.mode == ReadMode::Cached {
+            return self.file.read(buf);
+        }
+        let buffered = self.buffered_at..self.buffered_at + self.buffered_len as u64;
+        if !buffered.contains(&self.pos) {
+            self.fill()?;
+        }
+        let skip = (self.pos - self.buffered_at) as usize;
+        if skip >= self.buffered_len {
+            return Ok(0);
+        }
+        let available = &self.buffer[self.align + skip..self.align + self.buffered_len];
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for DirectReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        if self.mode == ReadMode::Cached {
+            return self.file.seek(pos);
+        }
+        let pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.file.metadata()?.len().checked_add_signed(delta),
+        };
+        self.pos = pos.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative offset")
+        })?;
+        Ok(self.pos)
+    }
+}
+
+/// Flush `len` bytes of `mmap`, a shared mapping of `file`, from `offset` and drop them from
+/// the page cache, so writing a huge file through a map doesn't fill the cache either.
+pub fn release_written(mmap: &MmapMut, file: &File, offset: usize, len: usize) -> io::Result<()> {
+    mmap.flush_range(offset, len)?;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        // Mapped pages stay cached, so unmap them first. The mapping is shared and flushed,
+        // so their data is kept and read back from the file if touched again.
+        mmap.unchecked_advise_range(memmap2::UncheckedAdvice::DontNeed, offset, len)?;
+    }
+    drop_cached(file, offset as u64, len as u64);
+    Ok(())
+}
+
+/// Open `path` for uncached reads: `O_DIRECT` where the filesystem takes it, else buffered
+/// reads dropping their pages.
+#[cfg(unix)]
+fn open_uncached(path: &Path) -> io::Result<(File, ReadMode)> {
+    match open_direct(path) {
+        Ok(file) => Ok((file, ReadMode::Direct)),
+        Err(e) if is_unsupported(&e) => Ok((File::open(path)?, ReadMode::Dropping)),
+        Err(e) => Err(e),
+    }
+}
+
+/// Off unix there's no way to keep pages out of the cache, so reads go through it.
+#[cfg(not(unix))]
+fn open_uncached(path: &Path) -> io::Result<(File, ReadMode)> {
+    Ok((File::open(path)?, ReadMode::Cached))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn open_direct(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    File::options()
+        .read(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(path)
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+fn open_direct(_path: &Path) -> io::Result<File> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Read into `buf` from `offset` of `file`, without moving its cursor.
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    use std::os::unix::fs::FileExt;
+    file.read_at(buf, offset)
+}
+
+#[cfg(not(unix))]
+fn read_at(mut file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.read(buf)
+}
+
+fn is_unsupported(e: &io::Error) -> bool {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if e.raw_os_error() == Some(libc::EINVAL) {
+        return true;
+    }
+    e.kind() == io::ErrorKind::Unsupported
+}
+
+/// Ask the kernel to drop the cached pages of a range of `file`. Only a hint: failures are
+/// ignored.
+fn drop_cached(file: &File, offset: u64, len: u64) {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        use std::os::fd::AsRawFd;
+        if let (Ok(offset), Ok(len)) = (libc::off_t::try_from(offset), libc::off_t::try_from(len)) {
+            unsafe {
+                libc::posix_fadvise(file.as_raw_fd(), offset, len, libc::POSIX_FADV_DONTNEED);
+            }
+        }
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    let _ = (file, offset, len);
+}
@@ -0,0 +1,115 @@
+use crate::error::{IoContext, Result, SyncError};
+use aes_gcm::{
+    Aes256Gcm, KeyInit, Nonce,
+    aead::{Aead, Payload},
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{fmt, fs, path::Path};
+
+/// Length of an encryption key in bytes; key files hold it as 64 hex digits.
+pub const KEY_LEN: usize = 32;
+
+/// Length of the random nonce stored in front of every sealed object.
+const NONCE_LEN: usize = 12;
+
+/// Length of the authentication tag AES-GCM appends to the ciphertext.
+const TAG_LEN: usize = 16;
+
+/// The key encrypting a `store://` store, so the storage holding it sees neither file
+/// contents nor names.
+///
+/// Two subkeys are derived from the user's key: one for AES-256-GCM, sealing chunks and
+/// manifests, and one for HMAC-SHA256, naming chunks by their plaintext. Identical chunks
+/// still get identical names, so deduplication and block-level updates keep working, but the
+/// names can't be matched against known content without the key.
+#[derive(Clone)]
+pub struct StoreKey {
+    cipher: Aes256Gcm,
+    naming: [u8; 32],
+}
+
+impl fmt::Debug for StoreKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StoreKey(..)")
+    }
+}
+
+impl StoreKey {
+    pub fn new(key: &[u8; KEY_LEN]) -> Self {
+        Self {
+            cipher: Aes256Gcm::new(&derive(key, b"rsynx store encryption").into()),
+            naming: derive(key, b"rsynx store naming"),
+        }
+    }
+
+    /// Read a key file holding 64 hex digits, such as one written by `openssl rand -hex 32`.
+    pub fn read_from(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read encryption key {:?}", path))?;
+        let mut key = [0u8; KEY_LEN];
+        hex::decode_to_slice(text.trim(), &mut key).map_err(|_| {
+            SyncError::Config(format!(
+                "encryption key {:?} must hold {} hex digits",
+                path,
+                KEY_LEN * 2
+            ))
+        })?;
+        Ok(Self::new(&key))
+    }
+
+    /// The keyed hash naming `data` in the store.
+    pub fn name(&self, data: &[u8]) -> [u8; 32] {
+        derive(&self.naming, data)
+    }
+
+    /// Encrypt `data` under a fresh random nonce, bound to `label` so a sealed object can't
+    /// be passed off as another one.
+    pub fn seal(&self, data: &[u8], label: &[u8]) -> Vec<u8> {
+        let nonce = rand::random::<[u8; NONCE_LEN]>();
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: data,
+                    aad: label,
+                },
+            )
+            .expect("AES-GCM encrypts any length a chunk or manifest can have");
+        let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        sealed
+    }
+
+    /// Decrypt what `seal` produced for the same `label`, failing if the key is wrong or the
+    /// data was altered.
+    pub fn open(&self, sealed: &[u8], label: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < NONCE_LEN + TAG_LEN {
+            return Err(SyncError::Decryption(
+                "encrypted object is truncated".to_string(),
+            ));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: label,
+                },
+            )
+            .map_err(|_| {
+                SyncError::Decryption(
+                    "the key is wrong or the encrypted data was modified".to_string(),
+                )
+            })
+    }
+}
+
+fn derive(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
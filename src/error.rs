@@ -0,0 +1,121 @@
+use std::{fmt::Display, io, path::PathBuf};
+use thiserror::Error;
+
+/// Result type used throughout the library.
+pub type Result<T, E = SyncError> = std::result::Result<T, E>;
+
+/// Every way a sync, signature, delta or patch operation can fail.
+#[derive(Debug, Error)]
+pub enum SyncError {
+    /// Reading or writing a file or connection failed; `context` says what was attempted.
+    #[error("{context}: {source}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+
+    /// The connection to the remote end couldn't be established.
+    #[error("Failed to connect to remote address: {peer}: {source}")]
+    Connect {
+        peer: String,
+        #[source]
+        source: io::Error,
+    },
+
+    /// The peer sent something that doesn't follow the sync protocol.
+    #[error("Protocol error: {0}")]
+    Protocol(String),
+
+    /// A signature or delta stream is malformed or in an unsupported format.
+    #[error("{0}")]
+    Format(String),
+
+    /// The server turned the request down, for instance for an unknown module or failed
+    /// authentication.
+    #[error("Server refused the request: {0}")]
+    Refused(String),
+
+    /// A config file can't be parsed or refers to something that doesn't exist.
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+
+    /// A reconstructed file doesn't hash to the value of its source.
+    #[error("Checksum mismatch for {path:?}: expected {expected}, got {actual}")]
+    ChecksumMismatch {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+
+    /// Data read back from an encrypted store failed to decrypt or authenticate.
+    #[error("Decryption failed: {0}")]
+    Decryption(String),
+
+    /// The source can't be synced in the requested mode.
+    #[error("Can't sync {path:?}: {reason}")]
+    UnsupportedSource { path: PathBuf, reason: &'static str },
+
+    /// The source file was modified while it was being synced.
+    #[error("Source file {0:?} changed during sync")]
+    SourceChanged(PathBuf),
+
+    /// The block size is zero or too large to be useful.
+    #[error("Invalid block size {0}: must be between 1 and {max} bytes", max = crate::sync::MAX_BLOCK_SIZE)]
+    InvalidBlockSize(usize),
+
+    /// A required path was given as an empty string.
+    #[error("{0} path cannot be empty")]
+    EmptyPath(&'static str),
+
+    /// Options were combined in a way the chosen sync mode can't honour.
+    #[error("Conflicting options: {0}")]
+    ConflictingOptions(String),
+
+    /// A path given to the library lies outside the directory it must stay within.
+    #[error("{0:?} is outside the sync root")]
+    PathOutsideRoot(PathBuf),
+
+    /// Another process holds the lock on the destination; `owner` is its process ID when
+    /// the lock file names one.
+    #[error(
+        "Another rsynx process{} is already syncing into {path:?}",
+        .owner.map(|pid| format!(" (pid {})", pid)).unwrap_or_default()
+    )]
+    Locked { path: PathBuf, owner: Option<u32> },
+
+    /// The sync was aborted through its cancel token.
+    #[error("Sync cancelled")]
+    Cancelled,
+
+    /// A stage of the reconstruction pipeline stopped unexpectedly.
+    #[error("Reconstruction pipeline failed: {0}")]
+    Pipeline(&'static str),
+
+    /// The background thread or task running a sync panicked or was cancelled.
+    #[error("Sync task failed: {0}")]
+    Task(String),
+}
+
+impl From<io::Error> for SyncError {
+    fn from(source: io::Error) -> Self {
+        SyncError::Io {
+            context: "I/O error".to_string(),
+            source,
+        }
+    }
+}
+
+/// Attach a description of the attempted operation to I/O errors.
+pub(crate) trait IoContext<T> {
+    fn with_context<C: Display, F: FnOnce() -> C>(self, context: F) -> Result<T>;
+}
+
+impl<T> IoContext<T> for std::result::Result<T, io::Error> {
+    fn with_context<C: Display, F: FnOnce() -> C>(self, context: F) -> Result<T> {
+        self.map_err(|source| SyncError::Io {
+            context: context().to_string(),
+            source,
+        })
+    }
+}
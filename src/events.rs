@@ -0,0 +1,90 @@
+use crate::conflict::ConflictPolicy;
+use crate::error::{Result, SyncError};
+use crate::sync::{FileRecord, Syncer, TransferResult};
+use std::{
+    path::PathBuf,
+    sync::{Arc, mpsc},
+    thread::{self, JoinHandle},
+};
+
+/// Number of events buffered before a sync waits for its consumer to catch up.
+const EVENT_BUFFER: usize = 1024;
+
+/// Something that happened during a sync. Paths are destination paths.
+#[derive(Debug, Clone)]
+pub enum SyncEvent {
+    /// A file is about to be written; `size` is the size of its source.
+    FileStarted { path: PathBuf, size: u64 },
+    /// `len` bytes at `offset` of the existing destination were reused.
+    BlockMatched {
+        path: PathBuf,
+        offset: u64,
+        len: usize,
+    },
+    /// A file was written and is in place.
+    FileCompleted(FileRecord),
+    /// A file was left alone because the quick check found it unchanged.
+    FileSkipped { path: PathBuf },
+    /// An extraneous destination entry was removed.
+    Deleted { path: PathBuf },
+    /// The destination's `path` changed since the last sync, which would have overwritten or
+    /// deleted it; `policy` says what was done instead, and `renamed_to` where it went.
+    Conflict {
+        path: PathBuf,
+        policy: ConflictPolicy,
+        renamed_to: Option<PathBuf>,
+    },
+    /// Syncing `path` failed; the sync stops with the same error.
+    Error { path: PathBuf, message: String },
+}
+
+/// Callback receiving sync events; it is called from the thread driving the sync.
+pub type EventCallback = Arc<dyn Fn(&SyncEvent) + Send + Sync>;
+
+/// Events of a sync running on a background thread, yielded as they happen. Iteration ends
+/// when the sync does; `finish` then returns its result.
+pub struct SyncEvents {
+    receiver: mpsc::Receiver<SyncEvent>,
+    handle: JoinHandle<Result<TransferResult>>,
+}
+
+impl SyncEvents {
+    /// Start `run` on a new thread, handing it a callback that feeds this iterator.
+    pub(crate) fn spawn<F>(run: F) -> Self
+    where
+        F: FnOnce(EventCallback) -> Result<TransferResult> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(EVENT_BUFFER);
+        let callback: EventCallback = Arc::new(move |event: &SyncEvent| {
+            // Once the consumer is gone the sync simply runs to completion unobserved
+            let _ = sender.send(event.clone());
+        });
+        let handle = thread::spawn(move || run(callback));
+        Self { receiver, handle }
+    }
+
+    /// Wait for the sync to end and return its result, discarding events not yet consumed.
+    pub fn finish(self) -> Result<TransferResult> {
+        drop(self.receiver);
+        self.handle
+            .join()
+            .map_err(|_| SyncError::Task("sync thread panicked".to_string()))?
+    }
+}
+
+impl Iterator for SyncEvents {
+    type Item = SyncEvent;
+
+    fn next(&mut self) -> Option<SyncEvent> {
+        self.receiver.recv().ok()
+    }
+}
+
+impl Syncer {
+    /// Pass an event to the installed callback; `event` is only built when there is one.
+    pub(crate) fn emit<F: FnOnce() -> SyncEvent>(&self, event: F) {
+        if let Some(callback) = &self.options.events {
+            callback(&event());
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use crate::delta::{Delta, DeltaFormat, Signature};
+use crate::error::{Result, SyncError};
+use crate::local_sync::LocalSyncer;
+use crate::sync::{Syncer, TransferResult};
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString, c_char, c_void},
+    fs::File,
+    io::{BufReader, BufWriter},
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    ptr,
+};
+
+/// Result of an rsynx call; on anything but `Ok`, `rsynx_last_error` describes the failure.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RsynxStatus {
+    Ok = 0,
+    Error = 1,
+    InvalidArgument = 2,
+    ChecksumMismatch = 3,
+}
+
+/// Byte counts of a finished sync or patch.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RsynxStats {
+    pub new_bytes: u64,
+    pub reused_bytes: u64,
+}
+
+/// Called with the bytes handled so far in the current file and its total size.
+pub type RsynxProgressFn =
+    Option<unsafe extern "C" fn(bytes_done: u64, bytes_total: u64, user_data: *mut c_void)>;
+
+/// Opaque block signature of a basis file.
+pub struct RsynxSignature(Signature);
+
+/// Opaque delta between a signature and a new file.
+pub struct RsynxDelta(Delta);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Run `f`, recording its error or panic for `rsynx_last_error`.
+fn guard<T>(f: impl FnOnce() -> Result<T>) -> std::result::Result<T, RsynxStatus> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(e)) => {
+            let status = match e {
+                SyncError::ChecksumMismatch { .. } => RsynxStatus::ChecksumMismatch,
+                SyncError::EmptyPath(_)
+                | SyncError::InvalidBlockSize(_)
+                | SyncError::ConflictingOptions(_) => RsynxStatus::InvalidArgument,
+                _ => RsynxStatus::Error,
+            };
+            set_last_error(e.to_string());
+            Err(status)
+        }
+        Err(_) => {
+            set_last_error("rsynx panicked".to_string());
+            Err(RsynxStatus::Error)
+        }
+    }
+}
+
+fn status_of(result: std::result::Result<(), RsynxStatus>) -> RsynxStatus {
+    result.err().unwrap_or(RsynxStatus::Ok)
+}
+
+/// # Safety
+/// `ptr` must be null or a NUL-terminated string valid for the duration of the call.
+unsafe fn path_arg(ptr: *const c_char, name: &'static str) -> Result<PathBuf> {
+    if ptr.is_null() {
+        return Err(SyncError::ConflictingOptions(format!("{} is null", name)));
+    }
+    let path = unsafe { CStr::from_ptr(ptr) };
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Ok(PathBuf::from(std::ffi::OsStr::from_bytes(path.to_bytes())))
+    }
+    #[cfg(not(unix))]
+    {
+        path.to_str()
+            .map(PathBuf::from)
+            .map_err(|_| SyncError::ConflictingOptions(format!("{} is not valid UTF-8", name)))
+    }
+}
+
+fn syncer_with_block_size(block_size: usize) -> Result<Syncer> {
+    let mut syncer = Syncer::new();
+    if block_size != 0 {
+        syncer.options.block_size = block_size;
+    }
+    syncer.validate()?;
+    Ok(syncer)
+}
+
+/// # Safety
+/// `stats` must be null or writable.
+unsafe fn write_stats(stats: *mut RsynxStats, result: &TransferResult) {
+    if !stats.is_null() {
+        let value = RsynxStats {
+            new_bytes: result.new_bytes as u64,
+            reused_bytes: result.reused_bytes as u64,
+        };
+        unsafe { stats.write(value) };
+    }
+}
+
+/// Progress callback and its user data, handed to the sync thread.
+struct ProgressHook {
+    callback: unsafe extern "C" fn(u64, u64, *mut c_void),
+    user_data: *mut c_void,
+}
+
+// SAFETY: the caller of `rsynx_sync_local` promises the user data may be used from the thread
+// running the sync, which is the calling thread.
+unsafe impl Send for ProgressHook {}
+unsafe impl Sync for ProgressHook {}
+
+impl ProgressHook {
+    fn report(&self, bytes_done: u64, bytes_total: u64) {
+        unsafe { (self.callback)(bytes_done, bytes_total, self.user_data) }
+    }
+}
+
+/// Message describing the last failed call on this thread, or null if none failed. The
+/// string stays valid until the next failing call on the same thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn rsynx_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// Sync a local file or directory. A `block_size` of 0 uses the default, `progress` may be
+/// null, and `stats` receives the byte counts when non-null.
+///
+/// # Safety
+/// `source` and `destination` must be NUL-terminated strings and `stats` null or writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rsynx_sync_local(
+    source: *const c_char,
+    destination: *const c_char,
+    block_size: usize,
+    progress: RsynxProgressFn,
+    user_data: *mut c_void,
+    stats: *mut RsynxStats,
+) -> RsynxStatus {
+    status_of(guard(|| {
+        let source = unsafe { path_arg(source, "source")? };
+        let destination = unsafe { path_arg(destination, "destination")? };
+        let syncer = syncer_with_block_size(block_size)?;
+        let mut local = LocalSyncer::new(source, destination).with_options(syncer.options);
+        if let Some(callback) = progress {
+            let hook = ProgressHook {
+                callback,
+                user_data,
+            };
+            local = local.on_progress(move |p| hook.report(p.bytes_done, p.bytes_total));
+        }
+        let result = local.sync()?;
+        unsafe { write_stats(stats, &result) };
+        Ok(())
+    }))
+}
+
+/// Compute the signature of `basis_path`, or return null on failure.
+///
+/// # Safety
+/// `basis_path` must be a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rsynx_signature_new(
+    basis_path: *const c_char,
+    block_size: usize,
+) -> *mut RsynxSignature {
+    guard(|| {
+        let basis = unsafe { path_arg(basis_path, "basis_path")? };
+        let signature = syncer_with_block_size(block_size)?.generate_signature(&basis)?;
+        Ok(Box::into_raw(Box::new(RsynxSignature(signature))))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Read a signature saved with `rsynx_signature_save`, or return null on failure.
+///
+/// # Safety
+/// `path` must be a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rsynx_signature_load(path: *const c_char) -> *mut RsynxSignature {
+    guard(|| {
+        let path = unsafe { path_arg(path, "path")? };
+        let signature = Signature::read_from(BufReader::new(File::open(path)?))?;
+        Ok(Box::into_raw(Box::new(RsynxSignature(signature))))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Write a signature in rsynx's native format.
+///
+/// # Safety
+/// `signature` must come from this library and `path` be a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rsynx_signature_save(
+    signature: *const RsynxSignature,
+    path: *const c_char,
+) -> RsynxStatus {
+    status_of(guard(|| {
+        let signature = unsafe { signature.as_ref() }
+            .ok_or_else(|| SyncError::ConflictingOptions("signature is null".to_string()))?;
+        let path = unsafe { path_arg(path, "path")? };
+        signature
+            .0
+            .write_to(BufWriter::new(File::create(path)?), DeltaFormat::Native)
+    }))
+}
+
+/// # Safety
+/// `signature` must be null or come from this library, and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rsynx_signature_free(signature: *mut RsynxSignature) {
+    if !signature.is_null() {
+        drop(unsafe { Box::from_raw(signature) });
+    }
+}
+
+/// Compute the delta from the file described by `signature` to `new_path`, or return null
+/// on failure.
+///
+/// # Safety
+/// `signature` must come from this library and `new_path` be a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rsynx_delta_new(
+    signature: *const RsynxSignature,
+    new_path: *const c_char,
+) -> *mut RsynxDelta {
+    guard(|| {
+        let signature = unsafe { signature.as_ref() }
+            .ok_or_else(|| SyncError::ConflictingOptions("signature is null".to_string()))?;
+        let new_path = unsafe { path_arg(new_path, "new_path")? };
+        let syncer = syncer_with_block_size(signature.0.block_size)?;
+        let delta = syncer.generate_delta(&signature.0, &new_path)?;
+        Ok(Box::into_raw(Box::new(RsynxDelta(delta))))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Read a delta saved with `rsynx_delta_save`, or return null on failure.
+///
+/// # Safety
+/// `path` must be a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rsynx_delta_load(path: *const c_char) -> *mut RsynxDelta {
+    guard(|| {
+        let path = unsafe { path_arg(path, "path")? };
+        let delta = Delta::read_from(BufReader::new(File::open(path)?))?;
+        Ok(Box::into_raw(Box::new(RsynxDelta(delta))))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Write a delta in rsynx's native format.
+///
+/// # Safety
+/// `delta` must come from this library and `path` be a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rsynx_delta_save(
+    delta: *const RsynxDelta,
+    path: *const c_char,
+) -> RsynxStatus {
+    status_of(guard(|| {
+        let delta = unsafe { delta.as_ref() }
+            .ok_or_else(|| SyncError::ConflictingOptions("delta is null".to_string()))?;
+        let path = unsafe { path_arg(path, "path")? };
+        delta
+            .0
+            .write_to(BufWriter::new(File::create(path)?), DeltaFormat::Native)
+    }))
+}
+
+/// Rebuild `output_path` from `basis_path` and `delta`; `stats` receives the byte counts
+/// when non-null.
+///
+/// # Safety
+/// `delta` must come from this library, the paths be NUL-terminated strings and `stats` null
+/// or writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rsynx_delta_apply(
+    basis_path: *const c_char,
+    delta: *const RsynxDelta,
+    output_path: *const c_char,
+    stats: *mut RsynxStats,
+) -> RsynxStatus {
+    status_of(guard(|| {
+        let basis = unsafe { path_arg(basis_path, "basis_path")? };
+        let delta = unsafe { delta.as_ref() }
+            .ok_or_else(|| SyncError::ConflictingOptions("delta is null".to_string()))?;
+        let output = unsafe { path_arg(output_path, "output_path")? };
+        let result = Syncer::new().apply_delta(&basis, &delta.0, &output)?;
+        unsafe { write_stats(stats, &result) };
+        Ok(())
+    }))
+}
+
+/// # Safety
+/// `delta` must be null or come from this library, and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rsynx_delta_free(delta: *mut RsynxDelta) {
+    if !delta.is_null() {
+        drop(unsafe { Box::from_raw(delta) });
+    }
+}
@@ -0,0 +1,193 @@
+use crate::error::{IoContext, Result};
+use std::{fs, path::Path};
+
+/// What a matching rule does to a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterAction {
+    Include,
+    Exclude,
+}
+
+/// One include or exclude pattern, using rsync's pattern rules: `*` matches within a path
+/// component, `**` across components, `?` a single character and `[...]` a character class.
+/// A leading `/` anchors the pattern to the sync root, a trailing `/` makes it match only
+/// directories, and a pattern without any other `/` is matched against the final component.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterRule {
+    pub action: FilterAction,
+    pattern: String,
+    anchored: bool,
+    dir_only: bool,
+}
+
+impl FilterRule {
+    pub fn new(action: FilterAction, pattern: &str) -> Self {
+        let anchored = pattern.starts_with('/');
+        let dir_only = pattern.len() > 1 && pattern.ends_with('/');
+        let pattern = pattern.trim_start_matches('/').trim_end_matches('/');
+        Self {
+            action,
+            pattern: pattern.to_string(),
+            anchored,
+            dir_only,
+        }
+    }
+
+    pub fn include(pattern: &str) -> Self {
+        Self::new(FilterAction::Include, pattern)
+    }
+
+    pub fn exclude(pattern: &str) -> Self {
+        Self::new(FilterAction::Exclude, pattern)
+    }
+
+    /// The pattern as given, minus the anchoring and directory markers.
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Whether the rule matches `path`, given relative to the sync root.
+    pub fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let path = path.as_os_str().as_encoded_bytes();
+        let pattern = self.pattern.as_bytes();
+        if self.anchored {
+            return glob_match(pattern, path);
+        }
+        if !pattern.contains(&b'/') {
+            let name = path.rsplit(|&b| b == b'/').next().unwrap_or(path);
+            return glob_match(pattern, name);
+        }
+        // Unanchored patterns with a slash may match any trailing run of components
+        glob_match(pattern, path)
+            || path
+                .iter()
+                .enumerate()
+                .filter(|&(_, &b)| b == b'/')
+                .any(|(i, _)| glob_match(pattern, &path[i + 1..]))
+    }
+}
+
+/// Ordered include/exclude rules deciding which entries of a directory sync are transferred.
+/// The first matching rule wins; paths matching no rule are included.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    rules: Vec<FilterRule>,
+}
+
+impl Filter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn include(mut self, pattern: &str) -> Self {
+        self.rules.push(FilterRule::include(pattern));
+        self
+    }
+
+    pub fn exclude(mut self, pattern: &str) -> Self {
+        self.rules.push(FilterRule::exclude(pattern));
+        self
+    }
+
+    pub fn push(&mut self, rule: FilterRule) {
+        self.rules.push(rule);
+    }
+
+    pub fn rules(&self) -> &[FilterRule] {
+        &self.rules
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Read rules from an exclude file, one pattern per line. Blank lines and lines starting
+    /// with `#` or `;` are ignored; `+ ` and `- ` prefixes mark include and exclude rules, and
+    /// unprefixed lines are excludes.
+    pub fn read_rules(path: &Path) -> Result<Vec<FilterRule>> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read filter file: {:?}", path))?;
+        Ok(contents
+            .lines()
+            .map(|line| line.trim_end_matches('\r'))
+            .filter(|line| !line.trim().is_empty() && !line.starts_with(['#', ';']))
+            .map(|line| {
+                if let Some(pattern) = line.strip_prefix("+ ") {
+                    FilterRule::include(pattern)
+                } else if let Some(pattern) = line.strip_prefix("- ") {
+                    FilterRule::exclude(pattern)
+                } else {
+                    FilterRule::exclude(line)
+                }
+            })
+            .collect())
+    }
+
+    /// Whether `path`, relative to the sync root, is excluded from the sync.
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(path, is_dir))
+            .is_some_and(|rule| rule.action == FilterAction::Exclude)
+    }
+}
+
+/// Match `text` against a glob where `*` stops at `/` and `**` doesn't.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(b'*') if pattern.get(1) == Some(&b'*') => {
+            let rest = &pattern[2..];
+            (0..=text.len()).any(|i| glob_match(rest, &text[i..]))
+        }
+        Some(b'*') => {
+            let rest = &pattern[1..];
+            let segment = text.iter().position(|&b| b == b'/').unwrap_or(text.len());
+            (0..=segment).any(|i| glob_match(rest, &text[i..]))
+        }
+        Some(b'?') => {
+            text.first().is_some_and(|&b| b != b'/') && glob_match(&pattern[1..], &text[1..])
+        }
+        Some(b'[') => match (
+            class_match(&pattern[1..], text.first().copied()),
+            text.first(),
+        ) {
+            (Some((true, len)), Some(_)) => glob_match(&pattern[1 + len..], &text[1..]),
+            (None, Some(&b'[')) => glob_match(&pattern[1..], &text[1..]),
+            _ => false,
+        },
+        Some(b'\\') if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && glob_match(&pattern[2..], &text[1..])
+        }
+        Some(&c) => text.first() == Some(&c) && glob_match(&pattern[1..], &text[1..]),
+    }
+}
+
+/// Match a byte against the character class starting after `[`, returning whether it matched
+/// and the class length including the closing `]`, or `None` if the class is unterminated.
+fn class_match(class: &[u8], byte: Option<u8>) -> Option<(bool, usize)> {
+    let (negated, start) = match class.first() {
+        Some(b'!') | Some(b'^') => (true, 1),
+        _ => (false, 0),
+    };
+    let mut i = start;
+    let mut matched = false;
+    while i < class.len() {
+        let c = class[i];
+        if c == b']' && i > start {
+            let hit = byte.is_some_and(|b| b != b'/') && matched != negated;
+            return Some((hit, i + 1));
+        }
+        if i + 2 < class.len() && class[i + 1] == b'-' && class[i + 2] != b']' {
+            matched |= byte.is_some_and(|b| (c..=class[i + 2]).contains(&b));
+            i += 3;
+        } else {
+            matched |= byte == Some(c);
+            i += 1;
+        }
+    }
+    None
+}
@@ -0,0 +1,416 @@
+use crate::delta::{DeltaOp, Signature};
+use crate::error::{IoContext, Result, SyncError};
+use crate::events::SyncEvent;
+use crate::options::impl_option_builders;
+use crate::rdiff::read_full;
+use crate::snapshot::days_from_civil;
+use crate::sync::{FileAction, Syncer, TransferResult};
+use filetime::FileTime;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::{info, instrument};
+
+/// Extension appended to a source URL to find its published signature, as written by
+/// `rsynx signature`.
+pub const SIGNATURE_EXTENSION: &str = ".sig";
+
+/// Size of the literal instructions a whole-file download is split into.
+const DOWNLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Whether `spec` names an `http://` or `https://` source.
+pub fn is_http_url(spec: &str) -> bool {
+    spec.starts_with("http://") || spec.starts_with("https://")
+}
+
+/// Parse an HTTP date such as `Sun, 06 Nov 1994 08:49:37 GMT` (RFC 9110's IMF-fixdate, the
+/// only form servers may send) into Unix seconds.
+pub fn parse_http_date(date: &str) -> Option<i64> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let mut parts = date.split_whitespace().skip(1);
+    let day: u32 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|&name| name == month)? as u32 + 1;
+    let year: i64 = parts.next()?.parse().ok()?;
+    let mut time = parts
+        .next()?
+        .split(':')
+        .map(|part| part.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if parts.next() != Some("GMT") || !(1..=31).contains(&day) {
+        return None;
+    }
+    Some(days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+/// Size and modification time of the source, from a `HEAD` request.
+struct RemoteFile {
+    len: u64,
+    modified: Option<i64>,
+    /// Whether the server announced `Accept-Ranges: bytes`.
+    ranges: bool,
+}
+
+/// Downloads a file from a plain web server into a local destination, fetching only the
+/// parts the destination doesn't already have.
+///
+/// The server needs no rsynx support: the file's publisher runs `rsynx signature` and puts
+/// the signature next to it, at the file's URL plus `SIGNATURE_EXTENSION` unless another URL
+/// is given. The existing destination is then searched for the blocks the signature lists,
+/// and only the missing ones are requested, with one `Range` request per run of adjacent
+/// blocks. Every downloaded block is checked against the signature before it's written.
+/// Without a signature, a destination, or range support on the server, the whole file is
+/// downloaded. A destination of the same size and modification time as the server's
+/// `Last-Modified` is left alone; `preserve_metadata` sets it so on download.
+#[derive(Clone)]
+pub struct HttpSyncer {
+    syncer: Syncer,
+    url: String,
+    destination: PathBuf,
+    signature_url: Option<String>,
+}
+
+impl_option_builders!(HttpSyncer);
+
+impl HttpSyncer {
+    pub fn new(url: impl Into<String>, destination: impl Into<PathBuf>) -> Self {
+        Self {
+            syncer: Syncer::new(),
+            url: url.into(),
+            destination: destination.into(),
+            signature_url: None,
+        }
+    }
+
+    /// Fetch the signature from `url` instead of the source URL plus `SIGNATURE_EXTENSION`.
+    pub fn with_signature_url(mut self, url: impl Into<String>) -> Self {
+        self.signature_url = Some(url.into());
+        self
+    }
+
+    /// Reject invalid options before connecting.
+    pub fn validate(&self) -> Result<(), SyncError> {
+        self.syncer.validate()?;
+        if self.url.is_empty() {
+            return Err(SyncError::EmptyPath("Source"));
+        }
+        if self.destination.as_os_str().is_empty() {
+            return Err(SyncError::EmptyPath("Destination"));
+        }
+        if !is_http_url(&self.url) {
+            return Err(SyncError::UnsupportedSource {
+                path: PathBuf::from(&self.url),
+                reason: "only http:// and https:// URLs can be downloaded",
+            });
+        }
+        Ok(())
+    }
+
+    #[instrument(name = "http_sync", skip_all, fields(url = %self.url, destination = ?self.destination))]
+    pub fn sync(&self) -> Result<TransferResult> {
+        self.validate()?;
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(Duration::from_secs(30))
+            .build();
+        let dst_path = self.destination_file()?;
+        let remote = self.head(&agent)?;
+        if self.is_unchanged(&remote, &dst_path)? {
+            info!("Skipping unchanged file: {:?}", dst_path);
+            self.syncer.emit(|| SyncEvent::FileSkipped {
+                path: dst_path.clone(),
+            });
+            return Ok(TransferResult::for_file(
+                &dst_path,
+                FileAction::Skipped,
+                0,
+                remote.len as usize,
+            ));
+        }
+
+        self.syncer.emit(|| SyncEvent::FileStarted {
+            path: dst_path.clone(),
+            size: remote.len,
+        });
+        let existed = dst_path.is_file();
+        let signature = match existed && remote.ranges {
+            true => self.signature(&agent, remote.len)?,
+            false => None,
+        };
+        let synced = match &signature {
+            Some(signature) => self.fetch_blocks(&agent, signature, &dst_path),
+            None => self.fetch_whole(&agent, &dst_path),
+        };
+        let transfer = match synced {
+            Ok(transfer) => transfer,
+            Err(e) => {
+                self.syncer.emit(|| SyncEvent::Error {
+                    path: dst_path.clone(),
+                    message: e.to_string(),
+                });
+                return Err(e);
+            }
+        };
+        if let Some(modified) = remote
+            .modified
+            .filter(|_| self.syncer.options.preserve_metadata)
+        {
+            filetime::set_file_mtime(&dst_path, FileTime::from_unix_time(modified, 0))
+                .with_context(|| {
+                    format!("Failed to set the modification time of {:?}", dst_path)
+                })?;
+        }
+        let action = if existed {
+            FileAction::Updated
+        } else {
+            FileAction::Created
+        };
+        let result =
+            TransferResult::for_file(&dst_path, action, transfer.new_bytes, transfer.reused_bytes);
+        for record in &result.files {
+            self.syncer
+                .emit(|| SyncEvent::FileCompleted(record.clone()));
+        }
+        info!("HTTP download completed");
+        Ok(result)
+    }
+
+    /// The file to write: the destination itself, or the URL's last path segment inside it
+    /// when it's a directory.
+    fn destination_file(&self) -> Result<PathBuf> {
+        if !self.destination.is_dir() {
+            return Ok(self.destination.clone());
+        }
+        let name = self
+            .url
+            .split(['?', '#'])
+            .next()
+            .and_then(|url| url.split_once("://"))
+            .and_then(|(_, rest)| rest.split_once('/'))
+            .and_then(|(_, path)| path.rsplit('/').next())
+            .filter(|name| !name.is_empty());
+        match name {
+            Some(name) => Ok(self.destination.join(name)),
+            None => Err(SyncError::UnsupportedSource {
+                path: PathBuf::from(&self.url),
+                reason: "the URL names no file to create in the destination directory",
+            }),
+        }
+    }
+
+    fn error(&self, url: &str, e: ureq::Error) -> SyncError {
+        match e {
+            ureq::Error::Status(status, response) => SyncError::Refused(format!(
+                "{} returned {} {}",
+                url,
+                status,
+                response.status_text()
+            )),
+            e => SyncError::Connect {
+                peer: url.to_string(),
+                source: io::Error::other(e.to_string()),
+            },
+        }
+    }
+
+    fn head(&self, agent: &ureq::Agent) -> Result<RemoteFile> {
+        let response = agent
+            .head(&self.url)
+            .call()
+            .map_err(|e| self.error(&self.url, e))?;
+        let len = response
+            .header("Content-Length")
+            .and_then(|len| len.parse().ok())
+            .ok_or_else(|| {
+                SyncError::Protocol(format!("{} didn't report the file's size", self.url))
+            })?;
+        Ok(RemoteFile {
+            len,
+            modified: response.header("Last-Modified").and_then(parse_http_date),
+            ranges: response
+                .header("Accept-Ranges")
+                .is_some_and(|ranges| ranges.eq_ignore_ascii_case("bytes")),
+        })
+    }
+
+    /// The quick check: same size, and a modification time matching `Last-Modified`.
+    fn is_unchanged(&self, remote: &RemoteFile, dst_path: &Path) -> Result<bool> {
+        let options = &self.syncer.options;
+        let Ok(meta) = fs::metadata(dst_path) else {
+            return Ok(false);
+        };
+        if !meta.is_file() || meta.len() != remote.len || options.checksum || options.ignore_times {
+            return Ok(false);
+        }
+        let local = FileTime::from_last_modification_time(&meta);
+        Ok(remote.modified.is_some_and(|modified| {
+            self.syncer
+                .mtimes_match(local, FileTime::from_unix_time(modified, 0))
+        }))
+    }
+
+    /// The published signature of the source, or `None` if there's none or it describes a
+    /// file of another size, being stale.
+    fn signature(&self, agent: &ureq::Agent, len: u64) -> Result<Option<Signature>> {
+        let url = self
+            .signature_url
+            .clone()
+            .unwrap_or_else(|| format!("{}{}", self.url, SIGNATURE_EXTENSION));
+        let response = match agent.get(&url).call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(404, _)) => {
+                info!("No signature at {}; downloading the whole file", url);
+                return Ok(None);
+            }
+            Err(e) => return Err(self.error(&url, e)),
+        };
+        let signature = Signature::read_from(response.into_reader())?;
+        let described: u64 = signature.blocks.iter().map(|block| block.size as u64).sum();
+        if described != len {
+            info!("Signature at {} is stale; downloading the whole file", url);
+            return Ok(None);
+        }
+        Ok(Some(signature))
+    }
+
+    fn fetch_whole(&self, agent: &ureq::Agent, dst_path: &Path) -> Result<TransferResult> {
+        let mut reader = agent
+            .get(&self.url)
+            .call()
+            .map_err(|e| self.error(&self.url, e))?
+            .into_reader();
+        self.syncer.apply_ops(dst_path, dst_path, || {
+            let mut chunk = vec![0u8; DOWNLOAD_CHUNK_SIZE];
+            let len = read_full(&mut reader, &mut chunk)
+                .with_context(|| format!("Failed to download {}", self.url))?;
+            chunk.truncate(len);
+            Ok((len > 0).then_some(DeltaOp::Literal(chunk)))
+        })
+    }
+
+    /// Rebuild `dst_path` from its own blocks and ranges of the source, following `signature`.
+    fn fetch_blocks(
+        &self,
+        agent: &ureq::Agent,
+        signature: &Signature,
+        dst_path: &Path,
+    ) -> Result<TransferResult> {
+        let local = self.local_blocks(signature, dst_path)?;
+        let sources: Vec<Option<u64>> = signature
+            .blocks
+            .iter()
+            .map(|block| local.get(&(block.strong_checksum, block.size)).copied())
+            .collect();
+        let missing = sources.iter().filter(|source| source.is_none()).count();
+        info!(
+            "{} of {} blocks found locally",
+            signature.blocks.len() - missing,
+            signature.blocks.len()
+        );
+
+        let mut index = 0;
+        let mut range: Option<Box<dyn Read>> = None;
+        self.syncer.apply_ops(dst_path, dst_path, || {
+            let Some(block) = signature.blocks.get(index) else {
+                return Ok(None);
+            };
+            index += 1;
+            if let Some(offset) = sources[index - 1] {
+                range = None;
+                return Ok(Some(DeltaOp::Copy {
+                    offset,
+                    len: block.size,
+                }));
+            }
+            if range.is_none() {
+                let run = sources[index - 1..]
+                    .iter()
+                    .take_while(|source| source.is_none())
+                    .count();
+                let end = signature.blocks[index - 2 + run].offset
+                    + signature.blocks[index - 2 + run].size as u64;
+                range = Some(self.fetch_range(agent, block.offset, end)?);
+            }
+            let mut data = vec![0u8; block.size];
+            range
+                .as_mut()
+                .expect("range requested above")
+                .read_exact(&mut data)
+                .with_context(|| format!("Failed to download {}", self.url))?;
+            let actual = signature.strong_hash.checksum(&data);
+            let len = signature.strong_len;
+            if actual[..len] != block.strong_checksum[..len] {
+                return Err(SyncError::ChecksumMismatch {
+                    path: PathBuf::from(format!("{} at offset {}", self.url, block.offset)),
+                    expected: hex::encode(&block.strong_checksum[..len]),
+                    actual: hex::encode(&actual[..len]),
+                });
+            }
+            Ok(Some(DeltaOp::Literal(data)))
+        })
+    }
+
+    /// Request bytes `start..end` of the source, refusing servers that answer with anything
+    /// else.
+    fn fetch_range(&self, agent: &ureq::Agent, start: u64, end: u64) -> Result<Box<dyn Read>> {
+        let response = agent
+            .get(&self.url)
+            .set("Range", &format!("bytes={}-{}", start, end - 1))
+            .call()
+            .map_err(|e| self.error(&self.url, e))?;
+        let expected = format!("bytes {}-{}/", start, end - 1);
+        if response.status() != 206
+            || !response
+                .header("Content-Range")
+                .is_some_and(|range| range.starts_with(&expected))
+        {
+            return Err(SyncError::Protocol(format!(
+                "{} ignored the range request for bytes {}-{}",
+                self.url,
+                start,
+                end - 1
+            )));
+        }
+        Ok(Box::new(response.into_reader()))
+    }
+
+    /// Where each block of `signature` can be found in the destination, by strong checksum
+    /// and size.
+    fn local_blocks(
+        &self,
+        signature: &Signature,
+        dst_path: &Path,
+    ) -> Result<HashMap<([u8; 32], usize), u64>> {
+        let file = File::open(dst_path)
+            .with_context(|| format!("Failed to open destination file: {:?}", dst_path))?;
+        let mut found = HashMap::new();
+        let mut position = 0u64;
+        self.syncer.stream_delta(signature, file, |op| {
+            match op {
+                DeltaOp::Literal(data) => position += data.len() as u64,
+                DeltaOp::Copy { offset, len } => {
+                    // Runs of adjacent blocks are merged into one copy
+                    let first = (offset / signature.block_size as u64) as usize;
+                    let mut copied = 0;
+                    for block in &signature.blocks[first..] {
+                        if copied >= len {
+                            break;
+                        }
+                        found
+                            .entry((block.strong_checksum, block.size))
+                            .or_insert(position + copied as u64);
+                        copied += block.size;
+                    }
+                    position += len as u64;
+                }
+            }
+            Ok(())
+        })?;
+        Ok(found)
+    }
+}
@@ -0,0 +1,107 @@
+use crate::events::SyncEvent;
+use crate::sync::{FileAction, FileRecord};
+use filetime::FileTime;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Turns sync events into rsync's `--itemize-changes` lines: an 11-character change code,
+/// then the path relative to the destination root. The code starts with `>` for a received
+/// file or `c` for a local change such as a new symlink, then `f` or `L` for the entry type;
+/// the remaining letters are `+` for a created entry, or `c` (content), `s` (size) and `t`
+/// (modification time) where those changed and `.` where they didn't. Deletions print as
+/// `*deleting`, and unchanged files print nothing.
+#[derive(Debug)]
+pub struct Itemizer {
+    root: PathBuf,
+    /// Whether destination paths can be inspected, i.e. the destination isn't remote.
+    local: bool,
+    /// Size and modification time of destinations about to be rewritten.
+    before: Mutex<HashMap<PathBuf, (u64, FileTime)>>,
+}
+
+impl Itemizer {
+    /// Itemize a sync into the local path `root`.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            local: true,
+            before: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Itemize a sync into a remote `root`, whose old size and time can't be compared; updated
+    /// files only report whether content was sent.
+    pub fn remote(root: impl Into<PathBuf>) -> Self {
+        Self {
+            local: false,
+            ..Self::new(root)
+        }
+    }
+
+    /// The line to print for `event`, if it changed anything.
+    pub fn itemize(&self, event: &SyncEvent) -> Option<String> {
+        match event {
+            SyncEvent::FileStarted { path, .. } if self.local => {
+                if let Some(stamp) = stamp(path) {
+                    self.before.lock().unwrap().insert(path.clone(), stamp);
+                }
+                None
+            }
+            SyncEvent::FileCompleted(record) => {
+                let before = self.before.lock().unwrap().remove(&record.path);
+                let code = self.change_code(record, before)?;
+                Some(format!("{} {}", code, self.display_path(&record.path)))
+            }
+            SyncEvent::Deleted { path } => Some(format!("*deleting   {}", self.display_path(path))),
+            _ => None,
+        }
+    }
+
+    fn change_code(&self, record: &FileRecord, before: Option<(u64, FileTime)>) -> Option<String> {
+        let is_link = self.local
+            && fs::symlink_metadata(&record.path).is_ok_and(|meta| meta.file_type().is_symlink());
+        let (update, kind) = if is_link { ('c', 'L') } else { ('>', 'f') };
+        let flags = match record.action {
+            FileAction::Created => "+++++++++".to_string(),
+            FileAction::Updated => {
+                let after = if is_link { None } else { stamp(&record.path) };
+                let (size, time) = match (before, after) {
+                    (Some(before), Some(after)) => (
+                        if before.0 != after.0 { 's' } else { '.' },
+                        if before.1 != after.1 { 't' } else { '.' },
+                    ),
+                    _ => ('.', '.'),
+                };
+                let content = if record.literal_bytes > 0 || is_link {
+                    'c'
+                } else {
+                    '.'
+                };
+                format!("{}{}{}......", content, size, time)
+            }
+            FileAction::Skipped | FileAction::Deleted => return None,
+        };
+        Some(format!("{}{}{}", update, kind, flags))
+    }
+
+    /// `path` relative to the root, or its file name when the root is the file itself.
+    fn display_path(&self, path: &Path) -> String {
+        match path.strip_prefix(&self.root) {
+            Ok(relative) if !relative.as_os_str().is_empty() => relative.display().to_string(),
+            _ => path.file_name().map_or_else(
+                || path.display().to_string(),
+                |name| name.to_string_lossy().into_owned(),
+            ),
+        }
+    }
+}
+
+/// Size and modification time of the file at `path`, if there is one.
+fn stamp(path: &Path) -> Option<(u64, FileTime)> {
+    let meta = fs::symlink_metadata(path).ok()?;
+    Some((meta.len(), FileTime::from_last_modification_time(&meta)))
+}
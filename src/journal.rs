@@ -0,0 +1,203 @@
+use crate::error::{IoContext, Result, SyncError};
+use crate::pathname;
+use crate::sync::{FileAction, Syncer};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// One operation a sync performed on the destination, as a line of the journal.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Seconds since the Unix epoch.
+    pub time: u64,
+    /// `Created`, `Updated` or `Deleted`.
+    pub action: FileAction,
+    /// Absolute destination path, written by `pathname::escape`.
+    #[serde(with = "pathname::escaped")]
+    pub path: PathBuf,
+    /// Size of the file written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+    /// SHA-256 of the file before it was overwritten or deleted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_sha256: Option<String>,
+    /// SHA-256 of the file written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_sha256: Option<String>,
+    /// Where the previous content was kept: the `backup_dir` copy of an overwritten file, or
+    /// the `delete_to` location of a deleted entry.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "pathname::escaped::option"
+    )]
+    pub backup: Option<PathBuf>,
+}
+
+impl JournalEntry {
+    /// An entry for `path`, made absolute, timestamped now.
+    pub fn new(action: FileAction, path: &Path) -> Self {
+        Self {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            action,
+            path: std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()),
+            bytes: None,
+            old_sha256: None,
+            new_sha256: None,
+            backup: None,
+        }
+    }
+}
+
+/// Append-only journal of JSON lines shared by every clone, so all the files of a sync are
+/// recorded in the order they were handled.
+#[derive(Debug, Clone)]
+pub struct Journal(Arc<Mutex<File>>);
+
+impl Journal {
+    /// Open `path` for appending, creating it if needed.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open journal: {:?}", path))?;
+        Ok(Self(Arc::new(Mutex::new(file))))
+    }
+
+    /// Append `entry` and flush it to disk.
+    pub fn record(&self, entry: &JournalEntry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry)
+            .map_err(|e| SyncError::Format(format!("Failed to encode journal entry: {}", e)))?;
+        line.push(b'\n');
+        let mut file = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        file.write_all(&line)?;
+        file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Parse a journal. A torn last line, left by a crash mid-write, is ignored.
+pub fn read(reader: impl Read) -> Result<Vec<JournalEntry>> {
+    let lines = BufReader::new(reader)
+        .lines()
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let mut entries = Vec::new();
+    for (number, line) in lines.iter().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(entry) => entries.push(entry),
+            Err(_) if number + 1 == lines.len() => break,
+            Err(e) => {
+                return Err(SyncError::Format(format!(
+                    "Invalid journal line {}: {}",
+                    number + 1,
+                    e
+                )));
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// What `undo` did with one journal entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UndoOutcome {
+    Reverted(PathBuf),
+    /// The entry can't be reverted safely, for the reason given.
+    Skipped {
+        path: PathBuf,
+        reason: String,
+    },
+}
+
+impl fmt::Display for UndoOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UndoOutcome::Reverted(path) => write!(f, "reverted {}", path.display()),
+            UndoOutcome::Skipped { path, reason } => {
+                write!(f, "can't revert {}: {}", path.display(), reason)
+            }
+        }
+    }
+}
+
+/// Revert the operations in `entries`, newest first: created files are removed, and
+/// overwritten or deleted ones are restored from their backups. An entry is skipped when
+/// the file changed since, or its backup is missing or doesn't hold the old content.
+pub fn undo(entries: &[JournalEntry]) -> Vec<UndoOutcome> {
+    entries
+        .iter()
+        .rev()
+        .filter(|entry| entry.action != FileAction::Skipped)
+        .map(|entry| match undo_entry(entry) {
+            Ok(()) => UndoOutcome::Reverted(entry.path.clone()),
+            Err(reason) => UndoOutcome::Skipped {
+                path: entry.path.clone(),
+                reason,
+            },
+        })
+        .collect()
+}
+
+fn undo_entry(entry: &JournalEntry) -> std::result::Result<(), String> {
+    let path = entry.path.as_path();
+    let exists = fs::symlink_metadata(path).is_ok();
+    if entry.action == FileAction::Deleted {
+        if exists {
+            return Err("it exists again".to_string());
+        }
+    } else if !exists {
+        return Err("it no longer exists".to_string());
+    } else if entry.new_sha256.is_some() && hash(path) != entry.new_sha256 {
+        return Err("it changed since".to_string());
+    }
+    if entry.action == FileAction::Created {
+        return fs::remove_file(path).map_err(|e| e.to_string());
+    }
+
+    let backup = entry
+        .backup
+        .as_deref()
+        .ok_or_else(|| "no backup was kept".to_string())?;
+    if fs::symlink_metadata(backup).is_err() {
+        return Err(format!("its backup {} is gone", backup.display()));
+    }
+    if entry.old_sha256.is_some() && hash(backup) != entry.old_sha256 {
+        return Err(format!("its backup {} changed since", backup.display()));
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    if entry.action == FileAction::Deleted {
+        // Deleted entries were moved away whole, so move them back
+        return fs::rename(backup, path).map_err(|e| e.to_string());
+    }
+    let staged = path.with_extension("tmp");
+    fs::copy(backup, &staged)
+        .and_then(|_| fs::rename(&staged, path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&staged);
+            e.to_string()
+        })
+}
+
+fn hash(path: &Path) -> Option<String> {
+    if !fs::symlink_metadata(path).is_ok_and(|meta| meta.is_file()) {
+        return None;
+    }
+    Syncer::new()
+        .calculate_file_checksum(path)
+        .ok()
+        .map(hex::encode)
+}
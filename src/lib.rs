@@ -1,3 +1,106 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "async")]
+pub mod async_sync;
+#[cfg(feature = "std")]
+pub mod batch;
+#[cfg(feature = "std")]
+pub mod checkpoint;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod conflict;
+pub mod core;
+#[cfg(feature = "std")]
+pub mod daemon;
+#[cfg(feature = "std")]
+pub mod dedup;
+#[cfg(feature = "std")]
+pub mod delta;
+#[cfg(feature = "std")]
+pub mod diff;
+#[cfg(feature = "std")]
+pub mod direct;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+#[cfg(feature = "std")]
+pub mod error;
+#[cfg(feature = "std")]
+pub mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "std")]
+pub mod filter;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "std")]
+pub mod itemize;
+#[cfg(feature = "std")]
+pub mod journal;
+#[cfg(feature = "std")]
 pub mod local_sync;
+#[cfg(feature = "std")]
+pub mod lock;
+#[cfg(feature = "std")]
+pub mod manifest;
+#[cfg(feature = "std")]
+pub mod metrics;
+#[cfg(feature = "std")]
 pub mod network_sync;
+#[cfg(feature = "std")]
+pub mod notify;
+#[cfg(feature = "std")]
+pub mod options;
+#[cfg(feature = "std")]
+pub mod pathname;
+#[cfg(feature = "std")]
+pub mod plan;
+#[cfg(feature = "std")]
+pub mod probe;
+#[cfg(feature = "std")]
+pub mod progress;
+#[cfg(feature = "std")]
+pub mod protocol;
+#[cfg(feature = "std")]
+pub mod rdiff;
+#[cfg(feature = "std")]
+pub mod remote;
+#[cfg(feature = "s3")]
+pub mod s3;
+#[cfg(feature = "std")]
+pub mod schedule;
+#[cfg(feature = "sftp")]
+pub mod sftp;
+#[cfg(feature = "std")]
+pub mod snapshot;
+#[cfg(feature = "std")]
+pub mod store;
+#[cfg(feature = "std")]
 pub mod sync;
+#[cfg(feature = "std")]
+pub mod throttle;
+#[cfg(feature = "std")]
+pub mod transport;
+#[cfg(feature = "webdav")]
+pub mod webdav;
+
+/// Configured syncers are shared across threads and cloned per worker; keep them that way.
+#[cfg(feature = "std")]
+const _: () = {
+    const fn shareable<T: Send + Sync + Clone>() {}
+    shareable::<sync::Syncer>();
+    shareable::<options::SyncOptions>();
+    shareable::<local_sync::LocalSyncer>();
+    shareable::<network_sync::NetworkSyncer>();
+    shareable::<store::StoreSyncer>();
+    #[cfg(feature = "http")]
+    shareable::<http::HttpSyncer>();
+    #[cfg(feature = "s3")]
+    shareable::<s3::S3Syncer>();
+    #[cfg(feature = "sftp")]
+    shareable::<sftp::SftpSyncer>();
+    #[cfg(feature = "webdav")]
+    shareable::<webdav::WebDavSyncer>();
+};
@@ -1,181 +1,680 @@
-use crate::sync::{Block, Syncer, TransferResult};
-use anyhow::Context;
-use anyhow::Result;
+use crate::checkpoint::{CHECKPOINT_FILE_NAME, Checkpoint, SourceStamp};
+use crate::conflict::{
+    ConflictPolicy, STATE_FILE_NAME, SyncState, conflict_path, is_conflict_copy,
+};
+use crate::dedup;
+use crate::delta::{BlockIndex, BlockMatcher, DeltaOp, Signature, WeakHit, WeakScanner};
+use crate::direct;
+use crate::error::{IoContext, Result, SyncError};
+use crate::events::{SyncEvent, SyncEvents};
+use crate::journal::JournalEntry;
+use crate::lock::{DestinationLock, LOCK_FILE_NAME};
+use crate::options::impl_option_builders;
+use crate::plan::{PlanAction, PlanReason, PlannedOp, TransferOrder};
+use crate::progress::{Phase, Progress, ProgressReporter};
+use crate::sync::{FileAction, Syncer, TransferResult, preallocate};
+use crate::throttle::RateLimiter;
 use filetime::{FileTime, set_file_times};
-use indicatif::{ProgressBar, ProgressStyle};
-use log::info;
 use memmap2::MmapMut;
-use std::cmp::min;
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
 use std::fs::OpenOptions;
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
+    ffi::{OsStr, OsString},
     fs::{self, File},
-    io::{Read, Seek, SeekFrom},
-    path::Path,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Component, Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::SystemTime,
 };
+use tracing::{field, info, instrument, warn};
+use walkdir::WalkDir;
+
+/// Number of chunks or instructions buffered between reconstruction pipeline stages.
+const PIPELINE_DEPTH: usize = 16;
+
+/// Size of the source chunks flowing through the reconstruction pipeline.
+const PIPELINE_CHUNK_SIZE: usize = 256 * 1024;
+
+/// How much reconstructed data accumulates between progress records in the checkpoint.
+const CHECKPOINT_INTERVAL: usize = 64 * 1024 * 1024;
+
+/// Bytes a `direct_io` reconstruction writes between dropping them from the page cache.
+const RELEASE_INTERVAL: usize = 64 * 1024 * 1024;
+
+/// Suffix of partial files kept next to their destination.
+const PARTIAL_SUFFIX: &str = ".partial";
+
+/// Directory in the destination root where an atomic sync writes files until all are ready.
+pub const STAGING_DIR_NAME: &str = ".rsynx-staging";
+
+/// Checkpoint entry of the file currently being reconstructed.
+struct CheckpointedFile<'a> {
+    checkpoint: &'a mut Checkpoint,
+    key: PathBuf,
+    stamp: SourceStamp,
+}
+
+/// Signature of a destination file computed on a background thread, so hashing the next file
+/// of a directory overlaps the transfer of the current one.
+struct PrefetchedSignature {
+    basis: PathBuf,
+    /// Size and modification time of `basis` when hashing started.
+    stamp: (u64, SystemTime),
+    handle: thread::JoinHandle<Result<Signature>>,
+}
+
+impl PrefetchedSignature {
+    /// The signature, unless computing it failed or `basis` changed since it started.
+    fn finish(self) -> Option<Signature> {
+        let signature = self.handle.join().ok()?.ok()?;
+        (basis_stamp(&self.basis) == Some(self.stamp)).then_some(signature)
+    }
+}
+
+/// Keeps one signature prefetched ahead of a sequence of transfers.
+#[derive(Default)]
+struct Prefetcher {
+    pending: Option<PrefetchedSignature>,
+    /// Items before this one were considered already.
+    lookahead: usize,
+}
+
+impl Prefetcher {
+    /// The pending signature if it's the one for `basis`.
+    fn take(&mut self, basis: &Path) -> Option<PrefetchedSignature> {
+        match self.pending.take() {
+            Some(pending) if pending.basis == basis => Some(pending),
+            other => {
+                self.pending = other;
+                None
+            }
+        }
+    }
+
+    /// Unless a signature is pending, start one with `start` for the first of `items` from
+    /// index `from` it accepts. No item is considered twice.
+    fn advance<T>(
+        &mut self,
+        items: &[T],
+        from: usize,
+        mut start: impl FnMut(&T) -> Option<PrefetchedSignature>,
+    ) {
+        self.lookahead = self.lookahead.max(from);
+        while self.pending.is_none() && self.lookahead < items.len() {
+            self.pending = start(&items[self.lookahead]);
+            self.lookahead += 1;
+        }
+    }
+}
+
+fn basis_stamp(path: &Path) -> Option<(u64, SystemTime)> {
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.len(), meta.modified().ok()?))
+}
 
 /// LocalSyncer implements local file/directory synchronization using shared Syncer functionality.
+///
+/// It is `Send + Sync`, and cloning it only copies its paths and bumps reference counts, so one
+/// configured syncer can be shared across threads or reused via `for_paths`.
+#[derive(Clone)]
 pub struct LocalSyncer {
-    syncer: Syncer,
-    source: String,
-    destination: String,
+    pub(crate) syncer: Syncer,
+    pub(crate) source: PathBuf,
+    pub(crate) destination: PathBuf,
+    pub(crate) temp_dir: Option<PathBuf>,
 }
 
+impl_option_builders!(LocalSyncer);
+
 impl LocalSyncer {
-    pub fn new(source: String, destination: String) -> Self {
+    pub fn new(source: impl Into<PathBuf>, destination: impl Into<PathBuf>) -> Self {
         Self {
             syncer: Syncer::new(),
-            source,
-            destination,
+            source: source.into(),
+            destination: destination.into(),
+            temp_dir: None,
         }
     }
 
-    pub fn with_block_size(mut self, block_size: usize) -> Self {
-        self.syncer.block_size = block_size;
-        self
+    /// A syncer with the same options and temp directory for another source and destination.
+    pub fn for_paths(&self, source: impl Into<PathBuf>, destination: impl Into<PathBuf>) -> Self {
+        Self {
+            source: source.into(),
+            destination: destination.into(),
+            ..self.clone()
+        }
     }
 
-    pub fn with_preserve_metadata(mut self, preserve: bool) -> Self {
-        self.syncer.preserve_metadata = preserve;
+    /// Reconstruct files in `dir` instead of next to their destination. Finished files are
+    /// still moved into place atomically; across filesystems they are first copied beside the
+    /// destination.
+    pub fn with_temp_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.temp_dir = Some(dir.into());
         self
     }
 
-    pub fn with_delete_extraneous(mut self, delete: bool) -> Self {
-        self.syncer.delete_extraneous = delete;
-        self
+    /// Reject invalid or contradictory options before touching the filesystem.
+    pub fn validate(&self) -> Result<(), SyncError> {
+        self.syncer.validate()?;
+        if self.source.as_os_str().is_empty() {
+            return Err(SyncError::EmptyPath("Source"));
+        }
+        if self.destination.as_os_str().is_empty() {
+            return Err(SyncError::EmptyPath("Destination"));
+        }
+        if let Some(dir) = self.temp_dir.as_ref().filter(|dir| !dir.is_dir()) {
+            return Err(SyncError::ConflictingOptions(format!(
+                "temporary directory {:?} does not exist",
+                dir
+            )));
+        }
+        if self.syncer.options.checkpoint && self.source.is_file() {
+            return Err(SyncError::ConflictingOptions(
+                "checkpointing only applies to directory syncs".to_string(),
+            ));
+        }
+        if self.syncer.options.conflicts.is_some() && self.source.is_file() {
+            return Err(SyncError::ConflictingOptions(
+                "conflict tracking only applies to directory syncs".to_string(),
+            ));
+        }
+        if self.syncer.options.atomic
+            && (self.syncer.options.checkpoint || self.syncer.options.conflicts.is_some())
+        {
+            return Err(SyncError::ConflictingOptions(
+                "atomic syncs can't be checkpointed or track conflicts".to_string(),
+            ));
+        }
+        if self.syncer.options.order != TransferOrder::AsFound
+            && (self.syncer.options.checkpoint || self.syncer.options.conflicts.is_some())
+        {
+            return Err(SyncError::ConflictingOptions(
+                "ordered syncs can't be checkpointed or track conflicts".to_string(),
+            ));
+        }
+        Ok(())
     }
 
-    pub fn with_compression(mut self, compress: bool) -> Self {
-        self.syncer.compress = compress;
-        self
+    /// Run the sync on a background thread, yielding its events as they happen in place of any
+    /// `on_event` callback. Call `SyncEvents::finish` afterwards for the final result.
+    pub fn sync_events(&self) -> SyncEvents {
+        let mut syncer = self.clone();
+        SyncEvents::spawn(move |callback| {
+            syncer.syncer.options.events = Some(callback);
+            syncer.sync()
+        })
     }
 
+    #[instrument(
+        name = "local_sync",
+        skip_all,
+        fields(
+            source = ?self.source,
+            destination = ?self.destination,
+            new_bytes = field::Empty,
+            reused_bytes = field::Empty,
+            reuse_ratio = field::Empty,
+        ),
+    )]
     pub fn sync(&self) -> Result<TransferResult> {
+        self.validate()?;
         info!("Local syncing...");
-        let src_path = Path::new(&self.source);
-        let dst_path = Path::new(&self.destination);
+        let src_path = self.source.as_path();
+        let dst_path = self.destination.as_path();
         let result = if src_path.is_file() {
-            self.sync_file(src_path, dst_path)?
+            let _lock = DestinationLock::acquire(dst_path)?;
+            self.sync_file(src_path, dst_path, None, None)?
         } else if src_path.is_dir() {
-            self.sync_dir(src_path, dst_path)?
+            fs::create_dir_all(dst_path)?;
+            let _lock = DestinationLock::acquire(dst_path)?;
+            let mut checkpoint = match self.syncer.options.checkpoint {
+                true => Some(Checkpoint::open(dst_path)?),
+                false => None,
+            };
+            let mut state = match self.syncer.options.conflicts {
+                Some(_) => Some(SyncState::load(dst_path)?),
+                None => None,
+            };
+            let mut deletions = Vec::new();
+            let synced = if self.syncer.options.order == TransferOrder::AsFound {
+                self.sync_dir(src_path, dst_path, checkpoint.as_mut(), state.as_mut())
+            } else {
+                self.plan()
+                    .and_then(|plan| self.execute_ops(&plan, &mut deletions))
+            };
+            // Files written before a failure are recorded too, so they aren't taken for
+            // conflicts next time
+            if let Some(state) = &state {
+                state.save()?;
+            }
+            let mut result = self.finish_staged(synced, &deletions)?;
+            if let Some(checkpoint) = checkpoint {
+                checkpoint.finish()?;
+            }
+            if self.syncer.options.dedup {
+                let report = dedup::link_duplicates(dst_path, &self.syncer)?;
+                result.deduplicated_files = report.linked_files;
+                result.reclaimed_bytes = report.reclaimed_bytes;
+            }
+            result
         } else {
-            return Err(anyhow::anyhow!("Unsupported source type"));
+            return Err(SyncError::UnsupportedSource {
+                path: src_path.to_path_buf(),
+                reason: "only regular files and directories can be synced",
+            });
         };
+        result.record_in_span();
         info!("Local sync completed");
         Ok(result)
     }
 
-    fn sync_file(&self, src_path: &Path, dst_path: &Path) -> Result<TransferResult> {
+    /// Work out what `sync` would do, in the order it would do it, without touching the
+    /// destination. Checkpoints from earlier runs are not consulted.
+    pub fn plan(&self) -> Result<Vec<PlannedOp>> {
+        self.validate()?;
+        let src_path = self.source.as_path();
+        let dst_path = self.destination.as_path();
+        let mut plan = Vec::new();
+        if src_path.is_file() {
+            let (action, reason) = if dst_path.exists() {
+                (PlanAction::Update, PlanReason::FileSource)
+            } else {
+                (PlanAction::Create, PlanReason::Missing)
+            };
+            plan.push(PlannedOp {
+                action,
+                source: Some(src_path.to_path_buf()),
+                destination: dst_path.to_path_buf(),
+                size: fs::metadata(src_path)?.len(),
+                is_dir: false,
+                reason,
+            });
+        } else if src_path.is_dir() {
+            self.plan_dir(src_path, dst_path, &mut plan)?;
+            self.syncer.options.order.apply(&mut plan);
+        } else {
+            return Err(SyncError::UnsupportedSource {
+                path: src_path.to_path_buf(),
+                reason: "only regular files and directories can be synced",
+            });
+        }
+        Ok(plan)
+    }
+
+    fn plan_dir(&self, src_dir: &Path, dst_dir: &Path, plan: &mut Vec<PlannedOp>) -> Result<()> {
+        if !dst_dir.exists() {
+            plan.push(PlannedOp {
+                action: PlanAction::Create,
+                source: Some(src_dir.to_path_buf()),
+                destination: dst_dir.to_path_buf(),
+                size: 0,
+                is_dir: true,
+                reason: PlanReason::Missing,
+            });
+        }
+        let is_state_file = |name: &OsStr| {
+            name == LOCK_FILE_NAME
+                || name == STATE_FILE_NAME
+                || name == STAGING_DIR_NAME
+                || (self.syncer.options.checkpoint && name == CHECKPOINT_FILE_NAME)
+        };
+        let dst_names = self.destination_names(dst_dir)?;
+        let mut src_names = HashSet::new();
+        for entry in fs::read_dir(src_dir)? {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            if is_state_file(&file_name) {
+                continue;
+            }
+            src_names.insert(self.compared_name(&file_name));
+            let path = entry.path();
+            if self.is_filtered(&path, &self.source) {
+                continue;
+            }
+            let dest_path = dst_dir.join(self.destination_name(&file_name, &dst_names));
+            if self.is_preserved_link(&entry)? {
+                let reason = self.syncer.link_check(&path, &dest_path)?;
+                plan.push(PlannedOp {
+                    action: match reason {
+                        PlanReason::Unchanged => PlanAction::Skip,
+                        PlanReason::Missing => PlanAction::Create,
+                        _ => PlanAction::Update,
+                    },
+                    source: Some(path),
+                    destination: dest_path,
+                    size: 0,
+                    is_dir: false,
+                    reason,
+                });
+            } else if path.is_file() {
+                let reason = self.syncer.quick_check(&path, &dest_path)?;
+                let action = match reason {
+                    PlanReason::Unchanged => PlanAction::Skip,
+                    PlanReason::Missing => PlanAction::Create,
+                    _ => PlanAction::Update,
+                };
+                plan.push(PlannedOp {
+                    action,
+                    source: Some(path.clone()),
+                    destination: dest_path,
+                    size: fs::metadata(&path)?.len(),
+                    is_dir: false,
+                    reason,
+                });
+            } else if path.is_dir() && self.syncer.options.recursive {
+                self.plan_dir(&path, &dest_path, plan)?;
+            }
+        }
+        if self.syncer.options.delete_extraneous && dst_dir.is_dir() {
+            for entry in fs::read_dir(dst_dir)? {
+                let entry = entry?;
+                let file_name = self.compared_name(&entry.file_name());
+                if src_names.contains(&file_name)
+                    || is_state_file(&file_name)
+                    || self.is_conflict_entry(&file_name)
+                    || self.is_partial_entry(&file_name, &src_names)
+                {
+                    continue;
+                }
+                let path = entry.path();
+                if self.is_filtered(&path, &self.destination) || self.is_trash(&path) {
+                    continue;
+                }
+                let (size, is_dir) = if path.is_file() {
+                    (fs::metadata(&path)?.len(), false)
+                } else if path.is_dir() {
+                    (0, true)
+                } else if path.is_symlink() {
+                    (0, false)
+                } else {
+                    continue;
+                };
+                plan.push(PlannedOp {
+                    action: PlanAction::Delete,
+                    source: None,
+                    destination: path,
+                    size,
+                    is_dir,
+                    reason: PlanReason::Extraneous,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Carry out `plan` exactly as given, typically one returned by `plan` and approved by a
+    /// user. Every path must lie within this syncer's source and destination; the whole plan
+    /// is checked before anything is changed. Checkpointing doesn't apply.
+    pub fn execute(&self, plan: &[PlannedOp]) -> Result<TransferResult> {
+        self.validate()?;
+        for op in plan {
+            if !is_within(&op.destination, &self.destination) {
+                return Err(SyncError::PathOutsideRoot(op.destination.clone()));
+            }
+            match &op.source {
+                Some(source) if !is_within(source, &self.source) => {
+                    return Err(SyncError::PathOutsideRoot(source.clone()));
+                }
+                None if matches!(op.action, PlanAction::Create | PlanAction::Update) => {
+                    return Err(SyncError::ConflictingOptions(format!(
+                        "planned {:?} of {:?} has no source",
+                        op.action, op.destination
+                    )));
+                }
+                _ => {}
+            }
+        }
+
+        let mut deletions = Vec::new();
+        let executed = self.execute_ops(plan, &mut deletions);
+        self.finish_staged(executed, &deletions)
+    }
+
+    /// Carry out the checked `plan`. Atomic syncs stage files and leave the `deletions` to
+    /// `finish_staged`.
+    fn execute_ops(
+        &self,
+        plan: &[PlannedOp],
+        deletions: &mut Vec<PathBuf>,
+    ) -> Result<TransferResult> {
+        let mut result = TransferResult::default();
+        let mut prefetcher = Prefetcher::default();
+        for (i, op) in plan.iter().enumerate() {
+            self.syncer.check_cancelled()?;
+            let destination = op.destination.as_path();
+            match (op.action, &op.source) {
+                (PlanAction::Create | PlanAction::Update, _) if op.is_dir => {
+                    fs::create_dir_all(self.staged_path(destination))?;
+                }
+                (PlanAction::Create | PlanAction::Update, Some(source))
+                    if self.syncer.options.preserve_links && source.is_symlink() =>
+                {
+                    result.merge(self.sync_symlink(source, destination)?);
+                }
+                (PlanAction::Create | PlanAction::Update, Some(source)) => {
+                    if let Some(parent) = self.staged_path(destination).parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    let signature = prefetcher.take(destination);
+                    prefetcher.advance(plan, i + 1, |next| {
+                        let next_source = next.source.as_deref()?;
+                        let preserved_link =
+                            self.syncer.options.preserve_links && next_source.is_symlink();
+                        if next.action != PlanAction::Update || next.is_dir || preserved_link {
+                            return None;
+                        }
+                        self.prefetch_signature(next_source, &next.destination)
+                    });
+                    result.merge(self.sync_file(source, destination, None, signature)?);
+                }
+                (PlanAction::Skip, _) => {
+                    self.syncer.emit(|| SyncEvent::FileSkipped {
+                        path: destination.to_path_buf(),
+                    });
+                    result.merge(TransferResult::for_file(
+                        destination,
+                        FileAction::Skipped,
+                        0,
+                        op.size as usize,
+                    ));
+                }
+                (PlanAction::Delete, _) if self.syncer.options.atomic => {
+                    deletions.push(destination.to_path_buf());
+                    result.merge(TransferResult::for_file(
+                        destination,
+                        FileAction::Deleted,
+                        0,
+                        0,
+                    ));
+                }
+                (PlanAction::Delete, _) => {
+                    self.remove_extraneous(destination)?;
+                    self.syncer.emit(|| SyncEvent::Deleted {
+                        path: destination.to_path_buf(),
+                    });
+                    result.merge(TransferResult::for_file(
+                        destination,
+                        FileAction::Deleted,
+                        0,
+                        0,
+                    ));
+                }
+                (PlanAction::Create | PlanAction::Update, None) => unreachable!("checked above"),
+            }
+        }
+        Ok(result)
+    }
+
+    /// Sync one file, reporting its start, completion or failure as events.
+    #[instrument(
+        skip(self, checkpoint, prefetched),
+        fields(new_bytes = field::Empty, reused_bytes = field::Empty, reuse_ratio = field::Empty),
+    )]
+    fn sync_file(
+        &self,
+        src_path: &Path,
+        dst_path: &Path,
+        checkpoint: Option<&mut Checkpoint>,
+        prefetched: Option<PrefetchedSignature>,
+    ) -> Result<TransferResult> {
+        self.syncer.emit(|| SyncEvent::FileStarted {
+            path: dst_path.to_path_buf(),
+            size: fs::metadata(src_path).map_or(0, |meta| meta.len()),
+        });
+        let previous = self.keep_previous(dst_path)?;
+        match self.transfer_file(src_path, dst_path, checkpoint, prefetched) {
+            Ok(result) => {
+                result.record_in_span();
+                for record in &result.files {
+                    self.syncer
+                        .emit(|| SyncEvent::FileCompleted(record.clone()));
+                }
+                self.journal_written(dst_path, previous, &result)?;
+                Ok(result)
+            }
+            Err(e) => {
+                self.syncer.emit(|| SyncEvent::Error {
+                    path: dst_path.to_path_buf(),
+                    message: e.to_string(),
+                });
+                Err(e)
+            }
+        }
+    }
+
+    fn transfer_file(
+        &self,
+        src_path: &Path,
+        dst_path: &Path,
+        checkpoint: Option<&mut Checkpoint>,
+        prefetched: Option<PrefetchedSignature>,
+    ) -> Result<TransferResult> {
         info!("Syncing file: {:?} -> {:?}", src_path, dst_path);
 
         if !dst_path.exists() {
             info!("Destination doesn't exist, performing full copy");
-            return self.syncer.copy_file(src_path, dst_path);
+            return self.copy_file(src_path, dst_path);
         }
+        let target = self.stage_target(dst_path)?;
 
-        let dst_blocks = self.syncer.calculate_checksums(dst_path)?;
-        let mut weak_lookup: HashMap<u32, Vec<&Block>> = HashMap::new();
-        for block in &dst_blocks {
-            weak_lookup
-                .entry(block.weak_checksum)
-                .or_default()
-                .push(block);
-        }
+        let src_size = fs::metadata(src_path)
+            .with_context(|| format!("Failed to get metadata for source file: {:?}", src_path))?
+            .len();
 
-        let mut src_file = File::open(src_path)?;
-        let src_size = src_file.metadata()?.len();
+        if src_size < self.syncer.options.block_size as u64 {
+            return self.copy_file(src_path, dst_path);
+        }
 
-        if src_size < self.syncer.block_size as u64 {
-            return self.syncer.copy_file(src_path, dst_path);
+        let mut checkpointed = match checkpoint {
+            Some(checkpoint) => Some(CheckpointedFile {
+                checkpoint,
+                key: self.checkpoint_key(src_path),
+                stamp: SourceStamp::of(src_path)?,
+            }),
+            None => None,
+        };
+        let temp_path = self.temp_path(&target);
+        // Only a temp file of the final size, built from an unchanged source, can be resumed
+        let resume_from = checkpointed
+            .as_ref()
+            .and_then(|p| p.checkpoint.resume_offset(&p.key, &p.stamp))
+            .filter(|_| fs::metadata(&temp_path).is_ok_and(|meta| meta.len() == src_size))
+            .unwrap_or(0);
+        if resume_from > 0 {
+            info!("Resuming {:?} from offset {}", src_path, resume_from);
         }
+        // Data kept from a failed attempt already holds the start of the source
+        let partial_path = self.partial_path(dst_path);
+        let basis = match &partial_path {
+            Some(partial) if resume_from == 0 && partial.is_file() => {
+                info!("Using partial file {:?} as the basis", partial);
+                partial.as_path()
+            }
+            _ => dst_path,
+        };
 
-        // Create progress bar
-        let pb = ProgressBar::new(src_size);
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")
-                .expect("Failed to set progress bar template")
-                .progress_chars("#>-"),
+        let progress = ProgressReporter::new(
+            &self.syncer,
+            src_path,
+            src_size,
+            format!("Syncing {}", src_path.display()),
         );
-        pb.set_message(format!("Syncing {}", src_path.display()));
+        progress.update(Phase::Signature, 0);
+        let signature = match prefetched
+            .filter(|p| p.basis == basis)
+            .and_then(PrefetchedSignature::finish)
+        {
+            Some(signature) => signature,
+            None => self.syncer.generate_signature(basis)?,
+        };
 
-        let temp_path = dst_path.with_extension("tmp");
         let temp_file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
-            .truncate(true)
+            .truncate(resume_from == 0)
             .open(&temp_path)?;
         temp_file.set_len(src_size)?;
+        if self.syncer.options.preallocate {
+            preallocate(&temp_file, src_size).map_err(|e| {
+                if resume_from == 0 {
+                    let _ = fs::remove_file(&temp_path);
+                }
+                SyncError::Io {
+                    context: format!(
+                        "Failed to preallocate {} bytes for {:?}",
+                        src_size, temp_path
+                    ),
+                    source: e,
+                }
+            })?;
+        }
 
         let mut mmap = unsafe { MmapMut::map_mut(&temp_file)? };
-        let mut window = vec![0; min(self.syncer.block_size, src_size as usize)];
-        src_file.read_exact(&mut window)?;
-        let mut weak = self.syncer.calculate_weak_checksum(&window);
-        let mut offset: u64 = 0;
-        let mut last_match: u64 = 0;
-        let mut reused_bytes = 0usize;
-
-        while offset + self.syncer.block_size as u64 <= src_size {
-            if let Some(candidates) = weak_lookup.get(&weak) {
-                let strong = self.syncer.calculate_strong_checksum(&window);
-                if let Some(&block) = candidates.iter().find(|b| b.strong_checksum == strong) {
-                    if offset > last_match {
-                        src_file.seek(SeekFrom::Start(last_match))?;
-                        let mut unmatched = vec![0; (offset - last_match) as usize];
-                        src_file.read_exact(&mut unmatched)?;
-                        mmap[last_match as usize..offset as usize].copy_from_slice(&unmatched);
-                    }
-                    {
-                        let mut dst_file = File::open(dst_path)?;
-                        dst_file.seek(SeekFrom::Start(block.offset))?;
-                        let mut block_data = vec![0; block.size];
-                        dst_file.read_exact(&mut block_data)?;
-                        mmap[offset as usize..(offset + self.syncer.block_size as u64) as usize]
-                            .copy_from_slice(&block_data);
-                    }
-                    reused_bytes += block.size;
-                    offset += self.syncer.block_size as u64;
-                    last_match = offset;
-                    pb.set_position(offset);
-                    if offset + self.syncer.block_size as u64 <= src_size {
-                        src_file.seek(SeekFrom::Start(offset))?;
-                        src_file.read_exact(&mut window)?;
-                        weak = self.syncer.calculate_weak_checksum(&window);
-                    } else {
-                        break;
+        let mut done = 0;
+        let reconstructed = self.reconstruct(
+            src_path,
+            dst_path,
+            basis,
+            &signature,
+            (&temp_file, &mut mmap),
+            &progress,
+            resume_from,
+            checkpointed.as_mut(),
+            &mut done,
+        );
+        let (written, reused_bytes, source_checksum) = match reconstructed {
+            Ok(reconstructed) => reconstructed,
+            Err(e) => {
+                // A checkpointed temp file is kept so the next run can resume it
+                if checkpointed.is_none() {
+                    let flushed = mmap.flush();
+                    drop(mmap);
+                    match partial_path {
+                        Some(partial) if flushed.is_ok() && done > 0 => {
+                            self.keep_partial(temp_file, &temp_path, done, &partial)
+                        }
+                        _ => {
+                            drop(temp_file);
+                            let _ = fs::remove_file(&temp_path);
+                        }
                     }
-                    continue;
                 }
+                return Err(e);
             }
-            offset += 1;
-            pb.set_position(offset);
-            if offset + self.syncer.block_size as u64 <= src_size {
-                let old_byte = window[0];
-                window.copy_within(1.., 0);
-                src_file.seek(SeekFrom::Start(offset + self.syncer.block_size as u64 - 1))?;
-                src_file
-                    .read_exact(&mut window[self.syncer.block_size - 1..self.syncer.block_size])?;
-                weak = self.syncer.update_weak_checksum(
-                    old_byte,
-                    window[self.syncer.block_size - 1],
-                    weak,
-                    self.syncer.block_size,
-                );
-            } else {
-                break;
-            }
-        }
-        if last_match < src_size {
-            src_file.seek(SeekFrom::Start(last_match))?;
-            let mut remainder = Vec::new();
-            src_file.read_to_end(&mut remainder)?;
-            mmap[last_match as usize..].copy_from_slice(&remainder);
+        };
+        if written as u64 != src_size {
+            return Err(SyncError::SourceChanged(src_path.to_path_buf()));
         }
         mmap.flush()?;
 
-        if self.syncer.preserve_metadata {
+        if self.syncer.options.preserve_owner {
+            self.syncer
+                .copy_ownership(&fs::metadata(src_path)?, &temp_path)?;
+        }
+        if self.syncer.options.preserve_metadata {
             let src_meta = fs::metadata(src_path)?;
             fs::set_permissions(&temp_path, src_meta.permissions()).with_context(|| {
                 format!(
@@ -193,71 +692,985 @@ impl LocalSyncer {
             })?;
         }
 
-        fs::rename(temp_path.clone(), dst_path)?;
+        self.move_into_place(&temp_path, &target)?;
+        if let Some(partial) = partial_path.filter(|partial| partial.exists()) {
+            self.discard_partial(&partial);
+        }
+        // A resumed reconstruction never saw the start of the source, so hash it separately
+        let source_checksum = match source_checksum {
+            None if self.syncer.options.verify => {
+                Some(self.syncer.calculate_file_checksum(src_path)?)
+            }
+            checksum => checksum,
+        };
+        if let Some(expected) = source_checksum {
+            progress.update(Phase::Verify, src_size);
+            self.syncer.verify_file(&target, &expected)?;
+        }
 
-        // Complete progress bar
-        pb.finish_with_message(format!(
+        progress.finish(format!(
             "Synced {} ({} bytes)",
             src_path.display(),
             src_size
         ));
 
-        let total_bytes = src_size as usize;
+        let total_bytes = (src_size - resume_from) as usize;
         let new_bytes = total_bytes.saturating_sub(reused_bytes);
-        Ok(TransferResult {
+        Ok(TransferResult::for_file(
+            dst_path,
+            FileAction::Updated,
             new_bytes,
             reused_bytes,
+        ))
+    }
+
+    /// Where the reconstruction of `dst_path` is written. Names in the temp directory carry
+    /// a hash of the full destination path, so equally named files from different directories
+    /// don't collide and an interrupted reconstruction is found again on resume.
+    pub(crate) fn temp_path(&self, dst_path: &Path) -> PathBuf {
+        let Some(dir) = &self.temp_dir else {
+            return dst_path.with_extension("tmp");
+        };
+        let digest = Sha256::digest(dst_path.as_os_str().as_encoded_bytes());
+        let mut name = dst_path.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".{}.tmp", hex::encode(&digest[..8])));
+        dir.join(name)
+    }
+
+    /// Where the partial data of `dst_path` is kept, when `partial` or `partial_dir` is set.
+    fn partial_path(&self, dst_path: &Path) -> Option<PathBuf> {
+        let options = &self.syncer.options;
+        if !options.partial && options.partial_dir.is_none() {
+            return None;
+        }
+        let name = dst_path.file_name()?;
+        let parent = dst_path.parent().unwrap_or(Path::new(""));
+        Some(match &options.partial_dir {
+            Some(dir) => parent.join(dir).join(name),
+            None => {
+                let mut partial = name.to_os_string();
+                partial.push(PARTIAL_SUFFIX);
+                parent.join(partial)
+            }
+        })
+    }
+
+    /// `name` as compared against other entries: in the `unicode_normalize` form, if set.
+    fn compared_name(&self, name: &OsStr) -> OsString {
+        match self.syncer.options.unicode_normalize {
+            Some(form) => form.apply(name),
+            None => name.to_os_string(),
+        }
+    }
+
+    /// Entries of `dst_dir` by their `compared_name` when names are normalized, an entry
+    /// already in normal form winning over equivalent ones. Empty otherwise.
+    fn destination_names(&self, dst_dir: &Path) -> Result<HashMap<OsString, OsString>> {
+        let mut names = HashMap::new();
+        if self.syncer.options.unicode_normalize.is_none() || !dst_dir.is_dir() {
+            return Ok(names);
+        }
+        for entry in fs::read_dir(dst_dir)? {
+            let name = entry?.file_name();
+            let compared = self.compared_name(&name);
+            if compared == name {
+                names.insert(compared, name);
+            } else {
+                names.entry(compared).or_insert(name);
+            }
+        }
+        Ok(names)
+    }
+
+    /// Destination name for the source entry `name`: the existing entry in `dst_names`
+    /// equivalent to it, so it's updated rather than duplicated, or else its `compared_name`.
+    fn destination_name(&self, name: &OsStr, dst_names: &HashMap<OsString, OsString>) -> OsString {
+        let name = self.compared_name(name);
+        dst_names.get(&name).cloned().unwrap_or(name)
+    }
+
+    /// Whether the destination entry `name` holds partial data that deleting extraneous
+    /// entries must leave alone: the partial directory, or the partial file of a source entry.
+    fn is_partial_entry(&self, name: &OsStr, src_names: &HashSet<OsString>) -> bool {
+        let options = &self.syncer.options;
+        match &options.partial_dir {
+            Some(dir) => dir.is_relative() && dir.as_os_str() == name,
+            None if options.partial => name
+                .as_encoded_bytes()
+                .strip_suffix(PARTIAL_SUFFIX.as_bytes())
+                .is_some_and(|stem| {
+                    src_names
+                        .iter()
+                        .any(|source| source.as_encoded_bytes() == stem)
+                }),
+            None => false,
+        }
+    }
+
+    /// Move the first `len` reconstructed bytes in `temp_path` to `partial` for the next run.
+    /// Failing to keep them only costs that run some transfer, so errors are just logged.
+    fn keep_partial(&self, temp_file: File, temp_path: &Path, len: u64, partial: &Path) {
+        let kept = temp_file.set_len(len).and_then(|()| {
+            drop(temp_file);
+            if let Some(dir) = partial.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            fs::rename(temp_path, partial)
+        });
+        match kept {
+            Ok(()) => info!("Kept {} bytes of partial data in {:?}", len, partial),
+            Err(e) => {
+                warn!("Failed to keep partial data in {:?}: {}", partial, e);
+                let _ = fs::remove_file(temp_path);
+            }
+        }
+    }
+
+    /// Remove a partial file that has served as a basis, and its directory once empty.
+    fn discard_partial(&self, partial: &Path) {
+        if let Err(e) = fs::remove_file(partial) {
+            warn!("Failed to remove partial file {:?}: {}", partial, e);
+        }
+        if self.syncer.options.partial_dir.is_some()
+            && let Some(dir) = partial.parent()
+        {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+
+    /// Rename a finished temp file over `dst_path`. A temp file on another filesystem is
+    /// copied next to the destination first, keeping its times, so the final step is still
+    /// an atomic rename.
+    fn move_into_place(&self, temp_path: &Path, dst_path: &Path) -> Result<()> {
+        match fs::rename(temp_path, dst_path) {
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {}
+            result => {
+                return result
+                    .with_context(|| format!("Failed to move {:?} to {:?}", temp_path, dst_path));
+            }
+        }
+        let staged = dst_path.with_extension("tmp");
+        let temp_meta = fs::metadata(temp_path)?;
+        fs::copy(temp_path, &staged)
+            .with_context(|| format!("Failed to copy {:?} to {:?}", temp_path, staged))?;
+        set_file_times(
+            &staged,
+            FileTime::from_last_access_time(&temp_meta),
+            FileTime::from_last_modification_time(&temp_meta),
+        )?;
+        fs::rename(&staged, dst_path)
+            .with_context(|| format!("Failed to move {:?} to {:?}", staged, dst_path))?;
+        fs::remove_file(temp_path)
+            .with_context(|| format!("Failed to remove temporary file: {:?}", temp_path))
+    }
+
+    /// Rebuild the source into `mmap` using a three stage pipeline connected by bounded
+    /// channels: one thread reads the source and rolls weak checksums, one confirms hits with
+    /// strong checksums, and the calling thread writes the resulting instructions.
+    /// Returns the number of bytes written, how many of them were reused from `basis`, which
+    /// `signature` describes, and the source's checksum when verification is enabled.
+    /// Reconstruction starts at `start`, and with `checkpointed` set, flushed progress is
+    /// recorded every `CHECKPOINT_INTERVAL` bytes. `done` follows the end of the written data,
+    /// so it tells how much is usable when reconstruction fails. With `direct_io`, written
+    /// data is dropped from the page cache of `temp_file`, which `mmap` maps, as it goes.
+    #[allow(clippy::too_many_arguments)]
+    fn reconstruct(
+        &self,
+        src_path: &Path,
+        dst_path: &Path,
+        basis: &Path,
+        signature: &Signature,
+        (temp_file, mmap): (&File, &mut MmapMut),
+        progress: &ProgressReporter,
+        start: u64,
+        mut checkpointed: Option<&mut CheckpointedFile>,
+        done: &mut u64,
+    ) -> Result<(usize, usize, Option<[u8; 32]>)> {
+        let syncer = &self.syncer;
+        let index = &BlockIndex::new(signature);
+        let mut src_file = syncer
+            .open_input(src_path)
+            .with_context(|| format!("Failed to open source file: {:?}", src_path))?;
+        let mut dst_file = syncer
+            .open_input(basis)
+            .with_context(|| format!("Failed to open destination file: {:?}", basis))?;
+        src_file.seek(SeekFrom::Start(start))?;
+
+        thread::scope(|scope| {
+            let (chunk_tx, chunk_rx) =
+                mpsc::sync_channel::<(Vec<u8>, Vec<WeakHit>)>(PIPELINE_DEPTH);
+            let (op_tx, op_rx) = mpsc::sync_channel::<DeltaOp>(PIPELINE_DEPTH);
+
+            let reader = scope.spawn(move || -> Result<Option<[u8; 32]>> {
+                let mut scanner = WeakScanner::new(index);
+                let mut hasher = (syncer.options.verify && start == 0).then(Sha256::new);
+                loop {
+                    let mut chunk = vec![0u8; PIPELINE_CHUNK_SIZE];
+                    let read = match src_file.read(&mut chunk) {
+                        Ok(0) => break,
+                        Ok(read) => read,
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e.into()),
+                    };
+                    chunk.truncate(read);
+                    syncer.check_cancelled()?;
+                    if let Some(hasher) = hasher.as_mut() {
+                        hasher.update(&chunk);
+                    }
+                    let mut hits = Vec::new();
+                    scanner.scan(&chunk, &mut hits);
+                    if chunk_tx.send((chunk, hits)).is_err() {
+                        // The writer gave up and reports its own error
+                        break;
+                    }
+                }
+                Ok(hasher.map(|hasher| hasher.finalize().into()))
+            });
+
+            let matcher = scope.spawn(move || -> Result<()> {
+                let mut matcher = BlockMatcher::new(index);
+                let mut emit = |op| {
+                    op_tx
+                        .send(op)
+                        .map_err(|_| SyncError::Pipeline("writer stopped"))
+                };
+                for (chunk, hits) in chunk_rx {
+                    matcher.process(&chunk, &hits, &mut emit)?;
+                }
+                matcher.finish(&mut emit)
+            });
+
+            let mut offset = start as usize;
+            let mut next_checkpoint = offset + CHECKPOINT_INTERVAL;
+            let mut released = offset;
+            let mut reused_bytes = 0usize;
+            let mut limiter = syncer.options.bwlimit.map(RateLimiter::new);
+            let written = (|| -> Result<()> {
+                for op in op_rx {
+                    syncer.check_cancelled()?;
+                    let len = match &op {
+                        DeltaOp::Literal(data) => data.len(),
+                        DeltaOp::Copy { len, .. } => *len,
+                    };
+                    if offset + len > mmap.len() {
+                        return Err(SyncError::SourceChanged(src_path.to_path_buf()));
+                    }
+                    let target = &mut mmap[offset..offset + len];
+                    match op {
+                        DeltaOp::Literal(data) => target.copy_from_slice(&data),
+                        DeltaOp::Copy {
+                            offset: block_offset,
+                            ..
+                        } => {
+                            dst_file.seek(SeekFrom::Start(block_offset))?;
+                            dst_file.read_exact(target)?;
+                            reused_bytes += len;
+                            syncer.emit(|| SyncEvent::BlockMatched {
+                                path: dst_path.to_path_buf(),
+                                offset: block_offset,
+                                len,
+                            });
+                        }
+                    }
+                    offset += len;
+                    *done = offset as u64;
+                    syncer.consume_disk(len);
+                    if let Some(limiter) = limiter.as_mut() {
+                        limiter.consume(len);
+                    }
+                    progress.update(Phase::Transfer, offset as u64);
+                    if let Some(checkpointed) = checkpointed.as_deref_mut()
+                        && offset >= next_checkpoint
+                    {
+                        mmap.flush_range(0, offset)?;
+                        checkpointed.checkpoint.record_partial(
+                            &checkpointed.key,
+                            offset as u64,
+                            &checkpointed.stamp,
+                        )?;
+                        next_checkpoint = offset + CHECKPOINT_INTERVAL;
+                    }
+                    if syncer.options.direct_io && offset - released >= RELEASE_INTERVAL {
+                        direct::release_written(mmap, temp_file, released, offset - released)?;
+                        released = offset;
+                    }
+                }
+                if syncer.options.direct_io && offset > released {
+                    direct::release_written(mmap, temp_file, released, offset - released)?;
+                }
+                Ok(())
+            })();
+
+            let read = reader
+                .join()
+                .map_err(|_| SyncError::Pipeline("reader thread panicked"))?;
+            let matched = matcher
+                .join()
+                .map_err(|_| SyncError::Pipeline("matcher thread panicked"))?;
+            written?;
+            let source_checksum = read?;
+            matched?;
+            Ok((offset, reused_bytes, source_checksum))
         })
     }
 
-    fn sync_dir(&self, src_dir: &Path, dst_dir: &Path) -> Result<TransferResult> {
+    /// Full copy, re-hashing both sides afterwards when verification is enabled.
+    fn copy_file(&self, src_path: &Path, dst_path: &Path) -> Result<TransferResult> {
+        self.syncer.check_cancelled()?;
+        let existed = dst_path.exists();
+        let target = self.stage_target(dst_path)?;
+        // A hard-linked file, such as a deduplicated one, is replaced rather than rewritten
+        // through every link
+        if fs::symlink_metadata(&target).is_ok_and(|meta| meta.is_file() && is_hard_linked(&meta)) {
+            fs::remove_file(&target)
+                .with_context(|| format!("Failed to replace hard link {:?}", target))?;
+        }
+        let result = self.unstaged(self.syncer.copy_file(src_path, &target)?, dst_path, existed);
+        let size = result.new_bytes as u64;
+        let report = |phase| {
+            if let Some(callback) = &self.syncer.options.progress {
+                callback(&Progress {
+                    file: src_path,
+                    bytes_done: size,
+                    bytes_total: size,
+                    phase,
+                });
+            }
+        };
+        if self.syncer.options.verify {
+            report(Phase::Verify);
+            let expected = self.syncer.calculate_file_checksum(src_path)?;
+            self.syncer.verify_file(&target, &expected)?;
+        }
+        report(Phase::Complete);
+        Ok(result)
+    }
+
+    /// Whether `entry` is a symlink to recreate as such rather than follow.
+    fn is_preserved_link(&self, entry: &fs::DirEntry) -> Result<bool> {
+        Ok(self.syncer.options.preserve_links && entry.file_type()?.is_symlink())
+    }
+
+    /// Recreate a symlink, reporting it like a synced file.
+    fn sync_symlink(&self, src_path: &Path, dst_path: &Path) -> Result<TransferResult> {
+        info!("Syncing symlink: {:?} -> {:?}", src_path, dst_path);
+        let unchanged = fs::read_link(dst_path)
+            .is_ok_and(|existing| fs::read_link(src_path).is_ok_and(|link| link == existing));
+        let result = if unchanged {
+            self.syncer.copy_symlink(src_path, dst_path)?
+        } else {
+            let existed = fs::symlink_metadata(dst_path).is_ok();
+            let target = self.stage_target(dst_path)?;
+            self.unstaged(
+                self.syncer.copy_symlink(src_path, &target)?,
+                dst_path,
+                existed,
+            )
+        };
+        for record in &result.files {
+            if record.action == FileAction::Skipped {
+                self.syncer.emit(|| SyncEvent::FileSkipped {
+                    path: dst_path.to_path_buf(),
+                });
+            } else {
+                self.syncer
+                    .emit(|| SyncEvent::FileCompleted(record.clone()));
+                self.journal(JournalEntry::new(record.action, dst_path))?;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Path of `dst_path` relative to the destination, under which the sync state tracks it.
+    fn state_key(&self, dst_path: &Path) -> PathBuf {
+        dst_path
+            .strip_prefix(&self.destination)
+            .unwrap_or(dst_path)
+            .to_path_buf()
+    }
+
+    /// Whether `name` is a copy that conflict tracking moved aside, which is never extraneous.
+    fn is_conflict_entry(&self, name: &OsStr) -> bool {
+        self.syncer.options.conflicts.is_some() && is_conflict_copy(name)
+    }
+
+    /// If `dst_path` changed since the last sync recorded in `state`, apply the conflict
+    /// policy to it and return that policy; `Rename` has moved it aside by then.
+    fn resolve_conflict(
+        &self,
+        state: &SyncState,
+        dst_path: &Path,
+    ) -> Result<Option<ConflictPolicy>> {
+        let Some(policy) = self.syncer.options.conflicts else {
+            return Ok(None);
+        };
+        if !state.changed_since(&self.state_key(dst_path), dst_path) {
+            return Ok(None);
+        }
+        warn!(
+            "{:?} changed since the last sync; conflict policy: {}",
+            dst_path, policy
+        );
+        let renamed_to = match policy {
+            ConflictPolicy::Rename => {
+                let aside = conflict_path(dst_path);
+                fs::rename(dst_path, &aside).with_context(|| {
+                    format!("Failed to move conflicting {:?} to {:?}", dst_path, aside)
+                })?;
+                Some(aside)
+            }
+            ConflictPolicy::Skip | ConflictPolicy::Overwrite => None,
+        };
+        self.syncer.emit(|| SyncEvent::Conflict {
+            path: dst_path.to_path_buf(),
+            policy,
+            renamed_to: renamed_to.clone(),
+        });
+        Ok(Some(policy))
+    }
+
+    /// Where the new version of `dst_path` is written: its place in the staging area for atomic
+    /// directory syncs, else `dst_path` itself.
+    fn staged_path(&self, dst_path: &Path) -> PathBuf {
+        match dst_path.strip_prefix(&self.destination) {
+            Ok(relative) if self.syncer.options.atomic && !relative.as_os_str().is_empty() => {
+                self.destination.join(STAGING_DIR_NAME).join(relative)
+            }
+            _ => dst_path.to_path_buf(),
+        }
+    }
+
+    /// `staged_path`, with the staging directories leading to it created.
+    fn stage_target(&self, dst_path: &Path) -> Result<PathBuf> {
+        let target = self.staged_path(dst_path);
+        if target != dst_path
+            && let Some(parent) = target.parent()
+        {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create staging directory: {:?}", parent))?;
+        }
+        Ok(target)
+    }
+
+    /// `result` of writing the staged copy of `dst_path`, reported as `dst_path` itself: an
+    /// update when `dst_path` `existed` already.
+    fn unstaged(
+        &self,
+        mut result: TransferResult,
+        dst_path: &Path,
+        existed: bool,
+    ) -> TransferResult {
+        for record in &mut result.files {
+            record.path = dst_path.to_path_buf();
+            if existed && record.action == FileAction::Created {
+                record.action = FileAction::Updated;
+                result.created_files -= 1;
+                result.updated_files += 1;
+            }
+        }
+        result
+    }
+
+    /// End an atomic sync that `synced`: once everything is staged, move it all into place and
+    /// then apply the `deletions`, along with the deletions recorded in the result; after a
+    /// failure, throw the staging area away so the destination stays as it was.
+    fn finish_staged(
+        &self,
+        synced: Result<TransferResult>,
+        deletions: &[PathBuf],
+    ) -> Result<TransferResult> {
+        if !self.syncer.options.atomic {
+            return synced;
+        }
+        let staging = self.destination.join(STAGING_DIR_NAME);
+        let result = match synced {
+            Ok(result) => result,
+            Err(e) => {
+                if let Err(cleanup) = fs::remove_dir_all(&staging)
+                    && cleanup.kind() != io::ErrorKind::NotFound
+                {
+                    warn!("Failed to remove staging area {:?}: {}", staging, cleanup);
+                }
+                return Err(e);
+            }
+        };
+        if staging.is_dir() {
+            let staged = WalkDir::new(&staging)
+                .min_depth(1)
+                .into_iter()
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(|e| SyncError::Io {
+                    context: format!("Failed to walk {:?}", staging),
+                    source: e.into(),
+                })?;
+            let mut moved_dirs: Vec<PathBuf> = Vec::new();
+            for entry in staged {
+                if moved_dirs.iter().any(|dir| entry.path().starts_with(dir)) {
+                    continue;
+                }
+                let relative = entry
+                    .path()
+                    .strip_prefix(&staging)
+                    .expect("walked beneath the staging area");
+                let dst_path = self.destination.join(relative);
+                let existing = fs::symlink_metadata(&dst_path).ok();
+                if entry.file_type().is_dir() {
+                    // New directories move in whole, their contents with them
+                    if existing.is_none() {
+                        fs::rename(entry.path(), &dst_path).with_context(|| {
+                            format!("Failed to move {:?} to {:?}", entry.path(), dst_path)
+                        })?;
+                        moved_dirs.push(entry.into_path());
+                    }
+                    continue;
+                }
+                if existing.is_some_and(|meta| meta.is_dir()) {
+                    fs::remove_dir_all(&dst_path)
+                        .with_context(|| format!("Failed to replace {:?}", dst_path))?;
+                }
+                fs::rename(entry.path(), &dst_path).with_context(|| {
+                    format!("Failed to move {:?} to {:?}", entry.path(), dst_path)
+                })?;
+            }
+            fs::remove_dir_all(&staging)
+                .with_context(|| format!("Failed to remove staging area {:?}", staging))?;
+        }
+        let recorded = result
+            .files
+            .iter()
+            .filter(|record| record.action == FileAction::Deleted)
+            .map(|record| &record.path);
+        for path in deletions.iter().chain(recorded) {
+            if fs::symlink_metadata(path).is_err() {
+                continue;
+            }
+            self.remove_extraneous(path)?;
+            self.syncer
+                .emit(|| SyncEvent::Deleted { path: path.clone() });
+        }
+        Ok(result)
+    }
+
+    /// Whether `path` is the `delete_to` or `backup_dir` directory, which is never itself
+    /// extraneous.
+    fn is_trash(&self, path: &Path) -> bool {
+        let options = &self.syncer.options;
+        [&options.delete_to, &options.backup_dir]
+            .into_iter()
+            .flatten()
+            .any(|trash| path == self.destination.join(trash))
+    }
+
+    /// Delete the extraneous destination entry `path`, or move it into `delete_to`, else
+    /// `backup_dir`, at its path relative to the destination. An entry already in the way in
+    /// `delete_to` is kept under a numbered name, such as `notes.txt.~1~`; in `backup_dir`,
+    /// where earlier journal entries may point, the moved entry takes the numbered name.
+    fn remove_extraneous(&self, path: &Path) -> Result<()> {
+        let options = &self.syncer.options;
+        let old_sha256 = self.journal_hash(path)?;
+        let target = match (&options.delete_to, &options.backup_dir) {
+            (None, None) => {
+                if path.is_dir() && !path.is_symlink() {
+                    fs::remove_dir_all(path)
+                } else {
+                    fs::remove_file(path)
+                }
+                .with_context(|| format!("Failed to delete {:?}", path))?;
+                None
+            }
+            (Some(trash), _) => {
+                let target = self.kept_path(trash, path)?;
+                if fs::symlink_metadata(&target).is_ok() {
+                    let aside = numbered_path(&target);
+                    fs::rename(&target, &aside).with_context(|| {
+                        format!("Failed to move {:?} aside to {:?}", target, aside)
+                    })?;
+                }
+                Some(target)
+            }
+            (None, Some(backup_dir)) => Some(numbered_path(&self.kept_path(backup_dir, path)?)),
+        };
+        if let Some(target) = &target {
+            fs::rename(path, target)
+                .with_context(|| format!("Failed to move {:?} to {:?}", path, target))?;
+            info!("Moved extraneous entry {:?} to {:?}", path, target);
+        }
+        self.journal(JournalEntry {
+            old_sha256,
+            backup: target,
+            ..JournalEntry::new(FileAction::Deleted, path)
+        })
+    }
+
+    /// Where `path` goes in `dir`: at its path relative to the destination, with a relative
+    /// `dir` resolved against the destination, or against its parent for a single-file sync.
+    /// Creates the directories leading to it.
+    fn kept_path(&self, dir: &Path, path: &Path) -> Result<PathBuf> {
+        let target = match path.strip_prefix(&self.destination) {
+            Ok(relative) if !relative.as_os_str().is_empty() => {
+                self.destination.join(dir).join(relative)
+            }
+            _ => {
+                let name = path
+                    .file_name()
+                    .ok_or_else(|| SyncError::PathOutsideRoot(path.to_path_buf()))?;
+                path.parent().unwrap_or(Path::new("")).join(dir).join(name)
+            }
+        };
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {:?}", parent))?;
+        }
+        Ok(target)
+    }
+
+    /// Before `dst_path` is overwritten, hash it for the journal and copy it into
+    /// `backup_dir`. Returns the old hash and where the copy went.
+    fn keep_previous(&self, dst_path: &Path) -> Result<(Option<String>, Option<PathBuf>)> {
+        let old_sha256 = self.journal_hash(dst_path)?;
+        let backup = match &self.syncer.options.backup_dir {
+            Some(dir) if dst_path.is_file() && !dst_path.is_symlink() => {
+                let backup = numbered_path(&self.kept_path(dir, dst_path)?);
+                fs::copy(dst_path, &backup)
+                    .with_context(|| format!("Failed to back up {:?} to {:?}", dst_path, backup))?;
+                Some(backup)
+            }
+            _ => None,
+        };
+        Ok((old_sha256, backup))
+    }
+
+    /// Journal the file `sync_file` wrote to `dst_path`, along with what `keep_previous`
+    /// returned for it.
+    fn journal_written(
+        &self,
+        dst_path: &Path,
+        (old_sha256, backup): (Option<String>, Option<PathBuf>),
+        result: &TransferResult,
+    ) -> Result<()> {
+        let Some(record) = result.files.first() else {
+            return Ok(());
+        };
+        if self.syncer.options.journal.is_none() || record.action == FileAction::Skipped {
+            return Ok(());
+        }
+        let written = self.staged_path(dst_path);
+        self.journal(JournalEntry {
+            bytes: Some(fs::metadata(&written)?.len()),
+            old_sha256,
+            new_sha256: self.journal_hash(&written)?,
+            backup,
+            ..JournalEntry::new(record.action, dst_path)
+        })
+    }
+
+    /// SHA-256 of the regular file `path`, when there's a journal to record it in.
+    fn journal_hash(&self, path: &Path) -> Result<Option<String>> {
+        if self.syncer.options.journal.is_none() || !path.is_file() || path.is_symlink() {
+            return Ok(None);
+        }
+        Ok(Some(hex::encode(
+            self.syncer.calculate_file_checksum(path)?,
+        )))
+    }
+
+    fn journal(&self, mut entry: JournalEntry) -> Result<()> {
+        let Some(journal) = &self.syncer.options.journal else {
+            return Ok(());
+        };
+        // Undo may run from another directory
+        entry.backup = entry
+            .backup
+            .map(|backup| std::path::absolute(&backup).unwrap_or(backup));
+        journal.record(&entry)
+    }
+
+    /// Start computing the signature of `dst_path` on a background thread when transferring
+    /// `src_path` over it is likely to need one: both are regular files, the source isn't
+    /// small enough to be copied whole, and the quick check doesn't find them equal.
+    fn prefetch_signature(&self, src_path: &Path, dst_path: &Path) -> Option<PrefetchedSignature> {
+        let src_meta = fs::metadata(src_path).ok()?;
+        if !src_meta.is_file()
+            || src_meta.len() < self.syncer.options.block_size as u64
+            || !fs::metadata(dst_path).is_ok_and(|meta| meta.is_file())
+            || self
+                .partial_path(dst_path)
+                .is_some_and(|partial| partial.is_file())
+        {
+            return None;
+        }
+        // With checksums, the quick check would hash both files itself
+        if !self.syncer.options.checksum
+            && self.syncer.quick_check(src_path, dst_path).ok()? == PlanReason::Unchanged
+        {
+            return None;
+        }
+        let stamp = basis_stamp(dst_path)?;
+        let syncer = self.syncer.clone();
+        let basis = dst_path.to_path_buf();
+        let handle = thread::spawn(move || syncer.generate_signature(&basis));
+        Some(PrefetchedSignature {
+            basis: dst_path.to_path_buf(),
+            stamp,
+            handle,
+        })
+    }
+
+    /// Hard-link `dst_path`, which must not exist yet, to the `link_dest` copy of `src_path`
+    /// when that copy passes the quick check. Returns whether a link was made.
+    fn link_unchanged(&self, src_path: &Path, dst_path: &Path) -> Result<bool> {
+        let Some(link_dest) = &self.syncer.options.link_dest else {
+            return Ok(false);
+        };
+        if fs::symlink_metadata(dst_path).is_ok() {
+            return Ok(false);
+        }
+        // Named like the destination, which may differ from the source in normalization
+        let Ok(relative) = dst_path.strip_prefix(&self.destination) else {
+            return Ok(false);
+        };
+        let basis = self.destination.join(link_dest).join(relative);
+        if !self.syncer.is_unchanged(src_path, &basis)? {
+            return Ok(false);
+        }
+        match fs::hard_link(&basis, self.stage_target(dst_path)?) {
+            Ok(()) => {
+                info!("Hard-linked unchanged file: {:?} -> {:?}", basis, dst_path);
+                Ok(true)
+            }
+            // Another filesystem, or too many links: fall back to a copy
+            Err(e) => {
+                warn!("Failed to hard-link {:?}, copying instead: {}", basis, e);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Whether the filter excludes `path`, an entry somewhere beneath `root`.
+    fn is_filtered(&self, path: &Path, root: &Path) -> bool {
+        let filter = &self.syncer.options.filter;
+        !filter.is_empty()
+            && filter.is_excluded(path.strip_prefix(root).unwrap_or(path), path.is_dir())
+    }
+
+    /// Path of `path` relative to the sync root, as recorded in the checkpoint.
+    fn checkpoint_key(&self, path: &Path) -> PathBuf {
+        path.strip_prefix(&self.source)
+            .unwrap_or(path)
+            .to_path_buf()
+    }
+
+    #[instrument(skip(self, checkpoint, state))]
+    fn sync_dir(
+        &self,
+        src_dir: &Path,
+        dst_dir: &Path,
+        mut checkpoint: Option<&mut Checkpoint>,
+        mut state: Option<&mut SyncState>,
+    ) -> Result<TransferResult> {
         info!("Syncing directory: {:?} -> {:?}", src_dir, dst_dir);
         if !dst_dir.exists() {
-            fs::create_dir_all(dst_dir)?;
+            fs::create_dir_all(self.staged_path(dst_dir))?;
         }
+        let dst_names = self.destination_names(dst_dir)?;
         let mut src_names = HashSet::new();
-        let mut total_reused_bytes = 0usize;
-        let mut total_bytes = 0usize;
+        let mut result = TransferResult::default();
+        let entries = fs::read_dir(src_dir)?.collect::<io::Result<Vec<_>>>()?;
+        let mut prefetcher = Prefetcher::default();
 
-        for entry in fs::read_dir(src_dir)? {
-            let entry = entry?;
+        for (i, entry) in entries.iter().enumerate() {
+            self.syncer.check_cancelled()?;
             let file_name = entry.file_name();
-            src_names.insert(file_name.clone());
+            if file_name == LOCK_FILE_NAME
+                || file_name == STATE_FILE_NAME
+                || (checkpoint.is_some() && file_name == CHECKPOINT_FILE_NAME)
+            {
+                continue;
+            }
+            src_names.insert(self.compared_name(&file_name));
             let path = entry.path();
-            let dest_path = dst_dir.join(&file_name);
+            if self.is_filtered(&path, &self.source) {
+                info!("Skipping excluded entry: {:?}", path);
+                continue;
+            }
+            let dest_path = dst_dir.join(self.destination_name(&file_name, &dst_names));
+            let key = self.checkpoint_key(&path);
+            if checkpoint.as_ref().is_some_and(|c| c.is_completed(&key)) {
+                info!("Skipping entry completed by an earlier run: {:?}", path);
+                if path.is_file() {
+                    self.syncer.emit(|| SyncEvent::FileSkipped {
+                        path: dest_path.clone(),
+                    });
+                    let size = fs::metadata(&path)?.len() as usize;
+                    result.merge(TransferResult::for_file(
+                        &dest_path,
+                        FileAction::Skipped,
+                        0,
+                        size,
+                    ));
+                }
+                continue;
+            }
 
-            let entry_size = if path.is_file() {
-                let res = self.sync_file(&path, &dest_path)?;
-                total_reused_bytes += res.reused_bytes;
-                fs::metadata(&path)?.len() as usize
+            if self.is_preserved_link(entry)? {
+                result.merge(self.sync_symlink(&path, &dest_path)?);
+            } else if path.is_file() {
+                if self.syncer.is_unchanged(&path, &dest_path)?
+                    || self.link_unchanged(&path, &dest_path)?
+                {
+                    info!("Skipping unchanged file: {:?}", path);
+                    self.syncer.emit(|| SyncEvent::FileSkipped {
+                        path: dest_path.clone(),
+                    });
+                    let size = fs::metadata(&path)?.len() as usize;
+                    result.merge(TransferResult::for_file(
+                        &dest_path,
+                        FileAction::Skipped,
+                        0,
+                        size,
+                    ));
+                    if let Some(state) = state.as_deref_mut() {
+                        state.record(&self.state_key(&dest_path), &dest_path)?;
+                    }
+                } else {
+                    let conflict = match state.as_deref() {
+                        Some(state) => self.resolve_conflict(state, &dest_path)?,
+                        None => None,
+                    };
+                    if conflict.is_some() {
+                        result.conflicts += 1;
+                    }
+                    if conflict != Some(ConflictPolicy::Skip) {
+                        let signature = prefetcher.take(&dest_path);
+                        prefetcher.advance(&entries, i + 1, |next| {
+                            let next_path = next.path();
+                            if self.is_filtered(&next_path, &self.source)
+                                || self.is_preserved_link(next).unwrap_or(true)
+                            {
+                                return None;
+                            }
+                            let name = self.destination_name(&next.file_name(), &dst_names);
+                            self.prefetch_signature(&next_path, &dst_dir.join(name))
+                        });
+                        result.merge(self.sync_file(
+                            &path,
+                            &dest_path,
+                            checkpoint.as_deref_mut(),
+                            signature,
+                        )?);
+                        if let Some(state) = state.as_deref_mut() {
+                            state.record(&self.state_key(&dest_path), &dest_path)?;
+                        }
+                    }
+                }
+            } else if path.is_dir() && self.syncer.options.recursive {
+                result.merge(self.sync_dir(
+                    &path,
+                    &dest_path,
+                    checkpoint.as_deref_mut(),
+                    state.as_deref_mut(),
+                )?);
             } else if path.is_dir() {
-                let res = self.sync_dir(&path, &dest_path)?;
-                total_reused_bytes += res.reused_bytes;
-                res.new_bytes + res.reused_bytes
+                info!("Skipping directory in non-recursive mode: {:?}", path);
             } else {
-                info!("Skipping unsupported file type: {:?}", path);
-                0
-            };
+                warn!("Skipping unsupported file type: {:?}", path);
+                result.unsupported_files += 1;
+            }
 
-            total_bytes += entry_size;
+            if let Some(checkpoint) = checkpoint.as_deref_mut() {
+                checkpoint.record_completed(&key)?;
+            }
         }
-        if self.syncer.delete_extraneous {
+        // A directory new to an atomic sync only exists in the staging area so far
+        if self.syncer.options.delete_extraneous && dst_dir.is_dir() {
             for entry in fs::read_dir(dst_dir)? {
                 let entry = entry?;
-                if !src_names.contains(&entry.file_name()) {
+                let file_name = self.compared_name(&entry.file_name());
+                let is_state_file = file_name == LOCK_FILE_NAME
+                    || file_name == STATE_FILE_NAME
+                    || file_name == STAGING_DIR_NAME
+                    || (checkpoint.is_some() && file_name == CHECKPOINT_FILE_NAME)
+                    || self.is_conflict_entry(&file_name);
+                if !src_names.contains(&file_name)
+                    && !is_state_file
+                    && !self.is_partial_entry(&file_name, &src_names)
+                {
                     let extra_path = entry.path();
-                    if extra_path.is_file() {
-                        fs::remove_file(&extra_path)?;
-                    } else if extra_path.is_dir() {
-                        fs::remove_dir_all(&extra_path)?;
+                    if self.is_filtered(&extra_path, &self.destination)
+                        || self.is_trash(&extra_path)
+                        || !(extra_path.is_file() || extra_path.is_symlink() || extra_path.is_dir())
+                    {
+                        continue;
+                    }
+                    if let Some(state) = state.as_deref_mut() {
+                        if extra_path.is_file() && !extra_path.is_symlink() {
+                            let conflict = self.resolve_conflict(state, &extra_path)?;
+                            if conflict.is_some() {
+                                result.conflicts += 1;
+                            }
+                            if matches!(
+                                conflict,
+                                Some(ConflictPolicy::Skip | ConflictPolicy::Rename)
+                            ) {
+                                continue;
+                            }
+                        }
+                        state.forget(&self.state_key(&extra_path));
                     }
+                    // Atomic syncs delete once everything is staged, in `finish_staged`
+                    if !self.syncer.options.atomic {
+                        if let Err(e) = self.remove_extraneous(&extra_path) {
+                            self.syncer.emit(|| SyncEvent::Error {
+                                path: extra_path.clone(),
+                                message: e.to_string(),
+                            });
+                            return Err(e);
+                        }
+                        self.syncer.emit(|| SyncEvent::Deleted {
+                            path: extra_path.clone(),
+                        });
+                    }
+                    result.merge(TransferResult::for_file(
+                        &extra_path,
+                        FileAction::Deleted,
+                        0,
+                        0,
+                    ));
                 }
             }
         }
-        let new_bytes = total_bytes.saturating_sub(total_reused_bytes);
-        Ok(TransferResult {
-            new_bytes,
-            reused_bytes: total_reused_bytes,
-        })
+        Ok(result)
     }
 }
+
+/// `path` itself when it's free, else the first free `<path>.~<n>~`.
+fn numbered_path(path: &Path) -> PathBuf {
+    if fs::symlink_metadata(path).is_err() {
+        return path.to_path_buf();
+    }
+    (1..)
+        .map(|n| {
+            let mut name = path.as_os_str().to_os_string();
+            name.push(format!(".~{}~", n));
+            PathBuf::from(name)
+        })
+        .find(|candidate| fs::symlink_metadata(candidate).is_err())
+        .expect("some numbered name is free")
+}
+
+/// Whether `path` is `root` or lies beneath it without climbing back out through `..`.
+fn is_within(path: &Path, root: &Path) -> bool {
+    path.starts_with(root) && !path.components().any(|c| c == Component::ParentDir)
+}
+
+/// Whether the file described by `meta` has other hard links.
+#[cfg(unix)]
+fn is_hard_linked(meta: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    meta.nlink() > 1
+}
+
+#[cfg(not(unix))]
+fn is_hard_linked(_meta: &fs::Metadata) -> bool {
+    false
+}
@@ -0,0 +1,90 @@
+use crate::error::{IoContext, Result, SyncError};
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// Name of the lock file held in a destination directory while a sync writes into it.
+pub const LOCK_FILE_NAME: &str = ".rsynx-lock";
+
+/// Advisory lock keeping two rsynx processes from syncing into the same destination at once.
+/// The lock file records the holder's process ID and is removed when the lock is dropped.
+#[derive(Debug)]
+pub struct DestinationLock {
+    path: PathBuf,
+    _file: File,
+}
+
+impl DestinationLock {
+    /// Lock `dst`: a directory through `LOCK_FILE_NAME` inside it, anything else through a
+    /// hidden lock file beside it. Fails at once with `SyncError::Locked` if another process
+    /// holds the lock.
+    pub fn acquire(dst: &Path) -> Result<Self> {
+        let path = lock_path(dst);
+        loop {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)
+                .with_context(|| format!("Failed to open lock file: {:?}", path))?;
+            match file.try_lock() {
+                Ok(()) => {}
+                Err(TryLockError::WouldBlock) => {
+                    let mut owner = String::new();
+                    let _ = file.read_to_string(&mut owner);
+                    return Err(SyncError::Locked {
+                        path: dst.to_path_buf(),
+                        owner: owner.trim().parse().ok(),
+                    });
+                }
+                Err(TryLockError::Error(e)) => {
+                    return Err(e).with_context(|| format!("Failed to lock {:?}", path));
+                }
+            }
+            // The previous holder may have removed the file between our open and lock, leaving
+            // us locking a file nobody else will find; start over with the one there now
+            if !is_current(&file, &path) {
+                continue;
+            }
+            file.set_len(0)?;
+            write!(file, "{}", std::process::id())?;
+            return Ok(Self { path, _file: file });
+        }
+    }
+}
+
+impl Drop for DestinationLock {
+    fn drop(&mut self) {
+        // Removed while still locked, so nobody locks it in between
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn lock_path(dst: &Path) -> PathBuf {
+    if dst.is_dir() {
+        return dst.join(LOCK_FILE_NAME);
+    }
+    let mut name = OsString::from(".");
+    name.push(dst.file_name().unwrap_or_default());
+    name.push(".rsynx-lock");
+    dst.with_file_name(name)
+}
+
+/// Whether `file` is still the file at `path`.
+#[cfg(unix)]
+fn is_current(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(open), Ok(current)) => open.dev() == current.dev() && open.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_current(_file: &File, path: &Path) -> bool {
+    path.exists()
+}
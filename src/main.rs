@@ -1,17 +1,321 @@
 use anyhow::{Context, Result};
-use clap::Parser;
-use rsynx::{local_sync::LocalSyncer, network_sync::NetworkSyncer};
+use clap::{
+    ArgAction, ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum,
+    parser::ValueSource,
+};
+use rsynx::{
+    config::{Config, ConfigOptions},
+    conflict::ConflictPolicy,
+    daemon::{Daemon, DaemonConfig},
+    delta::{DeltaFormat, Signature, WeakHash},
+    error::SyncError,
+    events::SyncEvent,
+    filter::{Filter, FilterRule},
+    itemize::Itemizer,
+    journal::{self, Journal, UndoOutcome},
+    local_sync::LocalSyncer,
+    manifest::{self, ManifestEntry, ManifestFormat},
+    network_sync::NetworkSyncer,
+    notify::{self, RunReport, RunStats},
+    options::SyncOptions,
+    pathname::NameForm,
+    plan::{PlanAction, PlannedOp, TransferOrder},
+    probe,
+    remote::{self, RemoteSpec},
+    schedule::{self, Schedule},
+    store::{self, StoreSyncer},
+    sync::{FileAction, Syncer, TransferResult},
+    throttle,
+};
+use std::{
+    collections::HashSet,
+    env, fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+use tracing::{error, info, warn};
+use tracing_subscriber::{
+    EnvFilter,
+    filter::{LevelFilter, filter_fn},
+    fmt::{MakeWriter, writer::OptionalWriter},
+    prelude::*,
+};
+
 #[derive(Parser, Debug)]
-#[command(author, about, long_about = None)]
-struct Args {
+#[command(author, about, long_about = None, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    sync: SyncArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Sync a file or directory; the default when no subcommand is given
+    Sync(Box<SyncArgs>),
+    /// Run a server that receives network syncs
+    Daemon(DaemonArgs),
+    /// Write the block signature of a basis file, like `rdiff signature`
+    Signature(SignatureArgs),
+    /// Write the delta turning the file a signature describes into a new file
+    Delta(DeltaArgs),
+    /// Rebuild a file from its basis and a delta
+    Patch(PatchArgs),
+    /// Ask a server for its protocol version, features and modules without syncing
+    Probe(ProbeArgs),
+    /// List the paths, sizes, times, permissions and hashes beneath a path, or check a tree
+    /// against such a listing
+    Manifest(ManifestArgs),
+    /// Report the entries that differ between two trees, local or remote, without syncing
+    Diff(DiffArgs),
+    /// Revert the changes recorded in a --journal, restoring overwritten and deleted files
+    /// from their backups
+    Undo(UndoArgs),
+}
+
+#[derive(Args, Debug)]
+struct UndoArgs {
+    #[arg(
+        long = "journal",
+        value_name = "FILE",
+        help = "Journal written by the syncs to revert, newest change first"
+    )]
+    journal: PathBuf,
+}
+
+#[derive(Args, Debug)]
+struct DiffArgs {
+    #[arg(help = "First tree: a local path or a remote [user@]host:path")]
+    a: String,
+
+    #[arg(help = "Second tree: a local path or a remote [user@]host:path")]
+    b: String,
+
+    #[arg(
+        short = 'c',
+        long = "checksum",
+        default_value_t = false,
+        help = "Compare files by checksum, not size and modification time"
+    )]
+    checksum: bool,
+
+    #[arg(
+        long = "modify-window",
+        value_name = "SECONDS",
+        default_value_t = 0,
+        help = "Treat modification times this many seconds apart as equal"
+    )]
+    modify_window: u64,
+
+    #[arg(
+        long = "exclude",
+        value_name = "PATTERN",
+        help = "Leave out entries matching PATTERN; may be repeated"
+    )]
+    exclude: Vec<String>,
+
+    #[arg(
+        long = "json",
+        default_value_t = false,
+        help = "Print the differences as a JSON array"
+    )]
+    json: bool,
+
+    #[arg(
+        short = 'p',
+        long = "port",
+        default_value_t = 7878,
+        help = "Port of remote servers, unless their address names one"
+    )]
+    port: u16,
+}
+
+#[derive(Args, Debug)]
+struct ManifestArgs {
+    #[arg(help = "File or directory to list or check")]
+    path: PathBuf,
+
+    #[arg(
+        short = 'o',
+        long = "output",
+        value_name = "FILE",
+        default_value = "-",
+        help = "File to write the manifest to, or - for stdout"
+    )]
+    output: String,
+
+    #[arg(
+        long = "json",
+        default_value_t = false,
+        help = "Write JSON instead of mtree"
+    )]
+    json: bool,
+
+    #[arg(
+        long = "verify",
+        value_name = "MANIFEST",
+        help = "Check the path against MANIFEST (mtree or JSON, - for stdin) instead of writing one"
+    )]
+    verify: Option<String>,
+
+    #[arg(
+        long = "content-only",
+        default_value_t = false,
+        help = "With --verify, compare types, sizes, hashes and link targets but not times or permissions"
+    )]
+    content_only: bool,
+
+    #[arg(
+        long = "exclude",
+        value_name = "PATTERN",
+        help = "Leave out entries matching PATTERN; may be repeated"
+    )]
+    exclude: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct ProbeArgs {
+    #[arg(help = "Server to probe, as host[:port]")]
+    address: String,
+
+    #[arg(
+        short = 'p',
+        long = "port",
+        default_value_t = 7878,
+        help = "Port of the server, unless the address names one"
+    )]
+    port: u16,
+}
+
+#[derive(Args, Debug)]
+struct SignatureArgs {
+    #[arg(help = "Basis file, or - for stdin")]
+    basis: String,
+
+    #[arg(help = "Signature file to write, or - for stdout")]
+    signature: String,
+
     #[arg(
-        short = 's',
-        long = "server",
+        short = 'b',
+        long = "block-size",
+        default_value_t = 1024,
+        help = "Block size of the signature (in bytes)"
+    )]
+    block_size: usize,
+
+    #[arg(
+        long = "rdiff",
+        default_value_t = false,
+        help = "Write a librsync signature that rdiff can read"
+    )]
+    rdiff: bool,
+}
+
+#[derive(Args, Debug)]
+struct DeltaArgs {
+    #[arg(help = "Signature of the basis file, or - for stdin")]
+    signature: String,
+
+    #[arg(help = "New file, or - for stdin")]
+    new_file: String,
+
+    #[arg(help = "Delta file to write, or - for stdout")]
+    delta: String,
+}
+
+#[derive(Args, Debug)]
+struct PatchArgs {
+    #[arg(help = "Basis file the signature was taken from")]
+    basis: String,
+
+    #[arg(help = "Delta file, or - for stdin")]
+    delta: String,
+
+    #[arg(help = "File to write the rebuilt data to")]
+    output: String,
+}
+
+#[derive(Args, Debug)]
+struct DaemonArgs {
+    #[arg(
+        long = "config",
+        value_name = "FILE",
+        help = "Read the daemon settings and modules from a TOML file"
+    )]
+    config: Option<String>,
+
+    #[arg(
+        long = "address",
+        value_name = "ADDR",
+        help = "Address to listen on [default: 0.0.0.0]"
+    )]
+    address: Option<String>,
+
+    #[arg(short = 'p', long = "port", help = "Port to listen on [default: 7878]")]
+    port: Option<u16>,
+
+    #[arg(
+        short = 'b',
+        long = "block-size",
+        help = "Block size used for synchronization, in bytes [default: 1024]"
+    )]
+    block_size: Option<usize>,
+
+    #[arg(
+        long = "module",
+        value_name = "NAME=PATH",
+        value_parser = parse_module,
+        help = "Export PATH as module NAME without authentication; may be repeated"
+    )]
+    modules: Vec<(String, String)>,
+
+    #[arg(
+        long = "log-level",
+        value_name = "FILTER",
+        help = "Log filter such as info or rsynx=debug [default: RUST_LOG]"
+    )]
+    log_level: Option<String>,
+
+    #[arg(
+        long = "log-file",
+        value_name = "FILE",
+        help = "Also append timestamped logs of info level and above to FILE"
+    )]
+    log_file: Option<PathBuf>,
+
+    #[arg(
+        long = "metrics-address",
+        value_name = "HOST:PORT",
+        help = "Serve Prometheus metrics over HTTP at /metrics on HOST:PORT"
+    )]
+    metrics_address: Option<String>,
+
+    #[arg(
+        long = "preallocate",
+        default_value_t = false,
+        help = "Reserve the full size of each received file before accepting its data"
+    )]
+    preallocate: bool,
+
+    #[arg(
+        long = "read-only",
         default_value_t = false,
-        help = "Run in server mode"
+        help = "Refuse all writes, only serving listings"
     )]
-    server: bool,
+    read_only: bool,
+}
 
+#[derive(Args, Debug)]
+struct SyncArgs {
     #[arg(help = "Source path")]
     source: Option<String>,
 
@@ -42,14 +346,75 @@ struct Args {
     )]
     delete_extraneous: bool,
 
+    #[arg(
+        short = 'r',
+        long = "recursive",
+        default_value_t = false,
+        help = "Recurse into subdirectories; without it only a directory's top-level files are synced"
+    )]
+    recursive: bool,
+
+    #[arg(
+        short = 'l',
+        long = "links",
+        default_value_t = false,
+        help = "Copy symlinks as symlinks instead of the files they point to"
+    )]
+    preserve_links: bool,
+
+    #[arg(
+        short = 'o',
+        long = "owner",
+        default_value_t = false,
+        help = "Preserve owner and group (owner changes need root)"
+    )]
+    preserve_owner: bool,
+
+    #[arg(
+        short = 'a',
+        long = "archive",
+        default_value_t = false,
+        help = "Archive mode: same as -rmlo; devices and special files are skipped"
+    )]
+    archive: bool,
+
     #[arg(
         short = 'p',
         long = "port",
         default_value_t = 7878,
-        help = "Port number for server mode"
+        help = "Port of the remote daemon, unless the destination names one [default: 22 with --transport sftp]"
     )]
     port: u16,
 
+    #[arg(
+        long = "transport",
+        value_enum,
+        default_value_t = Transport::Rsynx,
+        help = "How to reach a remote destination: an rsynx daemon, or any SSH server over SFTP"
+    )]
+    transport: Transport,
+
+    #[arg(
+        long = "identity",
+        value_name = "FILE",
+        help = "With --transport sftp, authenticate with this private key"
+    )]
+    identity: Option<PathBuf>,
+
+    #[arg(
+        long = "signature-url",
+        value_name = "URL",
+        help = "With an http(s):// source, fetch its signature from URL instead of the source URL plus .sig"
+    )]
+    signature_url: Option<String>,
+
+    #[arg(
+        long = "encryption-key",
+        value_name = "FILE",
+        help = "Encrypt a store:// store with the 64-hex-digit key in FILE, or decrypt it on restore"
+    )]
+    encryption_key: Option<PathBuf>,
+
     #[arg(
         short = 'z',
         long = "compress",
@@ -57,54 +422,2040 @@ struct Args {
         help = "Enable compression during transfer"
     )]
     compress: bool,
-}
 
-fn main() -> Result<()> {
-    env_logger::init();
-    let args = Args::parse();
+    #[arg(
+        long = "bwlimit",
+        value_name = "RATE",
+        value_parser = parse_bwlimit,
+        help = "Limit transfer and write bandwidth, e.g. 500k or 10m; a bare number is KiB/s"
+    )]
+    bwlimit: Option<u64>,
+
+    #[arg(
+        long = "disk-limit",
+        value_name = "RATE",
+        value_parser = parse_bwlimit,
+        help = "Limit local disk reads and writes while scanning and rebuilding files, e.g. 20m"
+    )]
+    disk_limit: Option<u64>,
 
-    // Validate block_size
-    if args.block_size == 0 {
-        return Err(anyhow::anyhow!("Block size cannot be zero"));
-    }
+    #[arg(
+        short = 'c',
+        long = "checksum",
+        default_value_t = false,
+        help = "Skip files based on checksum, not size and modification time"
+    )]
+    checksum: bool,
 
-    if args.server {
-        println!("Starting server on port {}", args.port);
-        NetworkSyncer::serve(args.port, args.block_size)?;
-        // Server runs indefinitely, this line should never be reached
-    } else {
-        let source = args
-            .source
-            .ok_or_else(|| anyhow::anyhow!("Source path required in client mode"))?;
-        let destination = args
-            .destination
-            .ok_or_else(|| anyhow::anyhow!("Destination path required in client mode"))?;
-        println!("Syncing {} to {}", source, destination);
-
-        if destination.contains(":") {
-            let parts = destination.split(":").collect::<Vec<&str>>();
-            let syncer = NetworkSyncer::new(
-                parts[0].to_string(),
-                args.port,
-                source,
-                parts[1].to_string(),
-            )
-            .with_block_size(args.block_size)
-            .with_compression(args.compress);
-            let _result = syncer.sync().with_context(|| "Failed to sync")?;
-            println!("Sync complete!");
-        } else {
-            let syncer = LocalSyncer::new(source, destination)
-                .with_block_size(args.block_size)
-                .with_preserve_metadata(args.preserve_metadata)
-                .with_delete_extraneous(args.delete_extraneous)
-                .with_compression(args.compress);
-            let result = syncer.sync().with_context(|| "Failed to sync")?;
-            println!(
-                "Transferred: {} bytes, Not transferred: {} bytes",
-                result.new_bytes, result.reused_bytes
-            );
-        }
-    }
-    Ok(())
+    #[arg(
+        long = "modify-window",
+        value_name = "SECONDS",
+        default_value_t = 0,
+        help = "Treat modification times this many seconds apart as equal (1 for FAT destinations)"
+    )]
+    modify_window: u64,
+
+    #[arg(
+        short = 'I',
+        long = "ignore-times",
+        default_value_t = false,
+        help = "Don't skip files whose size and modification time match"
+    )]
+    ignore_times: bool,
+
+    #[arg(
+        long = "verify",
+        default_value_t = false,
+        help = "Re-hash each destination file after syncing and fail on mismatch"
+    )]
+    verify: bool,
+
+    #[arg(
+        long = "checkpoint",
+        default_value_t = false,
+        help = "Record directory sync progress so an interrupted sync can resume"
+    )]
+    checkpoint: bool,
+
+    #[arg(
+        long = "partial",
+        default_value_t = false,
+        help = "Keep the data of an interrupted file transfer and resume from it next time"
+    )]
+    partial: bool,
+
+    #[arg(
+        long = "partial-dir",
+        value_name = "DIR",
+        help = "Keep partial files in DIR, relative to each destination's directory; implies --partial"
+    )]
+    partial_dir: Option<PathBuf>,
+
+    #[arg(
+        long = "link-dest",
+        value_name = "DIR",
+        help = "Hard-link files unchanged from their copy in DIR, relative to the destination"
+    )]
+    link_dest: Option<PathBuf>,
+
+    #[arg(
+        long = "delete-to",
+        value_name = "DIR",
+        help = "Move extraneous files into DIR, relative to the destination, instead of deleting them; implies --delete"
+    )]
+    delete_to: Option<PathBuf>,
+
+    #[arg(
+        long = "conflicts",
+        value_name = "POLICY",
+        help = "Track destination files between syncs and skip, overwrite or rename those changed there since the last one"
+    )]
+    conflicts: Option<ConflictPolicy>,
+
+    #[arg(
+        long = "journal",
+        value_name = "FILE",
+        help = "Append a JSON line to FILE for every file written or deleted, with its old and new SHA-256"
+    )]
+    journal: Option<PathBuf>,
+
+    #[arg(
+        long = "backup-dir",
+        value_name = "DIR",
+        help = "Keep overwritten and deleted files in DIR, relative to the destination, so `rsynx undo` can restore them"
+    )]
+    backup_dir: Option<PathBuf>,
+
+    #[arg(
+        long = "atomic",
+        default_value_t = false,
+        help = "Stage every changed file of a directory sync and move them into place, then delete, only once all are written"
+    )]
+    atomic: bool,
+
+    #[arg(
+        long = "dedup",
+        default_value_t = false,
+        help = "After a directory sync, replace identical files in the destination with hard links to one copy"
+    )]
+    dedup: bool,
+
+    #[arg(
+        long = "order",
+        value_name = "ORDER",
+        help = "Transfer files of a directory sync as-found, smallest-first, largest-first or newest-first"
+    )]
+    order: Option<TransferOrder>,
+
+    #[arg(
+        long = "preallocate",
+        default_value_t = false,
+        help = "Reserve the full size of each file before writing it, failing early when the disk is too full"
+    )]
+    preallocate: bool,
+
+    #[arg(
+        long = "direct-io",
+        default_value_t = false,
+        help = "Keep the files a delta transfer scans and rebuilds out of the page cache (O_DIRECT where supported)"
+    )]
+    direct_io: bool,
+
+    #[arg(
+        long = "unicode-normalize",
+        value_name = "FORM",
+        help = "Treat file names differing only in Unicode normalization as the same, naming new ones in FORM (nfc or nfd)"
+    )]
+    unicode_normalize: Option<NameForm>,
+
+    #[arg(
+        long = "notify-url",
+        value_name = "URL",
+        help = "At the end of each run, POST its status and totals as JSON to URL"
+    )]
+    notify_url: Option<String>,
+
+    #[arg(
+        long = "notify-cmd",
+        value_name = "COMMAND",
+        help = "At the end of each run, run COMMAND through the shell with its status and totals as JSON on stdin"
+    )]
+    notify_cmd: Option<String>,
+
+    #[arg(
+        long = "snapshot",
+        default_value_t = false,
+        help = "Sync into a new timestamped directory of the destination, hard-linking files unchanged since the last one; implies -r and -m"
+    )]
+    snapshot: bool,
+
+    #[arg(
+        long = "keep-snapshots",
+        value_name = "N",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        requires = "snapshot",
+        help = "With --snapshot, delete all but the newest N snapshots afterwards"
+    )]
+    keep_snapshots: Option<usize>,
+
+    #[arg(
+        short = 'T',
+        long = "temp-dir",
+        help = "Directory for temporary files while reconstructing (local syncs only)"
+    )]
+    temp_dir: Option<String>,
+
+    #[arg(
+        long = "exclude",
+        value_name = "PATTERN",
+        help = "Exclude directory entries matching PATTERN; may be repeated"
+    )]
+    exclude: Vec<String>,
+
+    #[arg(
+        long = "include",
+        value_name = "PATTERN",
+        help = "Don't exclude entries matching PATTERN; the first matching rule wins"
+    )]
+    include: Vec<String>,
+
+    #[arg(
+        long = "exclude-from",
+        value_name = "FILE",
+        help = "Read exclude patterns from FILE, one per line"
+    )]
+    exclude_from: Vec<String>,
+
+    #[arg(
+        long = "progress",
+        default_value_t = false,
+        overrides_with = "no_progress",
+        help = "Show progress bars even when stdout isn't a terminal"
+    )]
+    progress: bool,
+
+    #[arg(
+        long = "no-progress",
+        default_value_t = false,
+        overrides_with = "progress",
+        help = "Never show progress bars"
+    )]
+    no_progress: bool,
+
+    #[arg(
+        long = "config",
+        value_name = "FILE",
+        help = "Read default options from FILE instead of ~/.config/rsynx/config.toml"
+    )]
+    config: Option<String>,
+
+    #[arg(
+        long = "profile",
+        value_name = "NAME",
+        help = "Apply the named profile from the config file"
+    )]
+    profile: Option<String>,
+
+    #[arg(
+        long = "log-file",
+        value_name = "FILE",
+        help = "Also append timestamped logs of every file action and error to FILE"
+    )]
+    log_file: Option<PathBuf>,
+
+    #[arg(
+        short = 'i',
+        long = "itemize-changes",
+        default_value_t = false,
+        help = "Print a change summary line, such as >f.st...... for each changed path"
+    )]
+    itemize_changes: bool,
+
+    #[arg(
+        long = "stats",
+        default_value_t = false,
+        help = "Print transfer statistics after the sync"
+    )]
+    stats: bool,
+
+    #[arg(
+        short = 'n',
+        long = "dry-run",
+        default_value_t = false,
+        help = "Show what would be transferred or deleted without changing anything"
+    )]
+    dry_run: bool,
+
+    #[arg(
+        long = "batch-from",
+        value_name = "FILE",
+        help = "Sync each SOURCE DESTINATION pair listed in FILE, one per line (- for stdin)"
+    )]
+    batch_from: Option<String>,
+
+    #[arg(
+        short = 'j',
+        long = "jobs",
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        help = "With --batch-from, sync up to N pairs at once"
+    )]
+    jobs: usize,
+
+    #[arg(
+        long = "every",
+        value_name = "INTERVAL",
+        value_parser = parse_every,
+        help = "Keep running and repeat the sync every INTERVAL (such as 30s, 5m or 1h)"
+    )]
+    every: Option<Duration>,
+
+    #[arg(
+        long = "interactive",
+        default_value_t = false,
+        help = "Ask before deleting files or overwriting files that differ significantly"
+    )]
+    interactive: bool,
+
+    #[arg(
+        long = "confirm-threshold",
+        value_name = "PERCENT",
+        default_value_t = 50,
+        value_parser = clap::value_parser!(u8).range(0..=100),
+        help = "With --interactive, ask before overwrites changing at least PERCENT% of a file"
+    )]
+    confirm_threshold: u8,
+
+    #[arg(
+        short = 'v',
+        long = "verbose",
+        action = ArgAction::Count,
+        help = "Print a line per changed file; -vv adds skipped files and debug logs"
+    )]
+    verbose: u8,
+
+    #[arg(
+        short = 'q',
+        long = "quiet",
+        action = ArgAction::Count,
+        conflicts_with = "verbose",
+        help = "Print nothing but errors"
+    )]
+    quiet: u8,
+}
+
+/// Protocol spoken to a remote destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Transport {
+    /// An rsynx daemon, which computes deltas on its side.
+    Rsynx,
+    /// Any SSH server over SFTP; changed files are sent whole.
+    Sftp,
+}
+
+/// How much a sync prints to stdout, set with `-q` and `-v`. Errors and prompts go to stderr
+/// whatever the level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Verbosity {
+    Quiet,
+    /// Summaries, and whatever was asked for explicitly, such as `--stats` or `-i`.
+    Normal,
+    /// A line per changed file.
+    Verbose,
+    /// Skipped files and transfer sizes too, with debug logs on stderr.
+    Debug,
+}
+
+impl Verbosity {
+    fn from_counts(verbose: u8, quiet: u8) -> Self {
+        match (verbose, quiet) {
+            (_, 1..) => Verbosity::Quiet,
+            (0, _) => Verbosity::Normal,
+            (1, _) => Verbosity::Verbose,
+            _ => Verbosity::Debug,
+        }
+    }
+}
+
+/// Print a line on stdout when `$verbosity` is at least `Verbosity::$level`.
+macro_rules! say {
+    ($verbosity:expr, $level:ident, $($arg:tt)*) => {
+        if $verbosity >= Verbosity::$level {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Print what `-i` or `-v` asks for about `event`; itemized lines replace the `-v` ones.
+/// Conflicts are printed at every verbosity but `-q`.
+fn print_event(event: &SyncEvent, itemizer: Option<&Itemizer>, verbosity: Verbosity) {
+    let line = match itemizer {
+        _ if matches!(event, SyncEvent::Conflict { .. }) => describe_event(event, verbosity),
+        Some(itemizer) => itemizer.itemize(event),
+        None if verbosity >= Verbosity::Verbose => describe_event(event, verbosity),
+        None => None,
+    };
+    if let Some(line) = line {
+        say!(verbosity, Normal, "{}", line);
+    }
+}
+
+/// The `-v` line for `event`, if it changed something or, at `-vv`, skipped a file.
+fn describe_event(event: &SyncEvent, verbosity: Verbosity) -> Option<String> {
+    match event {
+        SyncEvent::FileCompleted(record) => {
+            let action = match record.action {
+                FileAction::Created => "created",
+                FileAction::Updated => "updated",
+                FileAction::Skipped | FileAction::Deleted => return None,
+            };
+            if verbosity >= Verbosity::Debug {
+                Some(format!(
+                    "{} {} ({} bytes literal, {} bytes matched)",
+                    action,
+                    record.path.display(),
+                    record.literal_bytes,
+                    record.reused_bytes
+                ))
+            } else {
+                Some(format!("{} {}", action, record.path.display()))
+            }
+        }
+        SyncEvent::Deleted { path } => Some(format!("deleted {}", path.display())),
+        SyncEvent::Conflict {
+            path,
+            policy,
+            renamed_to,
+        } => Some(match (policy, renamed_to) {
+            (_, Some(aside)) => format!(
+                "conflict {} changed since the last sync; moved to {}",
+                path.display(),
+                aside.display()
+            ),
+            (ConflictPolicy::Skip, None) => format!(
+                "conflict {} changed since the last sync; kept",
+                path.display()
+            ),
+            (_, None) => format!(
+                "conflict {} changed since the last sync; overwritten",
+                path.display()
+            ),
+        }),
+        SyncEvent::FileSkipped { path } if verbosity >= Verbosity::Debug => {
+            Some(format!("skipped {}", path.display()))
+        }
+        _ => None,
+    }
+}
+
+/// Print a dry-run plan, one line per change, followed by totals.
+fn print_plan(plan: &[PlannedOp]) {
+    let count = |action| plan.iter().filter(|op| op.action == action).count();
+    for op in plan.iter().filter(|op| op.action != PlanAction::Skip) {
+        let suffix = if op.is_dir { "/" } else { "" };
+        println!("{:<6} {}{}", op.action, op.destination.display(), suffix);
+    }
+    let bytes: u64 = plan
+        .iter()
+        .filter(|op| matches!(op.action, PlanAction::Create | PlanAction::Update))
+        .map(|op| op.size)
+        .sum();
+    println!(
+        "Would create: {}, update: {}, delete: {}, skip: {}; {} bytes to sync (dry run)",
+        count(PlanAction::Create),
+        count(PlanAction::Update),
+        count(PlanAction::Delete),
+        count(PlanAction::Skip),
+        bytes
+    );
+}
+
+/// Ask before the deletions in `plan` and the updates changing at least `threshold` percent
+/// of their destination, returning the plan without the declined ones. Nothing has changed
+/// yet, so without a terminal to ask on, or when the user quits, the sync is refused.
+fn confirm_plan(plan: Vec<PlannedOp>, threshold: u8, syncer: &Syncer) -> Result<Vec<PlannedOp>> {
+    let mut destructive = Vec::new();
+    for (index, op) in plan.iter().enumerate() {
+        let description = match (op.action, &op.source) {
+            (PlanAction::Delete, _) if op.is_dir => {
+                format!("delete {}/ and everything in it", op.destination.display())
+            }
+            (PlanAction::Delete, _) => format!("delete {}", op.destination.display()),
+            (PlanAction::Update, Some(source)) if op.destination.is_file() => {
+                let changed = syncer.changed_fraction(source, &op.destination)? * 100.0;
+                if changed < f64::from(threshold) {
+                    continue;
+                }
+                format!(
+                    "overwrite {} ({:.0}% changed)",
+                    op.destination.display(),
+                    changed
+                )
+            }
+            _ => continue,
+        };
+        destructive.push((index, description));
+    }
+    if destructive.is_empty() {
+        return Ok(plan);
+    }
+    if !io::stdin().is_terminal() {
+        return Err(UsageError(format!(
+            "--interactive needs a terminal to confirm {} destructive change(s); nothing was changed",
+            destructive.len()
+        ))
+        .into());
+    }
+
+    eprintln!("{} destructive change(s):", destructive.len());
+    for (_, description) in &destructive {
+        eprintln!("  {}", description);
+    }
+    let mut declined = HashSet::new();
+    match ask("Apply them? [y]es, [n]o, [e]ach, [q]uit: ", "yneq")? {
+        'y' => {}
+        'n' => declined.extend(destructive.iter().map(|(index, _)| *index)),
+        'e' => {
+            for (index, description) in &destructive {
+                if ask(&format!("{}? [y/n] ", description), "yn")? == 'n' {
+                    declined.insert(*index);
+                }
+            }
+        }
+        _ => anyhow::bail!("Sync aborted; nothing was changed"),
+    }
+    Ok(plan
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| !declined.contains(index))
+        .map(|(_, op)| op)
+        .collect())
+}
+
+/// Prompt on stderr until the user answers with one of `choices`. End of input aborts.
+fn ask(prompt: &str, choices: &str) -> Result<char> {
+    let mut line = String::new();
+    loop {
+        eprint!("{}", prompt);
+        io::stderr().flush()?;
+        line.clear();
+        if io::stdin().read_line(&mut line)? == 0 {
+            anyhow::bail!("No answer to the confirmation prompt; nothing was changed");
+        }
+        let answer = line.trim().chars().next().map(|c| c.to_ascii_lowercase());
+        if let Some(answer) = answer.filter(|&answer| choices.contains(answer)) {
+            return Ok(answer);
+        }
+    }
+}
+
+/// Print the `--stats` summary of a finished sync.
+fn print_stats(result: &TransferResult, elapsed: Duration) {
+    let transferred = result.created_files + result.updated_files;
+    let total_size = result.new_bytes + result.reused_bytes;
+    let seconds = elapsed.as_secs_f64();
+    let throughput = if seconds > 0.0 {
+        total_size as f64 / seconds
+    } else {
+        0.0
+    };
+    println!();
+    println!(
+        "Number of files: {} (transferred: {}, unchanged: {})",
+        transferred + result.skipped_files,
+        transferred,
+        result.skipped_files
+    );
+    println!(
+        "Number of created files: {}, updated files: {}, deleted files: {}",
+        result.created_files, result.updated_files, result.deleted_files
+    );
+    println!("Total file size: {} bytes", total_size);
+    println!("Literal data: {} bytes", result.new_bytes);
+    println!("Matched data: {} bytes", result.reused_bytes);
+    println!(
+        "Compression saved: {} bytes",
+        result.compression_saved_bytes
+    );
+    if result.deduplicated_files > 0 {
+        println!(
+            "Deduplicated files: {} ({} bytes reclaimed)",
+            result.deduplicated_files, result.reclaimed_bytes
+        );
+    }
+    println!("Elapsed time: {:.3} seconds", seconds);
+    println!("Throughput: {:.0} bytes/sec", throughput);
+}
+
+fn parse_bwlimit(spec: &str) -> std::result::Result<u64, String> {
+    throttle::parse_rate(spec).ok_or_else(|| "expected a rate such as 500k or 10m".to_string())
+}
+
+fn parse_every(spec: &str) -> std::result::Result<Duration, String> {
+    schedule::parse_interval(spec)
+        .ok_or_else(|| "expected an interval such as 30s, 5m or 1h".to_string())
+}
+
+/// Whether to draw progress bars: on when asked for, off when suppressed, and otherwise only
+/// when stdout is a terminal so cron jobs and CI logs stay clean.
+fn show_progress(force: bool, suppress: bool) -> bool {
+    !suppress && (force || io::stdout().is_terminal())
+}
+
+/// Build the filter from `--include`, `--exclude` and `--exclude-from`, keeping the order in
+/// which they were given on the command line.
+fn filter_from_matches(matches: &ArgMatches) -> Result<Filter> {
+    let indexed = |id: &str| -> Vec<(usize, String)> {
+        match (matches.indices_of(id), matches.get_many::<String>(id)) {
+            (Some(indices), Some(values)) => indices.zip(values.cloned()).collect(),
+            _ => Vec::new(),
+        }
+    };
+    let mut rules = Vec::new();
+    for (index, pattern) in indexed("include") {
+        rules.push((index, vec![FilterRule::include(&pattern)]));
+    }
+    for (index, pattern) in indexed("exclude") {
+        rules.push((index, vec![FilterRule::exclude(&pattern)]));
+    }
+    for (index, path) in indexed("exclude_from") {
+        rules.push((index, Filter::read_rules(Path::new(&path))?));
+    }
+    rules.sort_by_key(|(index, _)| *index);
+    let mut filter = Filter::new();
+    for rule in rules.into_iter().flat_map(|(_, rules)| rules) {
+        filter.push(rule);
+    }
+    Ok(filter)
+}
+
+/// Load the config file and profile, if any. An explicitly given config file must exist;
+/// the default one is optional.
+fn load_config(args: &SyncArgs) -> Result<ConfigOptions> {
+    let config = match &args.config {
+        Some(path) => Config::load(Path::new(path))?,
+        None => match Config::default_path().filter(|path| path.is_file()) {
+            Some(path) => Config::load(&path)?,
+            None => Config::default(),
+        },
+    };
+    Ok(config.resolve(args.profile.as_deref())?)
+}
+
+/// Fill in every option not given on the command line from `config`. Config excludes are
+/// appended to `filter`, after the command line's rules.
+fn apply_config(
+    args: &mut SyncArgs,
+    matches: &ArgMatches,
+    config: ConfigOptions,
+    filter: &mut Filter,
+) -> Result<()> {
+    let unset = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+    if let Some(block_size) = config.block_size.filter(|_| unset("block_size")) {
+        args.block_size = block_size;
+    }
+    if let Some(window) = config.modify_window.filter(|_| unset("modify_window")) {
+        args.modify_window = window;
+    }
+    if let Some(port) = config.port.filter(|_| unset("port")) {
+        args.port = port;
+    } else if unset("port") && args.transport == Transport::Sftp {
+        args.port = 22;
+    }
+    if let Some(rate) = config.bwlimit.filter(|_| unset("bwlimit")) {
+        args.bwlimit = Some(
+            throttle::parse_rate(&rate)
+                .ok_or_else(|| UsageError(format!("Invalid bwlimit {:?} in config", rate)))?,
+        );
+    }
+    if let Some(rate) = config.disk_limit.filter(|_| unset("disk_limit")) {
+        args.disk_limit = Some(
+            throttle::parse_rate(&rate)
+                .ok_or_else(|| UsageError(format!("Invalid disk-limit {:?} in config", rate)))?,
+        );
+    }
+    args.compress |= config.compress.unwrap_or(false);
+    args.preserve_metadata |= config.metadata.unwrap_or(false);
+    args.recursive |= config.recursive.unwrap_or(false);
+    args.delete_extraneous |= config.delete.unwrap_or(false);
+    args.checksum |= config.checksum.unwrap_or(false);
+    if args.destination.is_none() {
+        args.destination = config.destination;
+    }
+    if args.notify_url.is_none() {
+        args.notify_url = config.notify_url;
+    }
+    if args.notify_cmd.is_none() {
+        args.notify_cmd = config.notify_cmd;
+    }
+    for pattern in &config.exclude {
+        filter.push(FilterRule::exclude(pattern));
+    }
+    Ok(())
+}
+
+/// Exit codes, documented in the README. Clap reports its own usage errors with 2 as well.
+const EXIT_ERROR: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+/// Post-sync verification found a corrupted destination.
+const EXIT_VERIFY_FAILED: i32 = 3;
+/// The server couldn't be reached, refused the request or spoke an unexpected protocol.
+const EXIT_CONNECTION: i32 = 4;
+/// The sync failed after it had already changed some destination files, or some entries of
+/// a batch failed while others were synced.
+const EXIT_PARTIAL: i32 = 5;
+/// The sync finished, but some source entries could not be transferred.
+const EXIT_SKIPPED_FILES: i32 = 6;
+/// Another rsynx process was already syncing into the destination.
+const EXIT_LOCKED: i32 = 7;
+
+/// A mistake in how rsynx was invoked rather than a failure of the sync itself.
+#[derive(Debug)]
+struct UsageError(String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+/// Context of a sync that failed after changing this many destination entries.
+#[derive(Debug)]
+struct PartialTransfer(usize);
+
+impl fmt::Display for PartialTransfer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Partial transfer: {} entries changed before the failure",
+            self.0
+        )
+    }
+}
+
+/// A sync that completed without transferring this many unsupported source entries.
+#[derive(Debug)]
+struct SkippedFiles(usize);
+
+impl fmt::Display for SkippedFiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} source entries could not be transferred, such as sockets or devices",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for SkippedFiles {}
+
+/// A tree that differs from its manifest in this many entries, each already printed.
+#[derive(Debug)]
+struct ManifestMismatch(usize);
+
+impl fmt::Display for ManifestMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} entries don't match the manifest", self.0)
+    }
+}
+
+impl std::error::Error for ManifestMismatch {}
+
+/// Trees compared by `diff` that differ in this many entries, each already printed.
+#[derive(Debug)]
+struct TreesDiffer(usize);
+
+impl fmt::Display for TreesDiffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} entries differ", self.0)
+    }
+}
+
+impl std::error::Error for TreesDiffer {}
+
+/// A batch in which some entries failed; each failure was already reported.
+#[derive(Debug)]
+struct BatchFailures {
+    failed: usize,
+    total: usize,
+}
+
+impl fmt::Display for BatchFailures {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} of {} batch entries failed", self.failed, self.total)
+    }
+}
+
+impl std::error::Error for BatchFailures {}
+
+/// An undo that couldn't revert this many journal entries; each was already reported.
+#[derive(Debug)]
+struct UndoIncomplete {
+    skipped: usize,
+    total: usize,
+}
+
+impl fmt::Display for UndoIncomplete {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} journal entries could not be reverted",
+            self.skipped, self.total
+        )
+    }
+}
+
+impl std::error::Error for UndoIncomplete {}
+
+/// The exit code reporting `e`, the most specific cause winning.
+fn exit_code(e: &anyhow::Error) -> i32 {
+    if e.downcast_ref::<UsageError>().is_some() {
+        return EXIT_USAGE;
+    }
+    if e.downcast_ref::<SkippedFiles>().is_some() {
+        return EXIT_SKIPPED_FILES;
+    }
+    if e.downcast_ref::<ManifestMismatch>().is_some() || e.downcast_ref::<TreesDiffer>().is_some() {
+        return EXIT_VERIFY_FAILED;
+    }
+    if let Some(batch) = e.downcast_ref::<BatchFailures>() {
+        return if batch.failed < batch.total {
+            EXIT_PARTIAL
+        } else {
+            EXIT_ERROR
+        };
+    }
+    if let Some(undo) = e.downcast_ref::<UndoIncomplete>() {
+        return if undo.skipped < undo.total {
+            EXIT_PARTIAL
+        } else {
+            EXIT_ERROR
+        };
+    }
+    match e
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<SyncError>())
+    {
+        Some(SyncError::ChecksumMismatch { .. }) => EXIT_VERIFY_FAILED,
+        Some(SyncError::Locked { .. }) => EXIT_LOCKED,
+        Some(
+            SyncError::EmptyPath(_)
+            | SyncError::InvalidBlockSize(_)
+            | SyncError::ConflictingOptions(_)
+            | SyncError::Config(_),
+        ) => EXIT_USAGE,
+        Some(SyncError::Connect { .. } | SyncError::Refused(_) | SyncError::Protocol(_)) => {
+            EXIT_CONNECTION
+        }
+        _ if e.downcast_ref::<PartialTransfer>().is_some() => EXIT_PARTIAL,
+        _ => EXIT_ERROR,
+    }
+}
+
+/// Target of the event recording why a run failed, which main already prints to stderr.
+const FAILURE_TARGET: &str = "rsynx::failure";
+
+/// Log to stderr, filtered by `filter` or else by `RUST_LOG`. With a log file, every event at
+/// info level or above is also appended there with a timestamp, whatever the stderr filter.
+fn init_tracing(filter: Option<&str>, log_file: Option<&Path>) -> Result<()> {
+    let filter = filter.map_or_else(EnvFilter::from_default_env, EnvFilter::new);
+    let stderr = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_filter(filter)
+        .with_filter(filter_fn(|meta| meta.target() != FAILURE_TARGET));
+    let file = match log_file {
+        Some(path) => Some(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(LogFile::new(path)?)
+                .with_filter(LevelFilter::INFO),
+        ),
+        None => None,
+    };
+    tracing_subscriber::registry()
+        .with(stderr)
+        .with(file)
+        .init();
+    Ok(())
+}
+
+/// Log file reopened in append mode for every event, so logrotate can move it away without
+/// a restart and concurrent runs don't interleave partial lines.
+struct LogFile {
+    path: PathBuf,
+}
+
+impl LogFile {
+    /// Check that `path` can be appended to before any event depends on it.
+    fn new(path: &Path) -> Result<Self> {
+        Self::open(path).with_context(|| format!("Failed to open log file {:?}", path))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+
+    fn open(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogFile {
+    type Writer = OptionalWriter<File>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        // Losing a log line beats failing the sync
+        Self::open(&self.path).ok().into()
+    }
+}
+
+fn parse_module(spec: &str) -> std::result::Result<(String, String), String> {
+    match spec.split_once('=') {
+        Some((name, path)) if !name.is_empty() && !path.is_empty() => {
+            Ok((name.to_string(), path.to_string()))
+        }
+        _ => Err("expected NAME=PATH".to_string()),
+    }
+}
+
+fn main() {
+    if let Err(e) = run() {
+        error!(target: FAILURE_TARGET, "{:#}", e);
+        eprintln!("Error: {:?}", e);
+        std::process::exit(exit_code(&e));
+    }
+}
+
+fn run() -> Result<()> {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches)?;
+    match cli.command {
+        Some(Command::Daemon(args)) => run_daemon(args),
+        Some(Command::Signature(args)) => run_signature(args),
+        Some(Command::Delta(args)) => run_delta(args),
+        Some(Command::Patch(args)) => run_patch(args),
+        Some(Command::Probe(args)) => run_probe(args),
+        Some(Command::Manifest(args)) => run_manifest(args),
+        Some(Command::Diff(args)) => run_diff(args),
+        Some(Command::Undo(args)) => run_undo(args),
+        Some(Command::Sync(args)) => {
+            let sync_matches = matches
+                .subcommand_matches("sync")
+                .expect("parsed a sync subcommand");
+            run_sync(*args, sync_matches)
+        }
+        None => run_sync(cli.sync, &matches),
+    }
+}
+
+fn run_daemon(args: DaemonArgs) -> Result<()> {
+    let mut config = match &args.config {
+        Some(path) => DaemonConfig::load(Path::new(path))?,
+        None => DaemonConfig::default(),
+    };
+    if let Some(address) = args.address {
+        config.address = address;
+    }
+    if let Some(port) = args.port {
+        config.port = port;
+    }
+    if let Some(block_size) = args.block_size {
+        config.block_size = block_size;
+    }
+    if args.log_level.is_some() {
+        config.log_level = args.log_level;
+    }
+    if args.metrics_address.is_some() {
+        config.metrics_address = args.metrics_address;
+    }
+    if args.preallocate {
+        config.preallocate = true;
+    }
+    if args.read_only {
+        config.read_only = true;
+    }
+    for (name, path) in args.modules {
+        config = config.with_module(name, path);
+    }
+    init_tracing(config.log_level.as_deref(), args.log_file.as_deref())?;
+    let daemon = Daemon::new(config).with_context(|| "Invalid daemon configuration")?;
+    let config = daemon.config();
+    println!(
+        "Starting daemon on {}:{} with {} module(s)",
+        config.address,
+        config.port,
+        config.modules.len()
+    );
+    daemon.serve()?;
+    Ok(())
+}
+
+/// Open `path` for reading, with `-` meaning stdin.
+fn open_input(path: &str) -> Result<Box<dyn Read>> {
+    if path == "-" {
+        return Ok(Box::new(io::stdin().lock()));
+    }
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+    Ok(Box::new(BufReader::new(file)))
+}
+
+/// Create `path` for writing, with `-` meaning stdout.
+fn create_output(path: &str) -> Result<Box<dyn Write>> {
+    if path == "-" {
+        return Ok(Box::new(io::stdout().lock()));
+    }
+    let file = File::create(path).with_context(|| format!("Failed to create {}", path))?;
+    Ok(Box::new(BufWriter::new(file)))
+}
+
+fn run_signature(args: SignatureArgs) -> Result<()> {
+    init_tracing(None, None)?;
+    let format = if args.rdiff {
+        DeltaFormat::Rdiff
+    } else {
+        DeltaFormat::Native
+    };
+    let options = SyncOptions::new().with_block_size(args.block_size);
+    options.validate()?;
+    let syncer = Syncer::with_options(options);
+    let signature = syncer
+        .signature_from_reader(open_input(&args.basis)?, format)
+        .with_context(|| format!("Failed to compute the signature of {}", args.basis))?;
+    signature.write_to(create_output(&args.signature)?, format)?;
+    Ok(())
+}
+
+/// Write the manifest of `args.path`, or with `--verify` report how it differs from one.
+fn run_manifest(args: ManifestArgs) -> Result<()> {
+    init_tracing(None, None)?;
+    let filter = args
+        .exclude
+        .iter()
+        .fold(Filter::new(), |filter, pattern| filter.exclude(pattern));
+    let Some(expected) = &args.verify else {
+        let entries = manifest::build(&args.path, &filter, true)
+            .with_context(|| format!("Failed to list {:?}", args.path))?;
+        let format = if args.json {
+            ManifestFormat::Json
+        } else {
+            ManifestFormat::Mtree
+        };
+        manifest::write(&entries, format, create_output(&args.output)?)?;
+        return Ok(());
+    };
+    let expected = manifest::read(open_input(expected)?)
+        .with_context(|| format!("Failed to read manifest {}", expected))?;
+    let differences = manifest::verify(&args.path, &filter, &expected, args.content_only)
+        .with_context(|| format!("Failed to list {:?}", args.path))?;
+    for difference in &differences {
+        println!("{}", difference);
+    }
+    if !differences.is_empty() {
+        return Err(ManifestMismatch(differences.len()).into());
+    }
+    Ok(())
+}
+
+/// Revert what the journal `args.journal` records, reporting each entry.
+fn run_undo(args: UndoArgs) -> Result<()> {
+    init_tracing(None, None)?;
+    let file = File::open(&args.journal)
+        .with_context(|| format!("Failed to open journal {:?}", args.journal))?;
+    let entries = journal::read(file)
+        .with_context(|| format!("Failed to read journal {:?}", args.journal))?;
+    let outcomes = journal::undo(&entries);
+    for outcome in &outcomes {
+        match outcome {
+            UndoOutcome::Reverted(_) => println!("{}", outcome),
+            UndoOutcome::Skipped { .. } => eprintln!("{}", outcome),
+        }
+    }
+    let skipped = outcomes
+        .iter()
+        .filter(|outcome| matches!(outcome, UndoOutcome::Skipped { .. }))
+        .count();
+    if skipped > 0 {
+        return Err(UndoIncomplete {
+            skipped,
+            total: outcomes.len(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Print how the trees `args.a` and `args.b` differ, failing when they do.
+fn run_diff(args: DiffArgs) -> Result<()> {
+    init_tracing(None, None)?;
+    let filter = args
+        .exclude
+        .iter()
+        .fold(Filter::new(), |filter, pattern| filter.exclude(pattern));
+    let list = |tree: &str| -> Result<Vec<ManifestEntry>> {
+        let listing = match RemoteSpec::parse(tree) {
+            Some(remote) => network_syncer(&remote, "", args.port, SyncOptions::new())
+                .list_destination(args.checksum),
+            None => manifest::build(Path::new(tree), &filter, args.checksum),
+        };
+        listing.with_context(|| format!("Failed to list {}", tree))
+    };
+    let (a, b) = (list(&args.a)?, list(&args.b)?);
+    let syncer = Syncer::with_options(
+        SyncOptions::new()
+            .with_checksum(args.checksum)
+            .with_modify_window(args.modify_window)
+            .with_filter(filter),
+    );
+    let differences = syncer.diff_listings(&a, &b);
+    if args.json {
+        serde_json::to_writer_pretty(io::stdout().lock(), &differences)?;
+        println!();
+    } else {
+        for difference in &differences {
+            println!("{}", difference);
+        }
+    }
+    if !differences.is_empty() {
+        return Err(TreesDiffer(differences.len()).into());
+    }
+    Ok(())
+}
+
+fn run_delta(args: DeltaArgs) -> Result<()> {
+    init_tracing(None, None)?;
+    if args.signature == "-" && args.new_file == "-" {
+        return Err(UsageError(
+            "The signature and the new file can't both be read from stdin".to_string(),
+        )
+        .into());
+    }
+    let signature = Signature::read_from(open_input(&args.signature)?)
+        .with_context(|| format!("Failed to read signature {}", args.signature))?;
+    // Deltas against librsync signatures are written so rdiff can apply them too
+    let format = match signature.weak_hash {
+        WeakHash::Adler => DeltaFormat::Native,
+        WeakHash::RollSum | WeakHash::RabinKarp => DeltaFormat::Rdiff,
+    };
+    let delta = Syncer::new()
+        .delta_from_reader(&signature, open_input(&args.new_file)?)
+        .with_context(|| format!("Failed to compute the delta of {}", args.new_file))?;
+    delta.write_to(create_output(&args.delta)?, format)?;
+    Ok(())
+}
+
+fn run_patch(args: PatchArgs) -> Result<()> {
+    init_tracing(None, None)?;
+    Syncer::new()
+        .patch(
+            Path::new(&args.basis),
+            open_input(&args.delta)?,
+            Path::new(&args.output),
+        )
+        .with_context(|| format!("Failed to patch {}", args.basis))?;
+    Ok(())
+}
+
+fn run_probe(args: ProbeArgs) -> Result<()> {
+    init_tracing(None, None)?;
+    let (host, port) = remote::parse_address(&args.address)
+        .ok_or_else(|| UsageError(format!("Invalid server address: {}", args.address)))?;
+    let port = port.unwrap_or(args.port);
+    let info = probe::probe(&host, port).with_context(|| "Failed to probe server")?;
+    println!("Server: {} port {}", host, port);
+    println!("Protocol version: {}", info.version);
+    println!("Compression: {}", info.compression.join(", "));
+    println!("Hashes: {}", info.hashes.join(", "));
+    println!("Features: {}", info.features.join(", "));
+    if info.modules.is_empty() {
+        println!("Modules: none");
+    } else {
+        println!("Modules:");
+    }
+    for module in &info.modules {
+        let auth = if module.auth { " (auth)" } else { "" };
+        match &module.comment {
+            Some(comment) => println!("  {}{} - {}", module.name, auth, comment),
+            None => println!("  {}{}", module.name, auth),
+        }
+    }
+    Ok(())
+}
+
+fn run_sync(mut args: SyncArgs, matches: &ArgMatches) -> Result<()> {
+    let verbosity = Verbosity::from_counts(args.verbose, args.quiet);
+    // -vv shows debug logs unless RUST_LOG says otherwise
+    let log_filter = (verbosity == Verbosity::Debug && env::var_os("RUST_LOG").is_none())
+        .then_some("rsynx=debug");
+    init_tracing(log_filter, args.log_file.as_deref())?;
+    let mut filter = filter_from_matches(matches)?;
+    let config = load_config(&args).with_context(|| "Failed to load configuration")?;
+    apply_config(&mut args, matches, config, &mut filter)?;
+    if args.notify_url.is_some() && !cfg!(feature = "http") {
+        return Err(
+            UsageError("--notify-url needs rsynx built with the http feature".to_string()).into(),
+        );
+    }
+    match args.every {
+        Some(interval) => run_every(&args, filter, interval, verbosity),
+        None => sync_once(&args, filter, verbosity),
+    }
+}
+
+/// Repeat the sync every `interval` until the process is stopped. A failed run is logged and
+/// the next one goes ahead; runs that would have started while one was still going are skipped.
+fn run_every(
+    args: &SyncArgs,
+    filter: Filter,
+    interval: Duration,
+    verbosity: Verbosity,
+) -> Result<()> {
+    if args.dry_run || args.interactive {
+        return Err(UsageError(
+            "--every can't be combined with --dry-run or --interactive".to_string(),
+        )
+        .into());
+    }
+    let mut schedule = Schedule::new(interval, Instant::now());
+    for run in 1.. {
+        let started = Instant::now();
+        match sync_once(args, filter.clone(), verbosity) {
+            Ok(()) => info!("Run {} finished in {:.3?}", run, started.elapsed()),
+            Err(e) if e.downcast_ref::<UsageError>().is_some() => return Err(e),
+            Err(e) => {
+                error!(target: FAILURE_TARGET, "Run {} failed: {:#}", run, e);
+                eprintln!("Run {} failed: {:#}", run, e);
+            }
+        }
+        let (wait, skipped) = schedule.next_run(Instant::now());
+        if skipped > 0 {
+            warn!(
+                "Run {} took longer than the interval, skipped {} run(s)",
+                run, skipped
+            );
+            say!(
+                verbosity,
+                Normal,
+                "Skipped {} run(s) still in progress",
+                skipped
+            );
+        }
+        info!("Next run in {:.0?}", wait);
+        thread::sleep(wait);
+    }
+    Ok(())
+}
+
+/// Run the sync the arguments describe once, then report how it went to `--notify-url` and
+/// `--notify-cmd`.
+fn sync_once(args: &SyncArgs, filter: Filter, verbosity: Verbosity) -> Result<()> {
+    let started = Instant::now();
+    let outcome = transfer_once(args, filter, verbosity);
+    if args.notify_url.is_some() || args.notify_cmd.is_some() {
+        notify_finished(args, &outcome, started.elapsed());
+    }
+    match outcome?.unsupported_files {
+        0 => Ok(()),
+        unsupported => Err(SkippedFiles(unsupported).into()),
+    }
+}
+
+/// Send the report of a finished run to `--notify-url` and `--notify-cmd`. A notification
+/// that can't be delivered is reported but doesn't fail the run.
+fn notify_finished(args: &SyncArgs, outcome: &Result<TransferResult>, elapsed: Duration) {
+    let (exit, error) = match outcome {
+        Ok(result) if result.unsupported_files > 0 => (
+            EXIT_SKIPPED_FILES,
+            Some(SkippedFiles(result.unsupported_files).to_string()),
+        ),
+        Ok(_) => (0, None),
+        Err(e) => (exit_code(e), Some(format!("{:#}", e))),
+    };
+    let report = RunReport {
+        error,
+        source: args.source.clone(),
+        destination: args.destination.clone(),
+        stats: outcome.as_ref().ok().map(RunStats::from),
+        ..RunReport::new(exit, elapsed)
+    };
+    if let Some(url) = &args.notify_url
+        && let Err(e) = post_report(url, &report)
+    {
+        warn!("Failed to notify {}: {:#}", url, e);
+        eprintln!("Failed to notify {}: {:#}", url, e);
+    }
+    if let Some(command) = &args.notify_cmd
+        && let Err(e) = notify::run_command(command, &report)
+    {
+        warn!("Notify command failed: {}", e);
+        eprintln!("Notify command failed: {}", e);
+    }
+}
+
+#[cfg(feature = "http")]
+fn post_report(url: &str, report: &RunReport) -> Result<()> {
+    Ok(notify::post(url, report)?)
+}
+
+#[cfg(not(feature = "http"))]
+fn post_report(_: &str, _: &RunReport) -> Result<()> {
+    Err(UsageError("--notify-url needs rsynx built with the http feature".to_string()).into())
+}
+
+/// Run the sync the arguments describe once: a batch, a local sync or a network sync.
+/// Entries that couldn't be transferred are left for the caller to report.
+fn transfer_once(args: &SyncArgs, filter: Filter, verbosity: Verbosity) -> Result<TransferResult> {
+    // Only a store seals what it writes: any other destination would get plaintext
+    let stored = match (&args.source, &args.destination) {
+        (Some(source), Some(destination)) => is_store_sync(source, destination),
+        _ => false,
+    };
+    if args.encryption_key.is_some() && (!stored || args.batch_from.is_some() || args.snapshot) {
+        return Err(
+            UsageError("--encryption-key only applies to store:// syncs".to_string()).into(),
+        );
+    }
+    if let Some(batch) = &args.batch_from {
+        return run_batch(args, filter, batch, verbosity);
+    }
+
+    let source = args
+        .source
+        .clone()
+        .ok_or_else(|| UsageError("Source path required".to_string()))?;
+    let destination = args
+        .destination
+        .clone()
+        .ok_or_else(|| UsageError("Destination path required".to_string()))?;
+    say!(verbosity, Verbose, "Syncing {} to {}", source, destination);
+    let started = Instant::now();
+
+    let remote = RemoteSpec::parse(&destination);
+    let local_only = [
+        ("--delete-to", args.delete_to.is_some()),
+        ("--conflicts", args.conflicts.is_some()),
+        ("--journal", args.journal.is_some()),
+        ("--backup-dir", args.backup_dir.is_some()),
+        ("--atomic", args.atomic),
+        ("--dedup", args.dedup),
+        ("--order", args.order.is_some()),
+        ("--preallocate", args.preallocate),
+        ("--direct-io", args.direct_io),
+        ("--unicode-normalize", args.unicode_normalize.is_some()),
+    ];
+    if let Some((option, _)) = local_only.iter().find(|(_, set)| *set)
+        && (remote.is_some() || destination.contains("://"))
+    {
+        return Err(UsageError(format!("{} only applies to local destinations", option)).into());
+    }
+    if args.snapshot {
+        return run_snapshot(args, filter, source, destination, verbosity);
+    }
+    if is_store_sync(&source, &destination) {
+        return run_store(args, filter, source, destination, verbosity);
+    }
+    if destination.starts_with("s3://") {
+        return run_s3(args, filter, source, destination, verbosity);
+    }
+    if source.starts_with("http://") || source.starts_with("https://") {
+        return run_http(args, filter, source, destination, verbosity);
+    }
+    if args.signature_url.is_some() {
+        return Err(
+            UsageError("--signature-url only applies to http(s):// sources".to_string()).into(),
+        );
+    }
+    if destination.starts_with("dav://") || destination.starts_with("davs://") {
+        return run_webdav(args, filter, source, destination, verbosity);
+    }
+    if args.identity.is_some() && args.transport != Transport::Sftp {
+        return Err(UsageError("--identity only applies to --transport sftp".to_string()).into());
+    }
+    if args.transport == Transport::Sftp {
+        let remote = remote.ok_or_else(|| {
+            UsageError("--transport sftp needs a user@host:/path destination".to_string())
+        })?;
+        return run_sftp(args, filter, source, remote, verbosity);
+    }
+    if args.interactive && remote.is_some() {
+        return Err(
+            UsageError("--interactive only applies to local destinations".to_string()).into(),
+        );
+    }
+    // Count finished changes so a failing sync can tell whether it left work half done
+    let changes = Arc::new(AtomicUsize::new(0));
+    let counter = changes.clone();
+    let itemizer = args.itemize_changes.then(|| match &remote {
+        Some(remote) => Itemizer::remote(&remote.path),
+        None => Itemizer::new(&destination),
+    });
+    let quiet = verbosity == Verbosity::Quiet;
+    let options = sync_options(args, filter)
+        .with_journal(open_journal(args)?)
+        .with_progress_bar(show_progress(args.progress, args.no_progress || quiet))
+        .on_event(move |event| {
+            if matches!(
+                event,
+                SyncEvent::FileCompleted(_) | SyncEvent::Deleted { .. }
+            ) {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+            print_event(event, itemizer.as_ref(), verbosity);
+        });
+
+    if let Some(remote) = remote {
+        let syncer = network_syncer(&remote, &source, args.port, options);
+        if args.dry_run {
+            syncer.validate()?;
+            let size = fs::metadata(&source)
+                .with_context(|| format!("Failed to read {}", source))?
+                .len();
+            say!(verbosity, Normal, "send   {} -> {}", source, remote);
+            say!(
+                verbosity,
+                Normal,
+                "Would send {} bytes at most (dry run)",
+                size
+            );
+            return Ok(TransferResult::default());
+        }
+        let result = syncer.sync().with_context(|| "Failed to sync")?;
+        say!(verbosity, Normal, "Sync complete!");
+        info!(
+            "Sync complete: {} bytes transferred, {} bytes reused",
+            result.new_bytes, result.reused_bytes
+        );
+        if args.stats && !quiet {
+            print_stats(&result, started.elapsed());
+        }
+        Ok(result)
+    } else {
+        let syncer = local_syncer(args, source, destination, options);
+        if args.dry_run {
+            let plan = syncer.plan().with_context(|| "Failed to plan sync")?;
+            if !quiet {
+                print_plan(&plan);
+            }
+            return Ok(TransferResult::default());
+        }
+        let synced = if args.interactive {
+            let plan = syncer.plan().with_context(|| "Failed to plan sync")?;
+            let checker = Syncer::with_options(SyncOptions::new().with_block_size(args.block_size));
+            let plan = confirm_plan(plan, args.confirm_threshold, &checker)?;
+            syncer.execute(&plan)
+        } else {
+            syncer.sync()
+        };
+        let result = synced.with_context(|| "Failed to sync").map_err(|e| {
+            match changes.load(Ordering::Relaxed) {
+                0 => e,
+                changed => e.context(PartialTransfer(changed)),
+            }
+        })?;
+        say!(
+            verbosity,
+            Normal,
+            "Transferred: {} bytes, Not transferred: {} bytes, Skipped: {} files",
+            result.new_bytes,
+            result.reused_bytes,
+            result.skipped_files
+        );
+        info!(
+            "Sync complete: {} bytes transferred, {} bytes reused, {} files skipped",
+            result.new_bytes, result.reused_bytes, result.skipped_files
+        );
+        if args.stats && !quiet {
+            print_stats(&result, started.elapsed());
+        }
+        Ok(result)
+    }
+}
+
+/// Sync every pair listed in `batch` (`-` for stdin), `--jobs` at a time, then report the
+/// totals. Failed pairs don't stop the rest.
+fn run_batch(
+    args: &SyncArgs,
+    filter: Filter,
+    batch: &str,
+    verbosity: Verbosity,
+) -> Result<TransferResult> {
+    if args.source.is_some() || args.dry_run || args.interactive || args.snapshot {
+        return Err(UsageError(
+            "--batch-from can't be combined with a source path, --dry-run, --interactive or --snapshot"
+                .to_string(),
+        )
+        .into());
+    }
+    if args.transport == Transport::Sftp {
+        return Err(UsageError("--batch-from only supports --transport rsynx".to_string()).into());
+    }
+    let entries = rsynx::batch::read_entries(BufReader::new(open_input(batch)?))
+        .map_err(|e| UsageError(e.to_string()))?;
+    let started = Instant::now();
+    // Parallel progress bars would draw over each other
+    let quiet = verbosity == Verbosity::Quiet;
+    let options = sync_options(args, filter)
+        .with_journal(open_journal(args)?)
+        .with_progress_bar(
+            args.jobs == 1 && show_progress(args.progress, args.no_progress || quiet),
+        );
+    let results = rsynx::batch::run(&entries, args.jobs, |entry| {
+        let remote = RemoteSpec::parse(&entry.destination);
+        let mut options = options.clone();
+        if args.itemize_changes || verbosity >= Verbosity::Verbose {
+            let itemizer = args.itemize_changes.then(|| match &remote {
+                Some(remote) => Itemizer::remote(&remote.path),
+                None => Itemizer::new(&entry.destination),
+            });
+            options =
+                options.on_event(move |event| print_event(event, itemizer.as_ref(), verbosity));
+        }
+        let result = match &remote {
+            _ if is_store_sync(&entry.source, &entry.destination) => {
+                store_syncer(args, &entry.source, &entry.destination)
+                    .and_then(|syncer| syncer.with_options(options).sync())
+            }
+            Some(remote) => network_syncer(remote, &entry.source, args.port, options).sync(),
+            None => local_syncer(
+                args,
+                entry.source.clone(),
+                entry.destination.clone(),
+                options,
+            )
+            .sync(),
+        };
+        match &result {
+            Ok(result) => say!(
+                verbosity,
+                Verbose,
+                "{} -> {}: {} bytes transferred, {} bytes reused",
+                entry.source,
+                entry.destination,
+                result.new_bytes,
+                result.reused_bytes
+            ),
+            Err(e) => {
+                // Logged for the log file only; stderr gets the line below
+                error!(
+                    target: FAILURE_TARGET,
+                    "Failed to sync {} to {}: {}", entry.source, entry.destination, e
+                );
+                eprintln!("{} -> {}: failed: {}", entry.source, entry.destination, e);
+            }
+        }
+        result
+    });
+
+    let mut total = TransferResult::default();
+    let mut failed = 0;
+    for result in results {
+        match result {
+            Ok(result) => total.merge(result),
+            Err(_) => failed += 1,
+        }
+    }
+    say!(
+        verbosity,
+        Normal,
+        "Batch complete: {} of {} entries synced",
+        entries.len() - failed,
+        entries.len()
+    );
+    info!(
+        "Batch complete: {} of {} entries synced, {} bytes transferred, {} bytes reused",
+        entries.len() - failed,
+        entries.len(),
+        total.new_bytes,
+        total.reused_bytes
+    );
+    if args.stats && !quiet {
+        print_stats(&total, started.elapsed());
+    }
+    if failed > 0 {
+        return Err(BatchFailures {
+            failed,
+            total: entries.len(),
+        }
+        .into());
+    }
+    Ok(total)
+}
+
+/// Take a new snapshot of `source` in the `destination` directory, then prune old snapshots
+/// when `--keep-snapshots` is given.
+fn run_snapshot(
+    args: &SyncArgs,
+    filter: Filter,
+    source: String,
+    destination: String,
+    verbosity: Verbosity,
+) -> Result<TransferResult> {
+    if RemoteSpec::parse(&destination).is_some() || args.dry_run || args.interactive {
+        return Err(UsageError(
+            "--snapshot needs a local destination and can't be combined with --dry-run or --interactive"
+                .to_string(),
+        )
+        .into());
+    }
+    let started = Instant::now();
+    let quiet = verbosity == Verbosity::Quiet;
+    let itemizer = args.itemize_changes.then(|| Itemizer::new(&destination));
+    let mut options = sync_options(args, filter)
+        .with_journal(open_journal(args)?)
+        .with_progress_bar(show_progress(args.progress, args.no_progress || quiet));
+    if args.itemize_changes || verbosity >= Verbosity::Verbose {
+        options = options.on_event(move |event| print_event(event, itemizer.as_ref(), verbosity));
+    }
+    let syncer = local_syncer(args, source.clone(), destination.clone(), options);
+    let (snapshot, result) = rsynx::snapshot::take(
+        &syncer,
+        Path::new(&source),
+        Path::new(&destination),
+        SystemTime::now(),
+    )
+    .with_context(|| "Failed to take snapshot")?;
+    say!(
+        verbosity,
+        Normal,
+        "Snapshot {}: {} bytes transferred, {} bytes linked or reused",
+        snapshot.display(),
+        result.new_bytes,
+        result.reused_bytes
+    );
+    info!(
+        "Snapshot {:?} complete: {} bytes transferred, {} bytes linked or reused",
+        snapshot, result.new_bytes, result.reused_bytes
+    );
+    if let Some(keep) = args.keep_snapshots {
+        for pruned in rsynx::snapshot::prune(Path::new(&destination), keep)? {
+            say!(verbosity, Verbose, "pruned {}", pruned.display());
+        }
+    }
+    if args.stats && !quiet {
+        print_stats(&result, started.elapsed());
+    }
+    Ok(result)
+}
+
+/// Whether either side is a `store://` path, making this a sync into or out of a
+/// content-addressed store.
+fn is_store_sync(source: &str, destination: &str) -> bool {
+    store::parse_store(source).is_some() || store::parse_store(destination).is_some()
+}
+
+/// Sync `source` into a content-addressed store, or restore a store into `destination`.
+fn run_store(
+    args: &SyncArgs,
+    filter: Filter,
+    source: String,
+    destination: String,
+    verbosity: Verbosity,
+) -> Result<TransferResult> {
+    if args.dry_run || args.interactive {
+        return Err(UsageError(
+            "store:// syncs can't be combined with --dry-run or --interactive".to_string(),
+        )
+        .into());
+    }
+    let started = Instant::now();
+    let quiet = verbosity == Verbosity::Quiet;
+    let itemizer = args.itemize_changes.then(|| Itemizer::new(&destination));
+    let mut options = sync_options(args, filter)
+        .with_progress_bar(show_progress(args.progress, args.no_progress || quiet));
+    if args.itemize_changes || verbosity >= Verbosity::Verbose {
+        options = options.on_event(move |event| print_event(event, itemizer.as_ref(), verbosity));
+    }
+    let result = store_syncer(args, &source, &destination)
+        .and_then(|syncer| syncer.with_options(options).sync())
+        .with_context(|| "Failed to sync")?;
+    say!(
+        verbosity,
+        Normal,
+        "Transferred: {} bytes, Deduplicated: {} bytes, Skipped: {} files",
+        result.new_bytes,
+        result.reused_bytes,
+        result.skipped_files
+    );
+    info!(
+        "Store sync complete: {} bytes transferred, {} bytes deduplicated, {} files skipped",
+        result.new_bytes, result.reused_bytes, result.skipped_files
+    );
+    if args.stats && !quiet {
+        print_stats(&result, started.elapsed());
+    }
+    Ok(result)
+}
+
+/// The syncer for a `store://` sync, encrypting with the `--encryption-key` if there is one.
+#[cfg(feature = "encryption")]
+fn store_syncer(
+    args: &SyncArgs,
+    source: &str,
+    destination: &str,
+) -> Result<StoreSyncer, SyncError> {
+    let syncer = StoreSyncer::new(source, destination);
+    match &args.encryption_key {
+        Some(path) => Ok(syncer.with_encryption_key(rsynx::encryption::StoreKey::read_from(path)?)),
+        None => Ok(syncer),
+    }
+}
+
+#[cfg(not(feature = "encryption"))]
+fn store_syncer(
+    args: &SyncArgs,
+    source: &str,
+    destination: &str,
+) -> Result<StoreSyncer, SyncError> {
+    if args.encryption_key.is_some() {
+        return Err(SyncError::ConflictingOptions(
+            "--encryption-key needs rsynx built with the encryption feature".to_string(),
+        ));
+    }
+    Ok(StoreSyncer::new(source, destination))
+}
+
+/// Sync `source` to the objects under an `s3://bucket/prefix` destination, with credentials
+/// and endpoint from the AWS environment variables.
+#[cfg(feature = "s3")]
+fn run_s3(
+    args: &SyncArgs,
+    filter: Filter,
+    source: String,
+    destination: String,
+    verbosity: Verbosity,
+) -> Result<TransferResult> {
+    use rsynx::s3::{S3Config, S3Syncer, parse_s3};
+
+    if args.dry_run || args.interactive {
+        return Err(UsageError(
+            "s3:// syncs can't be combined with --dry-run or --interactive".to_string(),
+        )
+        .into());
+    }
+    let location = parse_s3(&destination)
+        .ok_or_else(|| UsageError(format!("Invalid S3 destination: {}", destination)))?;
+    let config = S3Config::from_env(location).map_err(|e| UsageError(e.to_string()))?;
+    let started = Instant::now();
+    let quiet = verbosity == Verbosity::Quiet;
+    let mut options = sync_options(args, filter).with_progress_bar(false);
+    if verbosity >= Verbosity::Verbose {
+        options = options.on_event(move |event| print_event(event, None, verbosity));
+    }
+    let result = S3Syncer::new(source, config)
+        .with_options(options)
+        .sync()
+        .with_context(|| "Failed to sync")?;
+    say!(
+        verbosity,
+        Normal,
+        "Uploaded: {} bytes, Unchanged: {} bytes, Skipped: {} files",
+        result.new_bytes,
+        result.reused_bytes,
+        result.skipped_files
+    );
+    info!(
+        "S3 sync complete: {} bytes uploaded, {} bytes unchanged, {} files skipped",
+        result.new_bytes, result.reused_bytes, result.skipped_files
+    );
+    if args.stats && !quiet {
+        print_stats(&result, started.elapsed());
+    }
+    Ok(result)
+}
+
+#[cfg(not(feature = "s3"))]
+fn run_s3(_: &SyncArgs, _: Filter, _: String, _: String, _: Verbosity) -> Result<TransferResult> {
+    Err(UsageError("s3:// destinations need rsynx built with the s3 feature".to_string()).into())
+}
+
+/// Sync `source` to a `[user@]host:/path` destination over SFTP, for servers without rsynx.
+#[cfg(feature = "sftp")]
+fn run_sftp(
+    args: &SyncArgs,
+    filter: Filter,
+    source: String,
+    remote: RemoteSpec,
+    verbosity: Verbosity,
+) -> Result<TransferResult> {
+    use rsynx::sftp::{SftpSyncer, SftpTarget};
+
+    if args.dry_run || args.interactive {
+        return Err(UsageError(
+            "--transport sftp can't be combined with --dry-run or --interactive".to_string(),
+        )
+        .into());
+    }
+    if remote.module.is_some() {
+        return Err(
+            UsageError("daemon modules (host::module) need --transport rsynx".to_string()).into(),
+        );
+    }
+    let target = SftpTarget {
+        user: remote
+            .user
+            .clone()
+            .or_else(|| env::var("USER").ok())
+            .unwrap_or_default(),
+        port: remote.port.unwrap_or(args.port),
+        path: PathBuf::from(if remote.path.is_empty() {
+            "."
+        } else {
+            &remote.path
+        }),
+        host: remote.host,
+        identity: args.identity.clone(),
+    };
+    let started = Instant::now();
+    let quiet = verbosity == Verbosity::Quiet;
+    let itemizer = args.itemize_changes.then(|| Itemizer::remote(&target.path));
+    let mut options = sync_options(args, filter).with_progress_bar(false);
+    if verbosity >= Verbosity::Verbose || itemizer.is_some() {
+        options = options.on_event(move |event| print_event(event, itemizer.as_ref(), verbosity));
+    }
+    let result = SftpSyncer::new(source, target)
+        .with_options(options)
+        .sync()
+        .with_context(|| "Failed to sync")?;
+    say!(
+        verbosity,
+        Normal,
+        "Uploaded: {} bytes, Unchanged: {} bytes, Skipped: {} files",
+        result.new_bytes,
+        result.reused_bytes,
+        result.skipped_files
+    );
+    info!(
+        "SFTP sync complete: {} bytes uploaded, {} bytes unchanged, {} files skipped",
+        result.new_bytes, result.reused_bytes, result.skipped_files
+    );
+    if args.stats && !quiet {
+        print_stats(&result, started.elapsed());
+    }
+    Ok(result)
+}
+
+#[cfg(not(feature = "sftp"))]
+fn run_sftp(
+    _: &SyncArgs,
+    _: Filter,
+    _: String,
+    _: RemoteSpec,
+    _: Verbosity,
+) -> Result<TransferResult> {
+    Err(UsageError("--transport sftp needs rsynx built with the sftp feature".to_string()).into())
+}
+
+/// Download an `http(s)://` source into `destination`, fetching only the ranges the local
+/// copy lacks when the server publishes a signature.
+#[cfg(feature = "http")]
+fn run_http(
+    args: &SyncArgs,
+    filter: Filter,
+    source: String,
+    destination: String,
+    verbosity: Verbosity,
+) -> Result<TransferResult> {
+    use rsynx::http::HttpSyncer;
+
+    if args.dry_run || args.interactive || RemoteSpec::parse(&destination).is_some() {
+        return Err(UsageError(
+            "http(s):// sources need a local destination and can't be combined with --dry-run or --interactive"
+                .to_string(),
+        )
+        .into());
+    }
+    let started = Instant::now();
+    let quiet = verbosity == Verbosity::Quiet;
+    let itemizer = args.itemize_changes.then(|| Itemizer::new(&destination));
+    let mut options = sync_options(args, filter).with_progress_bar(false);
+    if verbosity >= Verbosity::Verbose || itemizer.is_some() {
+        options = options.on_event(move |event| print_event(event, itemizer.as_ref(), verbosity));
+    }
+    let mut syncer = HttpSyncer::new(source, destination).with_options(options);
+    if let Some(url) = &args.signature_url {
+        syncer = syncer.with_signature_url(url);
+    }
+    let result = syncer.sync().with_context(|| "Failed to sync")?;
+    say!(
+        verbosity,
+        Normal,
+        "Downloaded: {} bytes, Reused: {} bytes, Skipped: {} files",
+        result.new_bytes,
+        result.reused_bytes,
+        result.skipped_files
+    );
+    info!(
+        "HTTP download complete: {} bytes downloaded, {} bytes reused, {} files skipped",
+        result.new_bytes, result.reused_bytes, result.skipped_files
+    );
+    if args.stats && !quiet {
+        print_stats(&result, started.elapsed());
+    }
+    Ok(result)
+}
+
+#[cfg(not(feature = "http"))]
+fn run_http(_: &SyncArgs, _: Filter, _: String, _: String, _: Verbosity) -> Result<TransferResult> {
+    Err(UsageError("http(s):// sources need rsynx built with the http feature".to_string()).into())
+}
+
+/// Sync `source` into the collection at a `dav://` or `davs://` destination, authenticating
+/// with `RSYNX_PASSWORD` when it's set.
+#[cfg(feature = "webdav")]
+fn run_webdav(
+    args: &SyncArgs,
+    filter: Filter,
+    source: String,
+    destination: String,
+    verbosity: Verbosity,
+) -> Result<TransferResult> {
+    use rsynx::webdav::{WebDavSyncer, parse_webdav};
+
+    let location = parse_webdav(&destination)
+        .ok_or_else(|| UsageError(format!("Invalid WebDAV destination: {}", destination)))?;
+    let started = Instant::now();
+    let quiet = verbosity == Verbosity::Quiet;
+    let itemizer = args
+        .itemize_changes
+        .then(|| Itemizer::remote(&location.url));
+    let mut options = sync_options(args, filter).with_progress_bar(false);
+    if verbosity >= Verbosity::Verbose || itemizer.is_some() {
+        options = options.on_event(move |event| print_event(event, itemizer.as_ref(), verbosity));
+    }
+    let mut syncer = WebDavSyncer::new(source, location.clone()).with_options(options);
+    if let Ok(password) = env::var("RSYNX_PASSWORD") {
+        let user = location
+            .user
+            .or_else(|| env::var("USER").ok())
+            .unwrap_or_default();
+        syncer = syncer.with_credentials(user, password);
+    }
+    let plan = syncer.plan().with_context(|| "Failed to plan sync")?;
+    if args.dry_run {
+        if !quiet {
+            print_plan(&plan);
+        }
+        return Ok(TransferResult::default());
+    }
+    let plan = if args.interactive {
+        let checker = Syncer::with_options(SyncOptions::new().with_block_size(args.block_size));
+        confirm_plan(plan, args.confirm_threshold, &checker)?
+    } else {
+        plan
+    };
+    let result = syncer.execute(&plan).with_context(|| "Failed to sync")?;
+    say!(
+        verbosity,
+        Normal,
+        "Uploaded: {} bytes, Unchanged: {} bytes, Skipped: {} files",
+        result.new_bytes,
+        result.reused_bytes,
+        result.skipped_files
+    );
+    info!(
+        "WebDAV sync complete: {} bytes uploaded, {} bytes unchanged, {} files skipped",
+        result.new_bytes, result.reused_bytes, result.skipped_files
+    );
+    if args.stats && !quiet {
+        print_stats(&result, started.elapsed());
+    }
+    Ok(result)
+}
+
+#[cfg(not(feature = "webdav"))]
+fn run_webdav(
+    _: &SyncArgs,
+    _: Filter,
+    _: String,
+    _: String,
+    _: Verbosity,
+) -> Result<TransferResult> {
+    Err(
+        UsageError("dav:// destinations need rsynx built with the webdav feature".to_string())
+            .into(),
+    )
+}
+
+/// Options shared by every sync of this run, before progress and event reporting are added.
+fn sync_options(args: &SyncArgs, filter: Filter) -> SyncOptions {
+    SyncOptions::new()
+        .with_block_size(args.block_size)
+        .with_preserve_metadata(args.preserve_metadata || args.archive || args.snapshot)
+        .with_delete_extraneous(args.delete_extraneous || args.delete_to.is_some())
+        .with_recursive(args.recursive || args.archive || args.snapshot)
+        .with_preserve_links(args.preserve_links || args.archive)
+        .with_preserve_owner(args.preserve_owner || args.archive)
+        .with_filter(filter)
+        .with_compression(args.compress)
+        .with_bwlimit(args.bwlimit.filter(|&rate| rate > 0))
+        .with_disk_limit(args.disk_limit)
+        .with_checksum(args.checksum)
+        .with_modify_window(args.modify_window)
+        .with_ignore_times(args.ignore_times)
+        .with_verify(args.verify)
+        .with_checkpoint(args.checkpoint)
+        .with_partial(args.partial)
+        .with_partial_dir(args.partial_dir.clone())
+        .with_link_dest(args.link_dest.clone())
+        .with_delete_to(args.delete_to.clone())
+        .with_conflicts(args.conflicts)
+        .with_backup_dir(args.backup_dir.clone())
+        .with_atomic(args.atomic)
+        .with_dedup(args.dedup)
+        .with_order(args.order.unwrap_or_default())
+        .with_preallocate(args.preallocate)
+        .with_direct_io(args.direct_io)
+        .with_unicode_normalize(args.unicode_normalize)
+}
+
+/// The `--journal` of local syncs, opened for appending.
+fn open_journal(args: &SyncArgs) -> Result<Option<Journal>> {
+    args.journal
+        .as_deref()
+        .map(Journal::open)
+        .transpose()
+        .map_err(Into::into)
+}
+
+fn local_syncer(
+    args: &SyncArgs,
+    source: String,
+    destination: String,
+    options: SyncOptions,
+) -> LocalSyncer {
+    let syncer = LocalSyncer::new(source, destination).with_options(options);
+    match &args.temp_dir {
+        Some(temp_dir) => syncer.with_temp_dir(temp_dir),
+        None => syncer,
+    }
+}
+
+/// A syncer sending `source` to `remote`, authenticating to its module if there is one.
+fn network_syncer(
+    remote: &RemoteSpec,
+    source: &str,
+    port: u16,
+    options: SyncOptions,
+) -> NetworkSyncer {
+    let mut syncer = NetworkSyncer::new(
+        remote.host.clone(),
+        remote.port.unwrap_or(port),
+        source.to_string(),
+        remote.path.clone(),
+    )
+    .with_options(options);
+    if let Some(module) = &remote.module {
+        syncer = syncer.with_module(module);
+        // Like rsync, the secret comes from the environment rather than the command line
+        if let Ok(password) = env::var("RSYNX_PASSWORD") {
+            let user = remote
+                .user
+                .clone()
+                .or_else(|| env::var("USER").ok())
+                .unwrap_or_default();
+            syncer = syncer.with_credentials(user, password);
+        }
+    }
+    syncer
 }
@@ -0,0 +1,360 @@
+use crate::error::{IoContext, Result, SyncError};
+use crate::filter::Filter;
+use crate::sync::permission_bits;
+use filetime::FileTime;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::Path,
+};
+use walkdir::WalkDir;
+
+/// First line of an mtree manifest.
+const MTREE_HEADER: &str = "#mtree v2.0";
+
+/// Format of a written manifest; reading tells them apart by their first character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ManifestFormat {
+    /// BSD mtree: one `./path keyword=value ...` line per entry.
+    #[default]
+    Mtree,
+    /// A JSON array of entry objects.
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    File,
+    Dir,
+    Link,
+}
+
+/// One entry of a tree: its path relative to the root, with `/` separators, and what a
+/// sync is expected to reproduce about it. `size` and `sha256` are set for files and
+/// `link` for symlinks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub path: String,
+    #[serde(rename = "type")]
+    pub kind: EntryKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    pub mtime: i64,
+    pub mtime_nsec: u32,
+    /// Unix permission bits; zero on other platforms.
+    pub mode: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+}
+
+/// List everything beneath `root` that `filter` doesn't exclude, sorted by path so the same
+/// tree always gives the same manifest. With `hash`, files are hashed with SHA-256; symlinks
+/// are listed, not followed. A file `root` is listed under its own name.
+pub fn build(root: &Path, filter: &Filter, hash: bool) -> Result<Vec<ManifestEntry>> {
+    let mut entries = Vec::new();
+    let mut walker = WalkDir::new(root).follow_links(false).into_iter();
+    while let Some(entry) = walker.next() {
+        let entry = entry.map_err(|e| SyncError::Io {
+            context: format!("Failed to walk {:?}", root),
+            source: e.into(),
+        })?;
+        let relative = match entry.path().strip_prefix(root) {
+            Ok(relative) if relative.as_os_str().is_empty() => {
+                if entry.file_type().is_dir() {
+                    continue;
+                }
+                Path::new(entry.file_name())
+            }
+            Ok(relative) => relative,
+            Err(_) => continue,
+        };
+        let is_dir = entry.file_type().is_dir();
+        if !filter.is_empty() && filter.is_excluded(relative, is_dir) {
+            if is_dir {
+                walker.skip_current_dir();
+            }
+            continue;
+        }
+        let path = relative
+            .components()
+            .map(|part| part.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        if let Some(entry) = describe(entry.path(), path, hash)? {
+            entries.push(entry);
+        }
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+/// The manifest entry for the file, directory or symlink at `path`, or `None` for other
+/// file types.
+fn describe(path: &Path, relative: String, hash: bool) -> Result<Option<ManifestEntry>> {
+    let meta = fs::symlink_metadata(path)
+        .with_context(|| format!("Failed to get metadata for {:?}", path))?;
+    let mtime = FileTime::from_last_modification_time(&meta);
+    let mut entry = ManifestEntry {
+        path: relative,
+        kind: EntryKind::File,
+        size: None,
+        mtime: mtime.unix_seconds(),
+        mtime_nsec: mtime.nanoseconds(),
+        mode: permission_bits(&meta),
+        sha256: None,
+        link: None,
+    };
+    let file_type = meta.file_type();
+    if file_type.is_file() {
+        entry.size = Some(meta.len());
+        if hash {
+            let mut file =
+                File::open(path).with_context(|| format!("Failed to open file: {:?}", path))?;
+            let mut hasher = Sha256::new();
+            io::copy(&mut file, &mut hasher)
+                .with_context(|| format!("Failed to read {:?}", path))?;
+            entry.sha256 = Some(hex::encode(hasher.finalize()));
+        }
+    } else if file_type.is_dir() {
+        entry.kind = EntryKind::Dir;
+    } else if file_type.is_symlink() {
+        let target =
+            fs::read_link(path).with_context(|| format!("Failed to read link {:?}", path))?;
+        entry.kind = EntryKind::Link;
+        entry.link = Some(target.to_string_lossy().into_owned());
+    } else {
+        return Ok(None);
+    }
+    Ok(Some(entry))
+}
+
+/// Write `entries` in `format`.
+pub fn write(entries: &[ManifestEntry], format: ManifestFormat, mut out: impl Write) -> Result<()> {
+    match format {
+        ManifestFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, entries)
+                .map_err(|e| SyncError::Format(format!("Failed to write manifest: {}", e)))?;
+            writeln!(out)?;
+        }
+        ManifestFormat::Mtree => {
+            writeln!(out, "{}", MTREE_HEADER)?;
+            for entry in entries {
+                writeln!(out, "{}", mtree_line(entry))?;
+            }
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn mtree_line(entry: &ManifestEntry) -> String {
+    let kind = match entry.kind {
+        EntryKind::File => "file",
+        EntryKind::Dir => "dir",
+        EntryKind::Link => "link",
+    };
+    let mut line = format!("./{} type={}", mtree_escape(&entry.path), kind);
+    if let Some(size) = entry.size {
+        line.push_str(&format!(" size={}", size));
+    }
+    line.push_str(&format!(
+        " time={}.{:09} mode={:04o}",
+        entry.mtime, entry.mtime_nsec, entry.mode
+    ));
+    if let Some(sha256) = &entry.sha256 {
+        line.push_str(&format!(" sha256digest={}", sha256));
+    }
+    if let Some(link) = &entry.link {
+        line.push_str(&format!(" link={}", mtree_escape(link)));
+    }
+    line
+}
+
+/// Escape `text` the way mtree does: bytes that aren't printable ASCII, spaces, `#`, `=` and
+/// backslashes become `\` and three octal digits.
+fn mtree_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for &byte in text.as_bytes() {
+        if byte.is_ascii_graphic() && !matches!(byte, b'\\' | b'#' | b'=') {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("\\{:03o}", byte));
+        }
+    }
+    escaped
+}
+
+fn mtree_unescape(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' {
+            let digits = std::str::from_utf8(bytes.get(i + 1..i + 4)?).ok()?;
+            unescaped.push(u8::from_str_radix(digits, 8).ok()?);
+            i += 4;
+        } else {
+            unescaped.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(unescaped).ok()
+}
+
+/// Read a manifest written by `write`, in either format.
+pub fn read(mut input: impl Read) -> Result<Vec<ManifestEntry>> {
+    let mut text = String::new();
+    input.read_to_string(&mut text)?;
+    if text.trim_start().starts_with('[') {
+        return serde_json::from_str(&text)
+            .map_err(|e| SyncError::Format(format!("Invalid manifest: {}", e)));
+    }
+    let mut lines = text.lines();
+    if lines.next().map(str::trim_end) != Some(MTREE_HEADER) {
+        return Err(SyncError::Format(
+            "Invalid manifest: not mtree or JSON".to_string(),
+        ));
+    }
+    lines
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            parse_mtree_line(line)
+                .ok_or_else(|| SyncError::Format(format!("Invalid manifest line: {}", line)))
+        })
+        .collect()
+}
+
+fn parse_mtree_line(line: &str) -> Option<ManifestEntry> {
+    let mut words = line.split_whitespace();
+    let path = mtree_unescape(words.next()?.strip_prefix("./")?)?;
+    let mut entry = ManifestEntry {
+        path,
+        kind: EntryKind::File,
+        size: None,
+        mtime: 0,
+        mtime_nsec: 0,
+        mode: 0,
+        sha256: None,
+        link: None,
+    };
+    for word in words {
+        match word.split_once('=')? {
+            ("type", "file") => entry.kind = EntryKind::File,
+            ("type", "dir") => entry.kind = EntryKind::Dir,
+            ("type", "link") => entry.kind = EntryKind::Link,
+            ("size", size) => entry.size = Some(size.parse().ok()?),
+            ("time", time) => {
+                let (seconds, nanos) = time.split_once('.').unwrap_or((time, "0"));
+                entry.mtime = seconds.parse().ok()?;
+                entry.mtime_nsec = nanos.parse().ok()?;
+            }
+            ("mode", mode) => entry.mode = u32::from_str_radix(mode, 8).ok()?,
+            ("sha256digest", digest) => entry.sha256 = Some(digest.to_string()),
+            ("link", link) => entry.link = Some(mtree_unescape(link)?),
+            // Keywords other tools write, such as uid or nlink, aren't checked
+            _ => {}
+        }
+    }
+    Some(entry)
+}
+
+/// How a tree differs from its manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    /// Listed in the manifest but absent from the tree.
+    Missing(String),
+    /// Present in the tree but not listed.
+    Extra(String),
+    /// Present in both, with the named fields differing.
+    Changed {
+        path: String,
+        fields: Vec<&'static str>,
+    },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Difference::Missing(path) => write!(f, "missing: {}", path),
+            Difference::Extra(path) => write!(f, "extra: {}", path),
+            Difference::Changed { path, fields } => {
+                write!(f, "changed ({}): {}", fields.join(", "), path)
+            }
+        }
+    }
+}
+
+/// Compare the `actual` entries of a tree against the `expected` ones of a manifest. Types,
+/// sizes, hashes and link targets always count; file modification times and permissions
+/// only without `content_only`. Directory times are never compared, since adding or removing
+/// an entry changes them.
+pub fn compare(
+    expected: &[ManifestEntry],
+    actual: &[ManifestEntry],
+    content_only: bool,
+) -> Vec<Difference> {
+    let mut actual: BTreeMap<&str, &ManifestEntry> = actual
+        .iter()
+        .map(|entry| (entry.path.as_str(), entry))
+        .collect();
+    let mut differences = Vec::new();
+    for want in expected {
+        let Some(have) = actual.remove(want.path.as_str()) else {
+            differences.push(Difference::Missing(want.path.clone()));
+            continue;
+        };
+        let mut fields = Vec::new();
+        if want.kind != have.kind {
+            fields.push("type");
+        } else {
+            if want.size.is_some() && want.size != have.size {
+                fields.push("size");
+            }
+            if want.sha256.is_some() && want.sha256 != have.sha256 {
+                fields.push("sha256");
+            }
+            if want.link != have.link {
+                fields.push("link");
+            }
+            let timed = want.kind == EntryKind::File;
+            if !content_only
+                && timed
+                && (want.mtime, want.mtime_nsec) != (have.mtime, have.mtime_nsec)
+            {
+                fields.push("time");
+            }
+            if !content_only && want.kind != EntryKind::Link && want.mode != have.mode {
+                fields.push("mode");
+            }
+        }
+        if !fields.is_empty() {
+            differences.push(Difference::Changed {
+                path: want.path.clone(),
+                fields,
+            });
+        }
+    }
+    differences.extend(
+        actual
+            .into_keys()
+            .map(|path| Difference::Extra(path.to_string())),
+    );
+    differences
+}
+
+/// Check the tree at `root` against the `expected` manifest; see `compare`.
+pub fn verify(
+    root: &Path,
+    filter: &Filter,
+    expected: &[ManifestEntry],
+    content_only: bool,
+) -> Result<Vec<Difference>> {
+    Ok(compare(expected, &build(root, filter, true)?, content_only))
+}
@@ -0,0 +1,192 @@
+use crate::error::{IoContext, Result};
+use crate::sync::TransferResult;
+use std::{
+    fmt::Write as _,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+use tracing::{info, warn};
+
+/// Upper bounds, in seconds, of the transfer duration histogram buckets.
+const DURATION_BUCKETS: [f64; 10] = [0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0];
+
+/// Counters a daemon keeps about the clients it serves, published in the Prometheus text
+/// format by `serve`.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    connections: AtomicU64,
+    active_connections: AtomicU64,
+    received_bytes: AtomicU64,
+    reused_bytes: AtomicU64,
+    files_written: AtomicU64,
+    errors: AtomicU64,
+    /// Transfers at most as long as each of `DURATION_BUCKETS`, not cumulative.
+    duration_buckets: [AtomicU64; DURATION_BUCKETS.len()],
+    duration_count: AtomicU64,
+    duration_sum_micros: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a client connecting; it stays active until `finish_connection`.
+    pub fn start_connection(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record how a connection that took `elapsed` ended. Connections that wrote files count
+    /// as transfers in the duration histogram.
+    pub fn finish_connection(&self, outcome: &Result<TransferResult>, elapsed: Duration) {
+        self.active_connections.fetch_sub(1, Ordering::Relaxed);
+        let result = match outcome {
+            Ok(result) => result,
+            Err(_) => {
+                self.errors.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        };
+        let written = result.created_files + result.updated_files;
+        self.received_bytes
+            .fetch_add(result.new_bytes as u64, Ordering::Relaxed);
+        self.reused_bytes
+            .fetch_add(result.reused_bytes as u64, Ordering::Relaxed);
+        self.files_written
+            .fetch_add(written as u64, Ordering::Relaxed);
+        if written == 0 {
+            return;
+        }
+        let seconds = elapsed.as_secs_f64();
+        if let Some(bucket) = DURATION_BUCKETS.iter().position(|&bound| seconds <= bound) {
+            self.duration_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.duration_count.fetch_add(1, Ordering::Relaxed);
+        self.duration_sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let get = |value: &AtomicU64| value.load(Ordering::Relaxed);
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+            let _ = write!(
+                text,
+                "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"
+            );
+        };
+        metric(
+            "rsynx_connections_total",
+            "counter",
+            "Client connections accepted.",
+            get(&self.connections),
+        );
+        metric(
+            "rsynx_active_connections",
+            "gauge",
+            "Client connections being served.",
+            get(&self.active_connections),
+        );
+        metric(
+            "rsynx_received_bytes_total",
+            "counter",
+            "Literal bytes received from clients.",
+            get(&self.received_bytes),
+        );
+        metric(
+            "rsynx_reused_bytes_total",
+            "counter",
+            "Bytes copied from existing files instead of being sent.",
+            get(&self.reused_bytes),
+        );
+        metric(
+            "rsynx_files_written_total",
+            "counter",
+            "Files created or updated.",
+            get(&self.files_written),
+        );
+        metric(
+            "rsynx_errors_total",
+            "counter",
+            "Connections that ended in an error.",
+            get(&self.errors),
+        );
+
+        let name = "rsynx_transfer_duration_seconds";
+        let _ = writeln!(
+            text,
+            "# HELP {name} Time taken by connections that wrote files."
+        );
+        let _ = writeln!(text, "# TYPE {name} histogram");
+        let mut cumulative = 0;
+        for (bound, count) in DURATION_BUCKETS.iter().zip(&self.duration_buckets) {
+            cumulative += get(count);
+            let _ = writeln!(text, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        let count = get(&self.duration_count);
+        let sum = get(&self.duration_sum_micros) as f64 / 1e6;
+        let _ = writeln!(text, "{name}_bucket{{le=\"+Inf\"}} {count}");
+        let _ = writeln!(text, "{name}_sum {sum}");
+        let _ = writeln!(text, "{name}_count {count}");
+        text
+    }
+
+    /// Answer `GET /metrics` on `listener` from a background thread until accepting fails;
+    /// other paths get 404.
+    pub fn serve(self: &Arc<Self>, listener: TcpListener) {
+        let metrics = Arc::clone(self);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream
+                    .with_context(|| "Failed to accept metrics request")
+                    .and_then(|stream| metrics.answer(stream));
+                if let Err(e) = result {
+                    warn!("Failed to answer metrics request: {}", e);
+                }
+            }
+        });
+    }
+
+    fn answer(&self, stream: TcpStream) -> Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let mut reader = BufReader::new(stream);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        // Skip the headers; the request has no body worth reading
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 0 && !header.trim().is_empty() {
+            header.clear();
+        }
+        let path = request.split_whitespace().nth(1).unwrap_or("");
+        let (status, body) = if path == "/metrics" {
+            ("200 OK", self.render())
+        } else {
+            ("404 Not Found", "Not found\n".to_string())
+        };
+        write!(
+            reader.get_mut(),
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )?;
+        reader.get_mut().flush()?;
+        Ok(())
+    }
+}
+
+/// Bind `address` for `Metrics::serve`.
+pub fn bind(address: &str) -> Result<TcpListener> {
+    let listener = TcpListener::bind(address)
+        .with_context(|| format!("Failed to bind metrics address: {}", address))?;
+    info!("Serving metrics on http://{}/metrics", address);
+    Ok(listener)
+}
@@ -1,260 +1,415 @@
-use crate::sync::{Syncer, TransferResult};
-use anyhow::{Context, Result};
-use indicatif::{ProgressBar, ProgressStyle};
-use log::info;
-use std::collections::HashMap;
+use crate::daemon::auth_response;
+use crate::delta::{DeltaOp, Signature};
+use crate::error::{IoContext, Result, SyncError};
+use crate::events::SyncEvent;
+use crate::filter::Filter;
+use crate::lock::DestinationLock;
+use crate::manifest::{self, ManifestEntry, ManifestFormat};
+use crate::options::impl_option_builders;
+use crate::pathname;
+use crate::probe::ServerInfo;
+use crate::progress::{Phase, ProgressReporter};
+use crate::protocol::{self, Instruction, MAX_INSTRUCTION_SIZE, Request, parse_field};
+use crate::sync::{self, FileAction, Syncer, TransferResult, permission_bits};
+use crate::throttle::Throttled;
+use crate::transport::{Acceptor, Connector, TcpConnector, Transport};
+use filetime::{FileTime, set_file_times};
+use flate2::read::GzDecoder;
 use std::{
     fs::{self, File},
-    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
-    net::{TcpListener, TcpStream},
-    path::Path,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    net::TcpListener,
+    path::{Component, Path, PathBuf},
 };
+use tracing::{error, field, info, instrument};
 
 /// NetworkSyncer implements network synchronization using rsync algorithm, currently only supports file synchronization.
+#[derive(Clone)]
 pub struct NetworkSyncer {
     pub syncer: Syncer,
     pub remote_address: String,
     pub remote_port: u16,
-    pub source: String,
-    pub destination: String,
-    pub block_size: usize,
+    pub source: PathBuf,
+    /// Path on the server; it travels in the text protocol, so it must be valid UTF-8.
+    pub destination: PathBuf,
+    /// Module of a daemon to sync into; `destination` is then relative to the module.
+    pub module: Option<String>,
+    /// User name and secret answering the module's authentication challenge.
+    pub credentials: Option<(String, String)>,
 }
 
+impl_option_builders!(NetworkSyncer);
+
 impl NetworkSyncer {
     pub fn new(
-        remote_address: String,
+        remote_address: impl Into<String>,
         remote_port: u16,
-        source: String,
-        destination: String,
+        source: impl Into<PathBuf>,
+        destination: impl Into<PathBuf>,
     ) -> Self {
         Self {
             syncer: Syncer::new(),
-            remote_address,
+            remote_address: remote_address.into(),
             remote_port,
-            source,
-            destination,
-            block_size: 1024,
+            source: source.into(),
+            destination: destination.into(),
+            module: None,
+            credentials: None,
         }
     }
 
-    pub fn with_block_size(mut self, block_size: usize) -> Self {
-        self.syncer.block_size = block_size;
+    /// Sync into `module` on a daemon, with `destination` relative to it.
+    pub fn with_module(mut self, module: impl Into<String>) -> Self {
+        self.module = Some(module.into());
         self
     }
 
-    pub fn with_compression(mut self, compress: bool) -> Self {
-        self.syncer.compress = compress;
+    /// Authenticate as `user` with `secret` when the module asks for it.
+    pub fn with_credentials(mut self, user: impl Into<String>, secret: impl Into<String>) -> Self {
+        self.credentials = Some((user.into(), secret.into()));
         self
     }
 
+    /// Reject invalid or contradictory options before connecting.
+    pub fn validate(&self) -> Result<(), SyncError> {
+        self.syncer.validate()?;
+        if self.source.as_os_str().is_empty() {
+            return Err(SyncError::EmptyPath("Source"));
+        }
+        if self.destination.as_os_str().is_empty() {
+            return Err(SyncError::EmptyPath("Destination"));
+        }
+        // Only single files go over the network, so like a local file sync there is nothing
+        // to checkpoint, while deleting and checksum-based skipping have no effect
+        let is_token = |s: &str| !s.is_empty() && !s.contains(char::is_whitespace);
+        if self
+            .module
+            .as_deref()
+            .is_some_and(|module| !is_token(module))
+        {
+            return Err(SyncError::ConflictingOptions(
+                "module names can't be empty or contain whitespace".to_string(),
+            ));
+        }
+        if self
+            .credentials
+            .as_ref()
+            .is_some_and(|(user, _)| !is_token(user))
+        {
+            return Err(SyncError::ConflictingOptions(
+                "user names can't be empty or contain whitespace".to_string(),
+            ));
+        }
+        if self.syncer.options.checkpoint {
+            return Err(SyncError::ConflictingOptions(
+                "checkpointing only applies to directory syncs".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Sync over TCP to `remote_address:remote_port`.
     pub fn sync(&self) -> Result<TransferResult> {
-        let addr = format!("{}:{}", self.remote_address, self.remote_port);
-        let mut stream = TcpStream::connect(&addr)
-            .with_context(|| format!("Failed to connect to remote address: {}", addr))?;
-        info!("Connected to remote server at {}", addr);
+        self.sync_with(&TcpConnector {
+            address: self.remote_address.clone(),
+            port: self.remote_port,
+        })
+    }
 
-        let src_path = Path::new(&self.source);
-        if !src_path.is_file() {
-            return Err(anyhow::anyhow!(
-                "Only file sync supported for rsync algorithm in NetworkSyncer"
+    /// Sync over a connection established by `connector`.
+    pub fn sync_with<C: Connector>(&self, connector: &C) -> Result<TransferResult> {
+        self.validate()?;
+        let peer = connector.peer();
+        let transport = connector.connect().map_err(|source| SyncError::Connect {
+            peer: peer.clone(),
+            source,
+        })?;
+        info!("Connected to remote server at {}", peer);
+        self.run_client(transport)
+    }
+
+    /// Sync over an already established transport.
+    pub fn sync_over<T: Transport>(&self, transport: T) -> Result<TransferResult> {
+        self.validate()?;
+        self.run_client(transport)
+    }
+
+    /// List `destination` on the server, like `manifest::build` with files hashed when `hash`
+    /// is set, to compare trees without syncing; `source` isn't used.
+    pub fn list_destination(&self, hash: bool) -> Result<Vec<ManifestEntry>> {
+        let connector = TcpConnector {
+            address: self.remote_address.clone(),
+            port: self.remote_port,
+        };
+        let peer = connector.peer();
+        let transport = connector
+            .connect()
+            .map_err(|source| SyncError::Connect { peer, source })?;
+        self.list_destination_over(transport, hash)
+    }
+
+    /// List `destination` over an already established transport; see `list_destination`.
+    pub fn list_destination_over<T: Transport>(
+        &self,
+        transport: T,
+        hash: bool,
+    ) -> Result<Vec<ManifestEntry>> {
+        let mut reader = BufReader::new(transport);
+        let destination = match self.destination.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => self.destination.as_path(),
+        };
+        let destination = pathname::as_bytes(destination)?;
+        if let Some(module) = &self.module {
+            self.open_module(&mut reader, module)?;
+        }
+        // Format: LIST <0|1> <path_len> followed by the path's bytes, answered by
+        // LISTING <length> and that many bytes of JSON manifest, or ERROR <reason>
+        writeln!(
+            reader.get_mut(),
+            "LIST {} {}",
+            u8::from(hash),
+            destination.len()
+        )?;
+        reader.get_mut().write_all(destination)?;
+        reader.get_mut().flush()?;
+        let reply = protocol::read_line(&mut reader)?.unwrap_or_default();
+        if let Some(reason) = reply.strip_prefix("ERROR ") {
+            return Err(SyncError::Refused(reason.to_string()));
+        }
+        let length: u64 = parse_field(reply.strip_prefix("LISTING "), "length", "LISTING")?;
+        // Buffered as it arrives, rather than trusting the announced length up front
+        let mut listing = Vec::new();
+        reader.take(length).read_to_end(&mut listing)?;
+        if listing.len() as u64 != length {
+            return Err(SyncError::Protocol(
+                "Connection closed inside LISTING payload".to_string(),
             ));
         }
-        let file_size = fs::metadata(src_path)?.len();
+        manifest::read(&listing[..])
+    }
+
+    #[instrument(
+        name = "network_sync",
+        skip_all,
+        fields(
+            source = ?self.source,
+            destination = ?self.destination,
+            new_bytes = field::Empty,
+            reused_bytes = field::Empty,
+            reuse_ratio = field::Empty,
+        ),
+    )]
+    pub(crate) fn run_client<T: Transport>(&self, transport: T) -> Result<TransferResult> {
+        // Reads are buffered; writes go straight to the transport underneath
+        let mut reader = BufReader::new(transport);
+        let src_path = self.source.as_path();
+        if !src_path.is_file() {
+            return Err(SyncError::UnsupportedSource {
+                path: src_path.to_path_buf(),
+                reason: "only regular files can be synced over the network",
+            });
+        }
+        let file_size = fs::metadata(src_path)
+            .with_context(|| format!("Failed to get metadata for source file: {:?}", src_path))?
+            .len();
         let src_filename = src_path
             .file_name()
-            .ok_or_else(|| anyhow::anyhow!("Source file has no name"))?;
+            .ok_or_else(|| SyncError::UnsupportedSource {
+                path: src_path.to_path_buf(),
+                reason: "source has no file name",
+            })?;
 
         // Create progress bar
-        let pb = ProgressBar::new(file_size);
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")
-                .expect("Failed to set progress bar template")
-                .progress_chars("#>-"),
+        let progress = ProgressReporter::new(
+            &self.syncer,
+            src_path,
+            file_size,
+            format!("Network sync: {}", src_filename.to_string_lossy()),
         );
-        pb.set_message(format!("Network sync: {}", src_filename.to_string_lossy()));
-        // Send file sync request, format: FILE <src_filename> <dst_filename> <filesize>
+        progress.update(Phase::Signature, 0);
+        self.syncer.emit(|| SyncEvent::FileStarted {
+            path: self.destination.clone(),
+            size: file_size,
+        });
+        if let Some(module) = &self.module {
+            self.open_module(&mut reader, module)?;
+        }
+        // Send file sync request, format: FILE <src_name_len> <dst_path_len> <filesize>
+        // followed by the bytes of both, so any file name gets through intact
+        let src_name = pathname::as_bytes(Path::new(src_filename))?;
+        let destination = pathname::as_bytes(&self.destination)?;
         writeln!(
-            stream,
+            reader.get_mut(),
             "FILE {} {} {}",
-            src_filename.to_string_lossy(),
-            self.destination,
+            src_name.len(),
+            destination.len(),
             file_size
         )?;
+        reader.get_mut().write_all(src_name)?;
+        reader.get_mut().write_all(destination)?;
 
-        // Read server's block summary data
-        let mut reader = BufReader::new(stream.try_clone()?);
-        let mut first_line = String::new();
-        reader.read_line(&mut first_line)?;
-        let first_line = first_line.trim_end();
-        let mut block_table = Vec::new();
-        if first_line == "NOBLK" {
-            // Indicates destination file does not exist, cannot be reused
-            // TODO: send all data
-        } else if first_line.starts_with("BLK ") {
-            // Read all BLK data until BLKEND
-            let mut line = first_line.to_string();
-            loop {
-                if line == "BLKEND" {
-                    break;
-                }
-                // Format: BLK <offset> <size> <weak> <strong_hex>
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() != 5 {
-                    return Err(anyhow::anyhow!("Invalid BLK line: {}", line));
-                }
-                let offset: u64 = parts[1].parse()?;
-                let size: usize = parts[2].parse()?;
-                let weak: u32 = parts[3].parse()?;
-                let strong_hex = parts[4];
-                let strong_bytes = hex::decode(strong_hex).with_context(|| {
-                    format!("Failed to decode strong checksum from hex: {}", strong_hex)
-                })?;
-                let mut strong = [0u8; 32];
-                strong.copy_from_slice(&strong_bytes);
-                block_table.push((offset, size, weak, strong));
-                line.clear();
-                reader.read_line(&mut line)?;
-                line = line.trim_end().to_string();
-            }
-        } else {
-            return Err(anyhow::anyhow!(
-                "Invalid response from server: {}",
-                first_line
-            ));
-        }
-
-        // Build weak checksum lookup table: weak -> Vec<(offset, size, strong)>
-        let mut weak_lookup: HashMap<u32, Vec<(u64, usize, [u8; 32])>> = HashMap::new();
-        for &(offset, size, weak, strong) in &block_table {
-            weak_lookup
-                .entry(weak)
-                .or_default()
-                .push((offset, size, strong));
-        }
+        // Read server's block summary data; NOBLK means the destination doesn't exist yet and
+        // everything is sent as data
+        let (action, blocks) = match protocol::read_block_table(&mut reader)? {
+            Some(blocks) => (FileAction::Updated, blocks),
+            None => (FileAction::Created, Vec::new()),
+        };
+        let signature = Signature::new(self.syncer.options.block_size, blocks);
 
-        // Scan source file using rolling window to generate diff instructions
-        enum Instruction {
-            Data(Vec<u8>),
-            Copy(u64, usize),
-        }
-        let mut instructions = Vec::new();
-        let mut src_file = File::open(src_path)?;
-        let block_size = self.syncer.block_size;
+        // Scan source file using rolling window, streaming diff instructions as they are found
+        let src_file = self.syncer.disk_io(File::open(src_path)?);
+        let mut writer = BufWriter::new(Throttled::new(
+            reader.get_mut(),
+            self.syncer.options.bwlimit,
+        ));
         let mut pos: u64 = 0;
-        let mut unmatched = Vec::new();
-
-        if file_size < block_size as u64 {
-            let mut data = Vec::new();
-            src_file.read_to_end(&mut data)?;
-            instructions.push(Instruction::Data(data));
-            // pos = file_size;
-        } else {
-            let mut window = vec![0u8; block_size];
-            src_file.read_exact(&mut window)?;
-            let mut current_weak = self.syncer.calculate_weak_checksum(&window);
-            while pos + block_size as u64 <= file_size {
-                if let Some(candidates) = weak_lookup.get(&current_weak) {
-                    let current_strong = self.syncer.calculate_strong_checksum(&window);
-                    if let Some(&(blk_offset, blk_size, _)) =
-                        candidates.iter().find(|&&(_, _, s)| s == current_strong)
-                    {
-                        // Found a match, first send any unmatched data
-                        if !unmatched.is_empty() {
-                            instructions.push(Instruction::Data(unmatched.clone()));
-                            unmatched.clear();
+        let mut reused_bytes = 0usize;
+        let mut compression_saved_bytes = 0usize;
+        let source_checksum = self.syncer.stream_delta(&signature, src_file, |op| {
+            match op {
+                DeltaOp::Literal(data) => {
+                    match self.syncer.compress_frame(&data)? {
+                        // Format: ZDATA <compressed_len>, flagging a gzip-compressed payload
+                        Some(compressed) => {
+                            writeln!(writer, "ZDATA {}", compressed.len())?;
+                            writer.write_all(&compressed)?;
+                            compression_saved_bytes += data.len() - compressed.len();
                         }
-                        instructions.push(Instruction::Copy(blk_offset, blk_size));
-                        pos += block_size as u64;
-                        pb.set_position(pos);
-                        if pos + block_size as u64 <= file_size {
-                            src_file.seek(SeekFrom::Start(pos))?;
-                            src_file.read_exact(&mut window)?;
-                            current_weak = self.syncer.calculate_weak_checksum(&window);
-                        } else {
-                            break;
+                        None => {
+                            writeln!(writer, "DATA {}", data.len())?;
+                            writer.write_all(&data)?;
                         }
-                        continue;
                     }
+                    pos += data.len() as u64;
                 }
-                // No match found, add first byte of window to unmatched data and slide one byte
-                unmatched.push(window[0]);
-                pos += 1;
-                pb.set_position(pos);
-                if pos + block_size as u64 - 1 < file_size {
-                    let old_byte = window.remove(0);
-                    let mut next_byte = [0u8; 1];
-                    src_file.seek(SeekFrom::Start(pos + block_size as u64 - 1))?;
-                    src_file.read_exact(&mut next_byte)?;
-                    window.push(next_byte[0]);
-                    current_weak = self.syncer.update_weak_checksum(
-                        old_byte,
-                        next_byte[0],
-                        current_weak,
-                        block_size,
-                    );
-                } else {
-                    break;
+                DeltaOp::Copy { offset, len } => {
+                    writeln!(writer, "COPY {} {}", offset, len)?;
+                    pos += len as u64;
+                    reused_bytes += len;
+                    self.syncer.emit(|| SyncEvent::BlockMatched {
+                        path: self.destination.clone(),
+                        offset,
+                        len,
+                    });
                 }
             }
-            // Add remaining data
-            if pos < file_size {
-                src_file.seek(SeekFrom::Start(pos))?;
-                let mut remainder = Vec::new();
-                src_file.read_to_end(&mut remainder)?;
-                if !unmatched.is_empty() {
-                    unmatched.extend(remainder);
-                    instructions.push(Instruction::Data(unmatched));
-                } else {
-                    instructions.push(Instruction::Data(remainder));
-                }
-            } else if !unmatched.is_empty() {
-                instructions.push(Instruction::Data(unmatched));
-            }
+            progress.update(Phase::Transfer, pos);
+            Ok(())
+        })?;
+        if self.syncer.options.preserve_metadata {
+            let meta = fs::metadata(src_path).with_context(|| {
+                format!("Failed to get metadata for source file: {:?}", src_path)
+            })?;
+            let atime = FileTime::from_last_access_time(&meta);
+            let mtime = FileTime::from_last_modification_time(&meta);
+            // Format: META <mode_octal> <atime_secs> <atime_nanos> <mtime_secs> <mtime_nanos>
+            writeln!(
+                writer,
+                "META {:o} {} {} {} {}",
+                permission_bits(&meta),
+                atime.unix_seconds(),
+                atime.nanoseconds(),
+                mtime.unix_seconds(),
+                mtime.nanoseconds()
+            )?;
         }
+        if self.syncer.options.verify {
+            // Format: VERIFY <sha256_hex>, asking the server to re-hash the file once renamed
+            writeln!(writer, "VERIFY {}", hex::encode(source_checksum))?;
+        }
+        writeln!(writer, "DONE")?;
+        writer.flush()?;
+        drop(writer);
 
-        for ins in instructions {
-            match ins {
-                Instruction::Data(data) => {
-                    writeln!(stream, "DATA {}", data.len())?;
-                    stream.write_all(&data)?;
-                }
-                Instruction::Copy(offset, length) => {
-                    writeln!(stream, "COPY {} {}", offset, length)?;
-                }
+        if self.syncer.options.verify {
+            progress.update(Phase::Verify, file_size);
+            let reply = protocol::read_line(&mut reader)?.unwrap_or_default();
+            if let Some(actual) = reply.strip_prefix("MISMATCH ") {
+                return Err(SyncError::ChecksumMismatch {
+                    path: self.destination.clone(),
+                    expected: hex::encode(source_checksum),
+                    actual: actual.to_string(),
+                });
+            } else if reply != "VERIFIED" {
+                return Err(SyncError::Protocol(format!(
+                    "Invalid verification response from server: {}",
+                    reply
+                )));
             }
         }
-        writeln!(stream, "DONE")?;
-        stream.flush()?;
 
-        // Complete progress bar
-        pb.finish_with_message(format!(
+        progress.finish(format!(
             "Network sync complete: {} ({} bytes)",
             src_filename.to_string_lossy(),
             file_size
         ));
 
-        // TODO: reused_bytes calculation
-        Ok(TransferResult {
-            new_bytes: file_size as usize,
-            reused_bytes: 0,
-        })
+        let mut result = TransferResult::for_file(
+            &self.destination,
+            action,
+            (file_size as usize).saturating_sub(reused_bytes),
+            reused_bytes,
+        );
+        result.compression_saved_bytes = compression_saved_bytes;
+        result.record_in_span();
+        self.syncer
+            .emit(|| SyncEvent::FileCompleted(result.files[0].clone()));
+        Ok(result)
+    }
+
+    /// Ask the daemon for `module`, answering its challenge with our credentials if it sends
+    /// one. Format: MODULE <name>, answered by OK, ERROR <reason> or CHALLENGE <hex>, which
+    /// takes AUTH <user> <response> before the final OK or ERROR.
+    fn open_module<T: Transport>(&self, reader: &mut BufReader<T>, module: &str) -> Result<()> {
+        writeln!(reader.get_mut(), "MODULE {}", module)?;
+        let mut reply = protocol::read_line(reader)?.unwrap_or_default();
+        if let Some(challenge) = reply.strip_prefix("CHALLENGE ") {
+            let Some((user, secret)) = &self.credentials else {
+                return Err(SyncError::Refused(format!(
+                    "module {:?} requires authentication",
+                    module
+                )));
+            };
+            let response = auth_response(challenge, secret);
+            writeln!(reader.get_mut(), "AUTH {} {}", user, response)?;
+            reply = protocol::read_line(reader)?.unwrap_or_default();
+        }
+        match reply.as_str() {
+            "OK" => Ok(()),
+            reply => match reply.strip_prefix("ERROR ") {
+                Some(reason) => Err(SyncError::Refused(reason.to_string())),
+                None => Err(SyncError::Protocol(format!(
+                    "Invalid response to MODULE: {}",
+                    reply
+                ))),
+            },
+        }
     }
 
     pub fn serve(port: u16, block_size: usize) -> Result<()> {
-        Self::serve_with_options(port, block_size, false)
+        Self::validate_server_block_size(block_size)?;
+        Self::serve_on(&Self::bind(port)?, block_size)
     }
 
     pub fn serve_once(port: u16, block_size: usize) -> Result<TransferResult> {
-        let listen_addr = format!("0.0.0.0:{}", port);
-        let listener = TcpListener::bind(listen_addr.clone())
-            .with_context(|| format!("Failed to bind to address: {}", listen_addr))?;
-        info!("Server listening on {}", listen_addr);
+        Self::validate_server_block_size(block_size)?;
+        Self::serve_once_on(&Self::bind(port)?, block_size)
+    }
 
-        let (mut stream, addr) = listener.accept()?;
+    /// Serve clients from any acceptor until it fails.
+    pub fn serve_on<A: Acceptor>(acceptor: &A, block_size: usize) -> Result<()> {
+        Self::validate_server_block_size(block_size)?;
+        Self::serve_with_options(acceptor, block_size, false)
+    }
+
+    /// Serve a single client from any acceptor.
+    pub fn serve_once_on<A: Acceptor>(acceptor: &A, block_size: usize) -> Result<TransferResult> {
+        Self::validate_server_block_size(block_size)?;
+        let (transport, addr) = acceptor.accept()?;
         info!("Accepted connection from {:?}", addr);
 
-        let result = Self::handle_connection(&mut stream, block_size)?;
+        let result = Self::handle_connection(transport, block_size)?;
         info!(
             "Transfer completed successfully for client {:?}: {} bytes transferred, {} bytes reused",
             addr, result.new_bytes, result.reused_bytes
@@ -263,18 +418,31 @@ impl NetworkSyncer {
         Ok(result)
     }
 
-    fn serve_with_options(port: u16, block_size: usize, single_connection: bool) -> Result<()> {
+    pub(crate) fn validate_server_block_size(block_size: usize) -> Result<(), SyncError> {
+        let mut syncer = Syncer::new();
+        syncer.options.block_size = block_size;
+        syncer.validate()
+    }
+
+    fn bind(port: u16) -> Result<TcpListener> {
         let listen_addr = format!("0.0.0.0:{}", port);
         let listener = TcpListener::bind(listen_addr.clone())
             .with_context(|| format!("Failed to bind to address: {}", listen_addr))?;
         info!("Server listening on {}", listen_addr);
+        Ok(listener)
+    }
 
+    fn serve_with_options<A: Acceptor>(
+        acceptor: &A,
+        block_size: usize,
+        single_connection: bool,
+    ) -> Result<()> {
         loop {
-            let (mut stream, addr) = listener.accept()?;
+            let (transport, addr) = acceptor.accept()?;
             info!("Accepted connection from {:?}", addr);
 
             // Handle the connection in a separate scope to allow for error handling
-            match Self::handle_connection(&mut stream, block_size) {
+            match Self::handle_connection(transport, block_size) {
                 Ok(result) => {
                     info!(
                         "Transfer completed successfully for client {:?}: {} bytes transferred, {} bytes reused",
@@ -282,7 +450,7 @@ impl NetworkSyncer {
                     );
                 }
                 Err(e) => {
-                    log::error!("Error handling connection from {:?}: {}", addr, e);
+                    error!("Error handling connection from {:?}: {}", addr, e);
                     // Continue serving other connections even if one fails
                     if !single_connection {
                         continue;
@@ -298,109 +466,380 @@ impl NetworkSyncer {
         Ok(())
     }
 
-    fn handle_connection(stream: &mut TcpStream, block_size: usize) -> Result<TransferResult> {
-        let mut reader = BufReader::new(stream.try_clone()?);
+    /// Run the server side of the protocol for one client over `transport`, writing wherever
+    /// the client asks. Use a `Daemon` to confine clients to configured modules.
+    pub fn handle_connection<T: Transport>(
+        transport: T,
+        block_size: usize,
+    ) -> Result<TransferResult> {
+        let mut reader = BufReader::new(transport);
+        match protocol::read_request(&mut reader)? {
+            Request::Probe => {
+                ServerInfo::local().write_to(reader.get_mut())?;
+                Ok(TransferResult::default())
+            }
+            request => Self::serve_request(&mut reader, request, block_size, None, false, false),
+        }
+    }
 
-        let mut line = String::new();
-        reader.read_line(&mut line)?;
-        let line = line.trim_end();
-        let mut parts = line.split_whitespace();
-        let command = parts.next().unwrap_or("");
-        if command != "FILE" {
-            return Err(anyhow::anyhow!("Expected FILE command, got: {}", line));
+    /// Serve a LIST or FILE request; see `send_listing` and `receive_file`.
+    pub(crate) fn serve_request<T: Transport>(
+        reader: &mut BufReader<T>,
+        request: Request,
+        block_size: usize,
+        root: Option<&Path>,
+        preallocate: bool,
+        read_only: bool,
+    ) -> Result<TransferResult> {
+        match request {
+            Request::List { hash, path } => Self::send_listing(reader, hash, path, root),
+            Request::File {
+                destination, size, ..
+            } => Self::receive_file(
+                reader,
+                destination,
+                size,
+                block_size,
+                root,
+                preallocate,
+                read_only,
+            ),
+            request => Err(SyncError::Protocol(format!(
+                "Unexpected request: {:?}",
+                request
+            ))),
         }
-        let _src_filename = parts
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("Missing src filename in FILE command"))?;
-        let dst_filename = parts
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("Missing dst filename in FILE command"))?;
-        let _filesize: u64 = parts
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("Missing filesize in FILE command"))?
-            .parse()?;
-
-        let target = Path::new(dst_filename);
+    }
 
-        if target.exists() {
-            let mut syncer = Syncer::new();
-            syncer.block_size = block_size;
-            let checksums = syncer.calculate_checksums(target)?;
-            for block in checksums {
-                let strong_hex = hex::encode(block.strong_checksum);
-                writeln!(
-                    stream,
-                    "BLK {} {} {} {}",
-                    block.offset, block.size, block.weak_checksum, strong_hex
-                )?;
-            }
-            writeln!(stream, "BLKEND")?;
+    /// Answer a LIST request with the JSON manifest of `path`, with content hashes if `hash`
+    /// is set. With a `root`, the path is resolved inside it and may not climb out.
+    fn send_listing<T: Transport>(
+        reader: &mut BufReader<T>,
+        hash: bool,
+        path: PathBuf,
+        root: Option<&Path>,
+    ) -> Result<TransferResult> {
+        let listing = match root {
+            Some(root) => resolve_in_root(root, &path),
+            None => Ok(path),
+        }
+        .and_then(|target| manifest::build(&target, &Filter::new(), hash))
+        .and_then(|entries| {
+            let mut listing = Vec::new();
+            manifest::write(&entries, ManifestFormat::Json, &mut listing)?;
+            Ok(listing)
+        });
+        let listing = listing.inspect_err(|e| {
+            let _ = writeln!(reader.get_mut(), "ERROR {}", e);
+        })?;
+        writeln!(reader.get_mut(), "LISTING {}", listing.len())?;
+        reader.get_mut().write_all(&listing)?;
+        reader.get_mut().flush()?;
+        Ok(TransferResult::default())
+    }
+
+    /// Serve a FILE request for `destination`, receiving the file's instructions from
+    /// `reader`. With a `root`, the path is resolved inside it and may not climb out. With
+    /// `preallocate`, the file's full size is reserved before any of it is requested. With
+    /// `read_only`, the request is refused before anything is written.
+    #[instrument(
+        skip(reader),
+        fields(
+            new_bytes = field::Empty,
+            reused_bytes = field::Empty,
+            reuse_ratio = field::Empty,
+        ),
+    )]
+    pub(crate) fn receive_file<T: Transport>(
+        reader: &mut BufReader<T>,
+        destination: PathBuf,
+        filesize: u64,
+        block_size: usize,
+        root: Option<&Path>,
+        preallocate: bool,
+        read_only: bool,
+    ) -> Result<TransferResult> {
+        // Refused only once the whole request is read, so the client gets the answer rather
+        // than a reset connection
+        if read_only {
+            let reason = "this server is read-only";
+            let _ = writeln!(reader.get_mut(), "ERROR {}", reason);
+            return Err(SyncError::Refused(reason.to_string()));
+        }
+
+        let target = match root {
+            Some(root) => resolve_in_root(root, &destination).inspect_err(|e| {
+                let _ = writeln!(reader.get_mut(), "ERROR {}", e);
+            })?,
+            None => destination,
+        };
+        let target = target.as_path();
+        let _lock = DestinationLock::acquire(target).inspect_err(|e| {
+            let _ = writeln!(reader.get_mut(), "ERROR {}", e);
+        })?;
+        let action = if target.exists() {
+            FileAction::Updated
         } else {
-            writeln!(stream, "NOBLK")?;
+            FileAction::Created
+        };
+
+        let mut syncer = Syncer::new();
+        syncer.options.block_size = block_size;
+
+        // The announced size is the client's word, so it's checked against the free space
+        // before any of it is reserved
+        if preallocate
+            && let Some(space) =
+                sync::preallocatable_space(target.parent().unwrap_or(Path::new("")))
+            && filesize > space
+        {
+            let reason = format!(
+                "file of {} bytes exceeds the {} bytes free for it",
+                filesize, space
+            );
+            let _ = writeln!(reader.get_mut(), "ERROR {}", reason);
+            return Err(SyncError::Refused(reason));
         }
-        stream.flush()?;
 
         let temp_path = target.with_extension("tmp");
+        // A stale temp file, or a symlink in its place, is replaced rather than written through
+        let _ = fs::remove_file(&temp_path);
         let mut temp_file = File::create(&temp_path)?;
-        let mut old_file = if target.exists() {
+        // Reserve the space before asking for any data, so a full disk refuses the file early
+        if preallocate && let Err(e) = sync::preallocate(&temp_file, filesize) {
+            drop(temp_file);
+            let _ = fs::remove_file(&temp_path);
+            let _ = writeln!(
+                reader.get_mut(),
+                "ERROR Failed to preallocate {} bytes: {}",
+                filesize,
+                e
+            );
+            return Err(SyncError::Io {
+                context: format!(
+                    "Failed to preallocate {} bytes for {:?}",
+                    filesize, temp_path
+                ),
+                source: e,
+            });
+        }
+        let listed = Self::send_block_list(reader, &syncer, target);
+        if let Err(e) = listed {
+            drop(temp_file);
+            let _ = fs::remove_file(&temp_path);
+            return Err(e);
+        }
+
+        let old_file = if target.exists() {
             Some(File::open(target)?)
         } else {
             None
         };
 
-        loop {
-            let mut line = String::new();
-            if reader.read_line(&mut line)? == 0 {
-                break;
+        let received =
+            match Self::receive_instructions(reader, &mut temp_file, old_file, target, filesize) {
+                Ok(received) => received,
+                Err(e) => {
+                    drop(temp_file);
+                    let _ = fs::remove_file(&temp_path);
+                    return Err(e);
+                }
+            };
+
+        temp_file.flush()?;
+        drop(temp_file);
+        if let Some(metadata) = &received.metadata {
+            set_file_mode(&temp_path, metadata.mode).with_context(|| {
+                format!(
+                    "Failed to set permissions for temporary file: {:?}",
+                    temp_path
+                )
+            })?;
+            set_file_times(&temp_path, metadata.atime, metadata.mtime).with_context(|| {
+                format!(
+                    "Failed to set file times for temporary file: {:?}",
+                    temp_path
+                )
+            })?;
+        }
+        fs::rename(&temp_path, target)?;
+        if let Some(expected) = received.checksum {
+            let actual = syncer.calculate_file_checksum(target)?;
+            if actual != expected {
+                writeln!(reader.get_mut(), "MISMATCH {}", hex::encode(actual))?;
+                return Err(SyncError::ChecksumMismatch {
+                    path: target.to_path_buf(),
+                    expected: hex::encode(expected),
+                    actual: hex::encode(actual),
+                });
             }
-            let line_trim = line.trim_end();
-            if line_trim == "DONE" {
-                break;
+            writeln!(reader.get_mut(), "VERIFIED")?;
+        }
+        let result = TransferResult::for_file(
+            target,
+            action,
+            received.literal_bytes,
+            received.reused_bytes,
+        );
+        result.record_in_span();
+        Ok(result)
+    }
+
+    /// Send the block checksums of `target` for the client to match against, or NOBLK when
+    /// there's no such file yet.
+    fn send_block_list<T: Transport>(
+        reader: &mut BufReader<T>,
+        syncer: &Syncer,
+        target: &Path,
+    ) -> Result<()> {
+        if target.exists() {
+            let checksums = syncer.calculate_checksums(target)?;
+            for block in checksums {
+                let strong_hex = hex::encode(block.strong_checksum);
+                writeln!(
+                    reader.get_mut(),
+                    "BLK {} {} {} {}",
+                    block.offset,
+                    block.size,
+                    block.weak_checksum,
+                    strong_hex
+                )?;
             }
-            let mut parts = line_trim.split_whitespace();
-            let cmd = parts.next().unwrap_or("");
-            match cmd {
-                "DATA" => {
-                    let length: usize = parts
-                        .next()
-                        .ok_or_else(|| anyhow::anyhow!("Missing length in DATA command"))?
-                        .parse()?;
-                    let mut data = vec![0u8; length];
-                    reader.read_exact(&mut data)?;
-                    temp_file.write_all(&data)?;
+            writeln!(reader.get_mut(), "BLKEND")?;
+        } else {
+            writeln!(reader.get_mut(), "NOBLK")?;
+        }
+        reader.get_mut().flush()?;
+        Ok(())
+    }
+
+    /// Apply the client's instructions to `temp_file` until DONE, tallying literal and reused
+    /// bytes and collecting the checksum and metadata sent along. Ending the stream before DONE
+    /// is an error.
+    fn receive_instructions<T: Transport>(
+        reader: &mut BufReader<T>,
+        temp_file: &mut File,
+        mut old_file: Option<File>,
+        target: &Path,
+        filesize: u64,
+    ) -> Result<Received> {
+        let mut received = Received::default();
+        loop {
+            match protocol::read_instruction(reader)? {
+                Instruction::Done => break,
+                Instruction::Data(length) => {
+                    let copied = io::copy(&mut reader.take(length), temp_file)?;
+                    if copied != length {
+                        return Err(SyncError::Protocol(
+                            "Connection closed inside DATA payload".to_string(),
+                        ));
+                    }
+                    received.literal_bytes += copied as usize;
                 }
-                "COPY" => {
-                    let offset: u64 = parts
-                        .next()
-                        .ok_or_else(|| anyhow::anyhow!("Missing offset in COPY command"))?
-                        .parse()?;
-                    let length: usize = parts
-                        .next()
-                        .ok_or_else(|| anyhow::anyhow!("Missing length in COPY command"))?
-                        .parse()?;
-                    if let Some(ref mut f) = old_file {
-                        f.seek(SeekFrom::Start(offset))?;
-                        let mut buf = vec![0u8; length];
-                        f.read_exact(&mut buf)?;
-                        temp_file.write_all(&buf)?;
-                    } else {
-                        return Err(anyhow::anyhow!(
-                            "COPY command received but no old file available"
+                Instruction::ZData(length) => {
+                    // Cap the inflated size too, so a tiny payload can't expand without bound
+                    let mut decoder =
+                        GzDecoder::new(reader.take(length)).take(MAX_INSTRUCTION_SIZE + 1);
+                    let inflated = io::copy(&mut decoder, temp_file)
+                        .with_context(|| "Failed to decompress ZDATA payload")?;
+                    if inflated > MAX_INSTRUCTION_SIZE {
+                        return Err(SyncError::Protocol(format!(
+                            "ZDATA payload inflates beyond {} bytes",
+                            MAX_INSTRUCTION_SIZE
+                        )));
+                    }
+                    // Drain anything the decoder left unread so the next command lines up
+                    let mut compressed = decoder.into_inner().into_inner();
+                    io::copy(&mut compressed, &mut io::sink())?;
+                    received.literal_bytes += inflated as usize;
+                }
+                Instruction::Copy { offset, length } => {
+                    let Some(f) = old_file.as_mut() else {
+                        return Err(SyncError::Protocol(
+                            "COPY command received but no old file available".to_string(),
                         ));
+                    };
+                    f.seek(SeekFrom::Start(offset))?;
+                    let copied = io::copy(&mut f.take(length), temp_file)?;
+                    if copied != length {
+                        return Err(SyncError::Protocol(format!(
+                            "COPY {} {} reads past the end of {:?}",
+                            offset, length, target
+                        )));
                     }
+                    received.reused_bytes += copied as usize;
                 }
-                _ => {
-                    return Err(anyhow::anyhow!("Unknown command: {}", cmd));
+                Instruction::Verify(checksum) => received.checksum = Some(checksum),
+                Instruction::Meta { mode, atime, mtime } => {
+                    // Setuid, setgid and sticky bits are dropped, so a client can't plant a
+                    // setuid program owned by the server's user
+                    let mode = mode & 0o777;
+                    received.metadata = Some(RemoteMetadata { mode, atime, mtime });
                 }
             }
+            if (received.literal_bytes + received.reused_bytes) as u64 > filesize {
+                return Err(SyncError::Protocol(format!(
+                    "Client sent more than the announced {} bytes",
+                    filesize
+                )));
+            }
         }
+        Ok(received)
+    }
+}
 
-        temp_file.flush()?;
-        let total_bytes = fs::metadata(&temp_path)?.len() as usize;
-        fs::rename(&temp_path, target)?;
-        Ok(TransferResult {
-            new_bytes: total_bytes,
-            reused_bytes: 0,
-        })
+/// `path`, sent by a client, inside `root`; absolute paths and `..` components are refused,
+/// and so are paths through a symlink, which could lead anywhere outside it.
+fn resolve_in_root(root: &Path, relative: &Path) -> Result<PathBuf> {
+    if relative.as_os_str().is_empty()
+        || !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(SyncError::PathOutsideRoot(relative.to_path_buf()));
     }
+    let mut resolved = root.to_path_buf();
+    for component in relative.components() {
+        resolved.push(component);
+        match fs::symlink_metadata(&resolved) {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                return Err(SyncError::PathOutsideRoot(relative.to_path_buf()));
+            }
+            Ok(_) => {}
+            // Nothing further down exists yet
+            Err(_) => break,
+        }
+    }
+    Ok(root.join(relative))
+}
+
+/// Metadata of the source file, sent in a META instruction.
+pub(crate) struct RemoteMetadata {
+    pub(crate) mode: u32,
+    pub(crate) atime: FileTime,
+    pub(crate) mtime: FileTime,
+}
+
+/// Tally of a client's instruction stream, with the checksum and metadata it sent along.
+#[derive(Default)]
+pub(crate) struct Received {
+    pub(crate) literal_bytes: usize,
+    pub(crate) reused_bytes: usize,
+    pub(crate) checksum: Option<[u8; 32]>,
+    pub(crate) metadata: Option<RemoteMetadata>,
+}
+
+fn set_file_mode(path: &Path, mode: u32) -> io::Result<()> {
+    #[cfg(unix)]
+    let permissions = {
+        use std::os::unix::fs::PermissionsExt;
+        fs::Permissions::from_mode(mode)
+    };
+    #[cfg(not(unix))]
+    let permissions = {
+        let mut permissions = fs::metadata(path)?.permissions();
+        permissions.set_readonly(mode & 0o222 == 0);
+        permissions
+    };
+    fs::set_permissions(path, permissions)
 }
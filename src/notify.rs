@@ -0,0 +1,158 @@
+use crate::error::{IoContext, Result};
+use crate::sync::TransferResult;
+use serde::Serialize;
+use std::{
+    io::{self, Write},
+    process::{Command, Stdio},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// How a run ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    Success,
+    Failure,
+}
+
+/// Totals of a finished run; `TransferResult` without the per-file records.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RunStats {
+    pub new_bytes: usize,
+    pub reused_bytes: usize,
+    pub created_files: usize,
+    pub updated_files: usize,
+    pub deleted_files: usize,
+    pub skipped_files: usize,
+    pub unsupported_files: usize,
+    pub conflicts: usize,
+}
+
+impl From<&TransferResult> for RunStats {
+    fn from(result: &TransferResult) -> Self {
+        Self {
+            new_bytes: result.new_bytes,
+            reused_bytes: result.reused_bytes,
+            created_files: result.created_files,
+            updated_files: result.updated_files,
+            deleted_files: result.deleted_files,
+            skipped_files: result.skipped_files,
+            unsupported_files: result.unsupported_files,
+            conflicts: result.conflicts,
+        }
+    }
+}
+
+/// What `--notify-url` and `--notify-cmd` receive as JSON at the end of a run.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunReport {
+    pub status: RunStatus,
+    /// The exit code rsynx ends with.
+    pub exit_code: i32,
+    /// Why the run failed, with its causes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destination: Option<String>,
+    /// Unix seconds when the run finished.
+    pub finished: u64,
+    pub elapsed_seconds: f64,
+    /// Totals of the run, unless it failed before finishing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<RunStats>,
+}
+
+impl RunReport {
+    pub fn new(exit_code: i32, elapsed: Duration) -> Self {
+        Self {
+            status: match exit_code {
+                0 => RunStatus::Success,
+                _ => RunStatus::Failure,
+            },
+            exit_code,
+            error: None,
+            source: None,
+            destination: None,
+            finished: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs()),
+            elapsed_seconds: elapsed.as_secs_f64(),
+            stats: None,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("reports always serialize")
+    }
+}
+
+/// Run `command` through the shell with `report` as JSON on its stdin and its status in
+/// `RSYNX_STATUS`, failing unless it exits successfully.
+pub fn run_command(command: &str, report: &RunReport) -> Result<()> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let mut child = shell
+        .arg(command)
+        .env(
+            "RSYNX_STATUS",
+            match report.status {
+                RunStatus::Success => "success",
+                RunStatus::Failure => "failure",
+            },
+        )
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run notify command {:?}", command))?;
+    // A command that ignores its input may exit before reading it
+    if let Some(mut stdin) = child.stdin.take()
+        && let Err(e) = stdin.write_all(report.to_json().as_bytes())
+        && e.kind() != io::ErrorKind::BrokenPipe
+    {
+        return Err(e.into());
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "notify command {:?} exited with {}",
+            command, status
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// POST `report` as JSON to `url`, failing on connection errors and non-2xx responses.
+#[cfg(feature = "http")]
+pub fn post(url: &str, report: &RunReport) -> Result<()> {
+    use crate::error::SyncError;
+
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(30))
+        .build();
+    match agent
+        .post(url)
+        .set("Content-Type", "application/json")
+        .send_string(&report.to_json())
+    {
+        Ok(_) => Ok(()),
+        Err(ureq::Error::Status(status, response)) => Err(SyncError::Refused(format!(
+            "{} returned {} {}",
+            url,
+            status,
+            response.status_text()
+        ))),
+        Err(e) => Err(SyncError::Connect {
+            peer: url.to_string(),
+            source: io::Error::other(e.to_string()),
+        }),
+    }
+}
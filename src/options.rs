@@ -0,0 +1,528 @@
+use crate::conflict::ConflictPolicy;
+use crate::error::{Result, SyncError};
+use crate::events::{EventCallback, SyncEvent};
+use crate::filter::Filter;
+use crate::journal::Journal;
+use crate::pathname::NameForm;
+use crate::plan::TransferOrder;
+use crate::progress::{Progress, ProgressCallback};
+use crate::sync::{CancelToken, MAX_BLOCK_SIZE};
+use crate::throttle::SharedRateLimiter;
+use std::{path::PathBuf, sync::Arc};
+
+/// Options controlling a sync, shared by local and network syncs.
+#[derive(Clone)]
+pub struct SyncOptions {
+    pub block_size: usize,
+    pub preserve_metadata: bool,
+    pub delete_extraneous: bool,
+    /// Descend into subdirectories of a directory source; when off only its top-level files
+    /// are synced.
+    pub recursive: bool,
+    /// Recreate symlinks inside a directory source as symlinks instead of syncing what they
+    /// point to. Local syncs only.
+    pub preserve_links: bool,
+    /// Copy each file's owner and group (unix). Only root can change the owner; other users
+    /// keep the group where allowed, as rsync does.
+    pub preserve_owner: bool,
+    /// Include/exclude rules for the entries of a directory sync. Excluded destination
+    /// entries are also kept when deleting extraneous files.
+    pub filter: Filter,
+    pub compress: bool,
+    /// Cap in bytes per second on data sent over the network or written by local syncs.
+    pub bwlimit: Option<u64>,
+    /// Cap on local disk reads and writes while scanning and reconstructing files, shared by
+    /// every clone of these options. Independent of `bwlimit`.
+    pub disk_limit: Option<SharedRateLimiter>,
+    /// Compare full-file checksums instead of size and mtime when deciding whether to skip a file.
+    pub checksum: bool,
+    /// Seconds by which modification times may differ and still count as equal in the quick
+    /// check, for destinations with coarse timestamps such as FAT's two seconds. Zero requires
+    /// an exact match.
+    pub modify_window: u64,
+    /// Never trust equal sizes and times: every file goes through the delta algorithm, or the
+    /// full-file comparison when `checksum` is set.
+    pub ignore_times: bool,
+    /// Re-hash each written file after the rename and fail if it doesn't match the source.
+    pub verify: bool,
+    /// Record directory sync progress in the destination so an interrupted run can resume.
+    pub checkpoint: bool,
+    /// Keep the data of a failed or cancelled delta reconstruction instead of deleting it, and
+    /// use it as the basis of the next attempt. Local syncs only.
+    pub partial: bool,
+    /// Where kept partial files go, implying `partial`. A relative directory is resolved
+    /// against each destination file's directory; without one, the partial file is kept
+    /// next to its destination with a `.partial` suffix.
+    pub partial_dir: Option<PathBuf>,
+    /// Earlier copy of the destination to hard-link unchanged files from instead of copying
+    /// them, when they're missing from the destination. A relative directory is resolved
+    /// against the destination. Local directory syncs only.
+    pub link_dest: Option<PathBuf>,
+    /// Directory that `delete_extraneous` moves entries into, at their path relative to the
+    /// destination, instead of deleting them. A relative directory is resolved against the
+    /// destination, and it must be on the destination's filesystem. Local syncs only.
+    pub delete_to: Option<PathBuf>,
+    /// Track the destination's files in a state file and resolve those changed there since
+    /// the last sync, which the sync would overwrite or delete, by this policy. Local directory
+    /// syncs only.
+    pub conflicts: Option<ConflictPolicy>,
+    /// Directory keeping the previous version of each file a sync overwrites, and entries it
+    /// deletes when `delete_to` isn't set, at their path relative to the destination, so
+    /// `journal::undo` can restore them. A relative directory is resolved against the
+    /// destination. Local syncs only.
+    pub backup_dir: Option<PathBuf>,
+    /// Records every file written or deleted, with hashes of the old and new contents.
+    /// Local syncs only.
+    pub journal: Option<Journal>,
+    /// Write every new or changed file of a directory sync into a staging area first, and only
+    /// once all of them are written move them into place and delete extraneous entries, so the
+    /// destination never shows a half-updated tree. Local syncs only.
+    pub atomic: bool,
+    /// After a directory sync, replace files in the destination identical to another there
+    /// with hard links to it; see `dedup::link_duplicates`. Local syncs only.
+    pub dedup: bool,
+    /// Order in which a directory sync transfers new and changed files; anything but
+    /// `TransferOrder::AsFound` plans the whole tree before transferring. Local syncs only.
+    pub order: TransferOrder,
+    /// Reserve the full size of each file written before writing it; see
+    /// `sync::preallocate`. Running out of space then fails before any data is written.
+    pub preallocate: bool,
+    /// Keep the files a delta transfer scans and rebuilds out of the page cache, reading with
+    /// `O_DIRECT` where supported; see `direct::DirectReader`. Local syncs only.
+    pub direct_io: bool,
+    /// Compare file names of a directory sync in this Unicode normalization form, so names
+    /// differing only in composition, as between macOS and Linux, are the same entry. New
+    /// destination entries are named in this form; existing equivalent ones keep their name.
+    /// Local syncs only.
+    pub unicode_normalize: Option<NameForm>,
+    /// Draw a progress bar on the terminal for each file; ignored when `progress` is set.
+    pub progress_bar: bool,
+    /// Receives progress reports in place of the terminal progress bar.
+    pub progress: Option<ProgressCallback>,
+    /// Receives an event for each file started, matched, completed, skipped or deleted.
+    pub events: Option<EventCallback>,
+    /// Checked between chunks and instructions; once cancelled the sync stops with
+    /// `SyncError::Cancelled`.
+    pub cancel: CancelToken,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SyncOptions {
+    pub fn new() -> Self {
+        Self {
+            block_size: 1024,
+            preserve_metadata: false,
+            delete_extraneous: false,
+            recursive: true,
+            preserve_links: false,
+            preserve_owner: false,
+            filter: Filter::new(),
+            compress: false,
+            bwlimit: None,
+            disk_limit: None,
+            checksum: false,
+            modify_window: 0,
+            ignore_times: false,
+            verify: false,
+            checkpoint: false,
+            partial: false,
+            partial_dir: None,
+            link_dest: None,
+            delete_to: None,
+            conflicts: None,
+            backup_dir: None,
+            journal: None,
+            atomic: false,
+            dedup: false,
+            order: TransferOrder::AsFound,
+            preallocate: false,
+            direct_io: false,
+            unicode_normalize: None,
+            progress_bar: true,
+            progress: None,
+            events: None,
+            cancel: CancelToken::new(),
+        }
+    }
+
+    pub fn with_block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    pub fn with_preserve_metadata(mut self, preserve: bool) -> Self {
+        self.preserve_metadata = preserve;
+        self
+    }
+
+    pub fn with_delete_extraneous(mut self, delete: bool) -> Self {
+        self.delete_extraneous = delete;
+        self
+    }
+
+    pub fn with_recursive(mut self, recursive: bool) -> Self {
+        self.recursive = recursive;
+        self
+    }
+
+    pub fn with_preserve_links(mut self, preserve: bool) -> Self {
+        self.preserve_links = preserve;
+        self
+    }
+
+    pub fn with_preserve_owner(mut self, preserve: bool) -> Self {
+        self.preserve_owner = preserve;
+        self
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_compression(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    pub fn with_bwlimit(mut self, bytes_per_second: Option<u64>) -> Self {
+        self.bwlimit = bytes_per_second;
+        self
+    }
+
+    /// Limit local disk reads and writes to `bytes_per_second` in total; `None` or zero
+    /// leaves them unlimited.
+    pub fn with_disk_limit(mut self, bytes_per_second: Option<u64>) -> Self {
+        self.disk_limit = bytes_per_second
+            .filter(|&rate| rate > 0)
+            .map(SharedRateLimiter::new);
+        self
+    }
+
+    pub fn with_checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    pub fn with_modify_window(mut self, seconds: u64) -> Self {
+        self.modify_window = seconds;
+        self
+    }
+
+    pub fn with_ignore_times(mut self, ignore: bool) -> Self {
+        self.ignore_times = ignore;
+        self
+    }
+
+    pub fn with_verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    pub fn with_checkpoint(mut self, checkpoint: bool) -> Self {
+        self.checkpoint = checkpoint;
+        self
+    }
+
+    pub fn with_partial(mut self, keep: bool) -> Self {
+        self.partial = keep;
+        self
+    }
+
+    pub fn with_partial_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.partial_dir = dir;
+        self
+    }
+
+    pub fn with_link_dest(mut self, dir: Option<PathBuf>) -> Self {
+        self.link_dest = dir;
+        self
+    }
+
+    pub fn with_delete_to(mut self, dir: Option<PathBuf>) -> Self {
+        self.delete_to = dir;
+        self
+    }
+
+    pub fn with_conflicts(mut self, policy: Option<ConflictPolicy>) -> Self {
+        self.conflicts = policy;
+        self
+    }
+
+    pub fn with_backup_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.backup_dir = dir;
+        self
+    }
+
+    pub fn with_journal(mut self, journal: Option<Journal>) -> Self {
+        self.journal = journal;
+        self
+    }
+
+    pub fn with_atomic(mut self, atomic: bool) -> Self {
+        self.atomic = atomic;
+        self
+    }
+
+    pub fn with_dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    pub fn with_order(mut self, order: TransferOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    pub fn with_preallocate(mut self, preallocate: bool) -> Self {
+        self.preallocate = preallocate;
+        self
+    }
+
+    pub fn with_direct_io(mut self, direct_io: bool) -> Self {
+        self.direct_io = direct_io;
+        self
+    }
+
+    pub fn with_unicode_normalize(mut self, form: Option<NameForm>) -> Self {
+        self.unicode_normalize = form;
+        self
+    }
+
+    pub fn with_progress_bar(mut self, show: bool) -> Self {
+        self.progress_bar = show;
+        self
+    }
+
+    /// Abort the sync with `SyncError::Cancelled` once `token` is cancelled.
+    pub fn with_cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Report progress to `callback` instead of drawing a terminal progress bar.
+    pub fn on_progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&Progress) + Send + Sync + 'static,
+    {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// Report each file-level event to `callback` as the sync runs.
+    pub fn on_event<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&SyncEvent) + Send + Sync + 'static,
+    {
+        self.events = Some(Arc::new(callback));
+        self
+    }
+
+    /// Check the options that are invalid in every sync mode.
+    pub fn validate(&self) -> Result<(), SyncError> {
+        if self.block_size == 0 || self.block_size > MAX_BLOCK_SIZE {
+            return Err(SyncError::InvalidBlockSize(self.block_size));
+        }
+        Ok(())
+    }
+}
+
+/// Implement the `SyncOptions` builder methods on a syncer holding a `syncer: Syncer` field,
+/// so every syncer exposes the same options under the same names.
+macro_rules! impl_option_builders {
+    ($syncer:ty) => {
+        impl $syncer {
+            /// Replace all options at once.
+            pub fn with_options(mut self, options: $crate::options::SyncOptions) -> Self {
+                self.syncer.options = options;
+                self
+            }
+
+            pub fn options(&self) -> &$crate::options::SyncOptions {
+                &self.syncer.options
+            }
+
+            pub fn with_block_size(mut self, block_size: usize) -> Self {
+                self.syncer.options.block_size = block_size;
+                self
+            }
+
+            pub fn with_preserve_metadata(mut self, preserve: bool) -> Self {
+                self.syncer.options.preserve_metadata = preserve;
+                self
+            }
+
+            pub fn with_delete_extraneous(mut self, delete: bool) -> Self {
+                self.syncer.options.delete_extraneous = delete;
+                self
+            }
+
+            pub fn with_recursive(mut self, recursive: bool) -> Self {
+                self.syncer.options.recursive = recursive;
+                self
+            }
+
+            pub fn with_preserve_links(mut self, preserve: bool) -> Self {
+                self.syncer.options.preserve_links = preserve;
+                self
+            }
+
+            pub fn with_preserve_owner(mut self, preserve: bool) -> Self {
+                self.syncer.options.preserve_owner = preserve;
+                self
+            }
+
+            pub fn with_filter(mut self, filter: $crate::filter::Filter) -> Self {
+                self.syncer.options.filter = filter;
+                self
+            }
+
+            pub fn with_compression(mut self, compress: bool) -> Self {
+                self.syncer.options.compress = compress;
+                self
+            }
+
+            pub fn with_bwlimit(mut self, bytes_per_second: Option<u64>) -> Self {
+                self.syncer.options.bwlimit = bytes_per_second;
+                self
+            }
+
+            pub fn with_disk_limit(mut self, bytes_per_second: Option<u64>) -> Self {
+                self.syncer.options = self.syncer.options.with_disk_limit(bytes_per_second);
+                self
+            }
+
+            pub fn with_checksum(mut self, checksum: bool) -> Self {
+                self.syncer.options.checksum = checksum;
+                self
+            }
+
+            pub fn with_modify_window(mut self, seconds: u64) -> Self {
+                self.syncer.options.modify_window = seconds;
+                self
+            }
+
+            pub fn with_ignore_times(mut self, ignore: bool) -> Self {
+                self.syncer.options.ignore_times = ignore;
+                self
+            }
+
+            pub fn with_verify(mut self, verify: bool) -> Self {
+                self.syncer.options.verify = verify;
+                self
+            }
+
+            pub fn with_checkpoint(mut self, checkpoint: bool) -> Self {
+                self.syncer.options.checkpoint = checkpoint;
+                self
+            }
+
+            pub fn with_partial(mut self, keep: bool) -> Self {
+                self.syncer.options.partial = keep;
+                self
+            }
+
+            pub fn with_partial_dir(mut self, dir: Option<std::path::PathBuf>) -> Self {
+                self.syncer.options.partial_dir = dir;
+                self
+            }
+
+            pub fn with_link_dest(mut self, dir: Option<std::path::PathBuf>) -> Self {
+                self.syncer.options.link_dest = dir;
+                self
+            }
+
+            pub fn with_delete_to(mut self, dir: Option<std::path::PathBuf>) -> Self {
+                self.syncer.options.delete_to = dir;
+                self
+            }
+
+            pub fn with_conflicts(
+                mut self,
+                policy: Option<$crate::conflict::ConflictPolicy>,
+            ) -> Self {
+                self.syncer.options.conflicts = policy;
+                self
+            }
+
+            pub fn with_backup_dir(mut self, dir: Option<std::path::PathBuf>) -> Self {
+                self.syncer.options.backup_dir = dir;
+                self
+            }
+
+            pub fn with_journal(mut self, journal: Option<$crate::journal::Journal>) -> Self {
+                self.syncer.options.journal = journal;
+                self
+            }
+
+            pub fn with_atomic(mut self, atomic: bool) -> Self {
+                self.syncer.options.atomic = atomic;
+                self
+            }
+
+            pub fn with_dedup(mut self, dedup: bool) -> Self {
+                self.syncer.options.dedup = dedup;
+                self
+            }
+
+            pub fn with_order(mut self, order: $crate::plan::TransferOrder) -> Self {
+                self.syncer.options.order = order;
+                self
+            }
+
+            pub fn with_preallocate(mut self, preallocate: bool) -> Self {
+                self.syncer.options.preallocate = preallocate;
+                self
+            }
+
+            pub fn with_direct_io(mut self, direct_io: bool) -> Self {
+                self.syncer.options.direct_io = direct_io;
+                self
+            }
+
+            pub fn with_unicode_normalize(
+                mut self,
+                form: Option<$crate::pathname::NameForm>,
+            ) -> Self {
+                self.syncer.options.unicode_normalize = form;
+                self
+            }
+
+            pub fn with_progress_bar(mut self, show: bool) -> Self {
+                self.syncer.options.progress_bar = show;
+                self
+            }
+
+            /// Abort the sync with `SyncError::Cancelled` once `token` is cancelled.
+            pub fn with_cancel_token(mut self, token: $crate::sync::CancelToken) -> Self {
+                self.syncer.options.cancel = token;
+                self
+            }
+
+            /// Report progress to `callback` instead of drawing a terminal progress bar.
+            pub fn on_progress<F>(mut self, callback: F) -> Self
+            where
+                F: Fn(&$crate::progress::Progress) + Send + Sync + 'static,
+            {
+                self.syncer.options.progress = Some(std::sync::Arc::new(callback));
+                self
+            }
+
+            /// Report each file-level event to `callback` as the sync runs.
+            pub fn on_event<F>(mut self, callback: F) -> Self
+            where
+                F: Fn(&$crate::events::SyncEvent) + Send + Sync + 'static,
+            {
+                self.syncer.options.events = Some(std::sync::Arc::new(callback));
+                self
+            }
+        }
+    };
+}
+
+pub(crate) use impl_option_builders;
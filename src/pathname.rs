@@ -0,0 +1,174 @@
+use crate::error::Result;
+use std::{
+    ffi::{OsStr, OsString},
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use unicode_normalization::UnicodeNormalization;
+
+/// `path` as one line of text that `unescape` turns back into the same bytes, even where
+/// they aren't valid UTF-8. Valid UTF-8 is kept as it is apart from `%` and control
+/// characters such as newlines; those and invalid bytes become `%XX` hex escapes. Off unix,
+/// only names that are valid Unicode come back from `unescape`.
+pub fn escape(path: &Path) -> String {
+    fn push_escaped(escaped: &mut String, bytes: &[u8]) {
+        for byte in bytes {
+            escaped.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    let mut escaped = String::new();
+    for chunk in path.as_os_str().as_encoded_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            if c == '%' || c.is_control() {
+                push_escaped(&mut escaped, c.encode_utf8(&mut [0; 4]).as_bytes());
+            } else {
+                escaped.push(c);
+            }
+        }
+        push_escaped(&mut escaped, chunk.invalid());
+    }
+    escaped
+}
+
+/// The path `escape` turned into `text`, or `None` when an escape is malformed.
+pub fn unescape(text: &str) -> Option<PathBuf> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    from_bytes(bytes)
+}
+
+/// The raw bytes of `path`, as sent over the network.
+#[cfg(unix)]
+pub fn as_bytes(path: &Path) -> Result<&[u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(path.as_os_str().as_bytes())
+}
+
+/// The UTF-8 bytes of `path`, as sent over the network. Other platforms' names have no
+/// byte form a unix peer would read back the same, so names that aren't valid Unicode are
+/// refused.
+#[cfg(not(unix))]
+pub fn as_bytes(path: &Path) -> Result<&[u8]> {
+    use crate::error::SyncError;
+    path.to_str()
+        .map(str::as_bytes)
+        .ok_or_else(|| SyncError::UnsupportedSource {
+            path: path.to_path_buf(),
+            reason: "file names that aren't valid Unicode can only be sent from unix",
+        })
+}
+
+/// A path received as raw bytes. Any bytes make a unix path.
+#[cfg(unix)]
+pub fn from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+    Some(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+/// A path received as raw bytes, or `None` when they aren't valid UTF-8.
+#[cfg(not(unix))]
+pub fn from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+/// Unicode normalization form file names are compared and created in. macOS filesystems
+/// store names decomposed (NFD) while Linux keeps them as written, usually composed (NFC), so
+/// the same name can arrive as different bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameForm {
+    /// Composed: `é` is one code point.
+    Nfc,
+    /// Decomposed: `é` is `e` followed by a combining accent.
+    Nfd,
+}
+
+impl NameForm {
+    /// `name` in this form. Names that aren't valid UTF-8 are returned unchanged.
+    pub fn apply(self, name: &OsStr) -> OsString {
+        match name.to_str() {
+            Some(name) => match self {
+                NameForm::Nfc => name.nfc().collect::<String>().into(),
+                NameForm::Nfd => name.nfd().collect::<String>().into(),
+            },
+            None => name.to_os_string(),
+        }
+    }
+}
+
+impl FromStr for NameForm {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "nfc" => Ok(NameForm::Nfc),
+            "nfd" => Ok(NameForm::Nfd),
+            _ => Err(format!(
+                "unknown normalization form {:?}; expected nfc or nfd",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for NameForm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            NameForm::Nfc => "nfc",
+            NameForm::Nfd => "nfd",
+        })
+    }
+}
+
+/// Serde support for paths stored through `escape`, for formats such as JSON that only
+/// hold UTF-8 strings: `#[serde(with = "pathname::escaped")]`.
+pub mod escaped {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+    use std::path::{Path, PathBuf};
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::escape(path))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        super::unescape(&text).ok_or_else(|| D::Error::custom("invalid escape in path"))
+    }
+
+    /// The same for optional paths.
+    pub mod option {
+        use serde::{Deserialize, Deserializer, Serializer, de::Error};
+        use std::path::PathBuf;
+
+        pub fn serialize<S: Serializer>(
+            path: &Option<PathBuf>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match path {
+                Some(path) => super::serialize(path, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<PathBuf>, D::Error> {
+            match Option::<String>::deserialize(deserializer)? {
+                Some(text) => super::super::unescape(&text)
+                    .map(Some)
+                    .ok_or_else(|| D::Error::custom("invalid escape in path")),
+                None => Ok(None),
+            }
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use crate::delta::DeltaOp;
+use crate::error::{IoContext, Result};
+use crate::sync::Syncer;
+use filetime::FileTime;
+use serde::{Deserialize, Serialize};
+use std::{fmt, fs, fs::File, path::Path, path::PathBuf, str::FromStr, time::SystemTime};
+
+/// What a planned operation does to its destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanAction {
+    Create,
+    Update,
+    Delete,
+    Skip,
+}
+
+impl fmt::Display for PlanAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PlanAction::Create => "create",
+            PlanAction::Update => "update",
+            PlanAction::Delete => "delete",
+            PlanAction::Skip => "skip",
+        };
+        f.pad(name)
+    }
+}
+
+/// Why an operation was planned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanReason {
+    /// The destination doesn't exist.
+    Missing,
+    SizeChanged,
+    MtimeChanged,
+    /// Sizes match but the content hashes don't; only checked with the `checksum` option.
+    ChecksumChanged,
+    /// The destination isn't a symlink to the same target; only checked with `preserve_links`.
+    TargetChanged,
+    /// One side is a file and the other a directory or symlink, or the other way round.
+    TypeChanged,
+    /// The quick check found the destination up to date.
+    Unchanged,
+    /// Single-file syncs always rewrite their destination.
+    FileSource,
+    /// Sizes match and `ignore_times` skipped the modification time comparison.
+    TimesIgnored,
+    /// The destination entry has no counterpart in the source.
+    Extraneous,
+}
+
+impl fmt::Display for PlanReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PlanReason::Missing => "missing",
+            PlanReason::SizeChanged => "size_changed",
+            PlanReason::MtimeChanged => "mtime_changed",
+            PlanReason::ChecksumChanged => "checksum_changed",
+            PlanReason::TargetChanged => "target_changed",
+            PlanReason::TypeChanged => "type_changed",
+            PlanReason::Unchanged => "unchanged",
+            PlanReason::FileSource => "file_source",
+            PlanReason::TimesIgnored => "times_ignored",
+            PlanReason::Extraneous => "extraneous",
+        };
+        f.pad(name)
+    }
+}
+
+/// One step of a sync plan.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannedOp {
+    pub action: PlanAction,
+    /// Source entry; `None` for deletions.
+    pub source: Option<PathBuf>,
+    pub destination: PathBuf,
+    /// Size of the source, or of the destination for deletions; zero for directories.
+    pub size: u64,
+    pub is_dir: bool,
+    pub reason: PlanReason,
+}
+
+/// Order in which a directory sync transfers its new and changed files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TransferOrder {
+    /// The order the source tree is walked in.
+    #[default]
+    AsFound,
+    SmallestFirst,
+    LargestFirst,
+    /// Most recently modified source files first.
+    NewestFirst,
+}
+
+impl FromStr for TransferOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "as-found" => Ok(TransferOrder::AsFound),
+            "smallest-first" => Ok(TransferOrder::SmallestFirst),
+            "largest-first" => Ok(TransferOrder::LargestFirst),
+            "newest-first" => Ok(TransferOrder::NewestFirst),
+            _ => Err(format!(
+                "unknown transfer order {:?}; expected as-found, smallest-first, largest-first \
+                 or newest-first",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TransferOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TransferOrder::AsFound => "as-found",
+            TransferOrder::SmallestFirst => "smallest-first",
+            TransferOrder::LargestFirst => "largest-first",
+            TransferOrder::NewestFirst => "newest-first",
+        })
+    }
+}
+
+impl TransferOrder {
+    /// Reorder the file creates and updates of `plan` among themselves. Every other step keeps
+    /// its place, so directories are still created and entries deleted where they were
+    /// planned. Ties keep their planned order.
+    pub fn apply(self, plan: &mut [PlannedOp]) {
+        if self == TransferOrder::AsFound {
+            return;
+        }
+        let slots: Vec<usize> = plan
+            .iter()
+            .enumerate()
+            .filter(|(_, op)| {
+                !op.is_dir && matches!(op.action, PlanAction::Create | PlanAction::Update)
+            })
+            .map(|(i, _)| i)
+            .collect();
+        let mut transfers: Vec<PlannedOp> = slots.iter().map(|&i| plan[i].clone()).collect();
+        match self {
+            TransferOrder::AsFound => {}
+            TransferOrder::SmallestFirst => transfers.sort_by_key(|op| op.size),
+            TransferOrder::LargestFirst => transfers.sort_by_key(|op| std::cmp::Reverse(op.size)),
+            TransferOrder::NewestFirst => transfers.sort_by_cached_key(|op| {
+                // Files whose time can't be read go last
+                std::cmp::Reverse(
+                    op.source
+                        .as_deref()
+                        .and_then(|source| fs::symlink_metadata(source).ok())
+                        .and_then(|meta| meta.modified().ok())
+                        .unwrap_or(SystemTime::UNIX_EPOCH),
+                )
+            }),
+        }
+        for (slot, op) in slots.into_iter().zip(transfers) {
+            plan[slot] = op;
+        }
+    }
+}
+
+impl Syncer {
+    /// Quick check comparing `src` against `dst`: equal size and modification time, or equal
+    /// content hash when `checksum` is set, count as `PlanReason::Unchanged`. Times only need
+    /// to be within the `modify_window`, and never match with `ignore_times`.
+    pub fn quick_check(&self, src: &Path, dst: &Path) -> Result<PlanReason> {
+        let src_meta = fs::metadata(src)
+            .with_context(|| format!("Failed to get metadata for source file: {:?}", src))?;
+        let dst_meta = match fs::metadata(dst) {
+            Ok(meta) if meta.is_file() => meta,
+            _ => return Ok(PlanReason::Missing),
+        };
+        if src_meta.len() != dst_meta.len() {
+            return Ok(PlanReason::SizeChanged);
+        }
+        if self.options.checksum {
+            if self.calculate_file_checksum(src)? == self.calculate_file_checksum(dst)? {
+                return Ok(PlanReason::Unchanged);
+            }
+            return Ok(PlanReason::ChecksumChanged);
+        }
+        if self.options.ignore_times {
+            return Ok(PlanReason::TimesIgnored);
+        }
+        if self.mtimes_match(
+            FileTime::from_last_modification_time(&src_meta),
+            FileTime::from_last_modification_time(&dst_meta),
+        ) {
+            Ok(PlanReason::Unchanged)
+        } else {
+            Ok(PlanReason::MtimeChanged)
+        }
+    }
+
+    /// Whether two modification times are equal, or within `modify_window` seconds of each
+    /// other when one is set.
+    pub(crate) fn mtimes_match(&self, a: FileTime, b: FileTime) -> bool {
+        if self.options.modify_window == 0 {
+            return a == b;
+        }
+        a.unix_seconds().abs_diff(b.unix_seconds()) <= self.options.modify_window
+    }
+
+    /// Compare the symlink `src` against `dst`, which is unchanged only when it's a symlink
+    /// to the same target.
+    pub fn link_check(&self, src: &Path, dst: &Path) -> Result<PlanReason> {
+        let target =
+            fs::read_link(src).with_context(|| format!("Failed to read symlink {:?}", src))?;
+        if fs::symlink_metadata(dst).is_err() {
+            return Ok(PlanReason::Missing);
+        }
+        if fs::read_link(dst).is_ok_and(|existing| existing == target) {
+            Ok(PlanReason::Unchanged)
+        } else {
+            Ok(PlanReason::TargetChanged)
+        }
+    }
+
+    /// How much of `dst` would change if `src` replaced it, from 0.0 for the same content to
+    /// 1.0 when nothing is shared: the larger of the literal data a delta would send and the
+    /// part of `dst` it would drop, relative to the larger file. A `dst` that isn't a regular
+    /// file counts as entirely different.
+    pub fn changed_fraction(&self, src: &Path, dst: &Path) -> Result<f64> {
+        let src_size = fs::metadata(src)
+            .with_context(|| format!("Failed to get metadata for source file: {:?}", src))?
+            .len();
+        let dst_size = match fs::metadata(dst) {
+            Ok(meta) if meta.is_file() => meta.len(),
+            _ => return Ok(1.0),
+        };
+        if src_size == 0 && dst_size == 0 {
+            return Ok(0.0);
+        }
+        let signature = self.generate_signature(dst)?;
+        let file = self.disk_io(
+            File::open(src).with_context(|| format!("Failed to open source file: {:?}", src))?,
+        );
+        let (mut literal, mut copied) = (0u64, 0u64);
+        self.stream_delta(&signature, file, |op| {
+            match op {
+                DeltaOp::Literal(data) => literal += data.len() as u64,
+                DeltaOp::Copy { len, .. } => copied += len as u64,
+            }
+            Ok(())
+        })?;
+        let dropped = dst_size.saturating_sub(copied);
+        Ok(literal.max(dropped) as f64 / src_size.max(dst_size) as f64)
+    }
+}
@@ -0,0 +1,156 @@
+use crate::error::{Result, SyncError};
+use crate::protocol;
+use crate::transport::{Connector, TcpConnector, Transport};
+use std::io::{BufRead, BufReader, Write};
+use tracing::info;
+
+/// Version of the network protocol spoken by this build, bumped on incompatible changes.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// What a server reports about itself in answer to `PROBE`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerInfo {
+    pub version: u32,
+    /// Compression algorithms usable with `ZDATA`.
+    pub compression: Vec<String>,
+    /// Weak and strong checksums used for block signatures.
+    pub hashes: Vec<String>,
+    /// Optional protocol features, such as `verify` or `modules`.
+    pub features: Vec<String>,
+    pub modules: Vec<ModuleInfo>,
+}
+
+/// A daemon module as listed by a probe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleInfo {
+    pub name: String,
+    /// Whether syncing into the module needs credentials.
+    pub auth: bool,
+    pub comment: Option<String>,
+}
+
+impl ServerInfo {
+    /// Description of this build's plain server.
+    pub fn local() -> Self {
+        let words = |list: &[&str]| list.iter().map(|s| s.to_string()).collect();
+        Self {
+            version: PROTOCOL_VERSION,
+            compression: words(&["gzip"]),
+            hashes: words(&["adler", "sha256"]),
+            features: words(&["metadata", "verify", "list"]),
+            modules: Vec::new(),
+        }
+    }
+
+    /// This description for a daemon exporting `modules`.
+    pub fn with_modules(mut self, modules: Vec<ModuleInfo>) -> Self {
+        self.features
+            .extend(["modules".to_string(), "auth".to_string()]);
+        self.modules = modules;
+        self
+    }
+
+    /// Answer a probe. Format: VERSION <n>, then COMPRESSION, HASHES and FEATURES lines of
+    /// space-separated names, one MODULE <name> <auth|open> [comment] line per module, and END.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writeln!(writer, "VERSION {}", self.version)?;
+        writeln!(writer, "COMPRESSION {}", self.compression.join(" "))?;
+        writeln!(writer, "HASHES {}", self.hashes.join(" "))?;
+        writeln!(writer, "FEATURES {}", self.features.join(" "))?;
+        for module in &self.modules {
+            let auth = if module.auth { "auth" } else { "open" };
+            match &module.comment {
+                Some(comment) => {
+                    let comment = comment.replace(['\r', '\n'], " ");
+                    writeln!(writer, "MODULE {} {} {}", module.name, auth, comment)?
+                }
+                None => writeln!(writer, "MODULE {} {}", module.name, auth)?,
+            }
+        }
+        writeln!(writer, "END")?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Read a probe answer written by [`ServerInfo::write_to`]. Unknown lines are skipped so
+    /// newer servers can report more.
+    pub fn read_from<R: BufRead>(reader: &mut R) -> Result<Self> {
+        let mut info = Self {
+            version: 0,
+            compression: Vec::new(),
+            hashes: Vec::new(),
+            features: Vec::new(),
+            modules: Vec::new(),
+        };
+        let words = |rest: &str| rest.split_whitespace().map(str::to_string).collect();
+        loop {
+            let Some(line) = protocol::read_line(reader)? else {
+                return Err(SyncError::Protocol(
+                    "Connection closed before the end of the probe answer".to_string(),
+                ));
+            };
+            let line = line.as_str();
+            let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+            match command {
+                "END" => break,
+                "VERSION" => {
+                    info.version = rest.parse().map_err(|_| {
+                        SyncError::Protocol(format!("Invalid protocol version: {}", rest))
+                    })?;
+                }
+                "COMPRESSION" => info.compression = words(rest),
+                "HASHES" => info.hashes = words(rest),
+                "FEATURES" => info.features = words(rest),
+                "MODULE" => {
+                    let mut parts = rest.splitn(3, ' ');
+                    let (Some(name), Some(auth)) = (parts.next(), parts.next()) else {
+                        return Err(SyncError::Protocol(format!(
+                            "Invalid MODULE line: {}",
+                            line
+                        )));
+                    };
+                    info.modules.push(ModuleInfo {
+                        name: name.to_string(),
+                        auth: auth == "auth",
+                        comment: parts.next().map(str::to_string),
+                    });
+                }
+                "ERROR" => return Err(SyncError::Refused(rest.to_string())),
+                _ => {}
+            }
+        }
+        if info.version == 0 {
+            return Err(SyncError::Protocol(
+                "Probe answer has no protocol version".to_string(),
+            ));
+        }
+        Ok(info)
+    }
+}
+
+/// Connect to the server at `address:port` and ask what it supports, without syncing.
+pub fn probe(address: &str, port: u16) -> Result<ServerInfo> {
+    probe_with(&TcpConnector {
+        address: address.to_string(),
+        port,
+    })
+}
+
+/// Probe the server reached by `connector`.
+pub fn probe_with<C: Connector>(connector: &C) -> Result<ServerInfo> {
+    let peer = connector.peer();
+    let transport = connector.connect().map_err(|source| SyncError::Connect {
+        peer: peer.clone(),
+        source,
+    })?;
+    info!("Connected to remote server at {}", peer);
+    probe_over(transport)
+}
+
+/// Probe the server at the other end of `transport`.
+pub fn probe_over<T: Transport>(transport: T) -> Result<ServerInfo> {
+    let mut reader = BufReader::new(transport);
+    writeln!(reader.get_mut(), "PROBE")?;
+    reader.get_mut().flush()?;
+    ServerInfo::read_from(&mut reader)
+}
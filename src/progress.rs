@@ -0,0 +1,90 @@
+use crate::sync::Syncer;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::{path::Path, sync::Arc};
+
+/// Stage of a single file's sync.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Checksumming the existing destination.
+    Signature,
+    /// Writing the new content; `bytes_done` counts bytes of the source handled so far.
+    Transfer,
+    /// Re-hashing the written file.
+    Verify,
+    /// The file is in place.
+    Complete,
+}
+
+/// A progress report for the file currently being synced.
+#[derive(Debug, Clone, Copy)]
+pub struct Progress<'a> {
+    pub file: &'a Path,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub phase: Phase,
+}
+
+/// Callback receiving progress reports; it is called from the thread driving the sync.
+pub type ProgressCallback = Arc<dyn Fn(&Progress) + Send + Sync>;
+
+/// Routes a file's progress to the user's callback, or to a terminal progress bar (possibly
+/// hidden) when none is installed.
+pub(crate) enum ProgressReporter<'a> {
+    Bar(ProgressBar),
+    Callback {
+        callback: &'a ProgressCallback,
+        file: &'a Path,
+        total: u64,
+    },
+}
+
+impl<'a> ProgressReporter<'a> {
+    pub(crate) fn new(syncer: &'a Syncer, file: &'a Path, total: u64, message: String) -> Self {
+        if let Some(callback) = &syncer.options.progress {
+            return Self::Callback {
+                callback,
+                file,
+                total,
+            };
+        }
+        if !syncer.options.progress_bar {
+            return Self::Bar(ProgressBar::hidden());
+        }
+        let pb = ProgressBar::new(total);
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")
+                .expect("Failed to set progress bar template")
+                .progress_chars("#>-"),
+        );
+        pb.set_message(message);
+        Self::Bar(pb)
+    }
+
+    pub(crate) fn update(&self, phase: Phase, bytes_done: u64) {
+        match self {
+            Self::Bar(pb) => {
+                if phase == Phase::Transfer {
+                    pb.set_position(bytes_done);
+                }
+            }
+            Self::Callback {
+                callback,
+                file,
+                total,
+            } => callback(&Progress {
+                file,
+                bytes_done,
+                bytes_total: *total,
+                phase,
+            }),
+        }
+    }
+
+    pub(crate) fn finish(&self, message: String) {
+        match self {
+            Self::Bar(pb) => pb.finish_with_message(message),
+            Self::Callback { total, .. } => self.update(Phase::Complete, *total),
+        }
+    }
+}
@@ -0,0 +1,377 @@
+use crate::error::{Result, SyncError};
+use crate::pathname;
+use crate::sync::{Block, MAX_BLOCK_SIZE};
+use filetime::FileTime;
+use std::{
+    io::{BufRead, Read},
+    path::PathBuf,
+    str::FromStr,
+};
+
+/// Longest line a peer may send, line ending included. The longest legitimate lines, META
+/// and BLK, take well under 200 bytes.
+pub const MAX_LINE_LEN: usize = 1024;
+
+/// Longest path a request may carry, Linux's `PATH_MAX`.
+pub const MAX_PATH_LEN: usize = 4096;
+
+/// Largest payload a single DATA, ZDATA or COPY instruction may carry; clients send far less.
+pub const MAX_INSTRUCTION_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Most entries a server's block table may list: a 16 GiB file at the default 1 KiB block
+/// size, or about 900 MiB of signature in memory.
+pub const MAX_BLOCK_ENTRIES: usize = 16 * 1024 * 1024;
+
+/// A request opening a connection, or following the daemon's module handshake.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    /// `PROBE`: describe the server; see `probe::ServerInfo`.
+    Probe,
+    /// `MODULE <name>`: the rest of the connection concerns daemon module `name`.
+    Module(String),
+    /// `LIST <0|1> <path_len>` followed by the path's bytes: the manifest of `path`, with
+    /// content hashes when `hash` is set.
+    List { hash: bool, path: PathBuf },
+    /// `FILE <src_name_len> <dst_path_len> <size>` followed by the bytes of both paths:
+    /// receive a file of `size` bytes into `destination`.
+    File {
+        source_name: PathBuf,
+        destination: PathBuf,
+        size: u64,
+    },
+}
+
+/// One instruction of the stream following a FILE request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    /// `DATA <length>`, followed by that many literal bytes.
+    Data(u64),
+    /// `ZDATA <length>`, followed by that many bytes of gzip data.
+    ZData(u64),
+    /// `COPY <offset> <length>`: bytes of the existing file to reuse.
+    Copy { offset: u64, length: u64 },
+    /// `VERIFY <sha256_hex>`: checksum of the whole file, to check once it's written.
+    Verify([u8; 32]),
+    /// `META <mode_octal> <atime_secs> <atime_nanos> <mtime_secs> <mtime_nanos>`.
+    Meta {
+        mode: u32,
+        atime: FileTime,
+        mtime: FileTime,
+    },
+    /// `DONE`: the file is complete.
+    Done,
+}
+
+/// Read one line of at most `MAX_LINE_LEN` bytes, without trailing whitespace, or `None` at
+/// the end of the stream. Longer lines and invalid UTF-8 are protocol errors, so a peer can't
+/// make us buffer without bound.
+pub fn read_line<R: BufRead>(reader: &mut R) -> Result<Option<String>> {
+    let mut line = Vec::new();
+    reader
+        .take(MAX_LINE_LEN as u64)
+        .read_until(b'\n', &mut line)?;
+    decode_line(line)
+}
+
+/// Check and decode a line read as in `read_line`, taking at most `MAX_LINE_LEN` bytes.
+pub(crate) fn decode_line(line: Vec<u8>) -> Result<Option<String>> {
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if line.len() == MAX_LINE_LEN && line.last() != Some(&b'\n') {
+        return Err(SyncError::Protocol(format!(
+            "Line exceeds the {} byte limit",
+            MAX_LINE_LEN
+        )));
+    }
+    let line = String::from_utf8(line)
+        .map_err(|_| SyncError::Protocol("Line is not valid UTF-8".to_string()))?;
+    Ok(Some(line.trim_end().to_string()))
+}
+
+/// Read a request line and the paths following it.
+pub fn read_request<R: BufRead>(reader: &mut R) -> Result<Request> {
+    let line = read_line(reader)?
+        .ok_or_else(|| SyncError::Protocol("Connection closed before a request".to_string()))?;
+    Ok(match parse_request_line(&line)? {
+        RequestLine::Complete(request) => request,
+        RequestLine::List { hash, path_len } => Request::List {
+            hash,
+            path: read_path(reader, path_len)?,
+        },
+        RequestLine::File {
+            source_name_len,
+            destination_len,
+            size,
+        } => Request::File {
+            source_name: read_path(reader, source_name_len)?,
+            destination: read_path(reader, destination_len)?,
+            size,
+        },
+    })
+}
+
+/// A parsed request line, still missing the paths that follow it for LIST and FILE.
+pub(crate) enum RequestLine {
+    Complete(Request),
+    List {
+        hash: bool,
+        path_len: usize,
+    },
+    File {
+        source_name_len: usize,
+        destination_len: usize,
+        size: u64,
+    },
+}
+
+/// Parse a request line; the paths following LIST and FILE are left to the caller.
+pub(crate) fn parse_request_line(line: &str) -> Result<RequestLine> {
+    let mut parts = line.split_whitespace();
+    let command = parts.next().unwrap_or("");
+    let request = match command {
+        "PROBE" => RequestLine::Complete(Request::Probe),
+        "MODULE" => {
+            RequestLine::Complete(Request::Module(parse_field(parts.next(), "name", command)?))
+        }
+        "LIST" => {
+            let hash = match parts.next() {
+                Some("0") => false,
+                Some("1") => true,
+                arg => return Err(invalid_field(arg, "hash flag", command)),
+            };
+            RequestLine::List {
+                hash,
+                path_len: parse_path_len(parts.next(), command)?,
+            }
+        }
+        "FILE" => RequestLine::File {
+            source_name_len: parse_path_len(parts.next(), command)?,
+            destination_len: parse_path_len(parts.next(), command)?,
+            size: parse_field(parts.next(), "filesize", command)?,
+        },
+        _ => {
+            return Err(SyncError::Protocol(format!(
+                "Unknown request: {}",
+                truncated(line)
+            )));
+        }
+    };
+    end_of_command(parts, command)?;
+    Ok(request)
+}
+
+/// Read the next instruction of a FILE transfer. Ending the stream before DONE is an error.
+pub fn read_instruction<R: BufRead>(reader: &mut R) -> Result<Instruction> {
+    let line = read_line(reader)?
+        .ok_or_else(|| SyncError::Protocol("Connection closed before DONE".to_string()))?;
+    parse_instruction(&line)
+}
+
+/// Parse an instruction line; the payloads of DATA and ZDATA are left to the caller.
+pub fn parse_instruction(line: &str) -> Result<Instruction> {
+    let mut parts = line.split_whitespace();
+    let command = parts.next().unwrap_or("");
+    let instruction = match command {
+        "DONE" => Instruction::Done,
+        "DATA" => Instruction::Data(parse_instruction_len(parts.next(), command)?),
+        "ZDATA" => Instruction::ZData(parse_instruction_len(parts.next(), command)?),
+        "COPY" => Instruction::Copy {
+            offset: parse_field(parts.next(), "offset", command)?,
+            length: parse_instruction_len(parts.next(), command)?,
+        },
+        "VERIFY" => Instruction::Verify(parse_checksum(parts.next(), command)?),
+        "META" => {
+            let mode = parts.next();
+            let mode = mode
+                .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+                .filter(|&mode| mode <= 0o7777)
+                .ok_or_else(|| invalid_field(mode, "mode", command))?;
+            Instruction::Meta {
+                mode,
+                atime: parse_time(&mut parts, "atime", command)?,
+                mtime: parse_time(&mut parts, "mtime", command)?,
+            }
+        }
+        _ => {
+            return Err(SyncError::Protocol(format!(
+                "Unknown command: {}",
+                truncated(command)
+            )));
+        }
+    };
+    end_of_command(parts, command)?;
+    Ok(instruction)
+}
+
+/// Read a server's answer to a FILE request: `None` for NOBLK, when it has no such file yet,
+/// or the blocks of its BLK lines up to BLKEND. Format of each: BLK <offset> <size> <weak>
+/// <strong_hex>. ERROR <reason> is returned as `SyncError::Refused`.
+pub fn read_block_table<R: BufRead>(reader: &mut R) -> Result<Option<Vec<Block>>> {
+    let mut blocks = Vec::new();
+    loop {
+        let line = read_line(reader)?.ok_or_else(|| {
+            SyncError::Protocol("Connection closed inside the block table".to_string())
+        })?;
+        match parse_table_line(&line, blocks.len())? {
+            TableLine::Missing => return Ok(None),
+            TableLine::End => return Ok(Some(blocks)),
+            TableLine::Block(block) => blocks.push(block),
+        }
+    }
+}
+
+/// One line of a server's block table.
+pub(crate) enum TableLine {
+    /// NOBLK: the server has no such file yet.
+    Missing,
+    /// BLKEND: the table is complete.
+    End,
+    Block(Block),
+}
+
+/// Parse a line of a block table following `listed` blocks; see `read_block_table`.
+pub(crate) fn parse_table_line(line: &str, listed: usize) -> Result<TableLine> {
+    if line == "NOBLK" && listed == 0 {
+        return Ok(TableLine::Missing);
+    }
+    if line == "BLKEND" {
+        return Ok(TableLine::End);
+    }
+    if let Some(reason) = line.strip_prefix("ERROR ")
+        && listed == 0
+    {
+        return Err(SyncError::Refused(reason.to_string()));
+    }
+    let mut parts = line.split_whitespace();
+    if parts.next() != Some("BLK") {
+        return Err(SyncError::Protocol(format!(
+            "Invalid response from server: {}",
+            truncated(line)
+        )));
+    }
+    if listed == MAX_BLOCK_ENTRIES {
+        return Err(SyncError::Protocol(format!(
+            "Block table exceeds {} entries",
+            MAX_BLOCK_ENTRIES
+        )));
+    }
+    let offset = parse_field(parts.next(), "offset", "BLK")?;
+    let size = parts.next();
+    let size = size
+        .and_then(|size| size.parse().ok())
+        .filter(|&size| size <= MAX_BLOCK_SIZE)
+        .ok_or_else(|| invalid_field(size, "size", "BLK"))?;
+    let block = Block {
+        offset,
+        size,
+        weak_checksum: parse_field(parts.next(), "weak checksum", "BLK")?,
+        strong_checksum: parse_checksum(parts.next(), "BLK")?,
+    };
+    end_of_command(parts, "BLK")?;
+    Ok(TableLine::Block(block))
+}
+
+/// Parse one whitespace-separated argument of a protocol command.
+pub(crate) fn parse_field<T: FromStr>(arg: Option<&str>, name: &str, command: &str) -> Result<T> {
+    arg.and_then(|arg| arg.parse().ok())
+        .ok_or_else(|| invalid_field(arg, name, command))
+}
+
+fn invalid_field(arg: Option<&str>, name: &str, command: &str) -> SyncError {
+    SyncError::Protocol(match arg {
+        Some(arg) => format!(
+            "Invalid {} in {} command: {}",
+            name,
+            command,
+            truncated(arg)
+        ),
+        None => format!("Missing {} in {} command", name, command),
+    })
+}
+
+/// Fail if a command has arguments beyond those it takes.
+fn end_of_command<'a>(mut parts: impl Iterator<Item = &'a str>, command: &str) -> Result<()> {
+    match parts.next() {
+        Some(extra) => Err(SyncError::Protocol(format!(
+            "Unexpected argument in {} command: {}",
+            command,
+            truncated(extra)
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Parse the length argument of a payload instruction, rejecting anything above
+/// `MAX_INSTRUCTION_SIZE` before a single byte of it is read.
+fn parse_instruction_len(arg: Option<&str>, command: &str) -> Result<u64> {
+    let length: u64 = parse_field(arg, "length", command)?;
+    if length > MAX_INSTRUCTION_SIZE {
+        return Err(SyncError::Protocol(format!(
+            "{} length {} exceeds the {} byte limit",
+            command, length, MAX_INSTRUCTION_SIZE
+        )));
+    }
+    Ok(length)
+}
+
+/// Parse the length of a path following a command, at most `MAX_PATH_LEN`.
+fn parse_path_len(arg: Option<&str>, command: &str) -> Result<usize> {
+    let length: usize = parse_field(arg, "path length", command)?;
+    if length > MAX_PATH_LEN {
+        return Err(SyncError::Protocol(format!(
+            "{} path length {} exceeds the {} byte limit",
+            command, length, MAX_PATH_LEN
+        )));
+    }
+    Ok(length)
+}
+
+/// Read the `len` bytes of a path sent after a command line; see `decode_path`.
+fn read_path<R: Read>(reader: &mut R, len: usize) -> Result<PathBuf> {
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    decode_path(bytes)
+}
+
+/// The path sent as `bytes` after a command line. Paths can't hold NUL bytes, nor anything
+/// but UTF-8 off unix.
+pub(crate) fn decode_path(bytes: Vec<u8>) -> Result<PathBuf> {
+    if bytes.contains(&0) {
+        return Err(SyncError::Protocol("Path contains a NUL byte".to_string()));
+    }
+    pathname::from_bytes(bytes)
+        .ok_or_else(|| SyncError::Protocol("Path is not valid on this platform".to_string()))
+}
+
+/// Parse a hex-encoded 32 byte checksum argument.
+fn parse_checksum(arg: Option<&str>, command: &str) -> Result<[u8; 32]> {
+    let mut checksum = [0u8; 32];
+    match arg.map(|arg| hex::decode_to_slice(arg, &mut checksum)) {
+        Some(Ok(())) => Ok(checksum),
+        _ => Err(invalid_field(arg, "checksum", command)),
+    }
+}
+
+/// Parse a `<secs> <nanos>` pair of arguments.
+fn parse_time<'a>(
+    parts: &mut impl Iterator<Item = &'a str>,
+    name: &str,
+    command: &str,
+) -> Result<FileTime> {
+    let seconds = parse_field(parts.next(), name, command)?;
+    let nanos = parts.next();
+    let nanos = nanos
+        .and_then(|nanos| nanos.parse().ok())
+        .filter(|&nanos| nanos < 1_000_000_000)
+        .ok_or_else(|| invalid_field(nanos, &format!("{} nanos", name), command))?;
+    Ok(FileTime::from_unix_time(seconds, nanos))
+}
+
+/// `text` cut short for an error message, since it came from a peer.
+fn truncated(text: &str) -> &str {
+    match text.char_indices().nth(80) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    }
+}
@@ -0,0 +1,230 @@
+use crate::core::{Block, DeltaOp, Signature, StrongHash, WeakHash};
+pub use crate::core::{
+    blake2, md4, rabinkarp, rabinkarp_factor, rabinkarp_rotate, rollsum, rollsum_rotate,
+};
+use crate::error::{IoContext, Result, SyncError};
+use std::io::{self, Read, Write};
+
+/// librsync magic numbers, stored big-endian at the start of each file.
+pub const DELTA_MAGIC: u32 = 0x7273_0236;
+pub const MD4_SIG_MAGIC: u32 = 0x7273_0136;
+pub const BLAKE2_SIG_MAGIC: u32 = 0x7273_0137;
+pub const RK_MD4_SIG_MAGIC: u32 = 0x7273_0146;
+pub const RK_BLAKE2_SIG_MAGIC: u32 = 0x7273_0147;
+
+const OP_END: u8 = 0x00;
+const OP_LITERAL_N1: u8 = 0x41;
+const OP_LITERAL_N8: u8 = 0x44;
+const OP_COPY_N1_N1: u8 = 0x45;
+const OP_COPY_N8_N8: u8 = 0x54;
+
+fn signature_magic(signature: &Signature) -> Result<u32> {
+    match (signature.weak_hash, signature.strong_hash) {
+        (WeakHash::RollSum, StrongHash::Md4) => Ok(MD4_SIG_MAGIC),
+        (WeakHash::RollSum, StrongHash::Blake2) => Ok(BLAKE2_SIG_MAGIC),
+        (WeakHash::RabinKarp, StrongHash::Md4) => Ok(RK_MD4_SIG_MAGIC),
+        (WeakHash::RabinKarp, StrongHash::Blake2) => Ok(RK_BLAKE2_SIG_MAGIC),
+        (weak, strong) => Err(SyncError::Format(format!(
+            "{:?}/{:?} signatures can't be written in rdiff format",
+            weak, strong
+        ))),
+    }
+}
+
+/// Whether `magic` identifies an rdiff signature file.
+pub fn is_signature_magic(magic: u32) -> bool {
+    matches!(
+        magic,
+        MD4_SIG_MAGIC | BLAKE2_SIG_MAGIC | RK_MD4_SIG_MAGIC | RK_BLAKE2_SIG_MAGIC
+    )
+}
+
+/// Write a signature in rdiff format: header of magic, block length and strong sum length,
+/// then one weak sum and truncated strong sum per block.
+pub fn write_signature<W: Write>(signature: &Signature, writer: &mut W) -> Result<()> {
+    let magic = signature_magic(signature)?;
+    writer.write_all(&magic.to_be_bytes())?;
+    writer.write_all(&(signature.block_size as u32).to_be_bytes())?;
+    writer.write_all(&(signature.strong_len as u32).to_be_bytes())?;
+    for block in &signature.blocks {
+        writer.write_all(&block.weak_checksum.to_be_bytes())?;
+        writer.write_all(&block.strong_checksum[..signature.strong_len])?;
+    }
+    Ok(())
+}
+
+/// Read the rest of an rdiff signature whose magic has already been consumed.
+pub fn read_signature<R: Read>(magic: u32, reader: &mut R) -> Result<Signature> {
+    let (weak_hash, strong_hash) = match magic {
+        MD4_SIG_MAGIC => (WeakHash::RollSum, StrongHash::Md4),
+        BLAKE2_SIG_MAGIC => (WeakHash::RollSum, StrongHash::Blake2),
+        RK_MD4_SIG_MAGIC => (WeakHash::RabinKarp, StrongHash::Md4),
+        RK_BLAKE2_SIG_MAGIC => (WeakHash::RabinKarp, StrongHash::Blake2),
+        _ => {
+            return Err(SyncError::Format(format!(
+                "Not an rdiff signature: {:#010x}",
+                magic
+            )));
+        }
+    };
+    let block_size = read_u32(reader)? as usize;
+    let strong_len = read_u32(reader)? as usize;
+    if block_size == 0 {
+        return Err(SyncError::Format(
+            "Invalid rdiff signature block length: 0".to_string(),
+        ));
+    }
+    if strong_len == 0 || strong_len > strong_hash.digest_len() {
+        return Err(SyncError::Format(format!(
+            "Invalid rdiff strong sum length: {}",
+            strong_len
+        )));
+    }
+
+    let mut blocks = Vec::new();
+    loop {
+        let mut weak = [0u8; 4];
+        match read_full(reader, &mut weak)? {
+            0 => break,
+            4 => {}
+            _ => return Err(SyncError::Format("Truncated rdiff signature".to_string())),
+        }
+        let mut strong_checksum = [0u8; 32];
+        reader
+            .read_exact(&mut strong_checksum[..strong_len])
+            .with_context(|| "Truncated rdiff signature")?;
+        // rdiff doesn't record the length of a short final block
+        blocks.push(Block {
+            offset: (blocks.len() * block_size) as u64,
+            size: block_size,
+            weak_checksum: u32::from_be_bytes(weak),
+            strong_checksum,
+        });
+    }
+    Ok(Signature {
+        block_size,
+        weak_hash,
+        strong_hash,
+        strong_len,
+        blocks,
+    })
+}
+
+/// Smallest of the 1/2/4/8 byte parameter widths able to hold `value`, as an index.
+fn width_index(value: u64) -> u8 {
+    if value <= u8::MAX as u64 {
+        0
+    } else if value <= u16::MAX as u64 {
+        1
+    } else if value <= u32::MAX as u64 {
+        2
+    } else {
+        3
+    }
+}
+
+fn write_param<W: Write>(writer: &mut W, value: u64, width_index: u8) -> io::Result<()> {
+    let bytes = value.to_be_bytes();
+    writer.write_all(&bytes[8 - (1 << width_index)..])
+}
+
+pub fn write_delta_header<W: Write>(writer: &mut W) -> Result<()> {
+    writer.write_all(&DELTA_MAGIC.to_be_bytes())?;
+    Ok(())
+}
+
+pub fn write_delta_op<W: Write>(writer: &mut W, op: &DeltaOp) -> Result<()> {
+    match op {
+        DeltaOp::Literal(data) if data.is_empty() => {}
+        DeltaOp::Literal(data) if data.len() <= 64 => {
+            // Short literals carry their length in the opcode itself
+            writer.write_all(&[data.len() as u8])?;
+            writer.write_all(data)?;
+        }
+        DeltaOp::Literal(data) => {
+            let len = data.len() as u64;
+            let len_width = width_index(len);
+            writer.write_all(&[OP_LITERAL_N1 + len_width])?;
+            write_param(writer, len, len_width)?;
+            writer.write_all(data)?;
+        }
+        DeltaOp::Copy { offset, len } => {
+            let len = *len as u64;
+            let offset_width = width_index(*offset);
+            let len_width = width_index(len);
+            writer.write_all(&[OP_COPY_N1_N1 + offset_width * 4 + len_width])?;
+            write_param(writer, *offset, offset_width)?;
+            write_param(writer, len, len_width)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn write_delta_end<W: Write>(writer: &mut W) -> Result<()> {
+    writer.write_all(&[OP_END])?;
+    Ok(())
+}
+
+/// A decoded rdiff delta command; literal data is left in the stream for the caller.
+pub enum Command {
+    End,
+    Literal(u64),
+    Copy { offset: u64, len: u64 },
+}
+
+pub fn read_command<R: Read>(reader: &mut R) -> Result<Command> {
+    let mut opcode = [0u8; 1];
+    reader
+        .read_exact(&mut opcode)
+        .with_context(|| "Truncated rdiff delta")?;
+    match opcode[0] {
+        OP_END => Ok(Command::End),
+        op @ 0x01..=0x40 => Ok(Command::Literal(op as u64)),
+        op @ OP_LITERAL_N1..=OP_LITERAL_N8 => {
+            let len = read_param(reader, op - OP_LITERAL_N1)?;
+            Ok(Command::Literal(len))
+        }
+        op @ OP_COPY_N1_N1..=OP_COPY_N8_N8 => {
+            let widths = op - OP_COPY_N1_N1;
+            let offset = read_param(reader, widths / 4)?;
+            let len = read_param(reader, widths % 4)?;
+            Ok(Command::Copy { offset, len })
+        }
+        op => Err(SyncError::Format(format!(
+            "Unsupported rdiff delta opcode: {:#04x}",
+            op
+        ))),
+    }
+}
+
+fn read_param<R: Read>(reader: &mut R, width_index: u8) -> Result<u64> {
+    let width = 1usize << width_index;
+    let mut bytes = [0u8; 8];
+    reader
+        .read_exact(&mut bytes[8 - width..])
+        .with_context(|| "Truncated rdiff delta")?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader
+        .read_exact(&mut bytes)
+        .with_context(|| "Truncated rdiff header")?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+/// Fill `buf` unless the stream ends first, returning how many bytes were read. Interrupted
+/// reads are retried.
+pub(crate) fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
@@ -0,0 +1,155 @@
+use std::fmt;
+
+/// URL scheme accepted as an alternative to the `host:port:path` form.
+pub const URL_SCHEME: &str = "rsynx://";
+
+/// A network destination written as `[user@]host[:port]:path`, `[user@][v6::addr][:port]:path`,
+/// `[user@]host::module/path` or `rsynx://[user@]host[:port]/path`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteSpec {
+    /// User to authenticate as when the daemon module requires it.
+    pub user: Option<String>,
+    /// Host name or address, without the brackets of an IPv6 literal.
+    pub host: String,
+    /// Port given in the spec, overriding the `-p` default.
+    pub port: Option<u16>,
+    /// Daemon module of a `host::module/path` spec.
+    pub module: Option<String>,
+    /// Path on the remote side, relative to the module if there is one.
+    pub path: String,
+}
+
+impl RemoteSpec {
+    /// Parse `spec`, or return `None` if it names a local path. As with rsync, a spec is local
+    /// when a `/` comes before the first `:`; Windows drive paths like `C:\dir` are local too.
+    /// A segment of digits between the host and the path is a port, so `host:8000:/dir` goes
+    /// to port 8000.
+    pub fn parse(spec: &str) -> Option<Self> {
+        if let Some(url) = spec.strip_prefix(URL_SCHEME) {
+            return Self::parse_url(url);
+        }
+        if is_drive_path(spec) {
+            return None;
+        }
+        let (user, rest) = split_user(spec);
+        let (host, rest) = split_host(rest, ':')?;
+        if host.contains('/') {
+            return None;
+        }
+        let (port, rest) = match rest.split_once(':') {
+            Some((port, path)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => {
+                (Some(port.parse().ok()?), path)
+            }
+            _ => (None, rest),
+        };
+        let (module, path) = match rest.strip_prefix(':') {
+            Some(module_path) => {
+                let (module, path) = module_path.split_once('/').unwrap_or((module_path, ""));
+                (Some(module.to_string()), path.to_string())
+            }
+            None => (None, rest.to_string()),
+        };
+        Some(Self {
+            user,
+            host: host.to_string(),
+            port,
+            module,
+            path,
+        })
+    }
+
+    /// Parse the part of an `rsynx://` URL after the scheme. The path keeps its leading `/`.
+    fn parse_url(url: &str) -> Option<Self> {
+        let (authority, path) = match url.find('/') {
+            Some(i) => url.split_at(i),
+            None => (url, ""),
+        };
+        let (user, rest) = split_user(authority);
+        let (host, port) = split_port(rest)?;
+        Some(Self {
+            user,
+            host,
+            port,
+            module: None,
+            path: path.to_string(),
+        })
+    }
+}
+
+/// Parse a server address of the form `host[:port]`, as `rsynx probe` takes; IPv6 literals
+/// need brackets when a port follows. An `rsynx://` URL is accepted too, ignoring its path.
+pub fn parse_address(spec: &str) -> Option<(String, Option<u16>)> {
+    if spec.starts_with(URL_SCHEME) {
+        let remote = RemoteSpec::parse(spec)?;
+        return Some((remote.host, remote.port));
+    }
+    split_port(spec)
+}
+
+/// Split `host[:port]` into the host, without IPv6 brackets, and the port if there is one.
+fn split_port(spec: &str) -> Option<(String, Option<u16>)> {
+    let (host, port) = match split_host(spec, ':') {
+        Some((host, port)) => (host, Some(port.parse().ok()?)),
+        None if spec.starts_with('[') => (spec.strip_prefix('[')?.strip_suffix(']')?, None),
+        None => (spec, None),
+    };
+    if host.is_empty() {
+        return None;
+    }
+    Some((host.to_string(), port))
+}
+
+/// Split a leading `user@` off `spec`.
+fn split_user(spec: &str) -> (Option<String>, &str) {
+    match spec.split_once('@') {
+        Some((user, rest)) if !user.is_empty() && !user.contains([':', '/', '[']) => {
+            (Some(user.to_string()), rest)
+        }
+        _ => (None, spec),
+    }
+}
+
+/// Split `rest` into a host, possibly a bracketed IPv6 literal, and what follows `separator`.
+fn split_host(rest: &str, separator: char) -> Option<(&str, &str)> {
+    let (host, rest) = match rest.strip_prefix('[') {
+        Some(bracketed) => {
+            let (host, rest) = bracketed.split_once(']')?;
+            (host, rest.strip_prefix(separator)?)
+        }
+        None => rest.split_once(separator)?,
+    };
+    if host.is_empty() {
+        return None;
+    }
+    Some((host, rest))
+}
+
+impl fmt::Display for RemoteSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(user) = &self.user {
+            write!(f, "{}@", user)?;
+        }
+        if self.host.contains(':') {
+            write!(f, "[{}]:", self.host)?;
+        } else {
+            write!(f, "{}:", self.host)?;
+        }
+        if let Some(port) = self.port {
+            write!(f, "{}:", port)?;
+        }
+        match &self.module {
+            Some(module) if self.path.is_empty() => write!(f, ":{}", module),
+            Some(module) => write!(f, ":{}/{}", module, self.path),
+            None => write!(f, "{}", self.path),
+        }
+    }
+}
+
+/// Whether `spec` starts with a Windows drive letter, as in `C:\dir` or `C:/dir`.
+fn is_drive_path(spec: &str) -> bool {
+    let bytes = spec.as_bytes();
+    bytes.len() >= 3
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && matches!(bytes[2], b'\\' | b'/')
+}
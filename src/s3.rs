@@ -0,0 +1,668 @@
+use crate::error::{IoContext, Result, SyncError};
+use crate::events::SyncEvent;
+use crate::options::impl_option_builders;
+use crate::rdiff::read_full;
+use crate::sync::{FileAction, Syncer, TransferResult};
+use filetime::FileTime;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::{info, instrument, warn};
+
+/// Prefix of a destination naming an S3 bucket and key prefix: `s3://bucket/prefix`.
+pub const S3_SCHEME: &str = "s3://";
+
+/// Files larger than this are sent with a multipart upload.
+pub const MULTIPART_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// Size of each part of a multipart upload; S3 requires at least 5 MiB for all but the last.
+pub const PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// User metadata recording the SHA-256 of an uploaded file, to recognise unchanged content
+/// even after its modification time changed.
+const SHA256_HEADER: &str = "x-amz-meta-rsynx-sha256";
+
+/// User metadata recording the modification time of an uploaded file, as `seconds.nanos`.
+const MTIME_HEADER: &str = "x-amz-meta-rsynx-mtime";
+
+/// Bucket and key prefix of an `s3://` destination.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Location {
+    pub bucket: String,
+    /// Prepended to every key; empty or ending in `/`.
+    pub prefix: String,
+}
+
+/// Parse `s3://bucket[/prefix]`.
+pub fn parse_s3(spec: &str) -> Option<S3Location> {
+    let rest = spec.strip_prefix(S3_SCHEME)?;
+    let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+    if bucket.is_empty() {
+        return None;
+    }
+    let prefix = prefix.trim_matches('/');
+    Some(S3Location {
+        bucket: bucket.to_string(),
+        prefix: if prefix.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", prefix)
+        },
+    })
+}
+
+#[derive(Clone)]
+pub struct S3Credentials {
+    pub access_key: String,
+    pub secret_key: String,
+    /// Sent as `x-amz-security-token` with temporary credentials.
+    pub session_token: Option<String>,
+}
+
+/// Where and as whom to connect.
+#[derive(Clone)]
+pub struct S3Config {
+    /// Scheme and host, with a port if needed, such as `https://s3.eu-west-1.amazonaws.com`
+    /// or `http://localhost:9000`. Buckets are addressed path-style, which every
+    /// S3-compatible server supports.
+    pub endpoint: String,
+    pub region: String,
+    pub location: S3Location,
+    pub credentials: S3Credentials,
+}
+
+impl S3Config {
+    /// Configuration from the standard AWS variables: `AWS_ACCESS_KEY_ID`,
+    /// `AWS_SECRET_ACCESS_KEY`, optionally `AWS_SESSION_TOKEN`, `AWS_REGION` (default
+    /// `us-east-1`) and `AWS_ENDPOINT_URL` for servers other than AWS.
+    pub fn from_env(location: S3Location) -> Result<Self> {
+        let var = |name: &str| env::var(name).ok().filter(|value| !value.is_empty());
+        let required =
+            |name: &str| var(name).ok_or_else(|| SyncError::Config(format!("{} is not set", name)));
+        let region = var("AWS_REGION")
+            .or_else(|| var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|| "us-east-1".to_string());
+        Ok(Self {
+            endpoint: var("AWS_ENDPOINT_URL")
+                .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region)),
+            region,
+            location,
+            credentials: S3Credentials {
+                access_key: required("AWS_ACCESS_KEY_ID")?,
+                secret_key: required("AWS_SECRET_ACCESS_KEY")?,
+                session_token: var("AWS_SESSION_TOKEN"),
+            },
+        })
+    }
+
+    fn host(&self) -> &str {
+        self.endpoint
+            .split_once("://")
+            .map_or(self.endpoint.as_str(), |(_, host)| host)
+            .trim_end_matches('/')
+    }
+}
+
+/// The parts of a request that AWS Signature Version 4 covers.
+pub struct CanonicalRequest<'a> {
+    pub method: &'a str,
+    /// URI-encoded path, starting with `/`.
+    pub path: &'a str,
+    /// URI-encoded query string with its parameters sorted, without the `?`.
+    pub query: &'a str,
+    /// Lowercase header names and their values, sorted by name.
+    pub headers: &'a [(&'a str, String)],
+    /// Hex SHA-256 of the body.
+    pub payload_hash: &'a str,
+}
+
+/// The `Authorization` header signing `request` at `amz_date` (`YYYYMMDDTHHMMSSZ`) with
+/// AWS Signature Version 4.
+pub fn authorization(
+    credentials: &S3Credentials,
+    region: &str,
+    amz_date: &str,
+    request: &CanonicalRequest,
+) -> String {
+    let mut canonical = format!("{}\n{}\n{}\n", request.method, request.path, request.query);
+    for (name, value) in request.headers {
+        canonical.push_str(&format!("{}:{}\n", name, value.trim()));
+    }
+    let signed_headers = request
+        .headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    canonical.push_str(&format!("\n{}\n{}", signed_headers, request.payload_hash));
+
+    let date = &amz_date[..8];
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex::encode(Sha256::digest(canonical.as_bytes()))
+    );
+    let mut key = hmac(
+        format!("AWS4{}", credentials.secret_key).as_bytes(),
+        date.as_bytes(),
+    );
+    for part in [region, "s3", "aws4_request"] {
+        key = hmac(&key, part.as_bytes());
+    }
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        credentials.access_key,
+        scope,
+        signed_headers,
+        hex::encode(hmac(&key, string_to_sign.as_bytes()))
+    )
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// `time` as the `x-amz-date` timestamp, `YYYYMMDDTHHMMSSZ` in UTC.
+pub fn amz_date(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (year, month, day) = crate::snapshot::civil_from_days((seconds / 86400) as i64);
+    let rest = seconds % 86400;
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
+
+/// Percent-encode everything but RFC 3986 unreserved characters, and `/` when `keep_slash`.
+pub fn uri_encode(value: &str, keep_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if keep_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Text of each `<tag>` element in `xml`, unescaped. Enough for the flat responses S3 sends.
+pub fn xml_values(xml: &str, tag: &str) -> Vec<String> {
+    xml_elements(xml, tag)
+        .into_iter()
+        .map(|text| {
+            text.replace("&quot;", "\"")
+                .replace("&apos;", "'")
+                .replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&amp;", "&")
+        })
+        .collect()
+}
+
+/// Raw content of each `<tag>` element in `xml`.
+fn xml_elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let mut elements = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let Some(end) = rest.find(&close) else {
+            break;
+        };
+        elements.push(&rest[..end]);
+        rest = &rest[end + close.len()..];
+    }
+    elements
+}
+
+/// An object as listed in the bucket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteObject {
+    pub size: u64,
+    pub etag: String,
+}
+
+/// Minimal S3 client for the calls a sync needs, signing every request.
+struct Client {
+    agent: ureq::Agent,
+    config: S3Config,
+}
+
+impl Client {
+    fn new(config: S3Config) -> Self {
+        Self {
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(Duration::from_secs(30))
+                .build(),
+            config,
+        }
+    }
+
+    /// Send a signed request for `key` (or the bucket when `None`), failing on any status
+    /// other than 2xx.
+    fn send(
+        &self,
+        method: &str,
+        key: Option<&str>,
+        query: &[(&str, &str)],
+        extra_headers: &[(&str, String)],
+        body: &[u8],
+    ) -> Result<ureq::Response> {
+        let config = &self.config;
+        let mut path = format!("/{}", uri_encode(&config.location.bucket, false));
+        if let Some(key) = key {
+            path.push('/');
+            path.push_str(&uri_encode(key, true));
+        }
+        let mut params: Vec<(String, String)> = query
+            .iter()
+            .map(|(name, value)| (uri_encode(name, false), uri_encode(value, false)))
+            .collect();
+        params.sort();
+        let query = params
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let payload_hash = hex::encode(Sha256::digest(body));
+        let date = amz_date(SystemTime::now());
+        let mut headers: Vec<(&str, String)> = vec![
+            ("host", config.host().to_string()),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", date.clone()),
+        ];
+        if let Some(token) = &config.credentials.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        headers.extend(extra_headers.iter().cloned());
+        headers.sort();
+        let authorization = authorization(
+            &config.credentials,
+            &config.region,
+            &date,
+            &CanonicalRequest {
+                method,
+                path: &path,
+                query: &query,
+                headers: &headers,
+                payload_hash: &payload_hash,
+            },
+        );
+
+        let mut url = format!("{}{}", config.endpoint.trim_end_matches('/'), path);
+        if !query.is_empty() {
+            url.push('?');
+            url.push_str(&query);
+        }
+        let mut request = self
+            .agent
+            .request(method, &url)
+            .set("Authorization", &authorization);
+        for (name, value) in &headers {
+            if *name != "host" {
+                request = request.set(name, value);
+            }
+        }
+        match request.send_bytes(body) {
+            Ok(response) => Ok(response),
+            Err(ureq::Error::Status(status, response)) => {
+                let text = response.into_string().unwrap_or_default();
+                let code = xml_values(&text, "Code").pop().unwrap_or_default();
+                Err(SyncError::Refused(format!(
+                    "{} {} returned {} {}",
+                    method, url, status, code
+                )))
+            }
+            Err(e) => Err(SyncError::Connect {
+                peer: config.endpoint.clone(),
+                source: io::Error::other(e.to_string()),
+            }),
+        }
+    }
+
+    /// Every object under the prefix, by key.
+    fn list(&self) -> Result<HashMap<String, RemoteObject>> {
+        let mut objects = HashMap::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![
+                ("list-type", "2"),
+                ("prefix", self.config.location.prefix.as_str()),
+            ];
+            if let Some(token) = &token {
+                query.push(("continuation-token", token.as_str()));
+            }
+            let body = self
+                .send("GET", None, &query, &[], &[])?
+                .into_string()
+                .with_context(|| "Failed to read the bucket listing")?;
+            for contents in xml_elements(&body, "Contents") {
+                let key = xml_values(contents, "Key").pop().unwrap_or_default();
+                let size = xml_values(contents, "Size")
+                    .pop()
+                    .and_then(|size| size.parse().ok())
+                    .unwrap_or(0);
+                let etag = xml_values(contents, "ETag").pop().unwrap_or_default();
+                objects.insert(key, RemoteObject { size, etag });
+            }
+            token = xml_values(&body, "NextContinuationToken").pop();
+            if token.is_none() {
+                return Ok(objects);
+            }
+        }
+    }
+
+    /// The `rsynx` metadata of `key`, as (sha256, mtime).
+    fn metadata(&self, key: &str) -> Result<(Option<String>, Option<String>)> {
+        let response = self.send("HEAD", Some(key), &[], &[], &[])?;
+        Ok((
+            response.header(SHA256_HEADER).map(str::to_string),
+            response.header(MTIME_HEADER).map(str::to_string),
+        ))
+    }
+
+    fn put(&self, key: &str, body: &[u8], metadata: &[(&str, String)]) -> Result<()> {
+        self.send("PUT", Some(key), &[], metadata, body)?;
+        Ok(())
+    }
+
+    /// Upload `file` in `PART_SIZE` parts, aborting the upload if a part fails.
+    fn put_multipart(
+        &self,
+        key: &str,
+        file: &mut File,
+        metadata: &[(&str, String)],
+        syncer: &Syncer,
+    ) -> Result<()> {
+        let response = self.send("POST", Some(key), &[("uploads", "")], metadata, &[])?;
+        let body = response
+            .into_string()
+            .with_context(|| "Failed to read the multipart upload response")?;
+        let upload_id = xml_values(&body, "UploadId")
+            .pop()
+            .ok_or_else(|| SyncError::Protocol("S3 returned no upload ID".to_string()))?;
+        let uploaded = (|| {
+            let mut etags = Vec::new();
+            let mut buffer = vec![0u8; PART_SIZE];
+            loop {
+                syncer.check_cancelled()?;
+                let len = read_full(file, &mut buffer)
+                    .with_context(|| format!("Failed to read source for {}", key))?;
+                if len == 0 && !etags.is_empty() {
+                    break;
+                }
+                let number = (etags.len() + 1).to_string();
+                let response = self.send(
+                    "PUT",
+                    Some(key),
+                    &[("partNumber", &number), ("uploadId", &upload_id)],
+                    &[],
+                    &buffer[..len],
+                )?;
+                etags.push(response.header("ETag").unwrap_or_default().to_string());
+                if len < PART_SIZE {
+                    break;
+                }
+            }
+            let mut complete = String::from("<CompleteMultipartUpload>");
+            for (index, etag) in etags.iter().enumerate() {
+                complete.push_str(&format!(
+                    "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                    index + 1,
+                    etag
+                ));
+            }
+            complete.push_str("</CompleteMultipartUpload>");
+            self.send(
+                "POST",
+                Some(key),
+                &[("uploadId", &upload_id)],
+                &[],
+                complete.as_bytes(),
+            )
+        })();
+        if let Err(e) = uploaded {
+            if let Err(abort) =
+                self.send("DELETE", Some(key), &[("uploadId", &upload_id)], &[], &[])
+            {
+                warn!("Failed to abort multipart upload of {}: {}", key, abort);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.send("DELETE", Some(key), &[], &[], &[])?;
+        Ok(())
+    }
+}
+
+/// Syncs a local file or directory tree to objects under an S3 prefix, one object per file.
+///
+/// Each upload records the file's SHA-256 and modification time as object metadata. An
+/// object of the same size is left alone when its recorded time matches, or, after the time
+/// changed or with `checksum`, when its recorded hash does; nothing is downloaded to compare.
+/// Files over `MULTIPART_THRESHOLD` go up in parts. `bwlimit`, `partial` and metadata
+/// preservation beyond the modification time don't apply.
+#[derive(Clone)]
+pub struct S3Syncer {
+    syncer: Syncer,
+    source: PathBuf,
+    config: S3Config,
+}
+
+impl_option_builders!(S3Syncer);
+
+impl S3Syncer {
+    pub fn new(source: impl Into<PathBuf>, config: S3Config) -> Self {
+        Self {
+            syncer: Syncer::new(),
+            source: source.into(),
+            config,
+        }
+    }
+
+    /// Reject invalid options before connecting.
+    pub fn validate(&self) -> Result<(), SyncError> {
+        self.syncer.validate()?;
+        if self.source.as_os_str().is_empty() {
+            return Err(SyncError::EmptyPath("Source"));
+        }
+        Ok(())
+    }
+
+    #[instrument(name = "s3_sync", skip_all, fields(source = ?self.source, bucket = %self.config.location.bucket))]
+    pub fn sync(&self) -> Result<TransferResult> {
+        self.validate()?;
+        let mut files = Vec::new();
+        let mut result = TransferResult::default();
+        if self.source.is_file() {
+            let name = self.source.file_name().unwrap_or_default();
+            files.push((self.source.clone(), PathBuf::from(name)));
+        } else if self.source.is_dir() {
+            self.collect(&self.source, Path::new(""), &mut files, &mut result)?;
+        } else {
+            return Err(SyncError::UnsupportedSource {
+                path: self.source.clone(),
+                reason: "only regular files and directories can be synced",
+            });
+        }
+
+        let client = Client::new(self.config.clone());
+        let remote = client.list()?;
+        let mut keys = HashSet::new();
+        for (path, relative) in &files {
+            self.syncer.check_cancelled()?;
+            let key = self.key(relative);
+            result.merge(self.upload(&client, path, &key, remote.get(&key))?);
+            keys.insert(key);
+        }
+        if self.syncer.options.delete_extraneous && self.source.is_dir() {
+            let mut extraneous: Vec<_> = remote
+                .keys()
+                .filter(|key| !keys.contains(*key))
+                .filter(|key| {
+                    let relative = &key[self.config.location.prefix.len()..];
+                    // Without recursion, objects below subdirectories are left alone
+                    let nested = relative.contains('/');
+                    (!nested || self.syncer.options.recursive)
+                        && !self.is_filtered(Path::new(relative), false)
+                })
+                .collect();
+            extraneous.sort();
+            for key in extraneous {
+                client.delete(key)?;
+                let path = self.display_path(key);
+                self.syncer
+                    .emit(|| SyncEvent::Deleted { path: path.clone() });
+                result.merge(TransferResult::for_file(&path, FileAction::Deleted, 0, 0));
+            }
+        }
+        info!("S3 sync completed");
+        Ok(result)
+    }
+
+    fn key(&self, relative: &Path) -> String {
+        let relative: Vec<_> = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect();
+        format!("{}{}", self.config.location.prefix, relative.join("/"))
+    }
+
+    /// Path of an object as reported in results and events.
+    fn display_path(&self, key: &str) -> PathBuf {
+        PathBuf::from(format!(
+            "{}{}/{}",
+            S3_SCHEME, self.config.location.bucket, key
+        ))
+    }
+
+    fn is_filtered(&self, relative: &Path, is_dir: bool) -> bool {
+        let filter = &self.syncer.options.filter;
+        !filter.is_empty() && filter.is_excluded(relative, is_dir)
+    }
+
+    /// Add the files to upload beneath `dir` to `files`, with their paths relative to the
+    /// source.
+    fn collect(
+        &self,
+        dir: &Path,
+        relative: &Path,
+        files: &mut Vec<(PathBuf, PathBuf)>,
+        result: &mut TransferResult,
+    ) -> Result<()> {
+        let mut entries = fs::read_dir(dir)
+            .with_context(|| format!("Failed to read {:?}", dir))?
+            .collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let path = entry.path();
+            let entry_relative = relative.join(entry.file_name());
+            if self.is_filtered(&entry_relative, path.is_dir()) {
+                info!("Skipping excluded entry: {:?}", path);
+            } else if path.is_file() {
+                files.push((path, entry_relative));
+            } else if path.is_dir() && self.syncer.options.recursive {
+                self.collect(&path, &entry_relative, files, result)?;
+            } else if !path.is_dir() {
+                warn!("Skipping unsupported file type: {:?}", path);
+                result.unsupported_files += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Upload `path` to `key` unless `existing` already holds the same content.
+    fn upload(
+        &self,
+        client: &Client,
+        path: &Path,
+        key: &str,
+        existing: Option<&RemoteObject>,
+    ) -> Result<TransferResult> {
+        let display = self.display_path(key);
+        let meta = fs::metadata(path)
+            .with_context(|| format!("Failed to get metadata for source file: {:?}", path))?;
+        let mtime = FileTime::from_last_modification_time(&meta);
+        let mtime = format!("{}.{:09}", mtime.unix_seconds(), mtime.nanoseconds());
+        let mut sha256 = None;
+        if let Some(object) = existing.filter(|object| object.size == meta.len()) {
+            let (remote_sha256, remote_mtime) = client.metadata(key)?;
+            let options = &self.syncer.options;
+            let same_time = remote_mtime.as_deref() == Some(mtime.as_str());
+            let unchanged = if same_time && !options.checksum && !options.ignore_times {
+                true
+            } else {
+                let local = hex::encode(self.syncer.calculate_file_checksum(path)?);
+                let same = remote_sha256.as_deref() == Some(local.as_str());
+                sha256 = Some(local);
+                same
+            };
+            if unchanged {
+                info!("Skipping unchanged object {} ({})", key, object.etag);
+                self.syncer.emit(|| SyncEvent::FileSkipped {
+                    path: display.clone(),
+                });
+                return Ok(TransferResult::for_file(
+                    &display,
+                    FileAction::Skipped,
+                    0,
+                    meta.len() as usize,
+                ));
+            }
+        }
+
+        self.syncer.emit(|| SyncEvent::FileStarted {
+            path: display.clone(),
+            size: meta.len(),
+        });
+        let sha256 = match sha256 {
+            Some(sha256) => sha256,
+            None => hex::encode(self.syncer.calculate_file_checksum(path)?),
+        };
+        let metadata = [(MTIME_HEADER, mtime), (SHA256_HEADER, sha256)];
+        let mut file =
+            File::open(path).with_context(|| format!("Failed to open source file: {:?}", path))?;
+        if meta.len() > MULTIPART_THRESHOLD {
+            client.put_multipart(key, &mut file, &metadata, &self.syncer)?;
+        } else {
+            let mut body = Vec::with_capacity(meta.len() as usize);
+            file.read_to_end(&mut body)
+                .with_context(|| format!("Failed to read source file: {:?}", path))?;
+            client.put(key, &body, &metadata)?;
+        }
+        let action = if existing.is_some() {
+            FileAction::Updated
+        } else {
+            FileAction::Created
+        };
+        let result = TransferResult::for_file(&display, action, meta.len() as usize, 0);
+        for record in &result.files {
+            self.syncer
+                .emit(|| SyncEvent::FileCompleted(record.clone()));
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,58 @@
+use std::time::{Duration, Instant};
+
+/// Parse an interval such as `90`, `30s`, `5m`, `2h` or `1d`; a bare number means seconds.
+/// Zero is rejected, since a sync can't run continuously.
+pub fn parse_interval(spec: &str) -> Option<Duration> {
+    let spec = spec.trim();
+    let (number, unit) = match spec.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => spec.split_at(i),
+        None => (spec, "s"),
+    };
+    let seconds = match unit.to_ascii_lowercase().as_str() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    let count: u64 = number.parse().ok()?;
+    let total = count.checked_mul(seconds)?;
+    (total > 0).then(|| Duration::from_secs(total))
+}
+
+/// Start times of a sync repeated every `interval`, on a fixed grid so runs don't drift.
+/// Each start is delayed by a random jitter of up to a tenth of the interval, so many
+/// scheduled clients don't hit a server at once, and ticks that passed while a run was still
+/// going are skipped rather than run back to back.
+#[derive(Debug)]
+pub struct Schedule {
+    interval: Duration,
+    start: Instant,
+    /// Ticks of the grid used or skipped so far.
+    ticks: u32,
+}
+
+impl Schedule {
+    /// A schedule whose first tick is one `interval` after `start`.
+    pub fn new(interval: Duration, start: Instant) -> Self {
+        Self {
+            interval,
+            start,
+            ticks: 0,
+        }
+    }
+
+    /// How long to wait from `now` until the next run, and how many ticks were skipped
+    /// because the last run overran them.
+    pub fn next_run(&mut self, now: Instant) -> (Duration, u32) {
+        let mut tick = self.ticks + 1;
+        let elapsed = now.saturating_duration_since(self.start);
+        let passed = (elapsed.as_nanos() / self.interval.as_nanos()) as u32;
+        let skipped = passed.saturating_sub(self.ticks);
+        tick += skipped;
+        self.ticks = tick;
+        let jitter = self.interval.mul_f64(rand::random::<f64>() / 10.0);
+        let due = self.start + self.interval * tick + jitter;
+        (due.saturating_duration_since(now), skipped)
+    }
+}
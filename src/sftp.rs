@@ -0,0 +1,430 @@
+use crate::error::{IoContext, Result, SyncError};
+use crate::events::SyncEvent;
+use crate::options::impl_option_builders;
+use crate::sync::{FileAction, Syncer, TransferResult, permission_bits};
+use filetime::FileTime;
+use sha2::{Digest, Sha256};
+use ssh2::{CheckResult, FileStat, KnownHostFileKind, RenameFlags, Session, Sftp};
+use std::{
+    collections::HashSet,
+    env,
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, Read},
+    net::TcpStream,
+    path::{Path, PathBuf},
+};
+use tracing::{info, instrument, warn};
+
+/// Port SSH servers listen on unless told otherwise.
+pub const SSH_PORT: u16 = 22;
+
+/// Key files tried, in order, after the SSH agent.
+const DEFAULT_IDENTITIES: [&str; 3] = ["id_ed25519", "id_ecdsa", "id_rsa"];
+
+/// Suffix of the temporary file an upload is written to before it's renamed into place.
+const UPLOAD_SUFFIX: &str = ".rsynx-tmp";
+
+/// An SFTP server and the path to sync to on it.
+#[derive(Debug, Clone)]
+pub struct SftpTarget {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub path: PathBuf,
+    /// Private key to authenticate with instead of the agent and `~/.ssh` defaults.
+    pub identity: Option<PathBuf>,
+}
+
+/// Syncs a local file or directory tree to a stock SSH server over SFTP, without rsynx on
+/// the other end.
+///
+/// SFTP offers no way to compute a delta on the server, so the delta algorithm doesn't apply:
+/// files that pass the quick check on the server's size and modification time are skipped,
+/// and all others are sent whole, to a temporary name renamed into place once complete. With
+/// `checksum`, files of the same size are downloaded and hashed instead, which trades upload
+/// for download bandwidth. The server's host key must be in `~/.ssh/known_hosts`.
+/// Authentication tries the SSH agent, then the identity or default `~/.ssh` keys, then the
+/// password in `RSYNX_PASSWORD`.
+#[derive(Clone)]
+pub struct SftpSyncer {
+    syncer: Syncer,
+    source: PathBuf,
+    target: SftpTarget,
+}
+
+impl_option_builders!(SftpSyncer);
+
+impl SftpSyncer {
+    pub fn new(source: impl Into<PathBuf>, target: SftpTarget) -> Self {
+        Self {
+            syncer: Syncer::new(),
+            source: source.into(),
+            target,
+        }
+    }
+
+    /// Reject invalid options before connecting.
+    pub fn validate(&self) -> Result<(), SyncError> {
+        self.syncer.validate()?;
+        if self.source.as_os_str().is_empty() {
+            return Err(SyncError::EmptyPath("Source"));
+        }
+        if self.target.path.as_os_str().is_empty() {
+            return Err(SyncError::EmptyPath("Destination"));
+        }
+        Ok(())
+    }
+
+    #[instrument(name = "sftp_sync", skip_all, fields(source = ?self.source, host = %self.target.host))]
+    pub fn sync(&self) -> Result<TransferResult> {
+        self.validate()?;
+        if !self.source.is_file() && !self.source.is_dir() {
+            return Err(SyncError::UnsupportedSource {
+                path: self.source.clone(),
+                reason: "only regular files and directories can be synced",
+            });
+        }
+        let session = self.connect()?;
+        let sftp = session.sftp().map_err(|e| self.refused(e))?;
+        let result = if self.source.is_file() {
+            self.upload(&sftp, &self.source, &self.target.path)?
+        } else {
+            self.sync_dir(&sftp, &self.source, &self.target.path)?
+        };
+        info!("SFTP sync completed");
+        Ok(result)
+    }
+
+    fn peer(&self) -> String {
+        format!("{}:{}", self.target.host, self.target.port)
+    }
+
+    fn refused(&self, e: ssh2::Error) -> SyncError {
+        SyncError::Refused(format!("{}: {}", self.peer(), e))
+    }
+
+    /// Connect, verify the host key against `known_hosts` and authenticate.
+    fn connect(&self) -> Result<Session> {
+        let connect_error = |source: io::Error| SyncError::Connect {
+            peer: self.peer(),
+            source,
+        };
+        let tcp = TcpStream::connect((self.target.host.as_str(), self.target.port))
+            .map_err(connect_error)?;
+        let mut session = Session::new().map_err(|e| connect_error(e.into()))?;
+        session.set_timeout(60_000);
+        session.set_tcp_stream(tcp);
+        session.handshake().map_err(|e| connect_error(e.into()))?;
+        self.check_host_key(&session)?;
+        self.authenticate(&session)?;
+        Ok(session)
+    }
+
+    fn check_host_key(&self, session: &Session) -> Result<()> {
+        let mut known_hosts = session.known_hosts().map_err(|e| self.refused(e))?;
+        let file = home_dir().join(".ssh").join("known_hosts");
+        if let Err(e) = known_hosts.read_file(&file, KnownHostFileKind::OpenSSH) {
+            warn!("Failed to read {:?}: {}", file, e);
+        }
+        let (key, _) = session
+            .host_key()
+            .ok_or_else(|| SyncError::Protocol("the server sent no host key".to_string()))?;
+        match known_hosts.check_port(&self.target.host, self.target.port, key) {
+            CheckResult::Match => Ok(()),
+            CheckResult::Mismatch => Err(SyncError::Refused(format!(
+                "host key of {} doesn't match {:?}; it may be impersonated",
+                self.peer(),
+                file
+            ))),
+            CheckResult::NotFound | CheckResult::Failure => Err(SyncError::Refused(format!(
+                "{} isn't in {:?}; connect once with ssh to verify its host key",
+                self.peer(),
+                file
+            ))),
+        }
+    }
+
+    fn authenticate(&self, session: &Session) -> Result<()> {
+        let user = self.target.user.as_str();
+        if self.target.identity.is_none() && session.userauth_agent(user).is_ok() {
+            return Ok(());
+        }
+        let identities = match &self.target.identity {
+            Some(identity) => vec![identity.clone()],
+            None => DEFAULT_IDENTITIES
+                .iter()
+                .map(|name| home_dir().join(".ssh").join(name))
+                .filter(|path| path.is_file())
+                .collect(),
+        };
+        for identity in identities {
+            match session.userauth_pubkey_file(user, None, &identity, None) {
+                Ok(()) => return Ok(()),
+                Err(e) => info!("Key {:?} was not accepted: {}", identity, e),
+            }
+        }
+        if let Ok(password) = env::var("RSYNX_PASSWORD") {
+            session
+                .userauth_password(user, &password)
+                .map_err(|e| self.refused(e))?;
+        }
+        if session.authenticated() {
+            Ok(())
+        } else {
+            Err(SyncError::Refused(format!(
+                "no key or password was accepted for {}@{}",
+                user,
+                self.peer()
+            )))
+        }
+    }
+
+    fn sync_dir(&self, sftp: &Sftp, src_dir: &Path, dst_dir: &Path) -> Result<TransferResult> {
+        info!("Syncing directory: {:?} -> {:?}", src_dir, dst_dir);
+        match sftp.stat(dst_dir) {
+            Ok(stat) if stat.is_dir() => {}
+            _ => sftp
+                .mkdir(dst_dir, 0o755)
+                .map_err(io::Error::from)
+                .with_context(|| format!("Failed to create remote directory {:?}", dst_dir))?,
+        }
+        let mut result = TransferResult::default();
+        let mut src_names = HashSet::new();
+        for entry in fs::read_dir(src_dir)? {
+            self.syncer.check_cancelled()?;
+            let entry = entry?;
+            let file_name = entry.file_name();
+            src_names.insert(file_name.clone());
+            let path = entry.path();
+            if self.is_filtered(&path, &self.source) {
+                info!("Skipping excluded entry: {:?}", path);
+                continue;
+            }
+            let dest_path = dst_dir.join(&file_name);
+            if path.is_file() {
+                result.merge(self.upload(sftp, &path, &dest_path)?);
+            } else if path.is_dir() && self.syncer.options.recursive {
+                result.merge(self.sync_dir(sftp, &path, &dest_path)?);
+            } else if path.is_dir() {
+                info!("Skipping directory in non-recursive mode: {:?}", path);
+            } else {
+                warn!("Skipping unsupported file type: {:?}", path);
+                result.unsupported_files += 1;
+            }
+        }
+        if self.syncer.options.delete_extraneous {
+            result.merge(self.delete_extraneous(sftp, dst_dir, &src_names)?);
+        }
+        Ok(result)
+    }
+
+    fn is_filtered(&self, path: &Path, root: &Path) -> bool {
+        let filter = &self.syncer.options.filter;
+        !filter.is_empty()
+            && filter.is_excluded(path.strip_prefix(root).unwrap_or(path), path.is_dir())
+    }
+
+    fn delete_extraneous(
+        &self,
+        sftp: &Sftp,
+        dst_dir: &Path,
+        src_names: &HashSet<OsString>,
+    ) -> Result<TransferResult> {
+        let mut result = TransferResult::default();
+        let entries = sftp
+            .readdir(dst_dir)
+            .map_err(io::Error::from)
+            .with_context(|| format!("Failed to list remote directory {:?}", dst_dir))?;
+        for (path, stat) in entries {
+            let Some(name) = path.file_name() else {
+                continue;
+            };
+            if src_names.contains(name) || name == "." || name == ".." {
+                continue;
+            }
+            let relative = path.strip_prefix(&self.target.path).unwrap_or(&path);
+            let filter = &self.syncer.options.filter;
+            if !filter.is_empty() && filter.is_excluded(relative, stat.is_dir()) {
+                continue;
+            }
+            remove_all(sftp, &path, &stat)
+                .with_context(|| format!("Failed to delete {:?}", path))?;
+            self.syncer
+                .emit(|| SyncEvent::Deleted { path: path.clone() });
+            result.merge(TransferResult::for_file(&path, FileAction::Deleted, 0, 0));
+        }
+        Ok(result)
+    }
+
+    /// Send `src_path` whole to `dst_path` unless the server's copy is unchanged.
+    fn upload(&self, sftp: &Sftp, src_path: &Path, dst_path: &Path) -> Result<TransferResult> {
+        let meta = fs::metadata(src_path)
+            .with_context(|| format!("Failed to get metadata for source file: {:?}", src_path))?;
+        let existing = sftp.stat(dst_path).ok().filter(FileStat::is_file);
+        if let Some(stat) = &existing
+            && self.is_unchanged(sftp, src_path, &meta, dst_path, stat)?
+        {
+            info!("Skipping unchanged file: {:?}", src_path);
+            self.syncer.emit(|| SyncEvent::FileSkipped {
+                path: dst_path.to_path_buf(),
+            });
+            return Ok(TransferResult::for_file(
+                dst_path,
+                FileAction::Skipped,
+                0,
+                meta.len() as usize,
+            ));
+        }
+
+        self.syncer.emit(|| SyncEvent::FileStarted {
+            path: dst_path.to_path_buf(),
+            size: meta.len(),
+        });
+        let mut temp_name = dst_path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(UPLOAD_SUFFIX);
+        let temp_path = dst_path.with_file_name(temp_name);
+        let sent = self.send(sftp, src_path, &temp_path).and_then(|sent| {
+            self.finish_upload(sftp, &meta, &temp_path, dst_path)?;
+            Ok(sent)
+        });
+        let sent = match sent {
+            Ok(sent) => sent,
+            Err(e) => {
+                let _ = sftp.unlink(&temp_path);
+                self.syncer.emit(|| SyncEvent::Error {
+                    path: dst_path.to_path_buf(),
+                    message: e.to_string(),
+                });
+                return Err(e);
+            }
+        };
+        let action = if existing.is_some() {
+            FileAction::Updated
+        } else {
+            FileAction::Created
+        };
+        let result = TransferResult::for_file(dst_path, action, sent, 0);
+        for record in &result.files {
+            self.syncer
+                .emit(|| SyncEvent::FileCompleted(record.clone()));
+        }
+        Ok(result)
+    }
+
+    /// The quick check against the server's copy: same size and modification time (to the
+    /// second, all SFTP carries), or same content hash with `checksum`.
+    fn is_unchanged(
+        &self,
+        sftp: &Sftp,
+        src_path: &Path,
+        meta: &fs::Metadata,
+        dst_path: &Path,
+        stat: &FileStat,
+    ) -> Result<bool> {
+        let options = &self.syncer.options;
+        if stat.size != Some(meta.len()) {
+            return Ok(false);
+        }
+        if options.checksum {
+            let mut remote = sftp
+                .open(dst_path)
+                .map_err(io::Error::from)
+                .with_context(|| format!("Failed to open remote file {:?}", dst_path))?;
+            let mut hasher = Sha256::new();
+            io::copy(&mut remote, &mut hasher)
+                .with_context(|| format!("Failed to read remote file {:?}", dst_path))?;
+            let remote: [u8; 32] = hasher.finalize().into();
+            return Ok(remote == self.syncer.calculate_file_checksum(src_path)?);
+        }
+        if options.ignore_times {
+            return Ok(false);
+        }
+        let mtime = FileTime::from_last_modification_time(meta).unix_seconds();
+        Ok(stat
+            .mtime
+            .is_some_and(|remote| remote.abs_diff(mtime.max(0) as u64) <= options.modify_window))
+    }
+
+    /// Copy `src_path` to `remote_path`, returning the number of bytes sent.
+    fn send(&self, sftp: &Sftp, src_path: &Path, remote_path: &Path) -> Result<usize> {
+        let mut source = File::open(src_path)
+            .with_context(|| format!("Failed to open source file: {:?}", src_path))?;
+        let mut remote = sftp
+            .create(remote_path)
+            .map_err(io::Error::from)
+            .with_context(|| format!("Failed to create remote file {:?}", remote_path))?;
+        let mut buffer = vec![0u8; 256 * 1024];
+        let mut sent = 0;
+        loop {
+            self.syncer.check_cancelled()?;
+            let len = source
+                .read(&mut buffer)
+                .with_context(|| format!("Failed to read source file: {:?}", src_path))?;
+            if len == 0 {
+                break;
+            }
+            io::Write::write_all(&mut remote, &buffer[..len])
+                .with_context(|| format!("Failed to write remote file {:?}", remote_path))?;
+            sent += len;
+        }
+        Ok(sent)
+    }
+
+    /// Set the times and, with `preserve_metadata`, the permissions of the uploaded
+    /// `temp_path`, then move it over `dst_path`.
+    fn finish_upload(
+        &self,
+        sftp: &Sftp,
+        meta: &fs::Metadata,
+        temp_path: &Path,
+        dst_path: &Path,
+    ) -> Result<()> {
+        if self.syncer.options.preserve_metadata {
+            let seconds = |time: FileTime| time.unix_seconds().max(0) as u64;
+            let stat = FileStat {
+                size: None,
+                uid: None,
+                gid: None,
+                perm: Some(permission_bits(meta)),
+                atime: Some(seconds(FileTime::from_last_access_time(meta))),
+                mtime: Some(seconds(FileTime::from_last_modification_time(meta))),
+            };
+            sftp.setstat(temp_path, stat)
+                .map_err(io::Error::from)
+                .with_context(|| format!("Failed to set times on remote file {:?}", temp_path))?;
+        }
+        let flags = RenameFlags::OVERWRITE | RenameFlags::ATOMIC | RenameFlags::NATIVE;
+        if sftp.rename(temp_path, dst_path, Some(flags)).is_ok() {
+            return Ok(());
+        }
+        // SFTP version 3 servers, OpenSSH among them, won't rename over an existing file
+        let _ = sftp.unlink(dst_path);
+        sftp.rename(temp_path, dst_path, None)
+            .map_err(io::Error::from)
+            .with_context(|| format!("Failed to rename {:?} to {:?}", temp_path, dst_path))
+    }
+}
+
+/// Delete the remote file or directory tree at `path`.
+fn remove_all(sftp: &Sftp, path: &Path, stat: &FileStat) -> io::Result<()> {
+    if !stat.is_dir() {
+        return Ok(sftp.unlink(path)?);
+    }
+    for (child, child_stat) in sftp.readdir(path)? {
+        if child
+            .file_name()
+            .is_some_and(|name| name != "." && name != "..")
+        {
+            remove_all(sftp, &child, &child_stat)?;
+        }
+    }
+    Ok(sftp.rmdir(path)?)
+}
+
+fn home_dir() -> PathBuf {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+        .unwrap_or_default()
+}
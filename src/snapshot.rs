@@ -0,0 +1,153 @@
+use crate::error::{IoContext, Result, SyncError};
+use crate::local_sync::LocalSyncer;
+use crate::sync::TransferResult;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::info;
+
+/// Suffix of a snapshot still being written. It's renamed to its timestamp once complete, so
+/// a failed run never becomes the basis of the next one.
+const INCOMPLETE_SUFFIX: &str = ".incomplete";
+
+/// Name of the snapshot taken at `time`: its UTC timestamp, such as `2024-06-01T12:00:00`.
+/// Names sort in the order the snapshots were taken.
+pub fn snapshot_name(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rest) = (seconds / 86400, seconds % 86400);
+    let (year, month, day) = civil_from_days(days as i64);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
+
+/// Calendar date of a day counted from 1970-01-01 (Howard Hinnant's algorithm).
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Days from 1970-01-01 to a calendar date; the inverse of `civil_from_days`.
+#[cfg(feature = "http")]
+pub(crate) fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Whether `name` has the shape of a snapshot name, so other entries of the snapshot root
+/// are never linked against or pruned.
+fn is_snapshot_name(name: &str) -> bool {
+    const SHAPE: &[u8] = b"0000-00-00T00:00:00";
+    name.len() == SHAPE.len()
+        && name
+            .bytes()
+            .zip(SHAPE)
+            .all(|(c, &expected)| match expected {
+                b'0' => c.is_ascii_digit(),
+                _ => c == expected,
+            })
+}
+
+/// Complete snapshots in `root`, oldest first.
+pub fn list(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut snapshots = Vec::new();
+    let entries = match fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(snapshots),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", root)),
+    };
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() && entry.file_name().to_str().is_some_and(is_snapshot_name) {
+            snapshots.push(entry.path());
+        }
+    }
+    snapshots.sort();
+    Ok(snapshots)
+}
+
+/// Sync `source` into a new snapshot of `root` named after `time`, hard-linking files that
+/// haven't changed since the latest snapshot. The data of an interrupted snapshot is picked
+/// up again rather than copied anew. Returns the snapshot's path and the transfer result.
+pub fn take(
+    syncer: &LocalSyncer,
+    source: &Path,
+    root: &Path,
+    time: SystemTime,
+) -> Result<(PathBuf, TransferResult)> {
+    let name = snapshot_name(time);
+    let snapshot = root.join(&name);
+    if snapshot.exists() {
+        return Err(SyncError::ConflictingOptions(format!(
+            "snapshot {:?} already exists",
+            snapshot
+        )));
+    }
+    let previous = list(root)?.pop();
+    fs::create_dir_all(root).with_context(|| format!("Failed to create {:?}", root))?;
+
+    let incomplete = root.join(format!("{}{}", name, INCOMPLETE_SUFFIX));
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        if entry.path() == incomplete {
+            continue;
+        }
+        let is_leftover = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_suffix(INCOMPLETE_SUFFIX))
+            .is_some_and(is_snapshot_name);
+        if is_leftover && entry.file_type()?.is_dir() && !incomplete.exists() {
+            info!("Resuming interrupted snapshot {:?}", entry.path());
+            fs::rename(entry.path(), &incomplete)
+                .with_context(|| format!("Failed to rename {:?}", entry.path()))?;
+        } else if is_leftover {
+            fs::remove_dir_all(entry.path())
+                .with_context(|| format!("Failed to delete {:?}", entry.path()))?;
+        }
+    }
+
+    // Resolved against the incomplete snapshot, so the link stays valid after the rename
+    let link_dest = previous.map(|previous| Path::new("..").join(previous.file_name().unwrap()));
+    let result = syncer
+        .for_paths(source, &incomplete)
+        .with_link_dest(link_dest)
+        .sync()?;
+    fs::rename(&incomplete, &snapshot)
+        .with_context(|| format!("Failed to rename {:?}", incomplete))?;
+    info!("Snapshot complete: {:?}", snapshot);
+    Ok((snapshot, result))
+}
+
+/// Delete all but the newest `keep` snapshots in `root`, returning the deleted paths.
+pub fn prune(root: &Path, keep: usize) -> Result<Vec<PathBuf>> {
+    let mut snapshots = list(root)?;
+    let expired = snapshots.len().saturating_sub(keep);
+    snapshots.truncate(expired);
+    for snapshot in &snapshots {
+        info!("Pruning snapshot {:?}", snapshot);
+        fs::remove_dir_all(snapshot).with_context(|| format!("Failed to delete {:?}", snapshot))?;
+    }
+    Ok(snapshots)
+}
@@ -0,0 +1,748 @@
+#[cfg(feature = "encryption")]
+use crate::encryption::StoreKey;
+use crate::error::{IoContext, Result, SyncError};
+use crate::events::SyncEvent;
+use crate::options::impl_option_builders;
+use crate::sync::{FileAction, Syncer, TransferResult, permission_bits};
+use filetime::{FileTime, set_file_mtime};
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+use tracing::{info, instrument, warn};
+
+/// Prefix of a source or destination naming a content-addressed store instead of a directory.
+pub const STORE_SCHEME: &str = "store://";
+
+/// Size of the chunks files are split into; identical chunks are stored once across all files.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// First line of every manifest, naming its format version.
+const MANIFEST_HEADER: &str = "rsynx-manifest 1";
+
+/// File at the root of an encrypted store, sealing a known value so a wrong key is caught
+/// before anything is written.
+const ENCRYPTED_MARKER: &str = "encrypted";
+
+/// The store directory named by a `store://path` spec, if it is one.
+pub fn parse_store(spec: &str) -> Option<PathBuf> {
+    spec.strip_prefix(STORE_SCHEME)
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+}
+
+/// A stored file: its size, modification time and permissions, and the hashes of its chunks
+/// in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub size: u64,
+    pub mtime: FileTime,
+    /// Unix permission bits; zero on other platforms.
+    pub mode: u32,
+    pub chunks: Vec<[u8; 32]>,
+}
+
+impl Manifest {
+    /// The text form written to the store: a header, `size`, `mtime` and `mode` lines, then
+    /// one hex chunk hash per line.
+    pub fn encode(&self) -> String {
+        let mut text = format!(
+            "{}\nsize {}\nmtime {} {}\nmode {:o}\n",
+            MANIFEST_HEADER,
+            self.size,
+            self.mtime.unix_seconds(),
+            self.mtime.nanoseconds(),
+            self.mode
+        );
+        for chunk in &self.chunks {
+            text.push_str(&hex::encode(chunk));
+            text.push('\n');
+        }
+        text
+    }
+
+    pub fn decode(text: &str) -> Result<Self> {
+        let invalid = |what: &str| SyncError::Format(format!("Invalid manifest: {}", what));
+        let mut lines = text.lines();
+        if lines.next() != Some(MANIFEST_HEADER) {
+            return Err(invalid("unknown header"));
+        }
+        let mut field = |name: &str| {
+            lines
+                .next()
+                .and_then(|line| line.strip_prefix(name))
+                .and_then(|value| value.strip_prefix(' '))
+                .ok_or_else(|| invalid(name))
+        };
+        let size = field("size")?.parse().map_err(|_| invalid("size"))?;
+        let (seconds, nanos) = field("mtime")?
+            .split_once(' ')
+            .ok_or_else(|| invalid("mtime"))?;
+        let mtime = FileTime::from_unix_time(
+            seconds.parse().map_err(|_| invalid("mtime"))?,
+            nanos.parse().map_err(|_| invalid("mtime"))?,
+        );
+        let mode = u32::from_str_radix(field("mode")?, 8).map_err(|_| invalid("mode"))?;
+        let chunks = lines
+            .map(|line| {
+                let mut hash = [0u8; 32];
+                hex::decode_to_slice(line, &mut hash).map_err(|_| invalid("chunk hash"))?;
+                Ok(hash)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            size,
+            mtime,
+            mode,
+            chunks,
+        })
+    }
+}
+
+/// A directory holding file chunks under `chunks/`, named by their SHA-256 hash, and one
+/// manifest per stored file under `files/`, at the file's path relative to the sync root.
+///
+/// An encrypted store seals every chunk and manifest with its key, names chunks by a keyed
+/// hash and keeps manifests in a flat `files/` directory under keyed hashes of their paths,
+/// each manifest recording its path inside the encryption.
+#[derive(Debug, Clone)]
+pub struct ChunkStore {
+    root: PathBuf,
+    #[cfg(feature = "encryption")]
+    key: Option<StoreKey>,
+}
+
+impl ChunkStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            #[cfg(feature = "encryption")]
+            key: None,
+        }
+    }
+
+    /// Encrypt everything written to the store, and decrypt what's read, with `key`.
+    #[cfg(feature = "encryption")]
+    pub fn with_key(mut self, key: StoreKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    #[cfg(feature = "encryption")]
+    fn key(&self) -> Option<&StoreKey> {
+        self.key.as_ref()
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    fn key(&self) -> Option<&Never> {
+        None
+    }
+
+    /// Check that the store is encrypted exactly when a key was given, and with that key.
+    /// With `initialize`, an empty store is marked as encrypted with the key.
+    pub fn check_key(&self, initialize: bool) -> Result<()> {
+        let marker = self.root.join(ENCRYPTED_MARKER);
+        let Some(key) = self.key() else {
+            if marker.exists() {
+                return Err(SyncError::ConflictingOptions(format!(
+                    "store {:?} is encrypted and needs its key",
+                    self.root
+                )));
+            }
+            return Ok(());
+        };
+        if marker.exists() {
+            let sealed =
+                fs::read(&marker).with_context(|| format!("Failed to read {:?}", marker))?;
+            key.open(&sealed, ENCRYPTED_MARKER.as_bytes())?;
+            return Ok(());
+        }
+        let has_files =
+            fs::read_dir(self.root.join("files")).is_ok_and(|mut entries| entries.next().is_some());
+        if has_files {
+            return Err(SyncError::ConflictingOptions(format!(
+                "store {:?} isn't encrypted",
+                self.root
+            )));
+        }
+        if initialize {
+            write_atomically(&marker, &key.seal(b"rsynx", ENCRYPTED_MARKER.as_bytes()))?;
+        }
+        Ok(())
+    }
+
+    /// The name `data` is stored under: its SHA-256 hash, or its keyed hash when encrypted.
+    fn chunk_hash(&self, data: &[u8]) -> [u8; 32] {
+        match self.key() {
+            Some(key) => key.name(data),
+            None => crate::core::strong_checksum(data),
+        }
+    }
+
+    fn chunk_path(&self, hash: &[u8; 32]) -> PathBuf {
+        let name = hex::encode(hash);
+        self.root.join("chunks").join(&name[..2]).join(name)
+    }
+
+    fn manifest_path(&self, relative: &Path) -> PathBuf {
+        match self.key() {
+            Some(key) => self.root.join("files").join(hex::encode(
+                key.name(relative.as_os_str().as_encoded_bytes()),
+            )),
+            None => self.root.join("files").join(relative),
+        }
+    }
+
+    /// Store `data` unless a chunk with the same hash is already there. Returns the hash and
+    /// whether the chunk was new.
+    pub fn put_chunk(&self, data: &[u8]) -> Result<([u8; 32], bool)> {
+        let hash = self.chunk_hash(data);
+        let path = self.chunk_path(&hash);
+        if path.exists() {
+            return Ok((hash, false));
+        }
+        match self.key() {
+            Some(key) => write_atomically(&path, &key.seal(data, &hash))?,
+            None => write_atomically(&path, data)?,
+        }
+        Ok((hash, true))
+    }
+
+    /// Read the chunk with `hash`, checking that its content still matches.
+    pub fn get_chunk(&self, hash: &[u8; 32]) -> Result<Vec<u8>> {
+        let path = self.chunk_path(hash);
+        let mut data =
+            fs::read(&path).with_context(|| format!("Failed to read chunk {:?}", path))?;
+        if let Some(key) = self.key() {
+            data = key.open(&data, hash)?;
+        }
+        let actual = self.chunk_hash(&data);
+        if &actual != hash {
+            return Err(SyncError::ChecksumMismatch {
+                path,
+                expected: hex::encode(hash),
+                actual: hex::encode(actual),
+            });
+        }
+        Ok(data)
+    }
+
+    /// The manifest of the file stored at `relative`, if there is one.
+    pub fn manifest(&self, relative: &Path) -> Result<Option<Manifest>> {
+        let path = self.manifest_path(relative);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        self.read_manifest(&path)
+            .map(|(_, manifest)| Some(manifest))
+    }
+
+    /// Read the manifest file at `path`, with the stored file's path when the store is
+    /// encrypted.
+    fn read_manifest(&self, path: &Path) -> Result<(Option<PathBuf>, Manifest)> {
+        let data = fs::read(path).with_context(|| format!("Failed to read manifest {:?}", path))?;
+        let Some(key) = self.key() else {
+            let text = String::from_utf8(data)
+                .map_err(|_| SyncError::Format("Invalid manifest: not UTF-8".to_string()))?;
+            return Ok((None, Manifest::decode(&text)?));
+        };
+        let name = path.file_name().unwrap_or_default().as_encoded_bytes();
+        let text = String::from_utf8(key.open(&data, name)?)
+            .map_err(|_| SyncError::Format("Invalid manifest: not UTF-8".to_string()))?;
+        let (relative, manifest) = text
+            .split_once('\n')
+            .and_then(|(first, rest)| Some((first.strip_prefix("path ")?, rest)))
+            .ok_or_else(|| SyncError::Format("Invalid manifest: path".to_string()))?;
+        Ok((Some(PathBuf::from(relative)), Manifest::decode(manifest)?))
+    }
+
+    pub fn write_manifest(&self, relative: &Path, manifest: &Manifest) -> Result<()> {
+        let path = self.manifest_path(relative);
+        let Some(key) = self.key() else {
+            // A directory stored at the same path earlier is replaced by the file
+            if path.is_dir() {
+                fs::remove_dir_all(&path)
+                    .with_context(|| format!("Failed to delete {:?}", path))?;
+            }
+            return write_atomically(&path, manifest.encode().as_bytes());
+        };
+        let relative = relative
+            .to_str()
+            .ok_or_else(|| SyncError::UnsupportedSource {
+                path: relative.to_path_buf(),
+                reason: "encrypted stores only hold UTF-8 file names",
+            })?;
+        let text = format!("path {}\n{}", relative, manifest.encode());
+        let name = path.file_name().unwrap_or_default().as_encoded_bytes();
+        write_atomically(&path, &key.seal(text.as_bytes(), name))
+    }
+
+    pub fn remove_manifest(&self, relative: &Path) -> Result<()> {
+        let path = self.manifest_path(relative);
+        fs::remove_file(&path).with_context(|| format!("Failed to delete {:?}", path))
+    }
+
+    /// Paths of all stored files, relative to the sync root, in sorted order.
+    pub fn files(&self) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        let root = self.root.join("files");
+        if root.is_dir() {
+            collect_files(&root, Path::new(""), &mut files)?;
+        }
+        if self.key().is_some() {
+            files = files
+                .into_iter()
+                .map(|name| {
+                    let (relative, _) = self.read_manifest(&root.join(name))?;
+                    Ok(relative.unwrap_or_default())
+                })
+                .collect::<Result<_>>()?;
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Delete the chunks no manifest refers to any more, returning how many were deleted.
+    pub fn collect_garbage(&self) -> Result<usize> {
+        let mut referenced = HashSet::new();
+        for file in self.files()? {
+            if let Some(manifest) = self.manifest(&file)? {
+                referenced.extend(manifest.chunks);
+            }
+        }
+        let chunks = self.root.join("chunks");
+        if !chunks.is_dir() {
+            return Ok(0);
+        }
+        let mut deleted = 0;
+        for dir in fs::read_dir(&chunks)? {
+            for entry in fs::read_dir(dir?.path())? {
+                let path = entry?.path();
+                let mut hash = [0u8; 32];
+                let name = path.file_name().and_then(|name| name.to_str());
+                let is_chunk =
+                    name.is_some_and(|name| hex::decode_to_slice(name, &mut hash).is_ok());
+                if is_chunk && !referenced.contains(&hash) {
+                    fs::remove_file(&path)
+                        .with_context(|| format!("Failed to delete {:?}", path))?;
+                    deleted += 1;
+                }
+            }
+        }
+        info!("Deleted {} unreferenced chunks", deleted);
+        Ok(deleted)
+    }
+}
+
+/// Stands in for `StoreKey` when the `encryption` feature is off, so `ChunkStore::key` can
+/// only be `None`.
+#[cfg(not(feature = "encryption"))]
+enum Never {}
+
+#[cfg(not(feature = "encryption"))]
+impl Never {
+    fn name(&self, _data: &[u8]) -> [u8; 32] {
+        match *self {}
+    }
+
+    fn seal(&self, _data: &[u8], _label: &[u8]) -> Vec<u8> {
+        match *self {}
+    }
+
+    fn open(&self, _sealed: &[u8], _label: &[u8]) -> Result<Vec<u8>> {
+        match *self {}
+    }
+}
+
+/// Add the regular files beneath `dir`, as paths under `relative`, to `files`.
+fn collect_files(dir: &Path, relative: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {:?}", dir))? {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            collect_files(&entry.path(), &path, files)?;
+        } else if file_type.is_file() && !is_temp_file(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Whether `path` was left behind by a `write_atomically` interrupted before its rename.
+fn is_temp_file(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "rsynx-tmp")
+}
+
+/// Write `data` to a temporary file beside `path`, then rename it into place, so readers
+/// never see half a chunk or manifest.
+fn write_atomically(path: &Path, data: &[u8]) -> Result<()> {
+    let parent = path.parent().unwrap_or(Path::new(""));
+    fs::create_dir_all(parent).with_context(|| format!("Failed to create {:?}", parent))?;
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".rsynx-tmp");
+    let temp = parent.join(name);
+    File::create(&temp)
+        .and_then(|mut file| file.write_all(data))
+        .with_context(|| format!("Failed to write {:?}", temp))?;
+    fs::rename(&temp, path).with_context(|| format!("Failed to rename {:?}", temp))
+}
+
+/// Syncs a directory tree into a `ChunkStore`, or restores one from it: whichever of the
+/// source and destination is a `store://` path is the store.
+///
+/// Unchanged files are found by the size and modification time recorded in their manifest.
+/// Changed files are split into fixed-size chunks and only chunks the store doesn't hold yet
+/// are written, so identical data in different files, or in different versions of one file,
+/// is stored once. Restoring never deletes extraneous files.
+#[derive(Clone)]
+pub struct StoreSyncer {
+    syncer: Syncer,
+    source: String,
+    destination: String,
+    #[cfg(feature = "encryption")]
+    key: Option<StoreKey>,
+}
+
+impl_option_builders!(StoreSyncer);
+
+impl StoreSyncer {
+    pub fn new(source: impl Into<String>, destination: impl Into<String>) -> Self {
+        Self {
+            syncer: Syncer::new(),
+            source: source.into(),
+            destination: destination.into(),
+            #[cfg(feature = "encryption")]
+            key: None,
+        }
+    }
+
+    /// Keep the store encrypted with `key`, for stores on storage that mustn't see the data.
+    #[cfg(feature = "encryption")]
+    pub fn with_encryption_key(mut self, key: StoreKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    fn open_store(&self, root: PathBuf) -> ChunkStore {
+        let store = ChunkStore::new(root);
+        #[cfg(feature = "encryption")]
+        if let Some(key) = &self.key {
+            return store.with_key(key.clone());
+        }
+        store
+    }
+
+    /// Reject invalid or contradictory options before touching the filesystem.
+    pub fn validate(&self) -> Result<(), SyncError> {
+        self.syncer.validate()?;
+        match (parse_store(&self.source), parse_store(&self.destination)) {
+            (Some(_), Some(_)) | (None, None) => Err(SyncError::ConflictingOptions(
+                "exactly one of the source and destination must be a store:// path".to_string(),
+            )),
+            (Some(_), None) if self.syncer.options.delete_extraneous => {
+                Err(SyncError::ConflictingOptions(
+                    "deleting extraneous files doesn't apply when restoring from a store"
+                        .to_string(),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    #[instrument(name = "store_sync", skip_all, fields(source = %self.source, destination = %self.destination))]
+    pub fn sync(&self) -> Result<TransferResult> {
+        self.validate()?;
+        if let Some(root) = parse_store(&self.source) {
+            let store = self.open_store(root);
+            store.check_key(false)?;
+            return self.restore(&store, Path::new(&self.destination));
+        }
+        let store = self.open_store(parse_store(&self.destination).expect("checked above"));
+        store.check_key(true)?;
+        let source = Path::new(&self.source);
+        let mut result = TransferResult::default();
+        if source.is_file() {
+            let name = source
+                .file_name()
+                .ok_or_else(|| SyncError::UnsupportedSource {
+                    path: source.to_path_buf(),
+                    reason: "a file source needs a file name",
+                })?;
+            result.merge(self.store_file(&store, source, Path::new(name))?);
+        } else if source.is_dir() {
+            let mut seen = HashSet::new();
+            let mut walked = HashSet::new();
+            self.store_dir(
+                &store,
+                source,
+                Path::new(""),
+                &mut seen,
+                &mut walked,
+                &mut result,
+            )?;
+            if self.syncer.options.delete_extraneous {
+                result.merge(self.delete_extraneous(&store, source, &seen, &walked)?);
+            }
+        } else {
+            return Err(SyncError::UnsupportedSource {
+                path: source.to_path_buf(),
+                reason: "only regular files and directories can be synced",
+            });
+        }
+        if result.updated_files + result.deleted_files > 0 {
+            store.collect_garbage()?;
+        }
+        info!("Store sync completed");
+        Ok(result)
+    }
+
+    /// Path of a stored file as reported in results and events.
+    fn display_path(&self, relative: &Path) -> PathBuf {
+        Path::new(&self.destination).join(relative)
+    }
+
+    fn store_dir(
+        &self,
+        store: &ChunkStore,
+        dir: &Path,
+        relative: &Path,
+        seen: &mut HashSet<PathBuf>,
+        walked: &mut HashSet<PathBuf>,
+        result: &mut TransferResult,
+    ) -> Result<()> {
+        walked.insert(relative.to_path_buf());
+        for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {:?}", dir))? {
+            self.syncer.check_cancelled()?;
+            let path = entry?.path();
+            let entry_relative = relative.join(path.file_name().unwrap_or_default());
+            if self.is_filtered(&entry_relative, path.is_dir()) {
+                info!("Skipping excluded entry: {:?}", path);
+                continue;
+            }
+            if path.is_file() {
+                result.merge(self.store_file(store, &path, &entry_relative)?);
+                seen.insert(entry_relative);
+            } else if path.is_dir() && self.syncer.options.recursive {
+                self.store_dir(store, &path, &entry_relative, seen, walked, result)?;
+            } else if path.is_dir() {
+                info!("Skipping directory in non-recursive mode: {:?}", path);
+            } else {
+                warn!("Skipping unsupported file type: {:?}", path);
+                result.unsupported_files += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_filtered(&self, relative: &Path, is_dir: bool) -> bool {
+        let filter = &self.syncer.options.filter;
+        !filter.is_empty() && filter.is_excluded(relative, is_dir)
+    }
+
+    /// Store `path` at `relative` unless its manifest shows it unchanged.
+    fn store_file(
+        &self,
+        store: &ChunkStore,
+        path: &Path,
+        relative: &Path,
+    ) -> Result<TransferResult> {
+        let display = self.display_path(relative);
+        let meta = fs::metadata(path)
+            .with_context(|| format!("Failed to get metadata for source file: {:?}", path))?;
+        let mtime = FileTime::from_last_modification_time(&meta);
+        let existing = store.manifest(relative)?;
+        let options = &self.syncer.options;
+        let unchanged = existing
+            .as_ref()
+            .is_some_and(|manifest| manifest.size == meta.len() && manifest.mtime == mtime);
+        if unchanged && !options.checksum && !options.ignore_times {
+            info!("Skipping unchanged file: {:?}", path);
+            self.syncer.emit(|| SyncEvent::FileSkipped {
+                path: display.clone(),
+            });
+            return Ok(TransferResult::for_file(
+                &display,
+                FileAction::Skipped,
+                0,
+                meta.len() as usize,
+            ));
+        }
+
+        self.syncer.emit(|| SyncEvent::FileStarted {
+            path: display.clone(),
+            size: meta.len(),
+        });
+        let mut file =
+            File::open(path).with_context(|| format!("Failed to open source file: {:?}", path))?;
+        let mut buffer = vec![0u8; CHUNK_SIZE];
+        let (mut chunks, mut new_bytes, mut reused_bytes, mut size) = (Vec::new(), 0, 0, 0);
+        loop {
+            self.syncer.check_cancelled()?;
+            let len = read_chunk(&mut file, &mut buffer)
+                .with_context(|| format!("Failed to read source file: {:?}", path))?;
+            if len == 0 {
+                break;
+            }
+            let (hash, new) = store.put_chunk(&buffer[..len])?;
+            if new {
+                new_bytes += len;
+            } else {
+                reused_bytes += len;
+            }
+            size += len as u64;
+            chunks.push(hash);
+        }
+        store.write_manifest(
+            relative,
+            &Manifest {
+                size,
+                mtime,
+                mode: permission_bits(&meta),
+                chunks,
+            },
+        )?;
+        let action = if existing.is_some() {
+            FileAction::Updated
+        } else {
+            FileAction::Created
+        };
+        let result = TransferResult::for_file(&display, action, new_bytes, reused_bytes);
+        for record in &result.files {
+            self.syncer
+                .emit(|| SyncEvent::FileCompleted(record.clone()));
+        }
+        Ok(result)
+    }
+
+    /// Remove the manifests of files gone from `source`. A stored file counts as gone when
+    /// the nearest directory above it that still exists in the source was walked, so
+    /// subdirectories skipped by a non-recursive sync are left alone.
+    fn delete_extraneous(
+        &self,
+        store: &ChunkStore,
+        source: &Path,
+        seen: &HashSet<PathBuf>,
+        walked: &HashSet<PathBuf>,
+    ) -> Result<TransferResult> {
+        let mut result = TransferResult::default();
+        for file in store.files()? {
+            if seen.contains(&file) || self.is_filtered(&file, false) {
+                continue;
+            }
+            let nearest = file
+                .ancestors()
+                .skip(1)
+                .find(|dir| source.join(dir).is_dir())
+                .unwrap_or(Path::new(""));
+            if !walked.contains(nearest) {
+                continue;
+            }
+            store.remove_manifest(&file)?;
+            let display = self.display_path(&file);
+            self.syncer.emit(|| SyncEvent::Deleted {
+                path: display.clone(),
+            });
+            result.merge(TransferResult::for_file(
+                &display,
+                FileAction::Deleted,
+                0,
+                0,
+            ));
+        }
+        Ok(result)
+    }
+
+    /// Write every stored file that differs from its copy beneath `destination`.
+    fn restore(&self, store: &ChunkStore, destination: &Path) -> Result<TransferResult> {
+        let mut result = TransferResult::default();
+        for relative in store.files()? {
+            self.syncer.check_cancelled()?;
+            let nested = relative.parent().is_some_and(|p| !p.as_os_str().is_empty());
+            if (nested && !self.syncer.options.recursive) || self.is_filtered(&relative, false) {
+                continue;
+            }
+            let Some(manifest) = store.manifest(&relative)? else {
+                continue;
+            };
+            let dst_path = destination.join(&relative);
+            let current = fs::metadata(&dst_path).ok().filter(|meta| meta.is_file());
+            let unchanged = current.as_ref().is_some_and(|meta| {
+                meta.len() == manifest.size
+                    && FileTime::from_last_modification_time(meta) == manifest.mtime
+            });
+            if unchanged && !self.syncer.options.ignore_times {
+                self.syncer.emit(|| SyncEvent::FileSkipped {
+                    path: dst_path.clone(),
+                });
+                result.merge(TransferResult::for_file(
+                    &dst_path,
+                    FileAction::Skipped,
+                    0,
+                    manifest.size as usize,
+                ));
+                continue;
+            }
+            self.syncer.emit(|| SyncEvent::FileStarted {
+                path: dst_path.clone(),
+                size: manifest.size,
+            });
+            let mut data = Vec::with_capacity(manifest.size as usize);
+            for hash in &manifest.chunks {
+                data.extend_from_slice(&store.get_chunk(hash)?);
+            }
+            write_atomically(&dst_path, &data)?;
+            if self.syncer.options.preserve_metadata {
+                set_permission_bits(&dst_path, manifest.mode)?;
+                set_file_mtime(&dst_path, manifest.mtime).with_context(|| {
+                    format!(
+                        "Failed to set file times for destination file: {:?}",
+                        dst_path
+                    )
+                })?;
+            }
+            let action = if current.is_some() {
+                FileAction::Updated
+            } else {
+                FileAction::Created
+            };
+            let restored = TransferResult::for_file(&dst_path, action, data.len(), 0);
+            for record in &restored.files {
+                self.syncer
+                    .emit(|| SyncEvent::FileCompleted(record.clone()));
+            }
+            result.merge(restored);
+        }
+        Ok(result)
+    }
+}
+
+/// Fill `buffer` as far as the file allows, returning how much was read; short only at the end.
+fn read_chunk(file: &mut File, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(unix)]
+fn set_permission_bits(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to set permissions for destination file: {:?}", path))
+}
+
+#[cfg(not(unix))]
+fn set_permission_bits(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
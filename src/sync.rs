@@ -1,67 +1,288 @@
-use anyhow::Context;
-use anyhow::Result;
+pub use crate::core::Block;
+use crate::core::{strong_checksum, update_weak_checksum, weak_checksum};
+use crate::direct::DirectReader;
+use crate::error::{IoContext, Result, SyncError};
+use crate::options::SyncOptions;
+use crate::plan::PlanReason;
+use crate::throttle::{DiskThrottled, Throttled};
 use filetime::{FileTime, set_file_times};
 use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::{
     fs::{self, File},
-    io::{Read, Write},
-    path::Path,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
 };
+use tracing::{Span, field, instrument};
 
-#[derive(Debug)]
-pub struct Block {
-    pub offset: u64,
-    pub size: usize,
-    pub weak_checksum: u32,
-    pub strong_checksum: [u8; 32],
-}
+/// Largest block size accepted; bigger blocks would exceed the protocol's instruction limit.
+pub const MAX_BLOCK_SIZE: usize = 16 * 1024 * 1024;
 
 /// Result returned by the sync process, measured in bytes.
+#[derive(Debug, Default, Clone, Serialize)]
 pub struct TransferResult {
     pub new_bytes: usize,
     pub reused_bytes: usize,
+    /// Files left untouched because the quick check found them unchanged.
+    pub skipped_files: usize,
+    /// Files written to a destination that didn't exist yet.
+    pub created_files: usize,
+    /// Existing destination files rewritten from the source.
+    pub updated_files: usize,
+    /// Extraneous destination entries removed; a deleted directory counts once.
+    pub deleted_files: usize,
+    /// Source entries that can't be synced, such as sockets, devices or dangling symlinks.
+    pub unsupported_files: usize,
+    /// Destination files changed since the last sync, resolved by the conflict policy.
+    pub conflicts: usize,
+    /// Destination files replaced by hard links to identical ones after the sync.
+    pub deduplicated_files: usize,
+    /// Space the deduplication reclaimed.
+    pub reclaimed_bytes: u64,
+    /// Bytes compression kept off the wire, i.e. literal bytes minus their compressed size.
+    pub compression_saved_bytes: usize,
+    /// What happened to each file, in the order they were handled.
+    pub files: Vec<FileRecord>,
 }
 
-/// Common functionality including checksum calculation, file copying, and metadata preservation.
-pub struct Syncer {
-    pub block_size: usize,
-    pub preserve_metadata: bool,
-    pub delete_extraneous: bool,
-    pub compress: bool,
+impl TransferResult {
+    /// Result of handling a single file.
+    pub fn for_file(
+        path: &Path,
+        action: FileAction,
+        literal_bytes: usize,
+        reused_bytes: usize,
+    ) -> Self {
+        let mut result = Self {
+            new_bytes: literal_bytes,
+            reused_bytes,
+            ..Default::default()
+        };
+        match action {
+            FileAction::Created => result.created_files = 1,
+            FileAction::Updated => result.updated_files = 1,
+            FileAction::Skipped => result.skipped_files = 1,
+            FileAction::Deleted => result.deleted_files = 1,
+        }
+        result.files.push(FileRecord {
+            path: path.to_path_buf(),
+            action,
+            literal_bytes,
+            reused_bytes,
+        });
+        result
+    }
+
+    /// Share of the synced bytes taken from the existing destination, from 0.0 to 1.0.
+    pub fn reuse_ratio(&self) -> f64 {
+        let total = self.new_bytes + self.reused_bytes;
+        if total == 0 {
+            return 0.0;
+        }
+        self.reused_bytes as f64 / total as f64
+    }
+
+    /// Record the byte counts on the current span, which must declare the fields.
+    pub(crate) fn record_in_span(&self) {
+        let span = Span::current();
+        span.record("new_bytes", self.new_bytes);
+        span.record("reused_bytes", self.reused_bytes);
+        span.record("reuse_ratio", self.reuse_ratio());
+    }
+
+    /// Add the counts and records of `other` to this result.
+    pub fn merge(&mut self, other: TransferResult) {
+        self.new_bytes += other.new_bytes;
+        self.reused_bytes += other.reused_bytes;
+        self.skipped_files += other.skipped_files;
+        self.created_files += other.created_files;
+        self.updated_files += other.updated_files;
+        self.deleted_files += other.deleted_files;
+        self.unsupported_files += other.unsupported_files;
+        self.conflicts += other.conflicts;
+        self.deduplicated_files += other.deduplicated_files;
+        self.reclaimed_bytes += other.reclaimed_bytes;
+        self.compression_saved_bytes += other.compression_saved_bytes;
+        self.files.extend(other.files);
+    }
+}
+
+/// What a sync did to one destination file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileAction {
+    Created,
+    Updated,
+    Skipped,
+    Deleted,
+}
+
+/// Per-file entry of a `TransferResult`.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileRecord {
+    /// Destination path of the file.
+    pub path: PathBuf,
+    pub action: FileAction,
+    /// Bytes sent or copied from the source.
+    pub literal_bytes: usize,
+    /// Bytes reused from the existing destination.
+    pub reused_bytes: usize,
+}
+
+/// Shared flag an embedding application can set from any thread to abort a running sync.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Reserve disk space for the first `len` bytes of `file`, so writing them can't run out of
+/// space midway and the data is laid out contiguously where the filesystem can manage it.
+/// Filesystems without support for it, and platforms other than Linux, are left to allocate
+/// as the file is written.
+pub fn preallocate(file: &File, len: u64) -> io::Result<()> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        use std::os::fd::AsRawFd;
+        let len = libc::off_t::try_from(len)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "file too large"))?;
+        if len == 0 {
+            return Ok(());
+        }
+        // posix_fallocate returns the error instead of setting errno
+        match unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, len) } {
+            0 | libc::EOPNOTSUPP => Ok(()),
+            errno => Err(io::Error::from_raw_os_error(errno)),
+        }
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        let _ = (file, len);
+        Ok(())
+    }
+}
+
+/// Most a preallocation in `dir`'s filesystem may reserve: the space available to
+/// unprivileged users less a twentieth of the filesystem's size, so no single file can fill
+/// it. `None` where that isn't known, as on platforms that don't preallocate.
+pub(crate) fn preallocatable_space(dir: &Path) -> Option<u64> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let dir = match dir.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => dir,
+        };
+        let dir = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+        let mut stats = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+        if unsafe { libc::statvfs(dir.as_ptr(), stats.as_mut_ptr()) } != 0 {
+            return None;
+        }
+        let stats = unsafe { stats.assume_init() };
+        // The field types vary between platforms
+        #[allow(clippy::unnecessary_cast)]
+        let (fragment, available, blocks) = (
+            stats.f_frsize as u64,
+            stats.f_bavail as u64,
+            stats.f_blocks as u64,
+        );
+        let available = available.saturating_mul(fragment);
+        let reserve = blocks.saturating_mul(fragment) / 20;
+        Some(available.saturating_sub(reserve))
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        let _ = dir;
+        None
+    }
 }
 
-impl Default for Syncer {
-    fn default() -> Self {
-        Self::new()
+/// Permission bits of a file, as manifests, stores and remote destinations record them.
+/// Outside Unix only the read-only flag is known, standing for 0o444 or else 0o644.
+pub(crate) fn permission_bits(meta: &fs::Metadata) -> u32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        meta.permissions().mode() & 0o7777
+    }
+    #[cfg(not(unix))]
+    {
+        if meta.permissions().readonly() {
+            0o444
+        } else {
+            0o644
+        }
     }
 }
 
+/// Common functionality including checksum calculation, file copying, and metadata preservation.
+#[derive(Clone, Default)]
+pub struct Syncer {
+    pub options: SyncOptions,
+}
+
 impl Syncer {
     pub fn new() -> Self {
-        Self {
-            block_size: 1024,
-            preserve_metadata: false,
-            delete_extraneous: false,
-            compress: false,
-        }
+        Self::default()
     }
 
-    pub fn calculate_weak_checksum(&self, data: &[u8]) -> u32 {
-        let mut a: u32 = 0;
-        let mut b: u32 = 0;
+    pub fn with_options(options: SyncOptions) -> Self {
+        Self { options }
+    }
+
+    /// Check the options shared by every sync mode.
+    pub fn validate(&self) -> Result<(), SyncError> {
+        self.options.validate()
+    }
+
+    /// Wrap a local file so reading or writing it counts against the `disk_limit`.
+    pub fn disk_io<T>(&self, file: T) -> DiskThrottled<T> {
+        DiskThrottled::new(file, self.options.disk_limit.clone())
+    }
+
+    /// Open `path` for reading through `disk_io`, uncached with `direct_io`.
+    pub fn open_input(&self, path: &Path) -> io::Result<DiskThrottled<DirectReader>> {
+        Ok(self.disk_io(DirectReader::open(path, self.options.direct_io)?))
+    }
+
+    /// Count `len` bytes of local disk I/O done outside `disk_io`, such as through a memory
+    /// map, against the `disk_limit`.
+    pub fn consume_disk(&self, len: usize) {
+        if let Some(limiter) = &self.options.disk_limit {
+            limiter.consume(len);
+        }
+    }
 
-        for &byte in data {
-            a = a.wrapping_add(byte as u32);
-            b = b.wrapping_add(a);
+    /// Fail with `SyncError::Cancelled` once the cancel token has been triggered.
+    pub fn check_cancelled(&self) -> Result<(), SyncError> {
+        if self.options.cancel.is_cancelled() {
+            return Err(SyncError::Cancelled);
         }
-        (a & 0xffff) | ((b & 0xffff) << 16)
+        Ok(())
+    }
+
+    pub fn calculate_weak_checksum(&self, data: &[u8]) -> u32 {
+        weak_checksum(data)
     }
 
     pub fn calculate_strong_checksum(&self, data: &[u8]) -> [u8; 32] {
-        let mut hasher = Sha256::new();
-        hasher.update(data);
-        hasher.finalize().into()
+        strong_checksum(data)
     }
 
     pub fn update_weak_checksum(
@@ -71,28 +292,23 @@ impl Syncer {
         old_sum: u32,
         len: usize,
     ) -> u32 {
-        let a_old = old_sum & 0xffff;
-        let b_old = (old_sum >> 16) & 0xffff;
-        let a_new = a_old
-            .wrapping_sub(old_byte as u32)
-            .wrapping_add(new_byte as u32);
-        let b_new = b_old
-            .wrapping_sub((len as u32).wrapping_mul(old_byte as u32))
-            .wrapping_add(a_new);
-        (a_new & 0xffff) | ((b_new & 0xffff) << 16)
+        update_weak_checksum(old_byte, new_byte, old_sum, len)
     }
 
+    #[instrument(skip(self), fields(block_size = self.options.block_size, blocks = field::Empty))]
     pub fn calculate_checksums(&self, path: &Path) -> Result<Vec<Block>> {
-        let mut file = File::open(path)?;
-        let file_size = file.metadata()?.len();
+        let mut file = self
+            .open_input(path)
+            .with_context(|| format!("Failed to calculate signature for {:?}", path))?;
+        let file_size = file.get_ref().get_ref().metadata()?.len();
         let mut blocks = Vec::new();
         let mut offset: u64 = 0;
-        let mut buffer = vec![0; self.block_size];
+        let mut buffer = vec![0; self.options.block_size];
         while offset < file_size {
-            let read_size = if offset + self.block_size as u64 > file_size {
+            let read_size = if offset + self.options.block_size as u64 > file_size {
                 (file_size - offset) as usize
             } else {
-                self.block_size
+                self.options.block_size
             };
             buffer.resize(read_size, 0);
             file.read_exact(&mut buffer)?;
@@ -106,14 +322,32 @@ impl Syncer {
             });
             offset += read_size as u64;
         }
+        Span::current().record("blocks", blocks.len());
         Ok(blocks)
     }
 
     pub fn copy_file(&self, src: &Path, dst: &Path) -> Result<TransferResult> {
-        fs::copy(src, dst)
-            .with_context(|| format!("Failed to copy file from {:?} to {:?}", src, dst))?;
+        let action = if dst.exists() {
+            FileAction::Updated
+        } else {
+            FileAction::Created
+        };
+        if self.options.bwlimit.is_some()
+            || self.options.disk_limit.is_some()
+            || self.options.preallocate
+        {
+            self.copy_throttled(src, dst)
+        } else {
+            fs::copy(src, dst).map(|_| ())
+        }
+        .with_context(|| format!("Failed to copy file from {:?} to {:?}", src, dst))?;
 
-        if self.preserve_metadata {
+        if self.options.preserve_owner {
+            let src_meta = fs::metadata(src)
+                .with_context(|| format!("Failed to get metadata for source file: {:?}", src))?;
+            self.copy_ownership(&src_meta, dst)?;
+        }
+        if self.options.preserve_metadata {
             let src_meta = fs::metadata(src)
                 .with_context(|| format!("Failed to get metadata for source file: {:?}", src))?;
             fs::set_permissions(dst, src_meta.permissions()).with_context(|| {
@@ -128,15 +362,112 @@ impl Syncer {
         let src_size = fs::metadata(src)
             .with_context(|| format!("Failed to get metadata for source file: {:?}", src))?
             .len() as usize;
-        Ok(TransferResult {
-            new_bytes: src_size,
-            reused_bytes: 0,
+        Ok(TransferResult::for_file(dst, action, src_size, 0))
+    }
+
+    /// `fs::copy` paced by the `bwlimit` and `disk_limit`, reserving the whole file first with
+    /// `preallocate`.
+    fn copy_throttled(&self, src: &Path, dst: &Path) -> io::Result<()> {
+        let reader = File::open(src)?;
+        let writer = File::create(dst)?;
+        if self.options.preallocate {
+            preallocate(&writer, reader.metadata()?.len())?;
+        }
+        let mut reader = self.disk_io(reader);
+        let mut writer = Throttled::new(self.disk_io(writer), self.options.bwlimit);
+        io::copy(&mut reader, &mut writer)?;
+        fs::set_permissions(dst, reader.get_ref().metadata()?.permissions())
+    }
+
+    /// Give `dst` the owner and group of the source described by `src_meta`, without following
+    /// a symlink at `dst`. Without the right to change the owner only the group is copied, and
+    /// without that either the file is left as it is.
+    #[cfg(unix)]
+    pub fn copy_ownership(&self, src_meta: &fs::Metadata, dst: &Path) -> Result<()> {
+        use std::os::unix::fs::{MetadataExt, lchown};
+        let denied = |e: &io::Error| e.kind() == io::ErrorKind::PermissionDenied;
+        let owned = match lchown(dst, Some(src_meta.uid()), Some(src_meta.gid())) {
+            Err(e) if denied(&e) => match lchown(dst, None, Some(src_meta.gid())) {
+                Err(e) if denied(&e) => Ok(()),
+                other => other,
+            },
+            other => other,
+        };
+        owned.with_context(|| format!("Failed to set owner of {:?}", dst))
+    }
+
+    #[cfg(not(unix))]
+    pub fn copy_ownership(&self, _src_meta: &fs::Metadata, _dst: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    /// Recreate the symlink `src` at `dst`, replacing whatever is there unless it's already a
+    /// link to the same target.
+    #[cfg(unix)]
+    pub fn copy_symlink(&self, src: &Path, dst: &Path) -> Result<TransferResult> {
+        let target =
+            fs::read_link(src).with_context(|| format!("Failed to read symlink {:?}", src))?;
+        let action = match fs::symlink_metadata(dst) {
+            Ok(_) if fs::read_link(dst).is_ok_and(|existing| existing == target) => {
+                return Ok(TransferResult::for_file(dst, FileAction::Skipped, 0, 0));
+            }
+            Ok(meta) => {
+                if meta.is_dir() {
+                    fs::remove_dir_all(dst)
+                } else {
+                    fs::remove_file(dst)
+                }
+                .with_context(|| format!("Failed to replace {:?} with a symlink", dst))?;
+                FileAction::Updated
+            }
+            Err(_) => FileAction::Created,
+        };
+        std::os::unix::fs::symlink(&target, dst)
+            .with_context(|| format!("Failed to create symlink {:?}", dst))?;
+        if self.options.preserve_owner {
+            self.copy_ownership(&fs::symlink_metadata(src)?, dst)?;
+        }
+        Ok(TransferResult::for_file(dst, action, 0, 0))
+    }
+
+    #[cfg(not(unix))]
+    pub fn copy_symlink(&self, src: &Path, _dst: &Path) -> Result<TransferResult> {
+        Err(SyncError::UnsupportedSource {
+            path: src.to_path_buf(),
+            reason: "symlinks can only be preserved on unix",
         })
     }
 
+    /// SHA-256 of a whole file.
+    pub fn calculate_file_checksum(&self, path: &Path) -> Result<[u8; 32]> {
+        let mut file = self
+            .disk_io(File::open(path).with_context(|| format!("Failed to open file: {:?}", path))?);
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut file, &mut hasher)?;
+        Ok(hasher.finalize().into())
+    }
+
+    /// Re-read `path` and fail with `SyncError::ChecksumMismatch` unless it hashes to `expected`.
+    pub fn verify_file(&self, path: &Path, expected: &[u8; 32]) -> Result<()> {
+        let actual = self.calculate_file_checksum(path)?;
+        if actual != *expected {
+            return Err(SyncError::ChecksumMismatch {
+                path: path.to_path_buf(),
+                expected: hex::encode(expected),
+                actual: hex::encode(actual),
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether `dst` already matches `src` and can be skipped; see `quick_check`.
+    pub fn is_unchanged(&self, src: &Path, dst: &Path) -> Result<bool> {
+        Ok(self.quick_check(src, dst)? == PlanReason::Unchanged)
+    }
+
     /// Compress data using gzip compression
     pub fn compress_data(&self, data: &[u8]) -> Result<Vec<u8>> {
-        if !self.compress {
+        if !self.options.compress {
             return Ok(data.to_vec());
         }
 
@@ -145,9 +476,24 @@ impl Syncer {
         encoder.finish().map_err(Into::into)
     }
 
+    /// Compress a transfer frame, returning `None` when compression is disabled or the
+    /// payload doesn't shrink (already-compressed media, encrypted files), in which
+    /// case the frame should be sent raw.
+    pub fn compress_frame(&self, data: &[u8]) -> Result<Option<Vec<u8>>> {
+        if !self.options.compress || data.is_empty() {
+            return Ok(None);
+        }
+        let compressed = self.compress_data(data)?;
+        if compressed.len() < data.len() {
+            Ok(Some(compressed))
+        } else {
+            Ok(None)
+        }
+    }
+
     /// Decompress data that was compressed with gzip
     pub fn decompress_data(&self, compressed_data: &[u8]) -> Result<Vec<u8>> {
-        if !self.compress {
+        if !self.options.compress {
             return Ok(compressed_data.to_vec());
         }
 
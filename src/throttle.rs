@@ -0,0 +1,167 @@
+use std::{
+    io::{self, Read, Seek, SeekFrom, Write},
+    sync::{Arc, Mutex, PoisonError},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Paces a stream of bytes to an average of at most `rate` bytes per second by sleeping
+/// whenever it gets ahead.
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: u64,
+    start: Instant,
+    bytes: u64,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        Self {
+            rate: bytes_per_second.max(1),
+            start: Instant::now(),
+            bytes: 0,
+        }
+    }
+
+    /// Account for `len` more bytes, sleeping until the average rate is back within the limit.
+    pub fn consume(&mut self, len: usize) {
+        self.bytes += len as u64;
+        let due = Duration::from_secs_f64(self.bytes as f64 / self.rate as f64);
+        if let Some(ahead) = due.checked_sub(self.start.elapsed()) {
+            thread::sleep(ahead);
+        }
+    }
+}
+
+/// Writer passing everything through to `inner`, paced by a rate limiter when one is set.
+#[derive(Debug)]
+pub struct Throttled<W> {
+    inner: W,
+    limiter: Option<RateLimiter>,
+}
+
+impl<W: Write> Throttled<W> {
+    /// Wrap `inner`; a rate of `None` or zero leaves writes unlimited.
+    pub fn new(inner: W, bytes_per_second: Option<u64>) -> Self {
+        Self {
+            inner,
+            limiter: bytes_per_second
+                .filter(|&rate| rate > 0)
+                .map(RateLimiter::new),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for Throttled<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(limiter) = self.limiter.as_mut() {
+            limiter.consume(written);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Rate limiter whose clones all draw on the same budget, so reads and writes from every
+/// thread and phase of a sync are paced together.
+#[derive(Debug, Clone)]
+pub struct SharedRateLimiter(Arc<Mutex<RateLimiter>>);
+
+impl SharedRateLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        Self(Arc::new(Mutex::new(RateLimiter::new(bytes_per_second))))
+    }
+
+    /// Account for `len` more bytes, sleeping while the shared budget is spent.
+    pub fn consume(&self, len: usize) {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .consume(len);
+    }
+}
+
+/// Reader or writer passing everything through to `inner`, paced by a shared rate limiter
+/// when one is set.
+#[derive(Debug)]
+pub struct DiskThrottled<T> {
+    inner: T,
+    limiter: Option<SharedRateLimiter>,
+}
+
+impl<T> DiskThrottled<T> {
+    pub fn new(inner: T, limiter: Option<SharedRateLimiter>) -> Self {
+        Self { inner, limiter }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn consume(&self, len: usize) {
+        if let Some(limiter) = &self.limiter {
+            limiter.consume(len);
+        }
+    }
+}
+
+impl<T: Read> Read for DiskThrottled<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl<T: Write> Write for DiskThrottled<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.consume(written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Seek> Seek for DiskThrottled<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// Parse a bandwidth limit such as `500k`, `10m` or `1.5M` into bytes per second, or `None`
+/// if it isn't one. Suffixes `b`, `k`, `m` and `g` are powers of 1024 and case-insensitive; as
+/// with rsync, a bare number is in KiB.
+pub fn parse_rate(spec: &str) -> Option<u64> {
+    let spec = spec.trim();
+    let (number, unit) = match spec.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&spec[..i], c.to_ascii_lowercase()),
+        _ => (spec, 'k'),
+    };
+    let multiplier: u64 = match unit {
+        'b' => 1,
+        'k' => 1 << 10,
+        'm' => 1 << 20,
+        'g' => 1 << 30,
+        _ => return None,
+    };
+    let value: f64 = number.parse().ok()?;
+    if !value.is_finite() || value < 0.0 {
+        return None;
+    }
+    Some((value * multiplier as f64) as u64)
+}
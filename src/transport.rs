@@ -0,0 +1,93 @@
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+#[cfg(unix)]
+use std::{
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+};
+
+/// A bidirectional byte channel the sync protocol can run over: TCP and Unix sockets,
+/// serial links, or in-memory pipes.
+pub trait Transport: Read + Write {}
+
+impl<T: Read + Write + ?Sized> Transport for T {}
+
+/// Establishes the client side of a connection.
+pub trait Connector {
+    type Transport: Transport;
+
+    fn connect(&self) -> io::Result<Self::Transport>;
+
+    /// Description of the remote end, used in logs and errors.
+    fn peer(&self) -> String;
+}
+
+/// Accepts the server side of connections.
+pub trait Acceptor {
+    type Transport: Transport;
+
+    /// Wait for the next client, returning its transport and a description of the peer.
+    fn accept(&self) -> io::Result<(Self::Transport, String)>;
+}
+
+/// Connects to a server over TCP.
+pub struct TcpConnector {
+    pub address: String,
+    pub port: u16,
+}
+
+impl Connector for TcpConnector {
+    type Transport = TcpStream;
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        TcpStream::connect((self.address.as_str(), self.port))
+    }
+
+    fn peer(&self) -> String {
+        format!("{}:{}", self.address, self.port)
+    }
+}
+
+impl Acceptor for TcpListener {
+    type Transport = TcpStream;
+
+    fn accept(&self) -> io::Result<(TcpStream, String)> {
+        let (stream, addr) = TcpListener::accept(self)?;
+        Ok((stream, addr.to_string()))
+    }
+}
+
+/// Connects to a server listening on a Unix domain socket.
+#[cfg(unix)]
+pub struct UnixConnector {
+    pub path: PathBuf,
+}
+
+#[cfg(unix)]
+impl Connector for UnixConnector {
+    type Transport = UnixStream;
+
+    fn connect(&self) -> io::Result<UnixStream> {
+        UnixStream::connect(&self.path)
+    }
+
+    fn peer(&self) -> String {
+        self.path.display().to_string()
+    }
+}
+
+#[cfg(unix)]
+impl Acceptor for UnixListener {
+    type Transport = UnixStream;
+
+    fn accept(&self) -> io::Result<(UnixStream, String)> {
+        let (stream, addr) = UnixListener::accept(self)?;
+        let peer = addr
+            .as_pathname()
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|| "unnamed unix socket".to_string());
+        Ok((stream, peer))
+    }
+}
@@ -0,0 +1,763 @@
+use crate::error::{IoContext, Result, SyncError};
+use crate::events::SyncEvent;
+use crate::options::impl_option_builders;
+use crate::plan::{PlanAction, PlanReason, PlannedOp};
+use crate::sync::{FileAction, Syncer, TransferResult};
+use filetime::FileTime;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+use tracing::{info, instrument, warn};
+
+/// Prefix of a destination on a WebDAV server reached over HTTP: `dav://host/path`.
+pub const DAV_SCHEME: &str = "dav://";
+
+/// Prefix of a destination on a WebDAV server reached over HTTPS: `davs://host/path`.
+pub const DAVS_SCHEME: &str = "davs://";
+
+/// Files larger than this are streamed with chunked transfer encoding instead of being sent
+/// with a known length in one piece.
+pub const CHUNKED_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// Namespace of the dead properties rsynx records on each uploaded file.
+const PROPERTY_NAMESPACE: &str = "urn:x-rsynx";
+
+/// Suffix of the temporary resource an upload is written to before it's moved into place.
+const UPLOAD_SUFFIX: &str = ".rsynx-tmp";
+
+const PROPFIND_BODY: &str = concat!(
+    r#"<?xml version="1.0" encoding="utf-8"?>"#,
+    r#"<d:propfind xmlns:d="DAV:" xmlns:r="urn:x-rsynx"><d:prop>"#,
+    "<d:resourcetype/><d:getcontentlength/><r:mtime/><r:sha256/>",
+    "</d:prop></d:propfind>"
+);
+
+/// Collection URL and user of a `dav://` or `davs://` destination.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebDavLocation {
+    /// `http` or `https` URL of the collection, with its path percent-encoded and ending in `/`.
+    pub url: String,
+    /// User given before an `@` in the spec.
+    pub user: Option<String>,
+}
+
+/// Parse `dav://[user@]host[:port]/path` or its `davs://` counterpart.
+pub fn parse_webdav(spec: &str) -> Option<WebDavLocation> {
+    let (scheme, rest) = if let Some(rest) = spec.strip_prefix(DAV_SCHEME) {
+        ("http", rest)
+    } else {
+        ("https", spec.strip_prefix(DAVS_SCHEME)?)
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, ""),
+    };
+    let (user, host) = match authority.rsplit_once('@') {
+        Some((user, host)) => (Some(user.to_string()), host),
+        None => (None, authority),
+    };
+    if host.is_empty() {
+        return None;
+    }
+    let mut url = format!("{}://{}/", scheme, host);
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        url.push_str(&percent_encode(segment));
+        url.push('/');
+    }
+    Some(WebDavLocation { url, user })
+}
+
+/// Percent-encode everything in a path segment but unreserved characters.
+pub fn percent_encode(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Undo `percent_encode`, leaving malformed escapes as they are.
+pub fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = (bytes[i] == b'%')
+            .then(|| value.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escape {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Contents of every element named `name` in `xml`, whatever its namespace prefix, with
+/// entities left escaped. A self-closing element has empty contents. Enough for multistatus
+/// responses, which never nest an element inside one of the same name.
+pub fn xml_elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let mut elements = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[..end];
+        rest = &rest[end + 1..];
+        let qualified = tag
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+        let local = qualified.rsplit(':').next().unwrap_or_default();
+        if tag.starts_with(['/', '?', '!']) || local != name {
+            continue;
+        }
+        if tag.ends_with('/') {
+            elements.push("");
+            continue;
+        }
+        let close = format!("</{}>", qualified);
+        if let Some(len) = rest.find(&close) {
+            elements.push(&rest[..len]);
+            rest = &rest[len + close.len()..];
+        }
+    }
+    elements
+}
+
+/// Text of the first non-empty element named `name`, with the predefined entities unescaped.
+fn xml_text(xml: &str, name: &str) -> Option<String> {
+    let text = xml_elements(xml, name)
+        .into_iter()
+        .map(str::trim)
+        .find(|text| !text.is_empty())?;
+    Some(
+        text.replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&"),
+    )
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+            bits | u32::from(byte) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// A member of a collection, as a `PROPFIND` reports it.
+#[derive(Debug, Clone)]
+struct RemoteEntry {
+    is_dir: bool,
+    size: u64,
+    /// Modification time rsynx recorded when uploading, as `seconds.nanos`.
+    mtime: Option<String>,
+    /// SHA-256 rsynx recorded when uploading, in hex.
+    sha256: Option<String>,
+}
+
+/// Minimal WebDAV client for the calls a sync needs.
+struct Client {
+    agent: ureq::Agent,
+    base: String,
+    authorization: Option<String>,
+}
+
+impl Client {
+    fn new(base: &str, credentials: Option<&(String, String)>) -> Self {
+        Self {
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(Duration::from_secs(30))
+                .build(),
+            base: base.to_string(),
+            authorization: credentials.map(|(user, password)| {
+                format!(
+                    "Basic {}",
+                    base64(format!("{}:{}", user, password).as_bytes())
+                )
+            }),
+        }
+    }
+
+    fn url(&self, relative: &str) -> String {
+        resource_url(&self.base, relative)
+    }
+
+    fn request(&self, method: &str, url: &str) -> ureq::Request {
+        let request = self.agent.request(method, url);
+        match &self.authorization {
+            Some(authorization) => request.set("Authorization", authorization),
+            None => request,
+        }
+    }
+
+    /// Turn a failed request into the matching error.
+    fn error(&self, method: &str, url: &str, e: ureq::Error) -> SyncError {
+        match e {
+            ureq::Error::Status(status, response) => SyncError::Refused(format!(
+                "{} {} returned {} {}",
+                method,
+                url,
+                status,
+                response.status_text()
+            )),
+            e => SyncError::Connect {
+                peer: self.base.clone(),
+                source: io::Error::other(e.to_string()),
+            },
+        }
+    }
+
+    /// Members of the collection at `relative` by name, or `None` if there's no collection.
+    fn list(&self, relative: &str) -> Result<Option<HashMap<String, RemoteEntry>>> {
+        let mut url = self.url(relative);
+        if !url.ends_with('/') {
+            url.push('/');
+        }
+        let response = self
+            .request("PROPFIND", &url)
+            .set("Depth", "1")
+            .set("Content-Type", "application/xml; charset=utf-8")
+            .send_string(PROPFIND_BODY);
+        let body = match response {
+            Ok(response) => response
+                .into_string()
+                .with_context(|| format!("Failed to read the listing of {}", url))?,
+            Err(ureq::Error::Status(404, _)) => return Ok(None),
+            Err(e) => return Err(self.error("PROPFIND", &url, e)),
+        };
+        let own_path = percent_decode(href_path(&url));
+        let mut entries = HashMap::new();
+        for response in xml_elements(&body, "response") {
+            let Some(href) = xml_text(response, "href") else {
+                continue;
+            };
+            let path = percent_decode(href_path(&href));
+            if path.trim_end_matches('/') == own_path.trim_end_matches('/') {
+                continue;
+            }
+            let Some(name) = path.trim_end_matches('/').rsplit('/').next() else {
+                continue;
+            };
+            let resource_type = xml_elements(response, "resourcetype").concat();
+            entries.insert(
+                name.to_string(),
+                RemoteEntry {
+                    is_dir: !xml_elements(&resource_type, "collection").is_empty(),
+                    size: xml_text(response, "getcontentlength")
+                        .and_then(|size| size.parse().ok())
+                        .unwrap_or(0),
+                    mtime: xml_text(response, "mtime"),
+                    sha256: xml_text(response, "sha256"),
+                },
+            );
+        }
+        Ok(Some(entries))
+    }
+
+    fn mkcol(&self, relative: &str) -> Result<()> {
+        let url = self.url(relative);
+        match self.request("MKCOL", &url).call() {
+            // 405 means something already exists there; a collection is what we want
+            Ok(_) | Err(ureq::Error::Status(405, _)) => Ok(()),
+            Err(e) => Err(self.error("MKCOL", &url, e)),
+        }
+    }
+
+    /// Upload `file` of `len` bytes to `relative`.
+    fn put(&self, relative: &str, file: File, len: u64, mtime: &FileTime) -> Result<()> {
+        let url = self.url(relative);
+        let request = self
+            .request("PUT", &url)
+            .set("Content-Type", "application/octet-stream")
+            // Nextcloud and ownCloud set the file's time from this; others ignore it
+            .set("X-OC-Mtime", &mtime.unix_seconds().to_string());
+        let request = if len > CHUNKED_THRESHOLD {
+            // Without a Content-Length ureq streams the body in chunks; this header tells
+            // servers such as sabre/dav how much to expect
+            request.set("X-Expected-Entity-Length", &len.to_string())
+        } else {
+            request.set("Content-Length", &len.to_string())
+        };
+        request.send(file).map_err(|e| self.error("PUT", &url, e))?;
+        Ok(())
+    }
+
+    /// Record the `mtime` and `sha256` dead properties on `relative`.
+    fn set_properties(&self, relative: &str, mtime: &str, sha256: &str) -> Result<()> {
+        let url = self.url(relative);
+        let body = format!(
+            concat!(
+                r#"<?xml version="1.0" encoding="utf-8"?>"#,
+                r#"<d:propertyupdate xmlns:d="DAV:" xmlns:r="{}"><d:set><d:prop>"#,
+                "<r:mtime>{}</r:mtime><r:sha256>{}</r:sha256>",
+                "</d:prop></d:set></d:propertyupdate>"
+            ),
+            PROPERTY_NAMESPACE, mtime, sha256
+        );
+        self.request("PROPPATCH", &url)
+            .set("Content-Type", "application/xml; charset=utf-8")
+            .send_string(&body)
+            .map_err(|e| self.error("PROPPATCH", &url, e))?;
+        Ok(())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let url = self.url(from);
+        self.request("MOVE", &url)
+            .set("Destination", &self.url(to))
+            .set("Overwrite", "T")
+            .call()
+            .map_err(|e| self.error("MOVE", &url, e))?;
+        Ok(())
+    }
+
+    /// Delete the resource at `relative`; collections go with everything in them.
+    fn delete(&self, relative: &str) -> Result<()> {
+        let url = self.url(relative);
+        match self.request("DELETE", &url).call() {
+            Ok(_) | Err(ureq::Error::Status(404, _)) => Ok(()),
+            Err(e) => Err(self.error("DELETE", &url, e)),
+        }
+    }
+}
+
+/// URL of the resource at `relative`, a `/`-separated path below the collection at `base`.
+fn resource_url(base: &str, relative: &str) -> String {
+    let segments: Vec<_> = relative
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(percent_encode)
+        .collect();
+    format!("{}{}", base, segments.join("/"))
+}
+
+/// Path part of `href`, which servers send either as an absolute path or a full URL.
+fn href_path(href: &str) -> &str {
+    match href.split_once("://") {
+        Some((_, rest)) => rest.find('/').map_or("/", |i| &rest[i..]),
+        None => href,
+    }
+}
+
+/// Syncs a local file or directory tree into a collection on a WebDAV server such as
+/// Nextcloud or SharePoint.
+///
+/// Syncs are planned like local ones, from a `PROPFIND` of each collection, so `plan` output
+/// can be shown or checked before `execute` carries it out. Each upload records the file's
+/// modification time and SHA-256 as dead properties, which the quick check compares in place
+/// of the server's own timestamps; files without them are sent again. Changed files are sent
+/// whole to a temporary name and moved into place, streamed in chunks when larger than
+/// `CHUNKED_THRESHOLD`.
+#[derive(Clone)]
+pub struct WebDavSyncer {
+    syncer: Syncer,
+    source: PathBuf,
+    location: WebDavLocation,
+    credentials: Option<(String, String)>,
+}
+
+impl_option_builders!(WebDavSyncer);
+
+impl WebDavSyncer {
+    pub fn new(source: impl Into<PathBuf>, location: WebDavLocation) -> Self {
+        Self {
+            syncer: Syncer::new(),
+            source: source.into(),
+            location,
+            credentials: None,
+        }
+    }
+
+    /// Authenticate as `user` with `password`, using HTTP basic authentication.
+    pub fn with_credentials(
+        mut self,
+        user: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        self.credentials = Some((user.into(), password.into()));
+        self
+    }
+
+    /// Reject invalid options before connecting.
+    pub fn validate(&self) -> Result<(), SyncError> {
+        self.syncer.validate()?;
+        if self.source.as_os_str().is_empty() {
+            return Err(SyncError::EmptyPath("Source"));
+        }
+        Ok(())
+    }
+
+    #[instrument(name = "webdav_sync", skip_all, fields(source = ?self.source, url = %self.location.url))]
+    pub fn sync(&self) -> Result<TransferResult> {
+        let plan = self.plan()?;
+        let result = self.execute(&plan)?;
+        info!("WebDAV sync completed");
+        Ok(result)
+    }
+
+    fn client(&self) -> Client {
+        Client::new(&self.location.url, self.credentials.as_ref())
+    }
+
+    /// Path of a remote resource as reported in plans, results and events: its URL.
+    fn display_path(&self, relative: &Path) -> PathBuf {
+        PathBuf::from(resource_url(&self.location.url, &remote_path(relative)))
+    }
+
+    fn is_filtered(&self, relative: &Path, is_dir: bool) -> bool {
+        let filter = &self.syncer.options.filter;
+        !filter.is_empty() && filter.is_excluded(relative, is_dir)
+    }
+
+    /// Work out what `sync` would do, listing the server's collections but changing nothing.
+    pub fn plan(&self) -> Result<Vec<PlannedOp>> {
+        self.validate()?;
+        let client = self.client();
+        let mut plan = Vec::new();
+        if self.source.is_file() {
+            let root = client.list("")?;
+            if root.is_none() {
+                plan.push(self.planned_dir(&self.source, Path::new("")));
+            }
+            let name = Path::new(self.source.file_name().unwrap_or_default());
+            let existing = root.as_ref().and_then(|root| root.get(&remote_path(name)));
+            self.plan_file(&self.source, name, existing, &mut plan)?;
+        } else if self.source.is_dir() {
+            let root = client.list("")?;
+            if root.is_none() {
+                plan.push(self.planned_dir(&self.source, Path::new("")));
+            }
+            self.plan_dir(&client, &self.source, Path::new(""), root, &mut plan)?;
+        } else {
+            return Err(SyncError::UnsupportedSource {
+                path: self.source.clone(),
+                reason: "only regular files and directories can be synced",
+            });
+        }
+        Ok(plan)
+    }
+
+    fn planned_dir(&self, source: &Path, relative: &Path) -> PlannedOp {
+        PlannedOp {
+            action: PlanAction::Create,
+            source: Some(source.to_path_buf()),
+            destination: self.display_path(relative),
+            size: 0,
+            is_dir: true,
+            reason: PlanReason::Missing,
+        }
+    }
+
+    /// Plan the contents of `src_dir` against `remote`, the listing of the collection at
+    /// `relative`, or `None` if it doesn't exist yet.
+    fn plan_dir(
+        &self,
+        client: &Client,
+        src_dir: &Path,
+        relative: &Path,
+        remote: Option<HashMap<String, RemoteEntry>>,
+        plan: &mut Vec<PlannedOp>,
+    ) -> Result<()> {
+        let remote = remote.unwrap_or_default();
+        let mut entries = fs::read_dir(src_dir)
+            .with_context(|| format!("Failed to read {:?}", src_dir))?
+            .collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        let mut src_names = HashSet::new();
+        for entry in entries {
+            let path = entry.path();
+            let name = remote_path(Path::new(&entry.file_name()));
+            let entry_relative = relative.join(entry.file_name());
+            src_names.insert(name.clone());
+            let existing = remote.get(&name);
+            if self.is_filtered(&entry_relative, path.is_dir()) {
+                info!("Skipping excluded entry: {:?}", path);
+            } else if path.is_file() {
+                if existing.is_some_and(|existing| existing.is_dir) {
+                    plan.push(self.planned_delete(&entry_relative, 0, true));
+                }
+                let existing = existing.filter(|existing| !existing.is_dir);
+                self.plan_file(&path, &entry_relative, existing, plan)?;
+            } else if path.is_dir() && self.syncer.options.recursive {
+                let listing = match existing {
+                    Some(existing) if existing.is_dir => {
+                        client.list(&remote_path(&entry_relative))?
+                    }
+                    Some(existing) => {
+                        plan.push(self.planned_delete(&entry_relative, existing.size, false));
+                        None
+                    }
+                    None => None,
+                };
+                if listing.is_none() {
+                    plan.push(self.planned_dir(&path, &entry_relative));
+                }
+                self.plan_dir(client, &path, &entry_relative, listing, plan)?;
+            } else if !path.is_dir() {
+                warn!("Skipping unsupported file type: {:?}", path);
+            }
+        }
+        if self.syncer.options.delete_extraneous {
+            let mut extraneous: Vec<_> = remote
+                .iter()
+                .filter(|(name, _)| !src_names.contains(*name))
+                .collect();
+            extraneous.sort_by_key(|(name, _)| *name);
+            for (name, entry) in extraneous {
+                let entry_relative = relative.join(name);
+                if !self.is_filtered(&entry_relative, entry.is_dir) {
+                    plan.push(self.planned_delete(&entry_relative, entry.size, entry.is_dir));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn planned_delete(&self, relative: &Path, size: u64, is_dir: bool) -> PlannedOp {
+        PlannedOp {
+            action: PlanAction::Delete,
+            source: None,
+            destination: self.display_path(relative),
+            size,
+            is_dir,
+            reason: PlanReason::Extraneous,
+        }
+    }
+
+    fn plan_file(
+        &self,
+        path: &Path,
+        relative: &Path,
+        existing: Option<&RemoteEntry>,
+        plan: &mut Vec<PlannedOp>,
+    ) -> Result<()> {
+        let size = fs::metadata(path)
+            .with_context(|| format!("Failed to get metadata for source file: {:?}", path))?
+            .len();
+        let reason = match existing {
+            Some(existing) => self.quick_check(path, size, existing)?,
+            None => PlanReason::Missing,
+        };
+        plan.push(PlannedOp {
+            action: match reason {
+                PlanReason::Unchanged => PlanAction::Skip,
+                PlanReason::Missing => PlanAction::Create,
+                _ => PlanAction::Update,
+            },
+            source: Some(path.to_path_buf()),
+            destination: self.display_path(relative),
+            size,
+            is_dir: false,
+            reason,
+        });
+        Ok(())
+    }
+
+    /// The quick check against the properties recorded on upload: equal size and
+    /// modification time, or equal SHA-256 with `checksum`.
+    fn quick_check(&self, path: &Path, size: u64, existing: &RemoteEntry) -> Result<PlanReason> {
+        let options = &self.syncer.options;
+        if existing.size != size {
+            return Ok(PlanReason::SizeChanged);
+        }
+        if options.checksum {
+            let local = hex::encode(self.syncer.calculate_file_checksum(path)?);
+            return Ok(if existing.sha256.as_deref() == Some(local.as_str()) {
+                PlanReason::Unchanged
+            } else {
+                PlanReason::ChecksumChanged
+            });
+        }
+        if options.ignore_times {
+            return Ok(PlanReason::TimesIgnored);
+        }
+        let meta = fs::metadata(path)?;
+        let local = FileTime::from_last_modification_time(&meta);
+        let same = existing
+            .mtime
+            .as_deref()
+            .and_then(parse_mtime)
+            .is_some_and(|remote| self.syncer.mtimes_match(local, remote));
+        Ok(if same {
+            PlanReason::Unchanged
+        } else {
+            PlanReason::MtimeChanged
+        })
+    }
+
+    /// Path below the base collection of a planned destination, or an error if it lies
+    /// elsewhere.
+    fn relative<'a>(&self, destination: &'a Path) -> Result<&'a str> {
+        let base = self.location.url.trim_end_matches('/');
+        destination
+            .to_str()
+            .and_then(|destination| destination.strip_prefix(base))
+            .map(|rest| rest.trim_start_matches('/'))
+            .filter(|rest| !rest.split('/').any(|segment| segment == ".."))
+            .ok_or_else(|| SyncError::PathOutsideRoot(destination.to_path_buf()))
+    }
+
+    /// Carry out `plan` exactly as given, typically one returned by `plan`. Every path must
+    /// lie within this syncer's source and collection; the whole plan is checked before
+    /// anything is changed.
+    pub fn execute(&self, plan: &[PlannedOp]) -> Result<TransferResult> {
+        self.validate()?;
+        for op in plan {
+            self.relative(&op.destination)?;
+            match &op.source {
+                Some(source)
+                    if !source.starts_with(&self.source)
+                        || source.components().any(|c| c == Component::ParentDir) =>
+                {
+                    return Err(SyncError::PathOutsideRoot(source.clone()));
+                }
+                None if matches!(op.action, PlanAction::Create | PlanAction::Update) => {
+                    return Err(SyncError::ConflictingOptions(format!(
+                        "planned {:?} of {:?} has no source",
+                        op.action, op.destination
+                    )));
+                }
+                _ => {}
+            }
+        }
+
+        let client = self.client();
+        let mut result = TransferResult::default();
+        for op in plan {
+            self.syncer.check_cancelled()?;
+            let destination = op.destination.as_path();
+            let relative = percent_decode(self.relative(destination)?);
+            match (op.action, &op.source) {
+                (PlanAction::Create | PlanAction::Update, _) if op.is_dir => {
+                    client.mkcol(&relative)?;
+                }
+                (PlanAction::Create | PlanAction::Update, Some(source)) => {
+                    let action = if op.action == PlanAction::Create {
+                        FileAction::Created
+                    } else {
+                        FileAction::Updated
+                    };
+                    result.merge(self.upload(&client, source, &relative, destination, action)?);
+                }
+                (PlanAction::Skip, _) => {
+                    self.syncer.emit(|| SyncEvent::FileSkipped {
+                        path: destination.to_path_buf(),
+                    });
+                    result.merge(TransferResult::for_file(
+                        destination,
+                        FileAction::Skipped,
+                        0,
+                        op.size as usize,
+                    ));
+                }
+                (PlanAction::Delete, _) => {
+                    client.delete(&relative)?;
+                    self.syncer.emit(|| SyncEvent::Deleted {
+                        path: destination.to_path_buf(),
+                    });
+                    result.merge(TransferResult::for_file(
+                        destination,
+                        FileAction::Deleted,
+                        0,
+                        0,
+                    ));
+                }
+                (PlanAction::Create | PlanAction::Update, None) => unreachable!("checked above"),
+            }
+        }
+        Ok(result)
+    }
+
+    /// Send `path` to a temporary resource next to `relative`, record its properties and move
+    /// it into place.
+    fn upload(
+        &self,
+        client: &Client,
+        path: &Path,
+        relative: &str,
+        display: &Path,
+        action: FileAction,
+    ) -> Result<TransferResult> {
+        let meta = fs::metadata(path)
+            .with_context(|| format!("Failed to get metadata for source file: {:?}", path))?;
+        self.syncer.emit(|| SyncEvent::FileStarted {
+            path: display.to_path_buf(),
+            size: meta.len(),
+        });
+        let mtime = FileTime::from_last_modification_time(&meta);
+        let temp = format!("{}{}", relative, UPLOAD_SUFFIX);
+        let uploaded = (|| {
+            let sha256 = hex::encode(self.syncer.calculate_file_checksum(path)?);
+            let file = File::open(path)
+                .with_context(|| format!("Failed to open source file: {:?}", path))?;
+            client.put(&temp, file, meta.len(), &mtime)?;
+            let mtime = format!("{}.{:09}", mtime.unix_seconds(), mtime.nanoseconds());
+            client.set_properties(&temp, &mtime, &sha256)?;
+            client.rename(&temp, relative)
+        })();
+        if let Err(e) = uploaded {
+            let _ = client.delete(&temp);
+            self.syncer.emit(|| SyncEvent::Error {
+                path: display.to_path_buf(),
+                message: e.to_string(),
+            });
+            return Err(e);
+        }
+        let result = TransferResult::for_file(display, action, meta.len() as usize, 0);
+        for record in &result.files {
+            self.syncer
+                .emit(|| SyncEvent::FileCompleted(record.clone()));
+        }
+        Ok(result)
+    }
+}
+
+/// `relative` as a `/`-separated remote path.
+fn remote_path(relative: &Path) -> String {
+    let segments: Vec<_> = relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect();
+    segments.join("/")
+}
+
+/// Parse a modification time recorded as `seconds.nanos`.
+fn parse_mtime(value: &str) -> Option<FileTime> {
+    let (seconds, nanos) = value.split_once('.').unwrap_or((value, "0"));
+    Some(FileTime::from_unix_time(
+        seconds.parse().ok()?,
+        nanos.parse().ok()?,
+    ))
+}
@@ -0,0 +1,126 @@
+#![cfg(feature = "async")]
+
+use anyhow::Result;
+use rsynx::local_sync::LocalSyncer;
+use rsynx::network_sync::NetworkSyncer;
+use std::fs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+#[tokio::test]
+async fn test_local_sync_async() -> Result<()> {
+    let src = "test_async_local_src.txt";
+    let dst = "test_async_local_dst.txt";
+    fs::write(src, b"Synced from an async runtime")?;
+    fs::write(dst, b"Synced from a thread")?;
+
+    let result = LocalSyncer::new(src.to_string(), dst.to_string())
+        .with_block_size(4)
+        .sync_async()
+        .await?;
+    assert_eq!(fs::read(dst)?, b"Synced from an async runtime");
+    assert!(result.reused_bytes > 0);
+
+    fs::remove_file(src)?;
+    fs::remove_file(dst)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_network_sync_async() -> Result<()> {
+    let src = "test_async_net_src.txt";
+    let dst = "test_async_net_dst.txt";
+    fs::write(src, b"Network sync without spawn_blocking")?;
+    fs::write(dst, b"Network sync with spawn_blocking")?;
+
+    let listener = TcpListener::bind("127.0.0.1:7893").await?;
+    let server = tokio::spawn(NetworkSyncer::serve_once_async(listener, 4));
+    let result = NetworkSyncer::new(
+        "127.0.0.1".to_string(),
+        7893,
+        src.to_string(),
+        dst.to_string(),
+    )
+    .with_block_size(4)
+    .with_verify(true)
+    .sync_async()
+    .await?;
+    server.await??;
+
+    assert_eq!(fs::read(dst)?, b"Network sync without spawn_blocking");
+    assert!(result.reused_bytes > 0);
+
+    fs::remove_file(src)?;
+    fs::remove_file(dst)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_local_sync_async_directory() -> Result<()> {
+    let src = "test_async_dir_src";
+    let dst = "test_async_dir_dst";
+    let _ = fs::remove_dir_all(src);
+    let _ = fs::remove_dir_all(dst);
+    fs::create_dir_all(format!("{}/nested", src))?;
+    fs::write(format!("{}/top.txt", src), b"top level file")?;
+    fs::write(
+        format!("{}/nested/inner.txt", src),
+        b"nested file, synced too",
+    )?;
+
+    let syncer = LocalSyncer::new(src.to_string(), dst.to_string())
+        .with_block_size(4)
+        .with_preserve_metadata(true);
+    let result = syncer.sync_async().await?;
+    assert_eq!(result.created_files, 2);
+    assert_eq!(fs::read(format!("{}/top.txt", dst))?, b"top level file");
+    assert_eq!(
+        fs::read(format!("{}/nested/inner.txt", dst))?,
+        b"nested file, synced too"
+    );
+
+    // Times were preserved and nothing changed, so the second run skips both files
+    let result = syncer.sync_async().await?;
+    assert_eq!(result.skipped_files, 2);
+
+    fs::remove_dir_all(src)?;
+    fs::remove_dir_all(dst)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_serve_async_survives_failed_connection() -> Result<()> {
+    let src = "test_async_serve_src.txt";
+    let dst = "test_async_serve_dst.txt";
+    fs::write(src, b"Served after a broken client")?;
+    let _ = fs::remove_file(dst);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    let server = tokio::spawn(NetworkSyncer::serve_async(listener, 4));
+
+    // A client speaking nonsense fails its connection, and one hanging up mid-request too
+    let mut broken = TcpStream::connect(("127.0.0.1", port)).await?;
+    broken.write_all(b"HELLO\n").await?;
+    let mut reply = Vec::new();
+    broken.read_to_end(&mut reply).await?;
+    let mut dropped = TcpStream::connect(("127.0.0.1", port)).await?;
+    dropped.write_all(b"FILE 3").await?;
+    drop(dropped);
+
+    let client = NetworkSyncer::new(
+        "127.0.0.1".to_string(),
+        port,
+        src.to_string(),
+        dst.to_string(),
+    )
+    .with_block_size(4)
+    .with_verify(true);
+    tokio::spawn(async move { client.sync_async().await }).await??;
+    assert_eq!(fs::read(dst)?, b"Served after a broken client");
+
+    server.abort();
+    fs::remove_file(src)?;
+    fs::remove_file(dst)?;
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use rsynx::batch::{self, BatchEntry};
+use rsynx::error::SyncError;
+use rsynx::local_sync::LocalSyncer;
+use std::{
+    fs,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::Duration,
+};
+
+fn entry(source: &str, destination: &str) -> BatchEntry {
+    BatchEntry {
+        source: source.to_string(),
+        destination: destination.to_string(),
+    }
+}
+
+#[test]
+fn test_read_entries() {
+    let input =
+        "src/a dst/a\n\n# comment\nsrc/with space\tdst/with space\n  host:/in  remote::mod/out  \n";
+    let entries = batch::read_entries(input.as_bytes()).unwrap();
+    assert_eq!(
+        entries,
+        vec![
+            entry("src/a", "dst/a"),
+            entry("src/with space", "dst/with space"),
+            entry("host:/in", "remote::mod/out"),
+        ]
+    );
+
+    for bad in ["only-a-source\n", "a b c\n", "a\tb\tc\n"] {
+        assert!(matches!(
+            batch::read_entries(bad.as_bytes()),
+            Err(SyncError::Format(_))
+        ));
+    }
+}
+
+#[test]
+fn test_run_keeps_order_and_limits_jobs() {
+    let entries: Vec<_> = (0..8)
+        .map(|i| entry(&i.to_string(), &format!("dst{}", i)))
+        .collect();
+    let running = AtomicUsize::new(0);
+    let peak = AtomicUsize::new(0);
+    let results = batch::run(&entries, 3, |entry| {
+        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+        peak.fetch_max(now, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(20));
+        running.fetch_sub(1, Ordering::SeqCst);
+        entry.source.parse::<usize>().unwrap()
+    });
+    assert_eq!(results, (0..8).collect::<Vec<_>>());
+    assert!(peak.load(Ordering::SeqCst) <= 3);
+    assert!(peak.load(Ordering::SeqCst) > 1);
+}
+
+#[test]
+fn test_run_local_syncs_past_failures() {
+    let dir = "test_batch_sync";
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(dir).unwrap();
+    fs::write(format!("{}/one.txt", dir), b"First").unwrap();
+    fs::write(format!("{}/two.txt", dir), b"Second").unwrap();
+    let entries = vec![
+        entry(&format!("{}/one.txt", dir), &format!("{}/one.out", dir)),
+        entry(
+            &format!("{}/missing.txt", dir),
+            &format!("{}/missing.out", dir),
+        ),
+        entry(&format!("{}/two.txt", dir), &format!("{}/two.out", dir)),
+    ];
+
+    let results = batch::run(&entries, 2, |entry| {
+        LocalSyncer::new(&entry.source, &entry.destination).sync()
+    });
+
+    assert!(results[0].is_ok());
+    assert!(results[1].is_err());
+    assert!(results[2].is_ok());
+    assert_eq!(fs::read(format!("{}/one.out", dir)).unwrap(), b"First");
+    assert_eq!(fs::read(format!("{}/two.out", dir)).unwrap(), b"Second");
+
+    let _ = fs::remove_dir_all(dir);
+}
@@ -1,4 +1,6 @@
+use rand::Rng;
 use rsynx::local_sync::LocalSyncer;
+use rsynx::sync::Syncer;
 use std::{
     fs::{self, File},
     io::{Read, Write},
@@ -109,3 +111,24 @@ fn test_compression_small_file() {
 
     cleanup_test_files(&src_path, &dst_path);
 }
+
+#[test]
+fn test_compress_frame_skips_incompressible_data() {
+    let mut syncer = Syncer::new();
+    syncer.options.compress = true;
+
+    // Repetitive text shrinks, so it should be sent compressed
+    let text = b"This is a test file with some repeated content. ".repeat(20);
+    let compressed = syncer.compress_frame(&text).unwrap().unwrap();
+    assert!(compressed.len() < text.len());
+    assert_eq!(syncer.decompress_data(&compressed).unwrap(), text);
+
+    // Random bytes don't shrink, so the frame should be sent raw
+    let mut random = vec![0u8; 4096];
+    rand::rng().fill(&mut random[..]);
+    assert!(syncer.compress_frame(&random).unwrap().is_none());
+
+    // Nothing is compressed when compression is disabled
+    syncer.options.compress = false;
+    assert!(syncer.compress_frame(&text).unwrap().is_none());
+}
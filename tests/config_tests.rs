@@ -0,0 +1,80 @@
+use rsynx::config::{Config, ConfigOptions};
+use rsynx::error::SyncError;
+use std::fs;
+
+const CONFIG: &str = r#"
+block-size = 4096
+compress = true
+exclude = ["*.tmp"]
+
+[profiles.backups]
+destination = "backup-host:/srv/backups"
+block-size = 8192
+exclude = ["cache/"]
+
+[profiles.mirror]
+delete = true
+modify-window = 1
+"#;
+
+#[test]
+fn test_defaults_without_profile() {
+    let config = Config::parse(CONFIG).unwrap();
+    let options = config.resolve(None).unwrap();
+    assert_eq!(options.block_size, Some(4096));
+    assert_eq!(options.compress, Some(true));
+    assert_eq!(options.exclude, vec!["*.tmp".to_string()]);
+    assert_eq!(options.destination, None);
+    assert_eq!(config.profiles.len(), 2);
+}
+
+#[test]
+fn test_profile_overrides_defaults() {
+    let config = Config::parse(CONFIG).unwrap();
+    let options = config.resolve(Some("backups")).unwrap();
+    assert_eq!(options.block_size, Some(8192));
+    assert_eq!(options.compress, Some(true));
+    assert_eq!(
+        options.destination.as_deref(),
+        Some("backup-host:/srv/backups")
+    );
+    assert_eq!(options.exclude, vec!["*.tmp", "cache/"]);
+
+    let mirror = config.resolve(Some("mirror")).unwrap();
+    assert_eq!(mirror.delete, Some(true));
+    assert_eq!(mirror.modify_window, Some(1));
+    assert_eq!(mirror.block_size, Some(4096));
+}
+
+#[test]
+fn test_unknown_profile_and_bad_config_are_rejected() {
+    let config = Config::parse(CONFIG).unwrap();
+    assert!(matches!(
+        config.resolve(Some("missing")),
+        Err(SyncError::Config(_))
+    ));
+    assert!(matches!(
+        Config::parse("block-size = \"big\""),
+        Err(SyncError::Config(_))
+    ));
+}
+
+#[test]
+fn test_load_config_file() {
+    let path = "test_config_load.toml";
+    fs::write(path, "port = 9000\n").unwrap();
+    let config = Config::load(path.as_ref()).unwrap();
+    assert_eq!(
+        config.resolve(None).unwrap(),
+        ConfigOptions {
+            port: Some(9000),
+            ..Default::default()
+        }
+    );
+    let _ = fs::remove_file(path);
+
+    assert!(matches!(
+        Config::load("test_config_missing.toml".as_ref()),
+        Err(SyncError::Io { .. })
+    ));
+}
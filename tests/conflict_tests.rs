@@ -0,0 +1,90 @@
+use rsynx::conflict::{ConflictPolicy, STATE_FILE_NAME};
+use rsynx::local_sync::LocalSyncer;
+use std::{fs, path::Path};
+
+fn sync(src: &Path, dst: &Path, policy: ConflictPolicy) -> usize {
+    LocalSyncer::new(src, dst)
+        .with_delete_extraneous(true)
+        .with_conflicts(Some(policy))
+        .sync()
+        .unwrap()
+        .conflicts
+}
+
+#[test]
+fn test_destination_changes_are_conflicts() {
+    let dir = Path::new("test_conflicts");
+    let _ = fs::remove_dir_all(dir);
+    let (src, dst) = (dir.join("src"), dir.join("dst"));
+    fs::create_dir_all(src.join("sub")).unwrap();
+    fs::write(src.join("sub/edited.txt"), b"Original").unwrap();
+    fs::write(src.join("updated.txt"), b"Original").unwrap();
+    fs::write(src.join("removed.txt"), b"Original").unwrap();
+    assert_eq!(sync(&src, &dst, ConflictPolicy::Skip), 0);
+    assert!(dst.join(STATE_FILE_NAME).exists());
+
+    // Edited on both sides, removed from the source after an edit at the destination
+    fs::write(src.join("sub/edited.txt"), b"Source edit").unwrap();
+    fs::write(dst.join("sub/edited.txt"), b"Destination edit").unwrap();
+    fs::write(src.join("updated.txt"), b"Source only edit").unwrap();
+    fs::remove_file(src.join("removed.txt")).unwrap();
+    fs::write(dst.join("removed.txt"), b"Destination edit").unwrap();
+
+    // Skipped conflicts are still conflicts on the next run
+    for _ in 0..2 {
+        assert_eq!(sync(&src, &dst, ConflictPolicy::Skip), 2);
+        assert_eq!(
+            fs::read(dst.join("sub/edited.txt")).unwrap(),
+            b"Destination edit"
+        );
+        assert_eq!(
+            fs::read(dst.join("removed.txt")).unwrap(),
+            b"Destination edit"
+        );
+        assert_eq!(
+            fs::read(dst.join("updated.txt")).unwrap(),
+            b"Source only edit"
+        );
+    }
+
+    assert_eq!(sync(&src, &dst, ConflictPolicy::Rename), 2);
+    assert_eq!(
+        fs::read(dst.join("sub/edited.txt")).unwrap(),
+        b"Source edit"
+    );
+    assert_eq!(
+        fs::read(dst.join("sub/edited.txt.conflict-1")).unwrap(),
+        b"Destination edit"
+    );
+    assert!(!dst.join("removed.txt").exists());
+    assert_eq!(
+        fs::read(dst.join("removed.txt.conflict-1")).unwrap(),
+        b"Destination edit"
+    );
+    // Resolved, and the copies moved aside are never deleted as extraneous
+    assert_eq!(sync(&src, &dst, ConflictPolicy::Rename), 0);
+    assert!(dst.join("removed.txt.conflict-1").exists());
+
+    fs::write(dst.join("updated.txt"), b"Another destination edit").unwrap();
+    assert_eq!(sync(&src, &dst, ConflictPolicy::Overwrite), 1);
+    assert_eq!(
+        fs::read(dst.join("updated.txt")).unwrap(),
+        b"Source only edit"
+    );
+
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_conflict_policy_parsing() {
+    assert_eq!("skip".parse(), Ok(ConflictPolicy::Skip));
+    assert_eq!("rename".parse(), Ok(ConflictPolicy::Rename));
+    assert_eq!(ConflictPolicy::Overwrite.to_string(), "overwrite");
+    assert!("merge".parse::<ConflictPolicy>().is_err());
+    assert!(
+        LocalSyncer::new("Cargo.toml", "test_conflicts_file.toml")
+            .with_conflicts(Some(ConflictPolicy::Skip))
+            .validate()
+            .is_err()
+    );
+}
@@ -0,0 +1,477 @@
+use anyhow::Result;
+use rsynx::daemon::{Daemon, DaemonConfig, HostPattern};
+use rsynx::error::SyncError;
+use rsynx::network_sync::NetworkSyncer;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::thread;
+
+/// Sync `src` through `daemon` with `client`, returning both sides' results.
+fn sync_through(
+    daemon: &Daemon,
+    client: NetworkSyncer,
+) -> (
+    rsynx::error::Result<rsynx::sync::TransferResult>,
+    rsynx::error::Result<rsynx::sync::TransferResult>,
+) {
+    let (client_stream, server_stream) = UnixStream::pair().unwrap();
+    thread::scope(|scope| {
+        let server = scope.spawn(|| daemon.handle_connection(server_stream));
+        let client_result = client.with_block_size(4).sync_over(client_stream);
+        (client_result, server.join().unwrap())
+    })
+}
+
+fn setup(name: &str) -> (String, String) {
+    let src = format!("test_daemon_{}_src.txt", name);
+    let root = format!("test_daemon_{}_root", name);
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    fs::write(&src, b"Daemon module content").unwrap();
+    (src, root)
+}
+
+fn cleanup(src: &str, root: &str) {
+    let _ = fs::remove_file(src);
+    let _ = fs::remove_dir_all(root);
+}
+
+#[test]
+fn test_sync_into_module() -> Result<()> {
+    let (src, root) = setup("module");
+    let config = DaemonConfig {
+        block_size: 4,
+        ..Default::default()
+    }
+    .with_module("files", &root);
+    let daemon = Daemon::new(config)?;
+
+    let client = NetworkSyncer::new("", 0, &src, "nested.txt").with_module("files");
+    let (client_result, server_result) = sync_through(&daemon, client);
+    client_result?;
+    server_result?;
+    assert_eq!(
+        fs::read(Path::new(&root).join("nested.txt"))?,
+        b"Daemon module content"
+    );
+
+    let client = NetworkSyncer::new("", 0, &src, "../escaped.txt").with_module("files");
+    let (client_result, server_result) = sync_through(&daemon, client);
+    assert!(matches!(client_result, Err(SyncError::Refused(_))));
+    assert!(matches!(server_result, Err(SyncError::PathOutsideRoot(_))));
+
+    let client = NetworkSyncer::new("", 0, &src, "x.txt").with_module("missing");
+    let (client_result, _) = sync_through(&daemon, client);
+    assert!(matches!(client_result, Err(SyncError::Refused(_))));
+
+    // Clients of a daemon with modules can't bypass them with a plain path
+    let client = NetworkSyncer::new("", 0, &src, format!("{}/direct.txt", root));
+    let (client_result, server_result) = sync_through(&daemon, client);
+    assert!(matches!(client_result, Err(SyncError::Refused(_))));
+    assert!(matches!(server_result, Err(SyncError::Refused(_))));
+    assert!(!Path::new(&root).join("direct.txt").exists());
+
+    cleanup(&src, &root);
+    Ok(())
+}
+
+#[test]
+fn test_module_symlinks_are_not_followed() -> Result<()> {
+    let (src, root) = setup("symlink");
+    let outside = format!("{}_outside", root);
+    let _ = fs::remove_dir_all(&outside);
+    fs::create_dir_all(&outside)?;
+    fs::write(Path::new(&outside).join("secret.txt"), b"keep")?;
+    std::os::unix::fs::symlink(fs::canonicalize(&outside)?, Path::new(&root).join("link"))?;
+    std::os::unix::fs::symlink(
+        fs::canonicalize(&outside)?.join("secret.txt"),
+        Path::new(&root).join("file.txt"),
+    )?;
+    let config = DaemonConfig {
+        block_size: 4,
+        ..Default::default()
+    }
+    .with_module("files", &root);
+    let daemon = Daemon::new(config)?;
+
+    for destination in ["link/secret.txt", "link/new.txt", "file.txt"] {
+        let client = NetworkSyncer::new("", 0, &src, destination).with_module("files");
+        let (client_result, server_result) = sync_through(&daemon, client);
+        assert!(matches!(client_result, Err(SyncError::Refused(_))));
+        assert!(matches!(server_result, Err(SyncError::PathOutsideRoot(_))));
+    }
+    assert_eq!(fs::read(Path::new(&outside).join("secret.txt"))?, b"keep");
+    assert!(!Path::new(&outside).join("new.txt").exists());
+
+    let (client_stream, server_stream) = UnixStream::pair()?;
+    let listed = thread::scope(|scope| {
+        scope.spawn(|| daemon.handle_connection(server_stream));
+        NetworkSyncer::new("", 0, &src, "link")
+            .with_module("files")
+            .list_destination_over(client_stream, false)
+    });
+    assert!(matches!(listed, Err(SyncError::Refused(_))));
+
+    cleanup(&src, &root);
+    let _ = fs::remove_dir_all(&outside);
+    Ok(())
+}
+
+#[test]
+fn test_daemon_without_modules_refuses_listings() -> Result<()> {
+    let daemon = Daemon::new(DaemonConfig {
+        read_only: true,
+        ..Default::default()
+    })?;
+    let (client_stream, server_stream) = UnixStream::pair()?;
+    let (listed, served) = thread::scope(|scope| {
+        let server = scope.spawn(|| daemon.handle_connection(server_stream));
+        let listed =
+            NetworkSyncer::new("", 0, "", "/etc").list_destination_over(client_stream, true);
+        (listed, server.join().unwrap())
+    });
+    assert!(matches!(listed, Err(SyncError::Refused(_))));
+    assert!(matches!(served, Err(SyncError::Refused(_))));
+    Ok(())
+}
+
+#[test]
+fn test_preallocating_daemon() -> Result<()> {
+    let (src, root) = setup("preallocate");
+    let config = DaemonConfig {
+        block_size: 4,
+        preallocate: true,
+        ..Default::default()
+    }
+    .with_module("files", &root);
+    let daemon = Daemon::new(config)?;
+    fs::write(Path::new(&root).join("file.txt"), b"Daemon module old")?;
+
+    let client = NetworkSyncer::new("", 0, &src, "file.txt").with_module("files");
+    let (client_result, server_result) = sync_through(&daemon, client);
+    client_result?;
+    server_result?;
+    assert_eq!(
+        fs::read(Path::new(&root).join("file.txt"))?,
+        b"Daemon module content"
+    );
+    assert!(!Path::new(&root).join("file.tmp").exists());
+
+    // A size no disk holds is refused before anything is reserved or sent
+    let (mut client_stream, server_stream) = UnixStream::pair()?;
+    let served = thread::scope(|scope| {
+        let server = scope.spawn(|| daemon.handle_connection(server_stream));
+        write!(
+            client_stream,
+            "MODULE files\nFILE 3 6 {}\nsrchuge.t",
+            u64::MAX / 2
+        )
+        .unwrap();
+        server.join().unwrap()
+    });
+    let mut reply = String::new();
+    client_stream.read_to_string(&mut reply)?;
+    assert!(reply.starts_with("OK\nERROR "), "{}", reply);
+    assert!(matches!(served, Err(SyncError::Refused(_))));
+    assert!(!Path::new(&root).join("huge.t").exists());
+    assert!(!Path::new(&root).join("huge.tmp").exists());
+
+    cleanup(&src, &root);
+    Ok(())
+}
+
+#[test]
+fn test_module_authentication() -> Result<()> {
+    let (src, root) = setup("auth");
+    let secrets = format!("{}.secrets", root);
+    fs::write(&secrets, "alice:s3cret\nbob:hunter2\n")?;
+    fs::set_permissions(&secrets, fs::Permissions::from_mode(0o600))?;
+    let config = DaemonConfig::parse(&format!(
+        r#"
+        block-size = 4
+
+        [modules.private]
+        path = "{}"
+        auth-users = ["alice"]
+        secrets-file = "{}"
+        "#,
+        root, secrets
+    ))?;
+    let daemon = Daemon::new(config)?;
+    let client = || NetworkSyncer::new("", 0, &src, "secret.txt").with_module("private");
+
+    let (client_result, _) = sync_through(&daemon, client());
+    assert!(matches!(client_result, Err(SyncError::Refused(_))));
+
+    let (client_result, server_result) =
+        sync_through(&daemon, client().with_credentials("alice", "wrong"));
+    assert!(matches!(client_result, Err(SyncError::Refused(_))));
+    assert!(matches!(server_result, Err(SyncError::Refused(_))));
+
+    // Bob has a secret but isn't among the module's users
+    let (client_result, _) = sync_through(&daemon, client().with_credentials("bob", "hunter2"));
+    assert!(matches!(client_result, Err(SyncError::Refused(_))));
+    assert!(!Path::new(&root).join("secret.txt").exists());
+
+    let (client_result, server_result) =
+        sync_through(&daemon, client().with_credentials("alice", "s3cret"));
+    client_result?;
+    server_result?;
+    assert_eq!(
+        fs::read(Path::new(&root).join("secret.txt"))?,
+        b"Daemon module content"
+    );
+
+    let _ = fs::remove_file(&secrets);
+    cleanup(&src, &root);
+    Ok(())
+}
+
+#[test]
+fn test_invalid_daemon_configs_are_rejected() -> Result<()> {
+    let (src, root) = setup("invalid");
+    let secrets = format!("{}.secrets", root);
+    fs::write(&secrets, "alice:s3cret\n")?;
+    fs::set_permissions(&secrets, fs::Permissions::from_mode(0o644))?;
+    let with_auth = |secrets_file: Option<&str>| {
+        let mut config = DaemonConfig::default().with_module("private", &root);
+        let module = config.modules.get_mut("private").unwrap();
+        module.auth_users = vec!["alice".to_string()];
+        module.secrets_file = secrets_file.map(Into::into);
+        config
+    };
+
+    assert!(matches!(
+        Daemon::new(with_auth(None)),
+        Err(SyncError::Config(_))
+    ));
+    assert!(matches!(
+        Daemon::new(with_auth(Some(&secrets))),
+        Err(SyncError::Config(_))
+    ));
+    assert!(matches!(
+        Daemon::new(DaemonConfig::default().with_module("missing", "test_daemon_no_such_dir")),
+        Err(SyncError::Config(_))
+    ));
+    assert!(matches!(
+        Daemon::new(DaemonConfig::default().with_module("two words", &root)),
+        Err(SyncError::Config(_))
+    ));
+    assert!(matches!(
+        DaemonConfig::parse("port = \"high\""),
+        Err(SyncError::Config(_))
+    ));
+    assert!(matches!(
+        DaemonConfig::parse("[modules.m]\nhosts-allow = [\"10.0.0.0/33\"]"),
+        Err(SyncError::Config(_))
+    ));
+    let mut unlimited = DaemonConfig::default().with_module("files", &root);
+    unlimited.modules.get_mut("files").unwrap().max_connections = Some(0);
+    assert!(matches!(Daemon::new(unlimited), Err(SyncError::Config(_))));
+
+    let _ = fs::remove_file(&secrets);
+    cleanup(&src, &root);
+    Ok(())
+}
+
+#[test]
+fn test_metrics_count_connections() -> Result<()> {
+    let (src, root) = setup("metrics");
+    let daemon = Daemon::new(DaemonConfig::default().with_module("files", &root))?;
+
+    let client = NetworkSyncer::new("", 0, &src, "copy.txt").with_module("files");
+    let (client_result, server_result) = sync_through(&daemon, client);
+    client_result?;
+    server_result?;
+    let client = NetworkSyncer::new("", 0, &src, "../escaped.txt").with_module("files");
+    let _ = sync_through(&daemon, client);
+
+    let text = daemon.metrics().render();
+    for line in [
+        "rsynx_connections_total 2",
+        "rsynx_active_connections 0",
+        "rsynx_received_bytes_total 21",
+        "rsynx_files_written_total 1",
+        "rsynx_errors_total 1",
+        "rsynx_transfer_duration_seconds_count 1",
+        "rsynx_transfer_duration_seconds_bucket{le=\"+Inf\"} 1",
+    ] {
+        assert!(
+            text.lines().any(|l| l == line),
+            "{} missing from:\n{}",
+            line,
+            text
+        );
+    }
+
+    // Served over HTTP at /metrics only
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let address = listener.local_addr()?;
+    std::sync::Arc::new(rsynx::metrics::Metrics::new()).serve(listener);
+    let get = |path: &str| -> Result<String> {
+        let mut stream = std::net::TcpStream::connect(address)?;
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path)?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    };
+    let response = get("/metrics")?;
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.contains("rsynx_connections_total 0"));
+    assert!(get("/other")?.starts_with("HTTP/1.1 404"));
+
+    cleanup(&src, &root);
+    Ok(())
+}
+
+#[test]
+fn test_read_only_daemon() -> Result<()> {
+    let (src, root) = setup("read_only");
+    let config = DaemonConfig::parse(&format!(
+        r#"
+        block-size = 4
+        read-only = true
+
+        [modules.reference]
+        path = "{}"
+        "#,
+        root
+    ))?;
+    let daemon = Daemon::new(config)?;
+    assert!(
+        daemon
+            .server_info()
+            .features
+            .iter()
+            .any(|f| f == "read-only")
+    );
+    fs::write(Path::new(&root).join("data.txt"), b"Reference data")?;
+
+    for destination in ["data.txt", "new.txt"] {
+        let client = NetworkSyncer::new("", 0, &src, destination).with_module("reference");
+        let (client_result, server_result) = sync_through(&daemon, client);
+        assert!(matches!(client_result, Err(SyncError::Refused(_))));
+        assert!(matches!(server_result, Err(SyncError::Refused(_))));
+    }
+    assert_eq!(
+        fs::read(Path::new(&root).join("data.txt"))?,
+        b"Reference data"
+    );
+    assert!(!Path::new(&root).join("new.txt").exists());
+    assert!(!Path::new(&root).join("data.tmp").exists());
+
+    // Listing is still allowed
+    let (client_stream, server_stream) = UnixStream::pair()?;
+    let listing = thread::scope(|scope| {
+        let server = scope.spawn(|| daemon.handle_connection(server_stream));
+        let listing = NetworkSyncer::new("", 0, &src, ".")
+            .with_module("reference")
+            .list_destination_over(client_stream, false);
+        server.join().unwrap().map(|_| listing)
+    })??;
+    assert_eq!(listing.len(), 1);
+
+    cleanup(&src, &root);
+    Ok(())
+}
+
+#[test]
+fn test_host_patterns() {
+    let pattern = |s: &str| s.parse::<HostPattern>().unwrap();
+    let ip = |s: &str| s.parse().unwrap();
+    assert!(pattern("10.0.0.0/8").matches(ip("10.20.30.40")));
+    assert!(!pattern("10.0.0.0/8").matches(ip("11.0.0.1")));
+    assert!(pattern("192.168.1.7").matches(ip("192.168.1.7")));
+    assert!(!pattern("192.168.1.7").matches(ip("192.168.1.8")));
+    assert!(pattern("0.0.0.0/0").matches(ip("203.0.113.9")));
+    assert!(pattern("fd00::/8").matches(ip("fd12::1")));
+    assert!(!pattern("fd00::/8").matches(ip("10.0.0.1")));
+    // IPv4 clients of dual-stack listeners, and IPv4-mapped patterns
+    assert!(pattern("10.0.0.0/8").matches(ip("::ffff:10.1.2.3")));
+    assert!(pattern("::ffff:10.0.0.0/104").matches(ip("10.1.2.3")));
+    for invalid in ["", "10.0.0.0/33", "::/129", "example.com", "10.0.0.0/x"] {
+        assert!(invalid.parse::<HostPattern>().is_err(), "{:?}", invalid);
+    }
+}
+
+#[test]
+fn test_module_access_control() -> Result<()> {
+    let (src, root) = setup("access");
+    let reference = format!("{}_reference", root);
+    fs::create_dir_all(&reference)?;
+    let config = DaemonConfig::parse(&format!(
+        r#"
+        block-size = 4
+
+        [modules.internal]
+        path = "{}"
+        hosts-allow = ["10.0.0.0/8"]
+        hosts-deny = ["10.6.6.0/24"]
+        max-connections = 1
+
+        [modules.reference]
+        path = "{}"
+        read-only = true
+        "#,
+        root, reference
+    ))?;
+    let daemon = Daemon::new(config)?;
+    let sync_from = |module: &str, peer: Option<&str>| {
+        let (client_stream, server_stream) = UnixStream::pair().unwrap();
+        let peer = peer.map(|peer| peer.parse().unwrap());
+        thread::scope(|scope| {
+            let server = scope.spawn(|| daemon.handle_connection_from(server_stream, peer));
+            let client_result = NetworkSyncer::new("", 0, &src, "file.txt")
+                .with_module(module)
+                .with_block_size(4)
+                .sync_over(client_stream);
+            (client_result, server.join().unwrap())
+        })
+    };
+
+    // Outside the allow list, in the deny list, or of unknown address
+    for peer in [Some("192.168.1.2"), Some("10.6.6.6"), None] {
+        let (client_result, server_result) = sync_from("internal", peer);
+        assert!(matches!(client_result, Err(SyncError::Refused(_))));
+        assert!(matches!(server_result, Err(SyncError::Refused(_))));
+    }
+    assert!(!Path::new(&root).join("file.txt").exists());
+    let (client_result, server_result) = sync_from("internal", Some("10.1.2.3"));
+    client_result?;
+    server_result?;
+    assert_eq!(
+        fs::read(Path::new(&root).join("file.txt"))?,
+        b"Daemon module content"
+    );
+
+    // Read-only per module
+    let (client_result, _) = sync_from("reference", None);
+    assert!(matches!(client_result, Err(SyncError::Refused(_))));
+    assert!(!Path::new(&reference).join("file.txt").exists());
+
+    // A second client is refused while the first holds the only connection
+    let (mut held, held_server) = UnixStream::pair()?;
+    thread::scope(|scope| -> Result<()> {
+        let server = scope.spawn(|| {
+            daemon.handle_connection_from(held_server, Some("10.0.0.1".parse().unwrap()))
+        });
+        held.write_all(b"MODULE internal\n")?;
+        let mut reply = String::new();
+        BufReader::new(&held).read_line(&mut reply)?;
+        assert_eq!(reply, "OK\n");
+        let (client_result, _) = sync_from("internal", Some("10.1.2.3"));
+        assert!(matches!(client_result, Err(SyncError::Refused(_))));
+        drop(held);
+        assert!(server.join().unwrap().is_err());
+        Ok(())
+    })?;
+    let (client_result, _) = sync_from("internal", Some("10.1.2.3"));
+    client_result?;
+
+    let _ = fs::remove_dir_all(&reference);
+    cleanup(&src, &root);
+    Ok(())
+}
@@ -0,0 +1,72 @@
+// Hard links are only detected on unix; elsewhere deduplication is skipped
+#![cfg(unix)]
+
+use rsynx::dedup::link_duplicates;
+use rsynx::local_sync::LocalSyncer;
+use rsynx::sync::Syncer;
+use std::{fs, os::unix::fs::MetadataExt, path::Path};
+
+fn inode(path: &Path) -> u64 {
+    fs::metadata(path).unwrap().ino()
+}
+
+#[test]
+fn test_link_duplicates() {
+    let dir = Path::new("test_dedup_link");
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(dir.join("a/b")).unwrap();
+    fs::write(dir.join("one.txt"), b"Same content").unwrap();
+    fs::write(dir.join("a/two.txt"), b"Same content").unwrap();
+    fs::write(dir.join("a/b/three.txt"), b"Same content").unwrap();
+    fs::write(dir.join("other.txt"), b"Else content").unwrap();
+    fs::write(dir.join("empty1"), b"").unwrap();
+    fs::write(dir.join("empty2"), b"").unwrap();
+
+    let report = link_duplicates(dir, &Syncer::new()).unwrap();
+    assert_eq!(report.linked_files, 2);
+    assert_eq!(report.reclaimed_bytes, 24);
+    assert_eq!(inode(&dir.join("one.txt")), inode(&dir.join("a/two.txt")));
+    assert_eq!(
+        inode(&dir.join("one.txt")),
+        inode(&dir.join("a/b/three.txt"))
+    );
+    assert_ne!(inode(&dir.join("one.txt")), inode(&dir.join("other.txt")));
+    assert_ne!(inode(&dir.join("empty1")), inode(&dir.join("empty2")));
+    assert_eq!(fs::read(dir.join("a/two.txt")).unwrap(), b"Same content");
+
+    // Already linked files aren't counted again
+    assert_eq!(
+        link_duplicates(dir, &Syncer::new()).unwrap().linked_files,
+        0
+    );
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_sync_dedup_and_later_updates() {
+    let dir = Path::new("test_dedup_sync");
+    let _ = fs::remove_dir_all(dir);
+    let (src, dst) = (dir.join("src"), dir.join("dst"));
+    fs::create_dir_all(&src).unwrap();
+    fs::write(src.join("copy1.txt"), b"Duplicate").unwrap();
+    fs::write(src.join("copy2.txt"), b"Duplicate").unwrap();
+
+    let result = LocalSyncer::new(&src, &dst)
+        .with_dedup(true)
+        .sync()
+        .unwrap();
+    assert_eq!(result.deduplicated_files, 1);
+    assert_eq!(inode(&dst.join("copy1.txt")), inode(&dst.join("copy2.txt")));
+
+    // Updating one of the linked files leaves the other alone
+    fs::write(src.join("copy1.txt"), b"Changed!!").unwrap();
+    LocalSyncer::new(&src, &dst)
+        .with_ignore_times(true)
+        .sync()
+        .unwrap();
+    assert_eq!(fs::read(dst.join("copy1.txt")).unwrap(), b"Changed!!");
+    assert_eq!(fs::read(dst.join("copy2.txt")).unwrap(), b"Duplicate");
+
+    fs::remove_dir_all(dir).unwrap();
+}
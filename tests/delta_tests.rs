@@ -0,0 +1,368 @@
+use rand::Rng;
+use rsynx::delta::{BlockIndex, Delta, DeltaFormat, DeltaGenerator, DeltaOp, Signature};
+use rsynx::error::SyncError;
+use rsynx::sync::Syncer;
+use std::{fs, path::Path};
+
+fn syncer_with_block_size(block_size: usize) -> Syncer {
+    let mut syncer = Syncer::new();
+    syncer.options.block_size = block_size;
+    syncer
+}
+
+fn cleanup(paths: &[&str]) {
+    for path in paths {
+        let _ = fs::remove_file(path);
+    }
+}
+
+#[test]
+fn test_delta_roundtrip() {
+    let basis = "test_delta_basis";
+    let new_file = "test_delta_new";
+    let output = "test_delta_output";
+    fs::write(basis, b"The quick brown cat jumps over the lazy dog").unwrap();
+    fs::write(new_file, b"The quick brown fox jumps over the lazy dog!").unwrap();
+
+    let syncer = syncer_with_block_size(4);
+    let signature = syncer.generate_signature(Path::new(basis)).unwrap();
+    let delta = syncer
+        .generate_delta(&signature, Path::new(new_file))
+        .unwrap();
+    assert!(delta.copied_bytes() > 0);
+    assert_eq!(delta.target_len(), 44);
+
+    let result = syncer
+        .apply_delta(Path::new(basis), &delta, Path::new(output))
+        .unwrap();
+    assert_eq!(fs::read(output).unwrap(), fs::read(new_file).unwrap());
+    assert_eq!(result.reused_bytes, delta.copied_bytes());
+    assert_eq!(result.new_bytes, delta.literal_bytes());
+
+    cleanup(&[basis, new_file, output]);
+}
+
+#[test]
+fn test_delta_identical_files_copies_everything() {
+    let basis = "test_delta_identical_basis";
+    let new_file = "test_delta_identical_new";
+    let content = b"0123456789abcdef";
+    fs::write(basis, content).unwrap();
+    fs::write(new_file, content).unwrap();
+
+    let syncer = syncer_with_block_size(4);
+    let signature = syncer.generate_signature(Path::new(basis)).unwrap();
+    let delta = syncer
+        .generate_delta(&signature, Path::new(new_file))
+        .unwrap();
+    assert_eq!(delta.literal_bytes(), 0);
+    assert_eq!(
+        delta.ops.first(),
+        Some(&DeltaOp::Copy { offset: 0, len: 4 })
+    );
+
+    cleanup(&[basis, new_file]);
+}
+
+#[test]
+fn test_delta_against_missing_basis_is_all_literal() {
+    let basis = "test_delta_missing_basis";
+    let new_file = "test_delta_missing_new";
+    let output = "test_delta_missing_output";
+    let mut content = vec![0u8; 200_000];
+    rand::rng().fill(&mut content[..]);
+    fs::write(new_file, &content).unwrap();
+
+    // An empty signature stands in for a basis that doesn't exist yet
+    let syncer = syncer_with_block_size(512);
+    let signature = Signature::new(512, Vec::new());
+    let delta = syncer
+        .generate_delta(&signature, Path::new(new_file))
+        .unwrap();
+    assert_eq!(delta.literal_bytes(), content.len());
+
+    syncer
+        .apply_delta(Path::new(basis), &delta, Path::new(output))
+        .unwrap();
+    assert_eq!(fs::read(output).unwrap(), content);
+
+    cleanup(&[new_file, output]);
+}
+
+#[test]
+fn test_patch_from_serialized_delta_in_place() {
+    let basis = "test_patch_basis";
+    let new_file = "test_patch_new";
+    let delta_file = "test_patch_delta";
+    fs::write(basis, b"0123456789abcdefghij").unwrap();
+    fs::write(new_file, b"0123456789ABCDEFghij++").unwrap();
+
+    let syncer = syncer_with_block_size(4);
+    let signature = syncer.generate_signature(Path::new(basis)).unwrap();
+    let delta = syncer
+        .generate_delta(&signature, Path::new(new_file))
+        .unwrap();
+    delta
+        .write_to(fs::File::create(delta_file).unwrap(), DeltaFormat::Native)
+        .unwrap();
+
+    let parsed = Delta::read_from(fs::File::open(delta_file).unwrap()).unwrap();
+    assert_eq!(parsed.ops, delta.ops);
+    assert_eq!(parsed.checksum, delta.checksum);
+
+    // Patching the basis in place replaces it atomically with the new content
+    let result = syncer
+        .patch(
+            Path::new(basis),
+            fs::File::open(delta_file).unwrap(),
+            Path::new(basis),
+        )
+        .unwrap();
+    assert_eq!(fs::read(basis).unwrap(), fs::read(new_file).unwrap());
+    assert_eq!(result.reused_bytes, delta.copied_bytes());
+
+    cleanup(&[basis, new_file, delta_file]);
+}
+
+#[test]
+fn test_patch_rejects_checksum_mismatch() {
+    let basis = "test_patch_mismatch_basis";
+    let new_file = "test_patch_mismatch_new";
+    let output = "test_patch_mismatch_output";
+    fs::write(basis, b"0123456789abcdef").unwrap();
+    fs::write(new_file, b"0123456789ABCDEF").unwrap();
+
+    let syncer = syncer_with_block_size(4);
+    let signature = syncer.generate_signature(Path::new(basis)).unwrap();
+    let delta = syncer
+        .generate_delta(&signature, Path::new(new_file))
+        .unwrap();
+
+    // The basis changed after the signature was taken, so copied blocks are now wrong
+    fs::write(basis, b"XXXX456789abcdef").unwrap();
+    let mut serialized = Vec::new();
+    delta
+        .write_to(&mut serialized, DeltaFormat::Native)
+        .unwrap();
+    let err = syncer
+        .patch(Path::new(basis), &serialized[..], Path::new(output))
+        .unwrap_err();
+    assert!(err.to_string().contains("Checksum mismatch"));
+    assert!(!Path::new(output).exists());
+    assert!(!Path::new("test_patch_mismatch_output.tmp").exists());
+
+    cleanup(&[basis, new_file]);
+}
+
+#[test]
+fn test_patch_rejects_garbage_stream() {
+    let syncer = syncer_with_block_size(4);
+    let err = syncer
+        .patch(
+            Path::new("test_patch_garbage_basis"),
+            &b"not a delta"[..],
+            Path::new("test_patch_garbage_output"),
+        )
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Not an rsynx or rdiff delta stream")
+    );
+}
+
+#[test]
+fn test_delta_generator_is_independent_of_chunking() {
+    let mut basis = vec![0u8; 20_000];
+    rand::rng().fill(&mut basis[..]);
+    let mut new_content = basis[5_000..].to_vec();
+    new_content.extend_from_slice(&basis[..5_003]);
+
+    let syncer = syncer_with_block_size(64);
+    let blocks = basis
+        .chunks(64)
+        .enumerate()
+        .map(|(i, chunk)| rsynx::sync::Block {
+            offset: (i * 64) as u64,
+            size: chunk.len(),
+            weak_checksum: syncer.calculate_weak_checksum(chunk),
+            strong_checksum: syncer.calculate_strong_checksum(chunk),
+        })
+        .collect();
+    let signature = Signature::new(64, blocks);
+    let index = BlockIndex::new(&signature);
+
+    let run = |chunk_size: usize| {
+        let mut generator = DeltaGenerator::new(&index);
+        let mut ops = Vec::new();
+        let mut emit = |op| {
+            ops.push(op);
+            Ok::<(), SyncError>(())
+        };
+        for chunk in new_content.chunks(chunk_size) {
+            generator.feed(chunk, &mut emit).unwrap();
+        }
+        generator.finish(&mut emit).unwrap();
+        ops
+    };
+
+    let whole = run(new_content.len());
+    assert_eq!(run(1), whole);
+    assert_eq!(run(100), whole);
+    assert_eq!(run(4096), whole);
+    let copied: usize = whole
+        .iter()
+        .map(|op| match op {
+            DeltaOp::Copy { len, .. } => *len,
+            DeltaOp::Literal(_) => 0,
+        })
+        .sum();
+    assert!(copied >= 19_000);
+}
+
+fn native_signature(syncer: &Syncer, basis: &[u8], block_size: usize) -> Signature {
+    let blocks = basis
+        .chunks(block_size)
+        .enumerate()
+        .map(|(i, chunk)| rsynx::sync::Block {
+            offset: (i * block_size) as u64,
+            size: chunk.len(),
+            weak_checksum: syncer.calculate_weak_checksum(chunk),
+            strong_checksum: syncer.calculate_strong_checksum(chunk),
+        })
+        .collect();
+    Signature::new(block_size, blocks)
+}
+
+fn delta_ops(signature: &Signature, new_content: &[u8]) -> Vec<DeltaOp> {
+    let index = BlockIndex::new(signature);
+    let mut generator = DeltaGenerator::new(&index);
+    let mut ops = Vec::new();
+    let mut emit = |op| {
+        ops.push(op);
+        Ok::<(), SyncError>(())
+    };
+    generator.feed(new_content, &mut emit).unwrap();
+    generator.finish(&mut emit).unwrap();
+    ops
+}
+
+#[test]
+fn test_matcher_prefers_sequential_duplicate_blocks() {
+    let syncer = syncer_with_block_size(4);
+    let basis = b"AAAABBBBAAAABBBB";
+    let signature = native_signature(&syncer, basis, 4);
+
+    // Identical blocks are copied from consecutive basis offsets, not the first duplicate
+    let ops = delta_ops(&signature, basis);
+    let offsets: Vec<u64> = ops
+        .iter()
+        .map(|op| match op {
+            DeltaOp::Copy { offset, len: 4 } => *offset,
+            other => panic!("unexpected op {:?}", other),
+        })
+        .collect();
+    assert_eq!(offsets, vec![0, 4, 8, 12]);
+}
+
+#[test]
+fn test_matcher_rejects_blocks_shorter_than_window() {
+    let syncer = syncer_with_block_size(4);
+    let window = b"WXYZ";
+
+    // A short block claiming the window's checksums must not be accepted as a match
+    let signature = Signature::new(
+        4,
+        vec![rsynx::sync::Block {
+            offset: 0,
+            size: 2,
+            weak_checksum: syncer.calculate_weak_checksum(window),
+            strong_checksum: syncer.calculate_strong_checksum(window),
+        }],
+    );
+    let ops = delta_ops(&signature, window);
+    assert_eq!(ops, vec![DeltaOp::Literal(window.to_vec())]);
+}
+
+#[test]
+fn test_errors_are_typed() {
+    let syncer = syncer_with_block_size(4);
+    let err = syncer
+        .generate_signature(Path::new("test_delta_no_such_basis"))
+        .unwrap_err();
+    match err {
+        SyncError::Io { context, source } => {
+            assert!(context.contains("test_delta_no_such_basis"));
+            assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+        }
+        other => panic!("expected an I/O error, got {:?}", other),
+    }
+
+    let err = Delta::read_from(&b"RSXD\x07"[..]).unwrap_err();
+    assert!(matches!(err, SyncError::Format(_)));
+}
+
+#[test]
+fn test_in_memory_roundtrip() {
+    let basis = b"The quick brown cat jumps over the lazy dog";
+    let target = b"The quick brown fox jumps over the lazy dog!";
+    let syncer = syncer_with_block_size(4);
+
+    let signature = syncer.signature_of(basis).unwrap();
+    let delta = syncer.delta_of(&signature, target).unwrap();
+    assert!(delta.copied_bytes() > 0);
+    assert_eq!(syncer.patch_bytes(basis, &delta).unwrap(), target);
+
+    let mut output = Vec::new();
+    let result = syncer
+        .apply_delta_to(std::io::Cursor::new(&basis[..]), &delta, &mut output)
+        .unwrap();
+    assert_eq!(output, target);
+    assert_eq!(result.reused_bytes, delta.copied_bytes());
+
+    let err = syncer.patch_bytes(b"The quick brown", &delta).unwrap_err();
+    assert!(matches!(err, SyncError::Io { .. }));
+}
+
+#[test]
+fn test_signature_serde_roundtrip() {
+    let syncer = syncer_with_block_size(4);
+    let signature = syncer.signature_of(b"The quick brown fox").unwrap();
+    let json = serde_json::to_value(&signature).unwrap();
+    assert_eq!(json["version"], rsynx::delta::SIGNATURE_SERDE_VERSION);
+    assert_eq!(json["weak_hash"], "adler");
+
+    let decoded: Signature = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!(decoded.block_size, 4);
+    assert_eq!(decoded.blocks.len(), signature.blocks.len());
+    let delta = syncer.delta_of(&decoded, b"The quick brown cat").unwrap();
+    assert!(delta.copied_bytes() > 0);
+    assert_eq!(
+        syncer.patch_bytes(b"The quick brown fox", &delta).unwrap(),
+        b"The quick brown cat"
+    );
+
+    let mut future = json;
+    future["version"] = 99.into();
+    assert!(serde_json::from_value::<Signature>(future).is_err());
+}
+
+#[test]
+fn test_core_matches_syncer() {
+    use rsynx::core::{StrongHash, WeakHash};
+
+    let basis = b"The quick brown cat jumps over the lazy dog";
+    let target = b"The quick brown fox jumps over the lazy dog!";
+    let syncer = syncer_with_block_size(4);
+
+    let signature = Signature::from_bytes(basis, 4, WeakHash::Adler, StrongHash::Sha256);
+    let expected = syncer.signature_of(basis).unwrap();
+    assert_eq!(signature.blocks.len(), expected.blocks.len());
+    for (block, expected) in signature.blocks.iter().zip(&expected.blocks) {
+        assert_eq!(block.weak_checksum, expected.weak_checksum);
+        assert_eq!(block.strong_checksum, expected.strong_checksum);
+    }
+
+    let delta = Delta::from_bytes(&signature, target);
+    assert_eq!(delta.ops, syncer.delta_of(&signature, target).unwrap().ops);
+    assert_eq!(syncer.patch_bytes(basis, &delta).unwrap(), target);
+}
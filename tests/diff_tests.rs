@@ -0,0 +1,146 @@
+use anyhow::Result;
+use filetime::{FileTime, set_file_mtime};
+use rsynx::{
+    daemon::{Daemon, DaemonConfig},
+    diff::{DiffEntry, DiffStatus},
+    filter::Filter,
+    manifest,
+    network_sync::NetworkSyncer,
+    options::SyncOptions,
+    plan::PlanReason,
+    sync::Syncer,
+};
+use std::{fs, os::unix::net::UnixStream, path::Path, thread};
+
+fn entry(path: &str, status: DiffStatus, reason: Option<PlanReason>, is_dir: bool) -> DiffEntry {
+    DiffEntry {
+        path: path.to_string(),
+        status,
+        reason,
+        is_dir,
+    }
+}
+
+#[test]
+fn test_diff_local_trees() -> Result<()> {
+    let dir = Path::new("test_diff_local");
+    let _ = fs::remove_dir_all(dir);
+    let (a, b) = (dir.join("a"), dir.join("b"));
+    for root in [&a, &b] {
+        fs::create_dir_all(root.join("shared"))?;
+        fs::write(root.join("same.txt"), b"Same")?;
+        fs::write(root.join("touched.txt"), b"Same size")?;
+    }
+    fs::write(a.join("shared/resized.txt"), b"Short")?;
+    fs::write(b.join("shared/resized.txt"), b"Longer")?;
+    fs::create_dir_all(a.join("only_a/nested"))?;
+    fs::write(a.join("only_a/nested/file.txt"), b"Nested")?;
+    fs::write(b.join("only_b.txt"), b"B")?;
+    fs::write(a.join("kind"), b"File in A")?;
+    fs::create_dir_all(b.join("kind"))?;
+    fs::write(b.join("kind/inside.txt"), b"Inside")?;
+    fs::write(b.join("skipped.log"), b"Excluded")?;
+    let time = FileTime::from_unix_time(1_700_000_000, 0);
+    for root in [&a, &b] {
+        set_file_mtime(root.join("same.txt"), time)?;
+        set_file_mtime(root.join("touched.txt"), time)?;
+    }
+    set_file_mtime(
+        b.join("touched.txt"),
+        FileTime::from_unix_time(1_700_000_001, 0),
+    )?;
+
+    let filter = Filter::new().exclude("*.log");
+    let options = SyncOptions::new().with_filter(filter.clone());
+    let list = |root: &Path, hash| manifest::build(root, &Filter::new(), hash).unwrap();
+    let differences =
+        Syncer::with_options(options.clone()).diff_listings(&list(&a, false), &list(&b, false));
+    assert_eq!(
+        differences,
+        vec![
+            entry(
+                "kind",
+                DiffStatus::Differs,
+                Some(PlanReason::TypeChanged),
+                false
+            ),
+            entry("only_a", DiffStatus::OnlyInA, None, true),
+            entry("only_b.txt", DiffStatus::OnlyInB, None, false),
+            entry(
+                "shared/resized.txt",
+                DiffStatus::Differs,
+                Some(PlanReason::SizeChanged),
+                false
+            ),
+            entry(
+                "touched.txt",
+                DiffStatus::Differs,
+                Some(PlanReason::MtimeChanged),
+                false
+            ),
+        ]
+    );
+    assert_eq!(differences[0].to_string(), "differs kind (type_changed)");
+    assert_eq!(differences[1].to_string(), "only-a  only_a/");
+
+    // Within the modify window, or compared by content, the touched file matches
+    let relaxed = Syncer::with_options(options.clone().with_modify_window(1));
+    assert!(
+        !relaxed
+            .diff_listings(&list(&a, false), &list(&b, false))
+            .iter()
+            .any(|entry| entry.path == "touched.txt")
+    );
+    let checksum = Syncer::with_options(options.with_checksum(true));
+    assert!(
+        !checksum
+            .diff_listings(&list(&a, true), &list(&b, true))
+            .iter()
+            .any(|entry| entry.path == "touched.txt")
+    );
+
+    fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_list_remote_destination() -> Result<()> {
+    let root = Path::new("test_diff_remote");
+    let _ = fs::remove_dir_all(root);
+    fs::create_dir_all(root.join("sub"))?;
+    fs::write(root.join("sub/file.txt"), b"Listed")?;
+
+    let (client, server) = UnixStream::pair()?;
+    let server_handle = thread::spawn(move || NetworkSyncer::handle_connection(server, 1024));
+    let listing =
+        NetworkSyncer::new("localhost", 0, "", root).list_destination_over(client, true)?;
+    server_handle.join().expect("Server thread panicked")?;
+    assert_eq!(listing, manifest::build(root, &Filter::new(), true)?);
+
+    let daemon = Daemon::new(DaemonConfig::default().with_module("files", root))?;
+    let list_module = |path: &str| {
+        let (client, server) = UnixStream::pair().unwrap();
+        thread::scope(|scope| {
+            let server_handle = scope.spawn(|| daemon.handle_connection(server));
+            let listing = NetworkSyncer::new("localhost", 0, "", path)
+                .with_module("files")
+                .list_destination_over(client, false);
+            let _ = server_handle.join().expect("Server thread panicked");
+            listing
+        })
+    };
+    let listing = list_module("")?;
+    assert_eq!(
+        listing
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect::<Vec<_>>(),
+        ["sub", "sub/file.txt"]
+    );
+    assert_eq!(listing[1].sha256, None);
+    assert_eq!(list_module("sub")?.len(), 1);
+    assert!(list_module("../outside").is_err());
+
+    fs::remove_dir_all(root)?;
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use rand::Rng;
+use rsynx::direct::{DirectReader, ReadMode};
+use rsynx::local_sync::LocalSyncer;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+#[test]
+fn test_uncached_reads_match_the_file() {
+    let path = Path::new("test_direct_reads.bin");
+    let mut content = vec![0u8; 3 * 1024 * 1024 + 123];
+    rand::rng().fill(&mut content[..]);
+    fs::write(path, &content).unwrap();
+
+    let mut reader = DirectReader::open(path, true).unwrap();
+    assert_ne!(reader.mode(), ReadMode::Cached);
+    let mut all = Vec::new();
+    reader.read_to_end(&mut all).unwrap();
+    assert_eq!(all, content);
+
+    // Unaligned ranges on either side of a buffer boundary, and past the end
+    for (offset, len) in [(5000, 100), (1024 * 1024 - 7, 20), (3, 2 * 1024 * 1024)] {
+        let mut buf = vec![0u8; len];
+        reader.seek(SeekFrom::Start(offset as u64)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, content[offset..offset + len]);
+    }
+    reader.seek(SeekFrom::End(-10)).unwrap();
+    let mut tail = Vec::new();
+    reader.read_to_end(&mut tail).unwrap();
+    assert_eq!(tail, content[content.len() - 10..]);
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_direct_io_sync() {
+    let src = Path::new("test_direct_sync_src.bin");
+    let dst = Path::new("test_direct_sync_dst.bin");
+    let mut content = vec![0u8; 2 * 1024 * 1024];
+    rand::rng().fill(&mut content[..]);
+    fs::write(src, &content).unwrap();
+    content[1000..2000].fill(0);
+    fs::write(dst, &content).unwrap();
+
+    let result = LocalSyncer::new(src, dst)
+        .with_direct_io(true)
+        .with_verify(true)
+        .sync()
+        .unwrap();
+    assert!(result.reused_bytes > 0);
+    assert_eq!(fs::read(dst).unwrap(), fs::read(src).unwrap());
+
+    fs::remove_file(src).unwrap();
+    fs::remove_file(dst).unwrap();
+}
@@ -0,0 +1,118 @@
+#![cfg(feature = "encryption")]
+
+use rsynx::{
+    encryption::StoreKey,
+    store::{CHUNK_SIZE, ChunkStore, StoreSyncer},
+};
+use std::{fs, path::Path};
+
+/// Every file in `dir` and below, read whole.
+fn contents(dir: &Path) -> Vec<Vec<u8>> {
+    let mut found = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            found.extend(contents(&path));
+        } else {
+            found.push(fs::read(path).unwrap());
+        }
+    }
+    found
+}
+
+#[test]
+fn test_seal_and_open() {
+    let key = StoreKey::new(&[7; 32]);
+    let sealed = key.seal(b"Secret", b"label");
+    assert_eq!(key.open(&sealed, b"label").unwrap(), b"Secret");
+    assert_ne!(key.seal(b"Secret", b"label"), sealed);
+
+    assert!(key.open(&sealed, b"other label").is_err());
+    assert!(StoreKey::new(&[8; 32]).open(&sealed, b"label").is_err());
+    let mut tampered = sealed.clone();
+    tampered[14] ^= 1;
+    assert!(key.open(&tampered, b"label").is_err());
+    assert!(key.open(&sealed[..10], b"label").is_err());
+
+    assert_eq!(key.name(b"Chunk"), key.name(b"Chunk"));
+    assert_ne!(key.name(b"Chunk"), StoreKey::new(&[8; 32]).name(b"Chunk"));
+}
+
+#[test]
+fn test_encrypted_store_round_trip() {
+    let dir = Path::new("test_encrypted_store");
+    let _ = fs::remove_dir_all(dir);
+    let source = dir.join("src");
+    fs::create_dir_all(source.join("private")).unwrap();
+    let mut data: Vec<u8> = (0..3 * CHUNK_SIZE).map(|i| (i % 251) as u8).collect();
+    fs::write(source.join("private/secret.bin"), &data).unwrap();
+    fs::write(dir.join("key"), format!("{}\n", "ab".repeat(32))).unwrap();
+    let key = StoreKey::read_from(&dir.join("key")).unwrap();
+    let store_spec = format!("store://{}", dir.join("store").display());
+    let syncer = StoreSyncer::new(source.to_str().unwrap(), &store_spec)
+        .with_recursive(true)
+        .with_delete_extraneous(true)
+        .with_encryption_key(key.clone());
+
+    let result = syncer.sync().unwrap();
+    assert_eq!(result.created_files, 1);
+    assert_eq!(result.new_bytes, data.len());
+
+    // Neither names nor contents are visible in the store
+    let stored = contents(&dir.join("store"));
+    assert_eq!(stored.len(), 1 + 1 + 3);
+    assert!(stored.iter().all(|file| {
+        !file.windows(6).any(|window| window == b"secret")
+            && !file.windows(64).any(|window| window == &data[..64])
+    }));
+    assert!(!dir.join("store/files/private").exists());
+
+    // Changing one chunk only stores that chunk again
+    data[CHUNK_SIZE + 10] ^= 0xff;
+    fs::write(source.join("private/secret.bin"), &data).unwrap();
+    let result = syncer.sync().unwrap();
+    assert_eq!(result.updated_files, 1);
+    assert_eq!(result.new_bytes, CHUNK_SIZE);
+    assert_eq!(result.reused_bytes, 2 * CHUNK_SIZE);
+
+    let store = ChunkStore::new(dir.join("store")).with_key(key.clone());
+    assert_eq!(
+        store.files().unwrap(),
+        vec![Path::new("private/secret.bin").to_path_buf()]
+    );
+
+    let restored = dir.join("restored");
+    StoreSyncer::new(&store_spec, restored.to_str().unwrap())
+        .with_recursive(true)
+        .with_encryption_key(key)
+        .sync()
+        .unwrap();
+    assert_eq!(fs::read(restored.join("private/secret.bin")).unwrap(), data);
+
+    // Restoring needs the right key, and the key can't be added to a plain store
+    assert!(
+        StoreSyncer::new(&store_spec, restored.to_str().unwrap())
+            .with_recursive(true)
+            .sync()
+            .is_err()
+    );
+    assert!(
+        StoreSyncer::new(&store_spec, restored.to_str().unwrap())
+            .with_encryption_key(StoreKey::new(&[1; 32]))
+            .sync()
+            .is_err()
+    );
+    let plain_spec = format!("store://{}", dir.join("plain").display());
+    StoreSyncer::new(source.to_str().unwrap(), &plain_spec)
+        .with_recursive(true)
+        .sync()
+        .unwrap();
+    assert!(
+        StoreSyncer::new(source.to_str().unwrap(), &plain_spec)
+            .with_encryption_key(StoreKey::new(&[1; 32]))
+            .sync()
+            .is_err()
+    );
+
+    let _ = fs::remove_dir_all(dir);
+}
@@ -0,0 +1,68 @@
+#![cfg(feature = "ffi")]
+
+use rsynx::ffi::*;
+use std::ffi::{CStr, CString, c_void};
+use std::fs;
+use std::ptr;
+
+unsafe extern "C" fn count_progress(_done: u64, _total: u64, user_data: *mut c_void) {
+    unsafe { *(user_data as *mut u32) += 1 };
+}
+
+#[test]
+fn test_ffi_sync_and_delta() {
+    let basis = "test_ffi_basis";
+    let new_file = "test_ffi_new";
+    let output = "test_ffi_output";
+    let synced = "test_ffi_synced";
+    fs::write(basis, b"The quick brown cat jumps over the lazy dog").unwrap();
+    fs::write(new_file, b"The quick brown fox jumps over the lazy dog!").unwrap();
+    let c = |s: &str| CString::new(s).unwrap();
+
+    unsafe {
+        let mut calls = 0u32;
+        let mut stats = RsynxStats::default();
+        let status = rsynx_sync_local(
+            c(new_file).as_ptr(),
+            c(synced).as_ptr(),
+            0,
+            Some(count_progress),
+            &mut calls as *mut u32 as *mut c_void,
+            &mut stats,
+        );
+        assert_eq!(status, RsynxStatus::Ok);
+        assert!(calls > 0);
+        assert_eq!(stats.new_bytes, 44);
+        assert_eq!(fs::read(synced).unwrap(), fs::read(new_file).unwrap());
+
+        let signature = rsynx_signature_new(c(basis).as_ptr(), 4);
+        assert!(!signature.is_null());
+        let delta = rsynx_delta_new(signature, c(new_file).as_ptr());
+        assert!(!delta.is_null());
+        let status = rsynx_delta_apply(c(basis).as_ptr(), delta, c(output).as_ptr(), &mut stats);
+        assert_eq!(status, RsynxStatus::Ok);
+        assert!(stats.reused_bytes > 0);
+        assert_eq!(fs::read(output).unwrap(), fs::read(new_file).unwrap());
+        rsynx_delta_free(delta);
+        rsynx_signature_free(signature);
+
+        let missing = rsynx_signature_new(c("test_ffi_no_such_basis").as_ptr(), 4);
+        assert!(missing.is_null());
+        let message = CStr::from_ptr(rsynx_last_error()).to_str().unwrap();
+        assert!(message.contains("test_ffi_no_such_basis"));
+
+        let status = rsynx_sync_local(
+            ptr::null(),
+            c(synced).as_ptr(),
+            0,
+            None,
+            ptr::null_mut(),
+            ptr::null_mut(),
+        );
+        assert_eq!(status, RsynxStatus::InvalidArgument);
+    }
+
+    for path in [basis, new_file, output, synced] {
+        let _ = fs::remove_file(path);
+    }
+}
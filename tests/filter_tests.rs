@@ -0,0 +1,80 @@
+use rsynx::filter::{Filter, FilterRule};
+use rsynx::local_sync::LocalSyncer;
+use std::{fs, path::Path};
+
+#[test]
+fn test_filter_patterns() {
+    let excluded = |pattern: &str, path: &str, is_dir: bool| {
+        Filter::new()
+            .exclude(pattern)
+            .is_excluded(Path::new(path), is_dir)
+    };
+    assert!(excluded("*.log", "app.log", false));
+    assert!(excluded("*.log", "logs/nested/app.log", false));
+    assert!(!excluded("*.log", "app.log.txt", false));
+    assert!(excluded("/build", "build", true));
+    assert!(!excluded("/build", "src/build", true));
+    assert!(excluded("cache/", "a/cache", true));
+    assert!(!excluded("cache/", "a/cache", false));
+    assert!(excluded("docs/*.md", "project/docs/README.md", false));
+    assert!(!excluded("docs/*.md", "docs/api/README.md", false));
+    assert!(excluded("docs/**.md", "docs/api/README.md", false));
+    assert!(excluded("file?.[ch]", "file1.c", false));
+    assert!(!excluded("file?.[!ch]", "file1.c", false));
+
+    let filter = Filter::new().include("keep.log").exclude("*.log");
+    assert!(!filter.is_excluded(Path::new("keep.log"), false));
+    assert!(filter.is_excluded(Path::new("other.log"), false));
+}
+
+#[test]
+fn test_read_rules() {
+    let path = "test_filter_rules";
+    fs::write(path, "# comment\n\n*.tmp\n+ important.tmp\n- /scratch/\n").unwrap();
+    let rules = Filter::read_rules(Path::new(path)).unwrap();
+    assert_eq!(
+        rules,
+        vec![
+            FilterRule::exclude("*.tmp"),
+            FilterRule::include("important.tmp"),
+            FilterRule::exclude("/scratch/"),
+        ]
+    );
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_sync_with_filter() {
+    let src = "test_filter_src";
+    let dst = "test_filter_dst";
+    let _ = fs::remove_dir_all(src);
+    let _ = fs::remove_dir_all(dst);
+    fs::create_dir_all(format!("{}/target", src)).unwrap();
+    fs::create_dir_all(dst).unwrap();
+    fs::write(format!("{}/main.rs", src), b"fn main() {}").unwrap();
+    fs::write(format!("{}/debug.log", src), b"noise").unwrap();
+    fs::write(format!("{}/keep.log", src), b"kept").unwrap();
+    fs::write(format!("{}/target/out.bin", src), b"binary").unwrap();
+    fs::write(format!("{}/old.log", dst), b"excluded, so not deleted").unwrap();
+    fs::write(format!("{}/stale.rs", dst), b"extraneous").unwrap();
+
+    let filter = Filter::new()
+        .include("keep.log")
+        .exclude("*.log")
+        .exclude("/target/");
+    LocalSyncer::new(src, dst)
+        .with_filter(filter)
+        .with_delete_extraneous(true)
+        .sync()
+        .unwrap();
+
+    assert!(Path::new(&format!("{}/main.rs", dst)).exists());
+    assert!(Path::new(&format!("{}/keep.log", dst)).exists());
+    assert!(!Path::new(&format!("{}/debug.log", dst)).exists());
+    assert!(!Path::new(&format!("{}/target", dst)).exists());
+    assert!(Path::new(&format!("{}/old.log", dst)).exists());
+    assert!(!Path::new(&format!("{}/stale.rs", dst)).exists());
+
+    let _ = fs::remove_dir_all(src);
+    let _ = fs::remove_dir_all(dst);
+}
@@ -0,0 +1,211 @@
+#![cfg(feature = "http")]
+
+use rsynx::{
+    delta::DeltaFormat,
+    http::{HttpSyncer, parse_http_date},
+    sync::Syncer,
+};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+#[test]
+fn test_parse_http_date() {
+    assert_eq!(
+        parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+        Some(784_111_777)
+    );
+    assert_eq!(parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+    assert_eq!(
+        parse_http_date("Tue, 29 Feb 2028 23:59:59 GMT"),
+        Some(1_835_481_599)
+    );
+    assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+    assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 PST"), None);
+}
+
+/// Files of a fake web server by path, and the number of bytes of each it has sent.
+#[derive(Default)]
+struct Site {
+    files: HashMap<String, Vec<u8>>,
+    sent: HashMap<String, usize>,
+}
+
+const LAST_MODIFIED: &str = "Sat, 01 Jun 2024 12:00:00 GMT";
+
+/// Serve `HEAD` and `GET` requests, honouring single `Range` headers, on an ephemeral port.
+fn serve(site: Arc<Mutex<Site>>) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let site = site.clone();
+            thread::spawn(move || handle(stream.unwrap(), &site));
+        }
+    });
+    port
+}
+
+fn handle(stream: TcpStream, site: &Mutex<Site>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut stream = stream;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap().to_string();
+        let path = parts.next().unwrap().to_string();
+        let mut range = None;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            let (name, value) = header.split_once(':').unwrap();
+            if name.eq_ignore_ascii_case("range") {
+                let (start, end) = value
+                    .trim()
+                    .strip_prefix("bytes=")
+                    .unwrap()
+                    .split_once('-')
+                    .unwrap();
+                range = Some((
+                    start.parse::<usize>().unwrap(),
+                    end.parse::<usize>().unwrap(),
+                ));
+            }
+        }
+
+        let mut site = site.lock().unwrap();
+        let Some(data) = site.files.get(&path).cloned() else {
+            stream
+                .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            continue;
+        };
+        let (status, body, content_range) = match range {
+            Some((start, end)) => (
+                "206 Partial Content",
+                data[start..=end].to_vec(),
+                format!("Content-Range: bytes {}-{}/{}\r\n", start, end, data.len()),
+            ),
+            None => ("200 OK", data, String::new()),
+        };
+        let mut reply = format!(
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\nLast-Modified: {}\r\n{}\r\n",
+            status,
+            body.len(),
+            LAST_MODIFIED,
+            content_range
+        )
+        .into_bytes();
+        if method == "GET" {
+            *site.sent.entry(path).or_default() += body.len();
+            reply.extend_from_slice(&body);
+        }
+        stream.write_all(&reply).unwrap();
+    }
+}
+
+/// Publish `data` at `/artifact.bin`, with its signature at `/artifact.bin.sig`.
+fn publish(site: &Mutex<Site>, data: &[u8]) {
+    let dir = "test_http_publish";
+    fs::create_dir_all(dir).unwrap();
+    let path = Path::new(dir).join("artifact.bin");
+    fs::write(&path, data).unwrap();
+    let mut signature = Vec::new();
+    Syncer::new()
+        .generate_signature(&path)
+        .unwrap()
+        .write_to(&mut signature, DeltaFormat::Native)
+        .unwrap();
+    fs::remove_dir_all(dir).unwrap();
+    let mut site = site.lock().unwrap();
+    site.files
+        .insert("/artifact.bin".to_string(), data.to_vec());
+    site.files
+        .insert("/artifact.bin.sig".to_string(), signature);
+    site.sent.clear();
+}
+
+fn artifact(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) as u8
+        })
+        .collect()
+}
+
+#[test]
+fn test_download_fetches_only_changed_ranges() {
+    let site = Arc::new(Mutex::new(Site::default()));
+    let port = serve(site.clone());
+    let url = format!("http://127.0.0.1:{}/artifact.bin", port);
+    let dir = "test_http_ranges";
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(dir).unwrap();
+
+    // Without a local copy the whole file is downloaded
+    let original = artifact(256 * 1024, 1);
+    publish(&site, &original);
+    let result = HttpSyncer::new(&url, dir).sync().unwrap();
+    assert_eq!(result.created_files, 1);
+    let local = Path::new(dir).join("artifact.bin");
+    assert_eq!(fs::read(&local).unwrap(), original);
+    assert_eq!(site.lock().unwrap().sent["/artifact.bin"], original.len());
+
+    // A new release shares most of its blocks, some of them moved
+    let mut release = original.clone();
+    release[10_000..10_100].copy_from_slice(&[0xAA; 100]);
+    release.splice(50_000..50_000, artifact(3000, 2));
+    release.truncate(200 * 1024);
+    publish(&site, &release);
+    let result = HttpSyncer::new(&url, &local).sync().unwrap();
+    assert_eq!(result.updated_files, 1);
+    assert_eq!(fs::read(&local).unwrap(), release);
+    let sent = site.lock().unwrap().sent["/artifact.bin"];
+    assert!(sent < 8 * 1024, "downloaded {} bytes", sent);
+    assert_eq!(result.new_bytes, sent);
+
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_download_without_signature_or_when_unchanged() {
+    let site = Arc::new(Mutex::new(Site::default()));
+    let port = serve(site.clone());
+    let url = format!("http://127.0.0.1:{}/artifact.bin", port);
+    let local = Path::new("test_http_whole.bin");
+    let data = artifact(10_000, 3);
+    publish(&site, &data);
+    site.lock().unwrap().files.remove("/artifact.bin.sig");
+    fs::write(local, artifact(10_000, 4)).unwrap();
+
+    let syncer = HttpSyncer::new(&url, local).with_preserve_metadata(true);
+    let result = syncer.sync().unwrap();
+    assert_eq!(result.updated_files, 1);
+    assert_eq!(fs::read(local).unwrap(), data);
+    let mtime = filetime::FileTime::from_last_modification_time(&fs::metadata(local).unwrap());
+    assert_eq!(Some(mtime.unix_seconds()), parse_http_date(LAST_MODIFIED));
+
+    // The size and Last-Modified now match, so nothing is downloaded
+    site.lock().unwrap().sent.clear();
+    assert_eq!(syncer.sync().unwrap().skipped_files, 1);
+    assert!(site.lock().unwrap().sent.is_empty());
+
+    fs::remove_file(local).unwrap();
+}
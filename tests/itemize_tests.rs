@@ -0,0 +1,65 @@
+use filetime::FileTime;
+use rsynx::itemize::Itemizer;
+use rsynx::local_sync::LocalSyncer;
+use std::{
+    fs,
+    sync::{Arc, Mutex},
+};
+
+#[test]
+fn test_itemized_changes() {
+    let src_dir = "test_itemize_src";
+    let dst_dir = "test_itemize_dst";
+
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+    fs::create_dir_all(format!("{}/sub", src_dir)).unwrap();
+    fs::create_dir_all(dst_dir).unwrap();
+
+    let old = FileTime::from_unix_time(1_600_000_000, 0);
+    let new = FileTime::from_unix_time(1_700_000_000, 0);
+    fs::write(format!("{}/sub/created.txt", src_dir), b"Brand new").unwrap();
+    fs::write(format!("{}/grown.txt", src_dir), b"0123456789abcdef").unwrap();
+    fs::write(format!("{}/grown.txt", dst_dir), b"0123456789ab").unwrap();
+    fs::write(format!("{}/touched.txt", src_dir), b"0123456789abcdef").unwrap();
+    fs::write(format!("{}/touched.txt", dst_dir), b"0123456789abcdef").unwrap();
+    fs::write(format!("{}/same.txt", src_dir), b"Same").unwrap();
+    fs::write(format!("{}/same.txt", dst_dir), b"Same").unwrap();
+    fs::write(format!("{}/extraneous.txt", dst_dir), b"Gone").unwrap();
+    for name in ["grown.txt", "touched.txt", "same.txt"] {
+        filetime::set_file_mtime(format!("{}/{}", src_dir, name), new).unwrap();
+        filetime::set_file_mtime(format!("{}/{}", dst_dir, name), old).unwrap();
+    }
+    filetime::set_file_mtime(format!("{}/same.txt", dst_dir), new).unwrap();
+
+    let itemizer = Itemizer::new(dst_dir);
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let sink = lines.clone();
+    LocalSyncer::new(src_dir, dst_dir)
+        .with_block_size(4)
+        .with_recursive(true)
+        .with_preserve_metadata(true)
+        .with_delete_extraneous(true)
+        .on_event(move |event| {
+            if let Some(line) = itemizer.itemize(event) {
+                sink.lock().unwrap().push(line);
+            }
+        })
+        .sync()
+        .unwrap();
+
+    let mut lines = lines.lock().unwrap().clone();
+    lines.sort();
+    assert_eq!(
+        lines,
+        [
+            "*deleting   extraneous.txt",
+            ">f+++++++++ sub/created.txt",
+            ">f..t...... touched.txt",
+            ">fcst...... grown.txt",
+        ]
+    );
+
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+}
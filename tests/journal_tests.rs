@@ -0,0 +1,84 @@
+use rsynx::journal::{self, Journal, UndoOutcome};
+use rsynx::local_sync::LocalSyncer;
+use rsynx::sync::FileAction;
+use std::{fs, path::Path};
+
+fn sync(src: &Path, dst: &Path, journal_path: &Path) {
+    LocalSyncer::new(src, dst)
+        .with_delete_extraneous(true)
+        .with_backup_dir(Some(".backup".into()))
+        .with_journal(Some(Journal::open(journal_path).unwrap()))
+        .sync()
+        .unwrap();
+}
+
+#[test]
+fn test_undo_reverts_a_journaled_sync() {
+    let dir = Path::new("test_journal_undo");
+    let _ = fs::remove_dir_all(dir);
+    let (src, dst, journal_path) = (dir.join("src"), dir.join("dst"), dir.join("journal"));
+    fs::create_dir_all(src.join("sub")).unwrap();
+    fs::create_dir_all(&dst).unwrap();
+    fs::write(src.join("sub/new.txt"), b"Created").unwrap();
+    fs::write(src.join("changed.txt"), b"New content").unwrap();
+    fs::write(dst.join("changed.txt"), b"Old").unwrap();
+    fs::write(dst.join("extra.txt"), b"Extraneous").unwrap();
+    sync(&src, &dst, &journal_path);
+    assert!(!dst.join("extra.txt").exists());
+
+    let entries = journal::read(fs::File::open(&journal_path).unwrap()).unwrap();
+    let actions: Vec<_> = entries.iter().map(|entry| entry.action).collect();
+    assert_eq!(actions.len(), 3);
+    for action in [
+        FileAction::Created,
+        FileAction::Updated,
+        FileAction::Deleted,
+    ] {
+        assert!(actions.contains(&action));
+    }
+    let updated = entries
+        .iter()
+        .find(|entry| entry.action == FileAction::Updated)
+        .unwrap();
+    assert!(updated.path.is_absolute());
+    assert!(updated.old_sha256.is_some() && updated.new_sha256.is_some());
+    assert_eq!(updated.bytes, Some(11));
+
+    let outcomes = journal::undo(&entries);
+    assert!(
+        outcomes
+            .iter()
+            .all(|outcome| matches!(outcome, UndoOutcome::Reverted(_)))
+    );
+    assert_eq!(fs::read(dst.join("changed.txt")).unwrap(), b"Old");
+    assert_eq!(fs::read(dst.join("extra.txt")).unwrap(), b"Extraneous");
+    assert!(!dst.join("sub/new.txt").exists());
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_undo_skips_files_changed_since() {
+    let dir = Path::new("test_journal_changed");
+    let _ = fs::remove_dir_all(dir);
+    let (src, dst, journal_path) = (dir.join("src"), dir.join("dst"), dir.join("journal"));
+    fs::create_dir_all(&src).unwrap();
+    fs::create_dir_all(&dst).unwrap();
+    fs::write(src.join("file.txt"), b"Synced").unwrap();
+    fs::write(dst.join("file.txt"), b"Old").unwrap();
+    sync(&src, &dst, &journal_path);
+    fs::write(dst.join("file.txt"), b"Edited after the sync").unwrap();
+
+    let entries = journal::read(fs::File::open(&journal_path).unwrap()).unwrap();
+    let outcomes = journal::undo(&entries);
+    assert!(matches!(
+        outcomes.as_slice(),
+        [UndoOutcome::Skipped { reason, .. }] if reason == "it changed since"
+    ));
+    assert_eq!(
+        fs::read(dst.join("file.txt")).unwrap(),
+        b"Edited after the sync"
+    );
+
+    fs::remove_dir_all(dir).unwrap();
+}
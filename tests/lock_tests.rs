@@ -0,0 +1,59 @@
+use rsynx::error::SyncError;
+use rsynx::local_sync::LocalSyncer;
+use rsynx::lock::{DestinationLock, LOCK_FILE_NAME};
+use std::{fs, path::Path};
+
+#[test]
+fn test_locked_destination_directory_is_refused() {
+    let dir = Path::new("test_lock_dir");
+    let _ = fs::remove_dir_all(dir);
+    let (src, dst) = (dir.join("src"), dir.join("dst"));
+    fs::create_dir_all(&src).unwrap();
+    fs::create_dir_all(&dst).unwrap();
+    fs::write(src.join("file.txt"), b"Locked out").unwrap();
+
+    let lock = DestinationLock::acquire(&dst).unwrap();
+    let lock_file = dst.join(LOCK_FILE_NAME);
+    assert_eq!(
+        fs::read_to_string(&lock_file).unwrap(),
+        std::process::id().to_string()
+    );
+    match DestinationLock::acquire(&dst) {
+        Err(SyncError::Locked { owner, .. }) => assert_eq!(owner, Some(std::process::id())),
+        other => panic!("expected the destination to be locked, got {:?}", other),
+    }
+    let syncer = LocalSyncer::new(&src, &dst).with_delete_extraneous(true);
+    assert!(matches!(syncer.sync(), Err(SyncError::Locked { .. })));
+    assert!(!dst.join("file.txt").exists());
+
+    // Once released, the sync goes ahead and neither copies nor deletes a lock file
+    drop(lock);
+    assert!(!lock_file.exists());
+    fs::write(src.join(LOCK_FILE_NAME), b"Not ours").unwrap();
+    syncer.sync().unwrap();
+    assert_eq!(fs::read(dst.join("file.txt")).unwrap(), b"Locked out");
+    assert!(!lock_file.exists());
+
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_locked_destination_file_is_refused() {
+    let dir = Path::new("test_lock_file");
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(dir).unwrap();
+    let (src, dst) = (dir.join("src.txt"), dir.join("dst.txt"));
+    fs::write(&src, b"Single file").unwrap();
+
+    let lock = DestinationLock::acquire(&dst).unwrap();
+    assert!(dir.join(".dst.txt.rsynx-lock").exists());
+    let error = LocalSyncer::new(&src, &dst).sync().unwrap_err();
+    assert!(error.to_string().starts_with("Another rsynx process (pid "));
+    drop(lock);
+
+    LocalSyncer::new(&src, &dst).sync().unwrap();
+    assert_eq!(fs::read(&dst).unwrap(), b"Single file");
+    assert!(!dir.join(".dst.txt.rsynx-lock").exists());
+
+    let _ = fs::remove_dir_all(dir);
+}
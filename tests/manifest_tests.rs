@@ -0,0 +1,100 @@
+use rsynx::{
+    filter::Filter,
+    manifest::{self, Difference, EntryKind, ManifestFormat},
+};
+use std::{fs, path::Path};
+
+#[test]
+fn test_manifest_round_trips_both_formats() {
+    let dir = Path::new("test_manifest_formats");
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("plain.txt"), b"Plain").unwrap();
+    fs::write(dir.join("sub/with space #1=ü.txt"), b"Odd name").unwrap();
+    fs::write(dir.join("skipped.log"), b"Excluded").unwrap();
+    #[cfg(unix)]
+    std::os::unix::fs::symlink("plain.txt", dir.join("link")).unwrap();
+
+    let filter = Filter::new().exclude("*.log");
+    let entries = manifest::build(dir, &filter, true).unwrap();
+    #[cfg(unix)]
+    assert_eq!(
+        entries
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect::<Vec<_>>(),
+        ["link", "plain.txt", "sub", "sub/with space #1=ü.txt"]
+    );
+    let plain = entries
+        .iter()
+        .find(|entry| entry.path == "plain.txt")
+        .unwrap();
+    assert_eq!(plain.kind, EntryKind::File);
+    assert_eq!(plain.size, Some(5));
+    assert_eq!(
+        plain.sha256.as_deref(),
+        Some(hex::encode(rsynx::core::strong_checksum(b"Plain")).as_str())
+    );
+    assert_eq!(manifest::build(dir, &filter, true).unwrap(), entries);
+
+    let mut mtree = Vec::new();
+    manifest::write(&entries, ManifestFormat::Mtree, &mut mtree).unwrap();
+    let text = String::from_utf8(mtree.clone()).unwrap();
+    assert!(text.starts_with("#mtree"));
+    assert!(text.contains("./sub/with\\040space\\040\\0431\\075\\303\\274.txt type=file size=8 "));
+    assert_eq!(manifest::read(&mtree[..]).unwrap(), entries);
+
+    let mut json = Vec::new();
+    manifest::write(&entries, ManifestFormat::Json, &mut json).unwrap();
+    assert_eq!(manifest::read(&json[..]).unwrap(), entries);
+
+    assert!(manifest::read(&b"not a manifest"[..]).is_err());
+    assert!(manifest::read(&b"#mtree v2.0\n./file size=x\n"[..]).is_err());
+
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_verify_reports_differences() {
+    let dir = Path::new("test_manifest_verify");
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("same.txt"), b"Same").unwrap();
+    fs::write(dir.join("edited.txt"), b"Before").unwrap();
+    fs::write(dir.join("sub/removed.txt"), b"Removed").unwrap();
+    let filter = Filter::new();
+    let expected = manifest::build(dir, &filter, true).unwrap();
+    assert!(
+        manifest::verify(dir, &filter, &expected, false)
+            .unwrap()
+            .is_empty()
+    );
+
+    fs::write(dir.join("edited.txt"), b"After!").unwrap();
+    fs::remove_file(dir.join("sub/removed.txt")).unwrap();
+    fs::write(dir.join("added.txt"), b"Added").unwrap();
+    let touched = filetime::FileTime::from_unix_time(1_000_000_000, 0);
+    filetime::set_file_mtime(dir.join("same.txt"), touched).unwrap();
+
+    let differences = manifest::verify(dir, &filter, &expected, true).unwrap();
+    assert_eq!(
+        differences,
+        vec![
+            Difference::Changed {
+                path: "edited.txt".to_string(),
+                fields: vec!["sha256"],
+            },
+            Difference::Missing("sub/removed.txt".to_string()),
+            Difference::Extra("added.txt".to_string()),
+        ]
+    );
+    assert_eq!(differences[0].to_string(), "changed (sha256): edited.txt");
+
+    let differences = manifest::verify(dir, &filter, &expected, false).unwrap();
+    assert!(differences.contains(&Difference::Changed {
+        path: "same.txt".to_string(),
+        fields: vec!["time"],
+    }));
+
+    let _ = fs::remove_dir_all(dir);
+}
@@ -1,7 +1,14 @@
 use anyhow::Result;
+use filetime::FileTime;
+use rand::Rng;
+use rsynx::error::SyncError;
 use rsynx::network_sync::NetworkSyncer;
+use rsynx::options::SyncOptions;
 use std::fs::{self, File};
-use std::io::{Read, Write};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::os::unix::net::UnixStream;
 use std::thread;
 use std::time::Duration;
 
@@ -65,3 +72,268 @@ fn test_network_sync_file() -> Result<()> {
     fs::remove_dir_all(dst_dir)?;
     Ok(())
 }
+
+#[test]
+fn test_network_sync_compressed_mixed_data() -> Result<()> {
+    let src_filename = "test_net_sync_compressed.bin";
+    let dst_dir = "test_net_sync_compressed_dir";
+    let dst_file = format!("{}/{}", dst_dir, src_filename);
+
+    // Compressible text followed by incompressible random bytes
+    let mut src_content = b"compressible text block ".repeat(200);
+    let mut random = vec![0u8; 8192];
+    rand::rng().fill(&mut random[..]);
+    src_content.extend_from_slice(&random);
+    fs::write(src_filename, &src_content)?;
+
+    let _ = fs::remove_dir_all(dst_dir);
+    fs::create_dir(dst_dir)?;
+
+    let block_size = 512;
+    let port = 7890;
+
+    let server_handle = thread::spawn(move || NetworkSyncer::serve_once(port, block_size));
+
+    thread::sleep(Duration::from_millis(100));
+
+    let client_syncer = NetworkSyncer::new(
+        "127.0.0.1".to_string(),
+        port,
+        src_filename.to_string(),
+        dst_file.to_string(),
+    )
+    .with_block_size(block_size)
+    .with_compression(true);
+    let result = client_syncer.sync()?;
+    server_handle.join().expect("Server thread panicked")?;
+
+    assert_eq!(fs::read(&dst_file)?, src_content);
+    // The repeated text shrinks; the random bytes are sent as they are
+    assert!(result.compression_saved_bytes > 0);
+    assert!(result.compression_saved_bytes < 200 * 24);
+
+    fs::remove_file(src_filename)?;
+    fs::remove_dir_all(dst_dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_network_sync_verified() -> Result<()> {
+    let src_filename = "test_net_sync_verify.txt";
+    let dst_dir = "test_net_sync_verify_dir";
+    let dst_file = format!("{}/{}", dst_dir, src_filename);
+    let src_content = b"Verified network sync content, block by block";
+    fs::write(src_filename, src_content)?;
+
+    let _ = fs::remove_dir_all(dst_dir);
+    fs::create_dir(dst_dir)?;
+    fs::write(&dst_file, b"Verified network content, block by block")?;
+
+    let block_size = 8;
+    let port = 7891;
+
+    let server_handle = thread::spawn(move || NetworkSyncer::serve_once(port, block_size));
+
+    thread::sleep(Duration::from_millis(100));
+
+    let client_syncer = NetworkSyncer::new(
+        "127.0.0.1".to_string(),
+        port,
+        src_filename.to_string(),
+        dst_file.to_string(),
+    )
+    .with_block_size(block_size)
+    .with_verify(true);
+    let result = client_syncer.sync()?;
+    server_handle.join().expect("Server thread panicked")?;
+
+    assert_eq!(fs::read(&dst_file)?, src_content);
+    assert!(result.reused_bytes > 0);
+
+    fs::remove_file(src_filename)?;
+    fs::remove_dir_all(dst_dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_server_rejects_oversized_instruction() -> Result<()> {
+    let dst_file = "test_net_oversized_dst.txt";
+    let _ = fs::remove_file(dst_file);
+    let port = 7892;
+
+    let server_handle = thread::spawn(move || NetworkSyncer::serve_once(port, 4));
+
+    thread::sleep(Duration::from_millis(100));
+
+    // Claim a payload far larger than any client would send, without sending it
+    let mut stream = TcpStream::connect(("127.0.0.1", port))?;
+    write!(stream, "FILE 3 {} 10\nsrc{}", dst_file.len(), dst_file)?;
+    let mut reply = String::new();
+    BufReader::new(stream.try_clone()?).read_line(&mut reply)?;
+    assert_eq!(reply.trim_end(), "NOBLK");
+    writeln!(stream, "DATA {}", u64::MAX / 2)?;
+
+    let err = server_handle
+        .join()
+        .expect("Server thread panicked")
+        .unwrap_err();
+    assert!(err.to_string().contains("exceeds"));
+    assert!(!std::path::Path::new(dst_file).exists());
+
+    let _ = fs::remove_file("test_net_oversized_dst.tmp");
+    Ok(())
+}
+
+#[test]
+fn test_network_sync_rejects_invalid_options() {
+    let syncer = NetworkSyncer::new(
+        "127.0.0.1".to_string(),
+        1,
+        "src".to_string(),
+        "dst".to_string(),
+    )
+    .with_checkpoint(true);
+    let err = syncer.validate().unwrap_err();
+    assert!(matches!(err, SyncError::ConflictingOptions(_)));
+    assert!(err.to_string().contains("checkpointing"));
+
+    assert!(matches!(
+        NetworkSyncer::serve_once(1, 0).unwrap_err(),
+        SyncError::InvalidBlockSize(0)
+    ));
+}
+
+#[test]
+fn test_network_sync_with_shared_options() -> Result<()> {
+    let src_filename = "test_net_options_src.txt";
+    let dst_file = "test_net_options_dst.txt";
+    let src_content = b"The same options drive local and network syncs alike";
+    fs::write(src_filename, src_content)?;
+    fs::write(dst_file, b"The same options drive local syncs")?;
+    let mtime = FileTime::from_unix_time(6666666, 0);
+    filetime::set_file_times(src_filename, mtime, mtime)?;
+    fs::set_permissions(src_filename, fs::Permissions::from_mode(0o600))?;
+
+    let options = SyncOptions::new()
+        .with_block_size(4)
+        .with_preserve_metadata(true)
+        .with_delete_extraneous(true)
+        .with_compression(true)
+        .with_verify(true);
+    let (client, server) = UnixStream::pair()?;
+    let server_handle = thread::spawn(move || NetworkSyncer::handle_connection(server, 4));
+    let client_syncer = NetworkSyncer::new(
+        String::new(),
+        0,
+        src_filename.to_string(),
+        dst_file.to_string(),
+    )
+    .with_options(options.clone());
+    client_syncer.sync_over(client)?;
+    server_handle.join().expect("Server thread panicked")?;
+
+    assert_eq!(fs::read(dst_file)?, src_content);
+    let dst_meta = fs::metadata(dst_file)?;
+    assert_eq!(dst_meta.mtime(), mtime.unix_seconds());
+    assert_eq!(dst_meta.permissions().mode() & 0o777, 0o600);
+    assert_eq!(client_syncer.options().block_size, options.block_size);
+
+    fs::remove_file(src_filename)?;
+    fs::remove_file(dst_file)?;
+    Ok(())
+}
+
+#[test]
+fn test_sync_over_custom_transport() -> Result<()> {
+    let src_filename = "test_transport_src.txt";
+    let dst_file = "test_transport_dst.txt";
+    let src_content = b"Protocol over a socket pair instead of TCP";
+    fs::write(src_filename, src_content)?;
+    fs::write(dst_file, b"Protocol over a pipe instead of TCP")?;
+
+    let block_size = 4;
+    let (client, server) = UnixStream::pair()?;
+    let server_handle = thread::spawn(move || NetworkSyncer::handle_connection(server, block_size));
+
+    let client_syncer = NetworkSyncer::new(
+        String::new(),
+        0,
+        src_filename.to_string(),
+        dst_file.to_string(),
+    )
+    .with_block_size(block_size)
+    .with_verify(true);
+    let result = client_syncer.sync_over(client)?;
+    server_handle.join().expect("Server thread panicked")?;
+
+    assert_eq!(fs::read(dst_file)?, src_content);
+    assert!(result.reused_bytes > 0);
+
+    fs::remove_file(src_filename)?;
+    fs::remove_file(dst_file)?;
+    Ok(())
+}
+
+#[test]
+fn test_server_discards_transfer_closed_before_done() -> Result<()> {
+    let dst_file = "test_net_closed_dst.txt";
+    fs::write(dst_file, b"original content")?;
+
+    let (mut client, server) = UnixStream::pair()?;
+    let server_handle = thread::spawn(move || NetworkSyncer::handle_connection(server, 4));
+
+    write!(client, "FILE 3 {} 16\nsrc{}", dst_file.len(), dst_file)?;
+    let mut reply = String::new();
+    let mut reader = BufReader::new(client.try_clone()?);
+    while reply.trim_end() != "BLKEND" {
+        reply.clear();
+        reader.read_line(&mut reply)?;
+    }
+    writeln!(client, "DATA 4")?;
+    client.write_all(b"part")?;
+    drop(reader);
+    drop(client);
+
+    let err = server_handle
+        .join()
+        .expect("Server thread panicked")
+        .unwrap_err();
+    assert!(err.to_string().contains("before DONE"));
+    assert_eq!(fs::read(dst_file)?, b"original content");
+    assert!(!std::path::Path::new("test_net_closed_dst.tmp").exists());
+
+    fs::remove_file(dst_file)?;
+    Ok(())
+}
+
+#[test]
+fn test_sync_non_utf8_names() -> Result<()> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::PathBuf;
+
+    let dir = PathBuf::from("test_net_non_utf8");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir)?;
+    let src = dir.join(OsStr::from_bytes(b"source \xff.txt"));
+    let dst = dir.join(OsStr::from_bytes(b"caf\xe9 with spaces\n.txt"));
+    fs::write(&src, b"Content under a Latin-1 name")?;
+
+    let (client, server) = UnixStream::pair()?;
+    let server_handle = thread::spawn(move || NetworkSyncer::handle_connection(server, 4));
+    NetworkSyncer::new(String::new(), 0, &src, &dst)
+        .with_block_size(4)
+        .sync_over(client)?;
+    server_handle.join().expect("Server thread panicked")?;
+    assert_eq!(fs::read(&dst)?, b"Content under a Latin-1 name");
+
+    let (client, server) = UnixStream::pair()?;
+    let server_handle = thread::spawn(move || NetworkSyncer::handle_connection(server, 4));
+    let listing =
+        NetworkSyncer::new(String::new(), 0, &src, &dir).list_destination_over(client, false)?;
+    server_handle.join().expect("Server thread panicked")?;
+    assert_eq!(listing.len(), 2);
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
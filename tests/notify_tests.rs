@@ -0,0 +1,51 @@
+use rsynx::notify::{self, RunReport, RunStats, RunStatus};
+use rsynx::sync::TransferResult;
+use std::{fs, path::Path, time::Duration};
+
+#[test]
+fn test_report_json() {
+    let result = TransferResult {
+        new_bytes: 10,
+        created_files: 2,
+        ..TransferResult::default()
+    };
+    let report = RunReport {
+        source: Some("src".to_string()),
+        stats: Some(RunStats::from(&result)),
+        ..RunReport::new(0, Duration::from_millis(1500))
+    };
+    assert_eq!(report.status, RunStatus::Success);
+    let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+    assert_eq!(json["status"], "success");
+    assert_eq!(json["elapsed_seconds"], 1.5);
+    assert_eq!(json["stats"]["created_files"], 2);
+    assert!(json.get("error").is_none());
+
+    let failed = RunReport::new(4, Duration::ZERO);
+    assert_eq!(failed.status, RunStatus::Failure);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_command_receives_report() {
+    let dir = Path::new("test_notify_command");
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(dir).unwrap();
+    let report = RunReport {
+        error: Some("Failed to sync".to_string()),
+        ..RunReport::new(1, Duration::ZERO)
+    };
+    let command = format!(
+        "cat > {0}/report.json && printf %s \"$RSYNX_STATUS\" > {0}/status",
+        dir.display()
+    );
+    notify::run_command(&command, &report).unwrap();
+    assert_eq!(
+        fs::read_to_string(dir.join("report.json")).unwrap(),
+        report.to_json()
+    );
+    assert_eq!(fs::read_to_string(dir.join("status")).unwrap(), "failure");
+
+    assert!(notify::run_command("exit 3", &report).is_err());
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,23 @@
+use rsynx::pathname::{escape, unescape};
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+#[test]
+fn test_escape_round_trips_any_bytes() {
+    for bytes in [
+        &b"plain/caf\xc3\xa9.txt"[..],
+        b"latin1 caf\xe9",
+        b"new\nline\r\ttab",
+        b"100% literal %41",
+        b"\xff\xfe",
+        b"",
+    ] {
+        let path = Path::new(OsStr::from_bytes(bytes));
+        let escaped = escape(path);
+        assert!(!escaped.contains('\n'));
+        assert_eq!(unescape(&escaped).as_deref(), Some(path));
+    }
+    assert_eq!(escape(Path::new("caf\u{e9} 100%")), "caf\u{e9} 100%25");
+    assert_eq!(unescape("bad %4"), None);
+}
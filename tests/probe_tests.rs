@@ -0,0 +1,74 @@
+use anyhow::Result;
+use rsynx::daemon::{Daemon, DaemonConfig};
+use rsynx::error::SyncError;
+use rsynx::network_sync::NetworkSyncer;
+use rsynx::probe::{self, ModuleInfo, PROTOCOL_VERSION, ServerInfo};
+use std::fs;
+use std::io::BufReader;
+use std::os::unix::net::UnixStream;
+use std::thread;
+
+#[test]
+fn test_probe_plain_server() -> Result<()> {
+    let (client, server) = UnixStream::pair()?;
+    let server_handle = thread::spawn(move || NetworkSyncer::handle_connection(server, 1024));
+
+    let info = probe::probe_over(client)?;
+    server_handle.join().expect("Server thread panicked")?;
+
+    assert_eq!(info, ServerInfo::local());
+    assert_eq!(info.version, PROTOCOL_VERSION);
+    assert!(info.compression.contains(&"gzip".to_string()));
+    assert!(!info.features.contains(&"modules".to_string()));
+    assert!(info.modules.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_probe_lists_daemon_modules() -> Result<()> {
+    let root = "test_probe_module_root";
+    let _ = fs::remove_dir_all(root);
+    fs::create_dir_all(root)?;
+    let mut config = DaemonConfig::default().with_module("files", root);
+    config.modules.get_mut("files").unwrap().comment = Some("Shared\nfiles".to_string());
+    let daemon = Daemon::new(config)?;
+
+    let (client, server) = UnixStream::pair()?;
+    let info = thread::scope(|scope| {
+        let server_handle = scope.spawn(|| daemon.handle_connection(server));
+        let info = probe::probe_over(client);
+        server_handle
+            .join()
+            .expect("Server thread panicked")
+            .map(|_| info)
+    })??;
+
+    assert!(info.features.contains(&"modules".to_string()));
+    assert_eq!(
+        info.modules,
+        [ModuleInfo {
+            name: "files".to_string(),
+            auth: false,
+            comment: Some("Shared files".to_string()),
+        }]
+    );
+
+    fs::remove_dir_all(root)?;
+    Ok(())
+}
+
+#[test]
+fn test_probe_answer_parsing() {
+    let answer = "VERSION 7\nHASHES adler sha256\nFUTURE thing\nMODULE a auth\nEND\n";
+    let info = ServerInfo::read_from(&mut BufReader::new(answer.as_bytes())).unwrap();
+    assert_eq!(info.version, 7);
+    assert_eq!(info.hashes, ["adler", "sha256"]);
+    assert!(info.modules[0].auth);
+
+    for answer in ["HASHES adler\nEND\n", "VERSION 1\n", "VERSION x\nEND\n"] {
+        assert!(matches!(
+            ServerInfo::read_from(&mut BufReader::new(answer.as_bytes())),
+            Err(SyncError::Protocol(_))
+        ));
+    }
+}
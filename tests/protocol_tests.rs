@@ -0,0 +1,336 @@
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rsynx::daemon::{Daemon, DaemonConfig};
+use rsynx::error::SyncError;
+use rsynx::protocol::{
+    self, Instruction, MAX_INSTRUCTION_SIZE, MAX_LINE_LEN, MAX_PATH_LEN, Request,
+};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, BufReader, Cursor, Read, Write};
+use std::path::Path;
+
+/// Transport replaying a fixed client stream and collecting whatever the server answers.
+struct Scripted {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl Read for Scripted {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for Scripted {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Contents of `file.txt` in the fuzzed module, which `valid_stream` rewrites unchanged.
+const CONTENTS: &[u8] = b"abcdefgh0123";
+
+/// A well-formed module sync of `CONTENTS` over itself, for mutating.
+fn valid_stream() -> Vec<u8> {
+    stream_with_mode("644")
+}
+
+/// `valid_stream`, sending `mode` in its META instruction.
+fn stream_with_mode(mode: &str) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&CONTENTS[4..8]).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let mut stream = b"MODULE fuzz\nFILE 3 8 12\nsrcfile.txt".to_vec();
+    stream.extend_from_slice(b"DATA 4\n");
+    stream.extend_from_slice(&CONTENTS[..4]);
+    stream.extend_from_slice(format!("ZDATA {}\n", compressed.len()).as_bytes());
+    stream.extend_from_slice(&compressed);
+    stream.extend_from_slice(b"COPY 8 4\n");
+    stream.extend_from_slice(format!("META {} 1700000000 5 1700000000 6\n", mode).as_bytes());
+    let checksum = hex::encode(Sha256::digest(CONTENTS));
+    stream.extend_from_slice(format!("VERIFY {}\nDONE\n", checksum).as_bytes());
+    stream
+}
+
+fn mutate(rng: &mut StdRng, mut stream: Vec<u8>) -> Vec<u8> {
+    for _ in 0..rng.random_range(1..4) {
+        let at = rng.random_range(0..=stream.len());
+        match rng.random_range(0..5) {
+            0 if at < stream.len() => stream[at] = rng.random(),
+            1 => stream.insert(at, rng.random()),
+            2 => stream.truncate(at),
+            3 => {
+                let digits = rng.random_range(1..20);
+                let number: String = (0..digits)
+                    .map(|_| char::from(b'0' + rng.random_range(0..10)))
+                    .collect();
+                stream.splice(at..at, number.bytes());
+            }
+            _ => {
+                let end = rng.random_range(at..=stream.len());
+                let copy = stream[at..end].to_vec();
+                stream.splice(at..at, copy);
+            }
+        }
+    }
+    stream
+}
+
+fn is_rejection(e: &SyncError) -> bool {
+    matches!(
+        e,
+        SyncError::Protocol(_)
+            | SyncError::Io { .. }
+            | SyncError::Refused(_)
+            | SyncError::PathOutsideRoot(_)
+            | SyncError::ChecksumMismatch { .. }
+    )
+}
+
+#[test]
+fn test_fuzz_parsers_with_random_bytes() {
+    let mut rng = StdRng::seed_from_u64(0x5eed);
+    let words: [&[u8]; 12] = [
+        b"FILE ",
+        b"LIST ",
+        b"MODULE ",
+        b"DATA ",
+        b"COPY ",
+        b"META ",
+        b"BLK ",
+        b"BLKEND\n",
+        b"NOBLK\n",
+        b"DONE\n",
+        b"\n",
+        b" ",
+    ];
+    for _ in 0..5000 {
+        let mut stream = Vec::new();
+        for _ in 0..rng.random_range(0..12) {
+            match rng.random_range(0..3) {
+                0 => stream.extend_from_slice(words[rng.random_range(0..words.len())]),
+                1 => stream.extend(rng.random_range(0u64..1 << 40).to_string().bytes()),
+                _ => stream.extend((0..rng.random_range(1..8)).map(|_| rng.random::<u8>())),
+            }
+        }
+        let mut reader = BufReader::new(&stream[..]);
+        while let Ok(request) = protocol::read_request(&mut reader) {
+            if let Request::File { destination, .. } = request {
+                assert!(destination.as_os_str().len() <= MAX_PATH_LEN);
+            }
+        }
+        let mut reader = BufReader::new(&stream[..]);
+        while let Ok(instruction) = protocol::read_instruction(&mut reader) {
+            if let Instruction::Data(length) | Instruction::ZData(length) = instruction {
+                assert!(length <= MAX_INSTRUCTION_SIZE);
+            }
+        }
+        let _ = protocol::read_block_table(&mut BufReader::new(&stream[..]));
+    }
+}
+
+#[test]
+fn test_fuzz_daemon_with_mutated_streams() {
+    let dir = Path::new("test_protocol_fuzz");
+    let _ = fs::remove_dir_all(dir);
+    let root = dir.join("root");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("file.txt"), CONTENTS).unwrap();
+    let config = DaemonConfig {
+        block_size: 4,
+        ..Default::default()
+    }
+    .with_module("fuzz", &root);
+    let daemon = Daemon::new(config).unwrap();
+
+    let serve = |stream: Vec<u8>| {
+        daemon.handle_connection(Scripted {
+            input: Cursor::new(stream),
+            output: Vec::new(),
+        })
+    };
+    let result = serve(valid_stream()).unwrap();
+    assert_eq!((result.new_bytes, result.reused_bytes), (8, 4));
+    assert_eq!(fs::read(root.join("file.txt")).unwrap(), CONTENTS);
+
+    let mut rng = StdRng::seed_from_u64(0xf022);
+    for _ in 0..3000 {
+        let stream = mutate(&mut rng, valid_stream());
+        if let Err(e) = serve(stream.clone()) {
+            assert!(is_rejection(&e), "{:?} for {:?}", e, stream);
+        }
+    }
+
+    // Nothing escaped the module
+    let entries: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(entries, ["root"]);
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_oversized_input_is_rejected_without_buffering() {
+    let huge = vec![b'A'; 64 * MAX_LINE_LEN];
+    let mut reader = BufReader::new(&huge[..]);
+    assert!(matches!(
+        protocol::read_line(&mut reader),
+        Err(SyncError::Protocol(_))
+    ));
+    // Only the limit was consumed
+    assert_eq!(reader.bytes().count(), huge.len() - MAX_LINE_LEN);
+
+    for line in [
+        "DATA 18446744073709551615".to_string(),
+        format!("ZDATA {}", MAX_INSTRUCTION_SIZE + 1),
+        "COPY 0 99999999999".to_string(),
+        "DATA -1".to_string(),
+        "DATA 4 4".to_string(),
+        "META 99999 0 0 0 0".to_string(),
+        "META 644 0 1000000000 0 0".to_string(),
+        "META 644 0 0 0".to_string(),
+        "VERIFY abc".to_string(),
+        "DONE now".to_string(),
+        "TRUNCATE 0".to_string(),
+    ] {
+        assert!(
+            matches!(
+                protocol::parse_instruction(&line),
+                Err(SyncError::Protocol(_))
+            ),
+            "{}",
+            line
+        );
+    }
+
+    for stream in [
+        format!("FILE {} 1 0\n", MAX_PATH_LEN + 1).into_bytes(),
+        b"FILE 1 1\nab".to_vec(),
+        b"FILE 1 1 1 1\nab".to_vec(),
+        b"FILE 1 2 3\nsa\0".to_vec(),
+        b"LIST 2 1\n.".to_vec(),
+        b"MODULE\n".to_vec(),
+        b"GET / HTTP/1.1\r\n".to_vec(),
+        b"\xff\xfe\n".to_vec(),
+        Vec::new(),
+    ] {
+        assert!(
+            matches!(
+                protocol::read_request(&mut BufReader::new(&stream[..])),
+                Err(SyncError::Protocol(_))
+            ),
+            "{:?}",
+            stream
+        );
+    }
+
+    let strong = "00".repeat(32);
+    for table in [
+        format!("BLK 0 {} 1 {}\nBLKEND\n", 64 * 1024 * 1024, strong),
+        format!("BLK 0 4 1 {} extra\nBLKEND\n", strong),
+        "BLK 0 4 1 zz\nBLKEND\n".to_string(),
+        format!("BLK 0 4 1 {}\n", strong),
+        format!("BLK 0 4 1 {}\nNOBLK\n", strong),
+        "HELLO\n".to_string(),
+    ] {
+        assert!(
+            matches!(
+                protocol::read_block_table(&mut BufReader::new(table.as_bytes())),
+                Err(SyncError::Protocol(_))
+            ),
+            "{}",
+            table
+        );
+    }
+}
+
+#[test]
+fn test_well_formed_messages_parse() {
+    let mut reader = BufReader::new(&b"LIST 1 3\nsubFILE 3 5 42\nsrcdst/xPROBE\nMODULE m\n"[..]);
+    assert_eq!(
+        protocol::read_request(&mut reader).unwrap(),
+        Request::List {
+            hash: true,
+            path: "sub".into()
+        }
+    );
+    assert_eq!(
+        protocol::read_request(&mut reader).unwrap(),
+        Request::File {
+            source_name: "src".into(),
+            destination: "dst/x".into(),
+            size: 42
+        }
+    );
+    assert_eq!(protocol::read_request(&mut reader).unwrap(), Request::Probe);
+    assert_eq!(
+        protocol::read_request(&mut reader).unwrap(),
+        Request::Module("m".to_string())
+    );
+
+    assert_eq!(
+        protocol::parse_instruction("COPY 1024 512").unwrap(),
+        Instruction::Copy {
+            offset: 1024,
+            length: 512
+        }
+    );
+    assert!(matches!(
+        protocol::parse_instruction("META 755 1 2 3 4").unwrap(),
+        Instruction::Meta { mode: 0o755, .. }
+    ));
+
+    let table = format!("BLK 0 4 7 {}\nBLKEND\n", "11".repeat(32));
+    let blocks = protocol::read_block_table(&mut BufReader::new(table.as_bytes()))
+        .unwrap()
+        .unwrap();
+    assert_eq!((blocks.len(), blocks[0].weak_checksum), (1, 7));
+    assert!(
+        protocol::read_block_table(&mut BufReader::new(&b"NOBLK\n"[..]))
+            .unwrap()
+            .is_none()
+    );
+    assert!(matches!(
+        protocol::read_block_table(&mut BufReader::new(&b"ERROR full\n"[..])),
+        Err(SyncError::Refused(_))
+    ));
+}
+
+#[test]
+fn test_special_mode_bits_are_dropped() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = Path::new("test_protocol_mode");
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(dir).unwrap();
+    fs::write(dir.join("file.txt"), CONTENTS).unwrap();
+    let config = DaemonConfig {
+        block_size: 4,
+        ..Default::default()
+    }
+    .with_module("fuzz", dir);
+    let daemon = Daemon::new(config).unwrap();
+
+    daemon
+        .handle_connection(Scripted {
+            input: Cursor::new(stream_with_mode("4755")),
+            output: Vec::new(),
+        })
+        .unwrap();
+    let mode = fs::metadata(dir.join("file.txt"))
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o7777, 0o755);
+    let _ = fs::remove_dir_all(dir);
+}
@@ -0,0 +1,122 @@
+use rand::Rng;
+use rsynx::delta::{Delta, DeltaFormat, Signature, StrongHash, WeakHash};
+use rsynx::rdiff;
+use rsynx::sync::Syncer;
+use std::{fs, path::Path};
+
+#[test]
+fn test_rolling_checksums_match_full_computation() {
+    let mut data = vec![0u8; 300];
+    rand::rng().fill(&mut data[..]);
+    let len = 64;
+    let factor = rdiff::rabinkarp_factor(len);
+
+    let mut rollsum = rdiff::rollsum(&data[..len]);
+    let mut rabinkarp = rdiff::rabinkarp(&data[..len]);
+    for start in 1..=data.len() - len {
+        let (old, new) = (data[start - 1], data[start + len - 1]);
+        rollsum = rdiff::rollsum_rotate(old, new, rollsum, len);
+        rabinkarp = rdiff::rabinkarp_rotate(old, new, rabinkarp, factor);
+        assert_eq!(rollsum, rdiff::rollsum(&data[start..start + len]));
+        assert_eq!(rabinkarp, rdiff::rabinkarp(&data[start..start + len]));
+    }
+}
+
+#[test]
+fn test_blake2_matches_reference_digest() {
+    assert_eq!(
+        hex::encode(rdiff::blake2(b"")),
+        "0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8"
+    );
+}
+
+#[test]
+fn test_rdiff_signature_layout() {
+    let basis = "test_rdiff_sig_basis";
+    fs::write(basis, b"0123456789").unwrap();
+
+    let mut syncer = Syncer::new();
+    syncer.options.block_size = 4;
+    let signature = syncer
+        .generate_signature_as(Path::new(basis), DeltaFormat::Rdiff)
+        .unwrap();
+    assert_eq!(signature.weak_hash, WeakHash::RabinKarp);
+    assert_eq!(signature.strong_hash, StrongHash::Blake2);
+
+    let mut serialized = Vec::new();
+    signature
+        .write_to(&mut serialized, DeltaFormat::Rdiff)
+        .unwrap();
+    // Header: magic, block length, strong sum length; then 3 blocks of weak + strong sums
+    assert_eq!(&serialized[..4], &rdiff::RK_BLAKE2_SIG_MAGIC.to_be_bytes());
+    assert_eq!(&serialized[4..8], &4u32.to_be_bytes());
+    assert_eq!(&serialized[8..12], &32u32.to_be_bytes());
+    assert_eq!(serialized.len(), 12 + 3 * (4 + 32));
+    assert_eq!(
+        &serialized[12..16],
+        &rdiff::rabinkarp(b"0123").to_be_bytes()
+    );
+
+    let parsed = Signature::read_from(&serialized[..]).unwrap();
+    assert_eq!(parsed.blocks.len(), 3);
+    assert_eq!(
+        parsed.blocks[1].strong_checksum,
+        signature.blocks[1].strong_checksum
+    );
+
+    // Native signatures use checksums rdiff doesn't know about
+    let native = syncer.generate_signature(Path::new(basis)).unwrap();
+    assert!(native.write_to(Vec::new(), DeltaFormat::Rdiff).is_err());
+
+    let _ = fs::remove_file(basis);
+}
+
+#[test]
+fn test_rdiff_delta_roundtrip() {
+    let basis = "test_rdiff_delta_basis";
+    let new_file = "test_rdiff_delta_new";
+    let output = "test_rdiff_delta_output";
+    let mut basis_content = vec![0u8; 100_000];
+    rand::rng().fill(&mut basis_content[..]);
+    let mut new_content = basis_content.clone();
+    new_content[50_000..50_100].fill(7);
+    new_content.extend_from_slice(&[1u8; 300]);
+    fs::write(basis, &basis_content).unwrap();
+    fs::write(new_file, &new_content).unwrap();
+
+    let mut syncer = Syncer::new();
+    syncer.options.block_size = 1024;
+    let signature = syncer
+        .generate_signature_as(Path::new(basis), DeltaFormat::Rdiff)
+        .unwrap();
+    let mut sig_bytes = Vec::new();
+    signature
+        .write_to(&mut sig_bytes, DeltaFormat::Rdiff)
+        .unwrap();
+    let signature = Signature::read_from(&sig_bytes[..]).unwrap();
+
+    let delta = syncer
+        .generate_delta(&signature, Path::new(new_file))
+        .unwrap();
+    assert!(delta.copied_bytes() >= 90_000);
+
+    let mut delta_bytes = Vec::new();
+    delta
+        .write_to(&mut delta_bytes, DeltaFormat::Rdiff)
+        .unwrap();
+    assert_eq!(&delta_bytes[..4], &rdiff::DELTA_MAGIC.to_be_bytes());
+    assert_eq!(delta_bytes.last(), Some(&0));
+
+    let parsed = Delta::read_from(&delta_bytes[..]).unwrap();
+    assert_eq!(parsed.ops, delta.ops);
+    assert_eq!(parsed.checksum, None);
+
+    syncer
+        .patch(Path::new(basis), &delta_bytes[..], Path::new(output))
+        .unwrap();
+    assert_eq!(fs::read(output).unwrap(), new_content);
+
+    for path in [basis, new_file, output] {
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,157 @@
+use rsynx::remote::{self, RemoteSpec};
+
+fn remote(user: Option<&str>, host: &str, module: Option<&str>, path: &str) -> RemoteSpec {
+    RemoteSpec {
+        user: user.map(str::to_string),
+        host: host.to_string(),
+        port: None,
+        module: module.map(str::to_string),
+        path: path.to_string(),
+    }
+}
+
+#[test]
+fn test_host_and_path() {
+    assert_eq!(
+        RemoteSpec::parse("backup:/srv/data"),
+        Some(remote(None, "backup", None, "/srv/data"))
+    );
+    assert_eq!(
+        RemoteSpec::parse("127.0.0.1:relative/dir"),
+        Some(remote(None, "127.0.0.1", None, "relative/dir"))
+    );
+    assert_eq!(
+        RemoteSpec::parse("host:"),
+        Some(remote(None, "host", None, ""))
+    );
+}
+
+#[test]
+fn test_user_at_host() {
+    assert_eq!(
+        RemoteSpec::parse("alice@backup:/srv/data"),
+        Some(remote(Some("alice"), "backup", None, "/srv/data"))
+    );
+    // An @ in the path doesn't make a user
+    assert_eq!(
+        RemoteSpec::parse("backup:/srv/alice@home"),
+        Some(remote(None, "backup", None, "/srv/alice@home"))
+    );
+}
+
+#[test]
+fn test_ipv6_literals() {
+    assert_eq!(
+        RemoteSpec::parse("[::1]:/tmp/out"),
+        Some(remote(None, "::1", None, "/tmp/out"))
+    );
+    assert_eq!(
+        RemoteSpec::parse("bob@[fe80::1%eth0]:data"),
+        Some(remote(Some("bob"), "fe80::1%eth0", None, "data"))
+    );
+    assert_eq!(RemoteSpec::parse("[::1]/tmp"), None);
+    assert_eq!(RemoteSpec::parse("[::1"), None);
+}
+
+#[test]
+fn test_module_paths() {
+    assert_eq!(
+        RemoteSpec::parse("host::backups/daily/db"),
+        Some(remote(None, "host", Some("backups"), "daily/db"))
+    );
+    assert_eq!(
+        RemoteSpec::parse("carol@host::backups"),
+        Some(remote(Some("carol"), "host", Some("backups"), ""))
+    );
+}
+
+#[test]
+fn test_local_paths() {
+    assert_eq!(RemoteSpec::parse("/tmp/out"), None);
+    assert_eq!(RemoteSpec::parse("dir/with:colon"), None);
+    assert_eq!(RemoteSpec::parse("./host:path"), None);
+    assert_eq!(RemoteSpec::parse(r"C:\Users\dave"), None);
+    assert_eq!(RemoteSpec::parse("d:/backups"), None);
+    assert_eq!(RemoteSpec::parse(":path"), None);
+    assert_eq!(RemoteSpec::parse("plain_file.txt"), None);
+}
+
+#[test]
+fn test_port_in_spec() {
+    let with_port = |spec: &str, port: u16, path: &str| {
+        let parsed = RemoteSpec::parse(spec).unwrap();
+        assert_eq!(parsed.port, Some(port), "{}", spec);
+        assert_eq!(parsed.path, path, "{}", spec);
+    };
+    with_port("host:9999:/dest/path", 9999, "/dest/path");
+    with_port("alice@host:8000:relative", 8000, "relative");
+    with_port("[::1]:7000:/tmp/out", 7000, "/tmp/out");
+    with_port("host:9999::backups/daily", 9999, "daily");
+    assert_eq!(RemoteSpec::parse("host:/dest:9999").unwrap().port, None);
+    assert_eq!(RemoteSpec::parse("host:99999:/dest"), None);
+}
+
+#[test]
+fn test_url_specs() {
+    assert_eq!(
+        RemoteSpec::parse("rsynx://host:9999/dest"),
+        Some(RemoteSpec {
+            port: Some(9999),
+            ..remote(None, "host", None, "/dest")
+        })
+    );
+    assert_eq!(
+        RemoteSpec::parse("rsynx://alice@[::1]:7000/srv/data"),
+        Some(RemoteSpec {
+            port: Some(7000),
+            ..remote(Some("alice"), "::1", None, "/srv/data")
+        })
+    );
+    assert_eq!(
+        RemoteSpec::parse("rsynx://host/dest"),
+        Some(remote(None, "host", None, "/dest"))
+    );
+    assert_eq!(
+        RemoteSpec::parse("rsynx://[::1]/dest"),
+        Some(remote(None, "::1", None, "/dest"))
+    );
+    assert_eq!(RemoteSpec::parse("rsynx://host:port/dest"), None);
+    assert_eq!(RemoteSpec::parse("rsynx:///dest"), None);
+}
+
+#[test]
+fn test_display_round_trips() {
+    for spec in [
+        "backup:/srv/data",
+        "alice@backup:/srv/data",
+        "[::1]:/tmp/out",
+        "host::backups/daily",
+        "carol@host::backups",
+        "host:9999:/dest/path",
+        "[::1]:7000:/tmp/out",
+    ] {
+        assert_eq!(RemoteSpec::parse(spec).unwrap().to_string(), spec);
+    }
+}
+
+#[test]
+fn test_server_addresses() {
+    assert_eq!(
+        remote::parse_address("backup"),
+        Some(("backup".to_string(), None))
+    );
+    assert_eq!(
+        remote::parse_address("backup:9000"),
+        Some(("backup".to_string(), Some(9000)))
+    );
+    assert_eq!(
+        remote::parse_address("[::1]:9000"),
+        Some(("::1".to_string(), Some(9000)))
+    );
+    assert_eq!(
+        remote::parse_address("rsynx://backup:9000/ignored"),
+        Some(("backup".to_string(), Some(9000)))
+    );
+    assert_eq!(remote::parse_address("backup:http"), None);
+    assert_eq!(remote::parse_address(""), None);
+}
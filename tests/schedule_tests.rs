@@ -0,0 +1,49 @@
+use rsynx::schedule::{Schedule, parse_interval};
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_parse_interval_units() {
+    assert_eq!(parse_interval("90"), Some(Duration::from_secs(90)));
+    assert_eq!(parse_interval("30s"), Some(Duration::from_secs(30)));
+    assert_eq!(parse_interval("5m"), Some(Duration::from_secs(300)));
+    assert_eq!(parse_interval("2H"), Some(Duration::from_secs(7200)));
+    assert_eq!(parse_interval("1d"), Some(Duration::from_secs(86400)));
+    assert_eq!(parse_interval("0"), None);
+    assert_eq!(parse_interval("0m"), None);
+    assert_eq!(parse_interval("5x"), None);
+    assert_eq!(parse_interval("m"), None);
+    assert_eq!(parse_interval("-5m"), None);
+    assert_eq!(parse_interval(""), None);
+}
+
+#[test]
+fn test_schedule_keeps_to_its_grid() {
+    let interval = Duration::from_secs(60);
+    let start = Instant::now();
+    let mut schedule = Schedule::new(interval, start);
+
+    // A quick first run waits out the rest of the interval, plus up to 10% jitter
+    let (wait, skipped) = schedule.next_run(start + Duration::from_secs(5));
+    assert_eq!(skipped, 0);
+    assert!(wait >= Duration::from_secs(55) && wait < Duration::from_secs(61));
+
+    // The second run starts from the grid, not from when the first one finished
+    let (wait, skipped) = schedule.next_run(start + Duration::from_secs(100));
+    assert_eq!(skipped, 0);
+    assert!(wait >= Duration::from_secs(20) && wait < Duration::from_secs(26));
+}
+
+#[test]
+fn test_schedule_skips_overrun_ticks() {
+    let interval = Duration::from_secs(60);
+    let start = Instant::now();
+    let mut schedule = Schedule::new(interval, start);
+
+    // A run lasting three and a half intervals misses the ticks at 60s, 120s and 180s
+    let (wait, skipped) = schedule.next_run(start + Duration::from_secs(210));
+    assert_eq!(skipped, 3);
+    assert!(wait >= Duration::from_secs(30) && wait < Duration::from_secs(36));
+
+    let (_, skipped) = schedule.next_run(start + Duration::from_secs(250));
+    assert_eq!(skipped, 0);
+}
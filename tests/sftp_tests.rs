@@ -0,0 +1,68 @@
+#![cfg(feature = "sftp")]
+
+use rsynx::{
+    error::SyncError,
+    sftp::{SftpSyncer, SftpTarget},
+};
+use std::{
+    fs,
+    io::Write,
+    net::TcpListener,
+    path::{Path, PathBuf},
+    thread,
+};
+
+fn target(port: u16) -> SftpTarget {
+    SftpTarget {
+        host: "127.0.0.1".to_string(),
+        port,
+        user: "rsynx".to_string(),
+        path: PathBuf::from("/tmp/sftp_dest"),
+        identity: None,
+    }
+}
+
+#[test]
+fn test_unreachable_server_is_a_connect_error() {
+    let source = Path::new("test_sftp_unreachable.txt");
+    fs::write(source, b"hello").unwrap();
+    // Bind and drop a listener to find a port nothing listens on
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    let result = SftpSyncer::new(source, target(port)).sync();
+
+    fs::remove_file(source).unwrap();
+    match result {
+        Err(SyncError::Connect { peer, .. }) => assert_eq!(peer, format!("127.0.0.1:{}", port)),
+        other => panic!("expected a connect error, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_non_ssh_server_fails_handshake() {
+    let source = Path::new("test_sftp_not_ssh.txt");
+    fs::write(source, b"hello").unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let _ = stream.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n");
+    });
+
+    let result = SftpSyncer::new(source, target(port)).sync();
+
+    server.join().unwrap();
+    fs::remove_file(source).unwrap();
+    assert!(matches!(result, Err(SyncError::Connect { .. })));
+}
+
+#[test]
+fn test_missing_source_is_rejected_before_connecting() {
+    // Port 1 is never reached: the source check comes first
+    let result = SftpSyncer::new("test_sftp_missing_source", target(1)).sync();
+    assert!(matches!(result, Err(SyncError::UnsupportedSource { .. })));
+}
@@ -0,0 +1,102 @@
+use rsynx::local_sync::LocalSyncer;
+use rsynx::snapshot::{self, snapshot_name};
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+fn at(seconds: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(seconds)
+}
+
+#[test]
+fn test_snapshot_name() {
+    assert_eq!(snapshot_name(at(0)), "1970-01-01T00:00:00");
+    assert_eq!(snapshot_name(at(1_717_243_200)), "2024-06-01T12:00:00");
+    assert_eq!(snapshot_name(at(951_825_599)), "2000-02-29T11:59:59");
+    assert_eq!(snapshot_name(at(4_102_444_800)), "2100-01-01T00:00:00");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_snapshots_link_unchanged_files() {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = "test_snapshot_link";
+    let _ = fs::remove_dir_all(dir);
+    let source = Path::new(dir).join("src");
+    let root = Path::new(dir).join("snapshots");
+    fs::create_dir_all(source.join("sub")).unwrap();
+    fs::write(source.join("same.txt"), b"Unchanged").unwrap();
+    fs::write(source.join("sub/changed.txt"), b"First").unwrap();
+
+    let syncer = LocalSyncer::new(&source, &root).with_preserve_metadata(true);
+    let (first, _) = snapshot::take(&syncer, &source, &root, at(1_717_243_200)).unwrap();
+    assert!(first.ends_with("2024-06-01T12:00:00"));
+
+    fs::write(source.join("sub/changed.txt"), b"Second").unwrap();
+    filetime::set_file_mtime(
+        source.join("sub/changed.txt"),
+        filetime::FileTime::from_unix_time(1_700_000_000, 0),
+    )
+    .unwrap();
+    let (second, result) = snapshot::take(&syncer, &source, &root, at(1_717_246_800)).unwrap();
+    assert!(second.ends_with("2024-06-01T13:00:00"));
+    assert_eq!(result.new_bytes, 6);
+
+    let inode = |path: &Path| fs::metadata(path).unwrap().ino();
+    assert_eq!(
+        inode(&first.join("same.txt")),
+        inode(&second.join("same.txt"))
+    );
+    assert_ne!(
+        inode(&first.join("sub/changed.txt")),
+        inode(&second.join("sub/changed.txt"))
+    );
+    assert_eq!(fs::read(first.join("sub/changed.txt")).unwrap(), b"First");
+    assert_eq!(fs::read(second.join("sub/changed.txt")).unwrap(), b"Second");
+
+    // An interrupted snapshot is picked up by the next one
+    let leftover = root.join("2024-06-01T13:30:00.incomplete");
+    fs::create_dir_all(&leftover).unwrap();
+    fs::write(leftover.join("stale.txt"), b"Left behind").unwrap();
+    let (third, _) = snapshot::take(&syncer, &source, &root, at(1_717_250_400)).unwrap();
+    assert!(!leftover.exists());
+    assert!(third.join("stale.txt").exists());
+    assert_eq!(
+        snapshot::list(&root).unwrap(),
+        vec![first.clone(), second.clone(), third]
+    );
+
+    // The same second twice is refused rather than mixed into the existing snapshot
+    assert!(snapshot::take(&syncer, &source, &root, at(1_717_246_800)).is_err());
+
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_prune_keeps_newest_snapshots() {
+    let root = Path::new("test_snapshot_prune");
+    let _ = fs::remove_dir_all(root);
+    for name in [
+        "2024-06-01T12:00:00",
+        "2024-06-02T12:00:00",
+        "2024-06-03T12:00:00",
+        "2024-06-04T12:00:00.incomplete",
+        "not-a-snapshot",
+    ] {
+        fs::create_dir_all(root.join(name)).unwrap();
+    }
+
+    assert_eq!(snapshot::list(root).unwrap().len(), 3);
+    let pruned = snapshot::prune(root, 2).unwrap();
+    assert_eq!(pruned, vec![root.join("2024-06-01T12:00:00")]);
+    assert!(root.join("2024-06-02T12:00:00").is_dir());
+    assert!(root.join("2024-06-03T12:00:00").is_dir());
+    assert!(root.join("2024-06-04T12:00:00.incomplete").is_dir());
+    assert!(root.join("not-a-snapshot").is_dir());
+    assert!(snapshot::prune(root, 5).unwrap().is_empty());
+
+    let _ = fs::remove_dir_all(root);
+}
@@ -0,0 +1,114 @@
+use filetime::FileTime;
+use rsynx::store::{CHUNK_SIZE, ChunkStore, Manifest, StoreSyncer, parse_store};
+use std::{fs, path::Path};
+
+#[test]
+fn test_manifest_round_trip() {
+    let manifest = Manifest {
+        size: 70_000,
+        mtime: FileTime::from_unix_time(1_700_000_000, 123),
+        mode: 0o644,
+        chunks: vec![[1; 32], [2; 32]],
+    };
+    let decoded = Manifest::decode(&manifest.encode()).unwrap();
+    assert_eq!(decoded, manifest);
+
+    assert!(Manifest::decode("not a manifest\n").is_err());
+    assert!(Manifest::decode("rsynx-manifest 1\nsize 1\nmtime 0 0\nmode 644\nxyz\n").is_err());
+    assert_eq!(parse_store("store://backups"), Some("backups".into()));
+    assert_eq!(parse_store("store://"), None);
+    assert_eq!(parse_store("backups"), None);
+}
+
+#[test]
+fn test_store_deduplicates_and_restores() {
+    let dir = Path::new("test_store_dedup");
+    let _ = fs::remove_dir_all(dir);
+    let source = dir.join("src");
+    fs::create_dir_all(source.join("sub")).unwrap();
+    let data: Vec<u8> = (0..3 * CHUNK_SIZE).map(|i| (i % 251) as u8).collect();
+    fs::write(source.join("one.bin"), &data).unwrap();
+    fs::write(source.join("sub/two.bin"), &data).unwrap();
+    let store_spec = format!("store://{}", dir.join("store").display());
+
+    let result = StoreSyncer::new(source.to_str().unwrap(), &store_spec)
+        .sync()
+        .unwrap();
+    assert_eq!(result.created_files, 2);
+    assert_eq!(result.new_bytes + result.reused_bytes, 2 * data.len());
+    assert!(result.reused_bytes >= data.len());
+
+    let store = ChunkStore::new(dir.join("store"));
+    assert_eq!(
+        store.files().unwrap(),
+        vec![
+            Path::new("one.bin").to_path_buf(),
+            Path::new("sub/two.bin").to_path_buf()
+        ]
+    );
+    let manifest = store.manifest(Path::new("one.bin")).unwrap().unwrap();
+    assert_eq!(manifest.size, data.len() as u64);
+    assert_eq!(manifest.chunks.len(), 3);
+
+    // Unchanged files are skipped on the next run
+    let result = StoreSyncer::new(source.to_str().unwrap(), &store_spec)
+        .sync()
+        .unwrap();
+    assert_eq!(result.skipped_files, 2);
+    assert_eq!(result.new_bytes, 0);
+
+    let restored = dir.join("restored");
+    StoreSyncer::new(&store_spec, restored.to_str().unwrap())
+        .with_preserve_metadata(true)
+        .sync()
+        .unwrap();
+    assert_eq!(fs::read(restored.join("one.bin")).unwrap(), data);
+    assert_eq!(fs::read(restored.join("sub/two.bin")).unwrap(), data);
+
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_store_delete_collects_garbage() {
+    let dir = Path::new("test_store_delete");
+    let _ = fs::remove_dir_all(dir);
+    let source = dir.join("src");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("keep.txt"), b"Kept").unwrap();
+    fs::write(source.join("gone.txt"), b"Deleted later").unwrap();
+    let store_spec = format!("store://{}", dir.join("store").display());
+    let syncer =
+        StoreSyncer::new(source.to_str().unwrap(), &store_spec).with_delete_extraneous(true);
+    syncer.sync().unwrap();
+
+    let chunks = |root: &Path| {
+        fs::read_dir(root.join("chunks"))
+            .unwrap()
+            .map(|dir| fs::read_dir(dir.unwrap().path()).unwrap().count())
+            .sum::<usize>()
+    };
+    assert_eq!(chunks(&dir.join("store")), 2);
+
+    fs::remove_file(source.join("gone.txt")).unwrap();
+    let result = syncer.sync().unwrap();
+    assert_eq!(result.deleted_files, 1);
+    assert_eq!(chunks(&dir.join("store")), 1);
+
+    // A chunk corrupted in the store is caught on restore
+    let store = ChunkStore::new(dir.join("store"));
+    let hash = store
+        .manifest(Path::new("keep.txt"))
+        .unwrap()
+        .unwrap()
+        .chunks[0];
+    let chunk_path = dir
+        .join("store/chunks")
+        .join(&hex::encode(hash)[..2])
+        .join(hex::encode(hash));
+    fs::write(chunk_path, b"Corrupt").unwrap();
+    assert!(store.get_chunk(&hash).is_err());
+
+    assert!(StoreSyncer::new(&store_spec, &store_spec).sync().is_err());
+
+    let _ = fs::remove_dir_all(dir);
+}
@@ -1,13 +1,23 @@
 use filetime::FileTime;
 use rand::Rng;
-use rsynx::local_sync::LocalSyncer;
+use rsynx::error::SyncError;
+use rsynx::events::SyncEvent;
+use rsynx::local_sync::{LocalSyncer, STAGING_DIR_NAME};
+use rsynx::options::SyncOptions;
+use rsynx::plan::{PlanAction, PlanReason, TransferOrder};
+use rsynx::progress::Phase;
+use rsynx::sync::{CancelToken, FileAction, Syncer};
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
 use std::os::unix::fs::MetadataExt;
 use std::os::unix::fs::PermissionsExt;
 use std::{
     fs::{self, File},
     io::{Read, Write},
-    path::Path,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
 };
+use tracing_subscriber::fmt::format::FmtSpan;
 
 fn setup_test_files(name: &str, src_content: &[u8], dst_content: &[u8]) -> (String, String) {
     let src_path = format!("test_src_{}", name);
@@ -159,6 +169,87 @@ fn test_basic_sync_directory() {
     let _ = fs::remove_dir_all(dst_dir);
 }
 
+#[test]
+fn test_non_recursive_directory_sync() {
+    let src_dir = "test_flat_src_dir";
+    let dst_dir = "test_flat_dst_dir";
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+    fs::create_dir_all(format!("{}/subdir", src_dir)).unwrap();
+    fs::create_dir_all(format!("{}/subdir", dst_dir)).unwrap();
+    fs::write(format!("{}/top.txt", src_dir), b"Top level").unwrap();
+    fs::write(format!("{}/subdir/nested.txt", src_dir), b"Nested").unwrap();
+    fs::write(format!("{}/subdir/old.txt", dst_dir), b"Old").unwrap();
+    fs::write(format!("{}/extra.txt", dst_dir), b"Extra").unwrap();
+
+    let syncer = LocalSyncer::new(src_dir, dst_dir)
+        .with_recursive(false)
+        .with_delete_extraneous(true);
+    let plan = syncer.plan().unwrap();
+    assert!(
+        plan.iter()
+            .all(|op| !op.destination.starts_with(format!("{}/subdir", dst_dir)))
+    );
+    syncer.sync().unwrap();
+
+    assert_eq!(
+        fs::read(format!("{}/top.txt", dst_dir)).unwrap(),
+        b"Top level"
+    );
+    assert!(!Path::new(&format!("{}/subdir/nested.txt", dst_dir)).exists());
+    assert!(Path::new(&format!("{}/subdir/old.txt", dst_dir)).exists());
+    assert!(!Path::new(&format!("{}/extra.txt", dst_dir)).exists());
+
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+}
+
+#[test]
+fn test_preserve_links() {
+    let src_dir = "test_links_src_dir";
+    let dst_dir = "test_links_dst_dir";
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+    fs::create_dir_all(src_dir).unwrap();
+    fs::create_dir_all(dst_dir).unwrap();
+    fs::write(format!("{}/target.txt", src_dir), b"Link target").unwrap();
+    std::os::unix::fs::symlink("target.txt", format!("{}/link.txt", src_dir)).unwrap();
+    std::os::unix::fs::symlink("missing.txt", format!("{}/dangling", src_dir)).unwrap();
+    fs::write(format!("{}/dangling", dst_dir), b"Replaced by a link").unwrap();
+
+    let syncer = LocalSyncer::new(src_dir, dst_dir).with_preserve_links(true);
+    let plan = syncer.plan().unwrap();
+    let reason_of = |name: &str| {
+        plan.iter()
+            .find(|op| op.destination.ends_with(name))
+            .map(|op| op.reason)
+    };
+    assert_eq!(reason_of("link.txt"), Some(PlanReason::Missing));
+    assert_eq!(reason_of("dangling"), Some(PlanReason::TargetChanged));
+
+    let result = syncer.sync().unwrap();
+    let link = format!("{}/link.txt", dst_dir);
+    assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+    assert_eq!(fs::read_link(&link).unwrap(), Path::new("target.txt"));
+    assert_eq!(
+        fs::read_link(format!("{}/dangling", dst_dir)).unwrap(),
+        Path::new("missing.txt")
+    );
+    assert_eq!(result.created_files, 2);
+    assert_eq!(result.updated_files, 1);
+
+    let result = syncer.sync().unwrap();
+    let skipped_links = result
+        .files
+        .iter()
+        .filter(|f| f.action == FileAction::Skipped && !f.path.ends_with("target.txt"))
+        .count();
+    assert_eq!(skipped_links, 2);
+
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+}
+
 #[test]
 #[ignore]
 fn test_preserve_metadata() {
@@ -187,6 +278,62 @@ fn test_preserve_metadata() {
     cleanup_test_files(&src, &dst);
 }
 
+#[test]
+fn test_sync_non_utf8_paths() {
+    let src = PathBuf::from(OsStr::from_bytes(b"test_src_non_utf8_\xff"));
+    let dst = PathBuf::from(OsStr::from_bytes(b"test_dst_non_utf8_\xff"));
+    fs::write(&src, b"0123456789").unwrap();
+    fs::write(&dst, b"012345a789").unwrap();
+
+    let syncer = LocalSyncer::new(&src, &dst).with_block_size(4);
+    syncer.sync().unwrap();
+    assert_eq!(fs::read(&dst).unwrap(), b"0123456789");
+
+    let _ = fs::remove_file(&src);
+    let _ = fs::remove_file(&dst);
+}
+
+/// Collects formatted tracing output for inspection.
+#[derive(Clone, Default)]
+struct CapturedLog(Arc<Mutex<Vec<u8>>>);
+
+impl Write for CapturedLog {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_tracing_spans_record_transfer_fields() {
+    let (src, dst) = setup_test_files("tracing", b"0123456789abcdef", b"0123456789ABCDEF");
+    let log = CapturedLog::default();
+    let writer = log.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(move || writer.clone())
+        .with_span_events(FmtSpan::CLOSE)
+        .with_ansi(false)
+        .finish();
+
+    tracing::subscriber::with_default(subscriber, || {
+        LocalSyncer::new(&src, &dst)
+            .with_block_size(4)
+            .sync()
+            .unwrap();
+    });
+
+    let output = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+    assert!(output.contains("sync_file"));
+    assert!(output.contains("calculate_checksums"));
+    assert!(output.contains("blocks=4"));
+    assert!(output.contains("reused_bytes=8"));
+    assert!(output.contains("reuse_ratio=0.5"));
+    cleanup_test_files(&src, &dst);
+}
+
 #[test]
 fn test_delete_extraneous() {
     let src_dir = "test_sync_src_delete";
@@ -222,6 +369,328 @@ fn test_delete_extraneous() {
     let _ = fs::remove_dir_all(dst_dir);
 }
 
+#[test]
+fn test_delete_to_moves_extraneous_entries() {
+    let src_dir = Path::new("test_sync_src_delete_to");
+    let dst_dir = Path::new("test_sync_dst_delete_to");
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+    fs::create_dir_all(src_dir.join("sub")).unwrap();
+    fs::create_dir_all(dst_dir.join("sub/old_dir")).unwrap();
+    fs::write(src_dir.join("sub/kept.txt"), b"Kept").unwrap();
+    fs::write(dst_dir.join("sub/extra.txt"), b"First").unwrap();
+    fs::write(dst_dir.join("sub/old_dir/inside.txt"), b"Inside").unwrap();
+
+    let syncer = LocalSyncer::new(src_dir, dst_dir)
+        .with_delete_extraneous(true)
+        .with_delete_to(Some(".trash".into()));
+    let result = syncer.sync().unwrap();
+    assert_eq!(result.deleted_files, 2);
+    assert!(!dst_dir.join("sub/extra.txt").exists());
+    let trash = dst_dir.join(".trash");
+    assert_eq!(fs::read(trash.join("sub/extra.txt")).unwrap(), b"First");
+    assert_eq!(
+        fs::read(trash.join("sub/old_dir/inside.txt")).unwrap(),
+        b"Inside"
+    );
+
+    // The trash itself stays, and an entry trashed again keeps the earlier one aside
+    fs::write(dst_dir.join("sub/extra.txt"), b"Second").unwrap();
+    let planned = syncer.plan().unwrap();
+    assert!(
+        planned
+            .iter()
+            .all(|op| op.action != PlanAction::Delete || !op.destination.starts_with(&trash))
+    );
+    syncer.sync().unwrap();
+    assert_eq!(fs::read(trash.join("sub/extra.txt")).unwrap(), b"Second");
+    assert_eq!(fs::read(trash.join("sub/extra.txt.~1~")).unwrap(), b"First");
+    assert_eq!(fs::read(dst_dir.join("sub/kept.txt")).unwrap(), b"Kept");
+
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+}
+
+#[test]
+fn test_transfer_result_breakdown() {
+    let src_dir = "test_sync_src_breakdown";
+    let dst_dir = "test_sync_dst_breakdown";
+
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+    fs::create_dir_all(src_dir).unwrap();
+    fs::create_dir_all(dst_dir).unwrap();
+
+    fs::write(format!("{}/new.txt", src_dir), b"Brand new").unwrap();
+    fs::write(format!("{}/changed.txt", src_dir), b"0123456789abcdef").unwrap();
+    fs::write(format!("{}/changed.txt", dst_dir), b"0123456789ABCDEF").unwrap();
+    fs::write(format!("{}/same.txt", src_dir), b"Same").unwrap();
+    fs::copy(
+        format!("{}/same.txt", src_dir),
+        format!("{}/same.txt", dst_dir),
+    )
+    .unwrap();
+    let mtime = FileTime::from_unix_time(1_700_000_000, 0);
+    filetime::set_file_mtime(format!("{}/same.txt", src_dir), mtime).unwrap();
+    filetime::set_file_mtime(format!("{}/same.txt", dst_dir), mtime).unwrap();
+    filetime::set_file_mtime(format!("{}/changed.txt", dst_dir), mtime).unwrap();
+    fs::write(format!("{}/extraneous.txt", dst_dir), b"Gone").unwrap();
+
+    let syncer = LocalSyncer::new(src_dir.to_string(), dst_dir.to_string())
+        .with_block_size(4)
+        .with_delete_extraneous(true);
+    let result = syncer.sync().unwrap();
+
+    assert_eq!(result.created_files, 1);
+    assert_eq!(result.updated_files, 1);
+    assert_eq!(result.skipped_files, 1);
+    assert_eq!(result.deleted_files, 1);
+    assert_eq!(result.files.len(), 4);
+    let record = |name: &str| {
+        let path = Path::new(dst_dir).join(name);
+        result.files.iter().find(|f| f.path == path).unwrap()
+    };
+    assert_eq!(record("new.txt").action, FileAction::Created);
+    assert_eq!(record("new.txt").literal_bytes, 9);
+    assert_eq!(record("changed.txt").action, FileAction::Updated);
+    assert_eq!(record("changed.txt").reused_bytes, 8);
+    assert_eq!(record("changed.txt").literal_bytes, 8);
+    assert_eq!(record("same.txt").action, FileAction::Skipped);
+    assert_eq!(record("extraneous.txt").action, FileAction::Deleted);
+
+    let json = serde_json::to_value(&result).unwrap();
+    assert_eq!(json["created_files"], 1);
+    assert!(
+        json["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|f| f["action"] == "deleted")
+    );
+
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+}
+
+#[test]
+fn test_sync_events_stream() {
+    let src_dir = "test_sync_src_events";
+    let dst_dir = "test_sync_dst_events";
+
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+    fs::create_dir_all(src_dir).unwrap();
+    fs::create_dir_all(dst_dir).unwrap();
+
+    fs::write(format!("{}/changed.txt", src_dir), b"0123456789abcdef").unwrap();
+    fs::write(format!("{}/changed.txt", dst_dir), b"0123456789ABCDEF").unwrap();
+    fs::write(format!("{}/same.txt", src_dir), b"Same").unwrap();
+    fs::write(format!("{}/same.txt", dst_dir), b"Same").unwrap();
+    let mtime = FileTime::from_unix_time(1_700_000_000, 0);
+    filetime::set_file_mtime(format!("{}/same.txt", src_dir), mtime).unwrap();
+    filetime::set_file_mtime(format!("{}/same.txt", dst_dir), mtime).unwrap();
+    filetime::set_file_mtime(format!("{}/changed.txt", dst_dir), mtime).unwrap();
+    fs::write(format!("{}/extraneous.txt", dst_dir), b"Gone").unwrap();
+
+    let mut events = LocalSyncer::new(src_dir, dst_dir)
+        .with_block_size(4)
+        .with_delete_extraneous(true)
+        .sync_events();
+    let received: Vec<SyncEvent> = events.by_ref().collect();
+    let result = events.finish().unwrap();
+
+    let changed = Path::new(dst_dir).join("changed.txt");
+    assert!(
+        received
+            .iter()
+            .any(|e| matches!(e, SyncEvent::FileStarted { path, size: 16 } if *path == changed))
+    );
+    assert!(
+        received
+            .iter()
+            .any(|e| matches!(e, SyncEvent::BlockMatched { offset: 0, .. }))
+    );
+    assert!(received.iter().any(
+        |e| matches!(e, SyncEvent::FileCompleted(record) if record.action == FileAction::Updated)
+    ));
+    assert!(received.iter().any(|e| matches!(
+        e,
+        SyncEvent::FileSkipped { path } if *path == Path::new(dst_dir).join("same.txt")
+    )));
+    assert!(received.iter().any(|e| matches!(
+        e,
+        SyncEvent::Deleted { path } if *path == Path::new(dst_dir).join("extraneous.txt")
+    )));
+    assert_eq!(result.updated_files, 1);
+
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+}
+
+#[test]
+fn test_plan_then_execute() {
+    let src_dir = "test_sync_src_plan";
+    let dst_dir = "test_sync_dst_plan";
+
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+    fs::create_dir_all(format!("{}/sub", src_dir)).unwrap();
+    fs::create_dir_all(dst_dir).unwrap();
+
+    fs::write(format!("{}/sub/new.txt", src_dir), b"Brand new").unwrap();
+    fs::write(format!("{}/changed.txt", src_dir), b"Longer content").unwrap();
+    fs::write(format!("{}/changed.txt", dst_dir), b"Content").unwrap();
+    fs::write(format!("{}/extraneous.txt", dst_dir), b"Gone").unwrap();
+
+    let syncer = LocalSyncer::new(src_dir, dst_dir)
+        .with_block_size(4)
+        .with_delete_extraneous(true);
+    let plan = syncer.plan().unwrap();
+    let op = |name: &str| {
+        let path = Path::new(dst_dir).join(name);
+        plan.iter().find(|op| op.destination == path).unwrap()
+    };
+    assert_eq!(op("sub").action, PlanAction::Create);
+    assert!(op("sub").is_dir);
+    assert_eq!(op("sub/new.txt").action, PlanAction::Create);
+    assert_eq!(op("changed.txt").action, PlanAction::Update);
+    assert_eq!(op("changed.txt").reason, PlanReason::SizeChanged);
+    assert_eq!(op("changed.txt").size, 14);
+    assert_eq!(op("extraneous.txt").action, PlanAction::Delete);
+    assert_eq!(op("extraneous.txt").reason, PlanReason::Extraneous);
+
+    // Planning changes nothing
+    assert!(!Path::new(&format!("{}/sub", dst_dir)).exists());
+    assert!(Path::new(&format!("{}/extraneous.txt", dst_dir)).exists());
+
+    // Only the approved part of the plan is carried out
+    let approved: Vec<_> = plan
+        .iter()
+        .filter(|op| op.action != PlanAction::Delete)
+        .cloned()
+        .collect();
+    let result = syncer.execute(&approved).unwrap();
+    assert_eq!(result.created_files, 1);
+    assert_eq!(result.updated_files, 1);
+    assert_eq!(
+        fs::read(format!("{}/sub/new.txt", dst_dir)).unwrap(),
+        b"Brand new"
+    );
+    assert_eq!(
+        fs::read(format!("{}/changed.txt", dst_dir)).unwrap(),
+        b"Longer content"
+    );
+    assert!(Path::new(&format!("{}/extraneous.txt", dst_dir)).exists());
+
+    let mut escaping = op("changed.txt").clone();
+    escaping.destination = Path::new(dst_dir).join("../escaped.txt");
+    assert!(matches!(
+        syncer.execute(&[escaping]),
+        Err(SyncError::PathOutsideRoot(_))
+    ));
+    assert!(!Path::new("escaped.txt").exists());
+
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+}
+
+#[test]
+fn test_changed_fraction() {
+    let dir = "test_changed_fraction";
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(dir).unwrap();
+    let file = |name: &str, data: &[u8]| {
+        let path = Path::new(dir).join(name);
+        fs::write(&path, data).unwrap();
+        path
+    };
+    let original = file("original", b"0123456789abcdef");
+    let same = file("same", b"0123456789abcdef");
+    let half = file("half", b"01234567XXXXXXXX");
+    let empty = file("empty", b"");
+    let syncer = Syncer::with_options(SyncOptions::new().with_block_size(4));
+
+    assert_eq!(syncer.changed_fraction(&same, &original).unwrap(), 0.0);
+    assert_eq!(syncer.changed_fraction(&half, &original).unwrap(), 0.5);
+    // Truncating a file loses all of it
+    assert_eq!(syncer.changed_fraction(&empty, &original).unwrap(), 1.0);
+    assert_eq!(
+        syncer
+            .changed_fraction(&original, &Path::new(dir).join("missing"))
+            .unwrap(),
+        1.0
+    );
+
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_modify_window_tolerates_coarse_timestamps() {
+    let src_dir = "test_modify_window_src";
+    let dst_dir = "test_modify_window_dst";
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+    fs::create_dir_all(src_dir).unwrap();
+    fs::create_dir_all(dst_dir).unwrap();
+    let src = format!("{}/file.txt", src_dir);
+    let dst = format!("{}/file.txt", dst_dir);
+    fs::write(&src, b"Source content").unwrap();
+    fs::write(&dst, b"Stale content!").unwrap();
+    // A FAT destination rounds times to two seconds
+    filetime::set_file_mtime(&src, FileTime::from_unix_time(1_700_000_001, 500_000_000)).unwrap();
+    filetime::set_file_mtime(&dst, FileTime::from_unix_time(1_700_000_002, 0)).unwrap();
+
+    let windowed = LocalSyncer::new(src_dir, dst_dir).with_modify_window(1);
+    assert_eq!(windowed.plan().unwrap()[0].action, PlanAction::Skip);
+    assert_eq!(windowed.sync().unwrap().skipped_files, 1);
+    assert_eq!(fs::read(&dst).unwrap(), b"Stale content!");
+
+    let exact = LocalSyncer::new(src_dir, dst_dir);
+    assert_eq!(exact.plan().unwrap()[0].action, PlanAction::Update);
+    assert_eq!(exact.sync().unwrap().updated_files, 1);
+    assert_eq!(fs::read(&dst).unwrap(), b"Source content");
+
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+}
+
+#[test]
+fn test_ignore_times_finds_silent_divergence() {
+    let src_dir = "test_ignore_times_src";
+    let dst_dir = "test_ignore_times_dst";
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+    fs::create_dir_all(src_dir).unwrap();
+    fs::create_dir_all(dst_dir).unwrap();
+    let src = format!("{}/file.txt", src_dir);
+    let dst = format!("{}/file.txt", dst_dir);
+    // Same size and time, different content: the quick check can't tell
+    fs::write(&src, b"The quick brown fox").unwrap();
+    fs::write(&dst, b"The quick brown cat").unwrap();
+    let time = FileTime::from_unix_time(1_700_000_000, 0);
+    filetime::set_file_mtime(&src, time).unwrap();
+    filetime::set_file_mtime(&dst, time).unwrap();
+
+    let result = LocalSyncer::new(src_dir, dst_dir).sync().unwrap();
+    assert_eq!(result.skipped_files, 1);
+
+    let syncer = LocalSyncer::new(src_dir, dst_dir)
+        .with_block_size(4)
+        .with_ignore_times(true);
+    assert_eq!(syncer.plan().unwrap()[0].reason, PlanReason::TimesIgnored);
+    let result = syncer.sync().unwrap();
+    assert_eq!(result.updated_files, 1);
+    assert!(result.reused_bytes > 0);
+    assert_eq!(fs::read(&dst).unwrap(), b"The quick brown fox");
+
+    // With --checksum, equal content is still skipped
+    let result = syncer.with_checksum(true).sync().unwrap();
+    assert_eq!(result.skipped_files, 1);
+
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+}
+
 #[test]
 fn test_no_delete_extraneous() {
     let src_dir = "test_sync_src_no_delete";
@@ -247,3 +716,655 @@ fn test_no_delete_extraneous() {
     let _ = fs::remove_dir_all(src_dir);
     let _ = fs::remove_dir_all(dst_dir);
 }
+
+#[test]
+fn test_skip_unchanged_files() {
+    let src_dir = "test_sync_src_skip";
+    let dst_dir = "test_sync_dst_skip";
+
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+    fs::create_dir_all(src_dir).unwrap();
+
+    fs::write(format!("{}/same.txt", src_dir), b"Unchanged content").unwrap();
+    fs::write(format!("{}/changed.txt", src_dir), b"New content").unwrap();
+
+    let syncer = LocalSyncer::new(src_dir.to_string(), dst_dir.to_string())
+        .with_block_size(4)
+        .with_preserve_metadata(true);
+    let first = syncer.sync().unwrap();
+    assert_eq!(first.skipped_files, 0);
+
+    // Same size, different content, and a matching mtime: only --checksum notices
+    fs::write(format!("{}/changed.txt", src_dir), b"Old content").unwrap();
+    let mtime = FileTime::from_unix_time(1_700_000_000, 0);
+    filetime::set_file_mtime(format!("{}/changed.txt", src_dir), mtime).unwrap();
+    filetime::set_file_mtime(format!("{}/changed.txt", dst_dir), mtime).unwrap();
+
+    let second = syncer.sync().unwrap();
+    assert_eq!(second.skipped_files, 2);
+    assert_eq!(second.new_bytes, 0);
+    assert_eq!(
+        fs::read(format!("{}/changed.txt", dst_dir)).unwrap(),
+        b"New content"
+    );
+
+    let checksum_syncer = LocalSyncer::new(src_dir.to_string(), dst_dir.to_string())
+        .with_block_size(4)
+        .with_checksum(true);
+    let third = checksum_syncer.sync().unwrap();
+    assert_eq!(third.skipped_files, 1);
+    assert_eq!(
+        fs::read(format!("{}/changed.txt", dst_dir)).unwrap(),
+        b"Old content"
+    );
+
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+}
+
+#[test]
+fn test_large_file_with_insertions_spanning_pipeline_chunks() {
+    let mut rng = rand::rng();
+    let mut dst_content = vec![0u8; 1_500_000];
+    rng.fill(&mut dst_content[..]);
+
+    // Shift everything after a few insertions so blocks only match at new offsets
+    let mut src_content = dst_content.clone();
+    src_content.splice(300_000..300_000, b"inserted".iter().copied());
+    src_content.splice(900_000..900_010, b"replaced!!!!!!".iter().copied());
+    src_content.extend_from_slice(b"appended tail");
+
+    let (src, dst) = setup_test_files("pipeline_large", &src_content, &dst_content);
+    let syncer = LocalSyncer::new(src.clone(), dst.clone()).with_block_size(4096);
+    let result = syncer.sync().unwrap();
+
+    verify_content(&dst, &src_content);
+    assert_eq!(fs::metadata(&dst).unwrap().len(), src_content.len() as u64);
+    assert!(result.reused_bytes > 1_400_000);
+    assert_eq!(result.new_bytes + result.reused_bytes, src_content.len());
+    cleanup_test_files(&src, &dst);
+}
+
+#[test]
+fn test_verify_after_sync() {
+    let (src, dst) = setup_test_files("verify", b"0123456789abcdef", b"0123XXXX89abcdef");
+    let syncer = LocalSyncer::new(src.clone(), dst.clone())
+        .with_block_size(4)
+        .with_verify(true);
+    syncer.sync().unwrap();
+    verify_content(&dst, b"0123456789abcdef");
+
+    // A destination that doesn't hash to the source's checksum is reported as a mismatch
+    let mut checker = Syncer::new();
+    checker.options.verify = true;
+    let expected = checker.calculate_file_checksum(Path::new(&src)).unwrap();
+    fs::write(&dst, b"corrupted").unwrap();
+    let err = checker.verify_file(Path::new(&dst), &expected).unwrap_err();
+    assert!(matches!(err, SyncError::ChecksumMismatch { .. }));
+    cleanup_test_files(&src, &dst);
+}
+
+#[test]
+fn test_checkpoint_resumes_interrupted_dir_sync() {
+    let src_dir = "test_checkpoint_src";
+    let dst_dir = "test_checkpoint_dst";
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+    fs::create_dir_all(src_dir).unwrap();
+    fs::create_dir_all(dst_dir).unwrap();
+
+    let mut big = vec![0u8; 300_000];
+    rand::rng().fill(&mut big[..]);
+    fs::write(format!("{}/big.bin", src_dir), &big).unwrap();
+    fs::write(format!("{}/big.bin", dst_dir), &big[..200_000]).unwrap();
+    fs::write(format!("{}/done.txt", src_dir), b"already synced").unwrap();
+    fs::write(format!("{}/new.txt", src_dir), b"not synced yet").unwrap();
+
+    // State left behind by a run that finished done.txt and got 100000 bytes into big.bin
+    let mut temp = big[..100_000].to_vec();
+    temp.resize(big.len(), 0);
+    fs::write(format!("{}/big.tmp", dst_dir), &temp).unwrap();
+    let mtime = FileTime::from_last_modification_time(
+        &fs::metadata(format!("{}/big.bin", src_dir)).unwrap(),
+    );
+    fs::write(
+        format!("{}/.rsynx-checkpoint", dst_dir),
+        format!(
+            "done done.txt\npartial 100000 {} {} {} big.bin\n",
+            big.len(),
+            mtime.unix_seconds(),
+            mtime.nanoseconds()
+        ),
+    )
+    .unwrap();
+
+    let syncer = LocalSyncer::new(src_dir.to_string(), dst_dir.to_string())
+        .with_block_size(1024)
+        .with_checkpoint(true);
+    syncer.sync().unwrap();
+
+    assert_eq!(fs::read(format!("{}/big.bin", dst_dir)).unwrap(), big);
+    assert_eq!(
+        fs::read(format!("{}/new.txt", dst_dir)).unwrap(),
+        b"not synced yet"
+    );
+    // Entries recorded as done are trusted rather than synced again
+    assert!(!Path::new(&format!("{}/done.txt", dst_dir)).exists());
+    assert!(!Path::new(&format!("{}/.rsynx-checkpoint", dst_dir)).exists());
+
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+}
+
+#[test]
+fn test_sync_with_temp_dir() {
+    let scratch = PathBuf::from("test_temp_dir_scratch");
+    let system_scratch =
+        std::env::temp_dir().join(format!("rsynx_temp_dir_{}", std::process::id()));
+    for (i, dir) in [&scratch, &system_scratch].into_iter().enumerate() {
+        fs::create_dir_all(dir).unwrap();
+        let (src, dst) = setup_test_files(
+            &format!("temp_dir_{}", i),
+            b"The quick brown fox jumps over the lazy dog",
+            b"The quick brown cat jumps over the lazy dog",
+        );
+        let result = LocalSyncer::new(src.clone(), dst.clone())
+            .with_block_size(4)
+            .with_temp_dir(dir)
+            .sync()
+            .unwrap();
+        assert!(result.reused_bytes > 0);
+        verify_content(&dst, b"The quick brown fox jumps over the lazy dog");
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 0);
+        assert!(!Path::new(&dst).with_extension("tmp").exists());
+        cleanup_test_files(&src, &dst);
+        fs::remove_dir(dir).unwrap();
+    }
+
+    let err = LocalSyncer::new("test_src_temp_dir", "test_dst_temp_dir")
+        .with_temp_dir("test_temp_dir_missing")
+        .validate()
+        .unwrap_err();
+    assert!(matches!(err, SyncError::ConflictingOptions(_)));
+}
+
+#[test]
+fn test_shared_syncer_across_threads() {
+    let template = Arc::new(LocalSyncer::new("", "").with_block_size(4));
+    let handles: Vec<_> = (0..4)
+        .map(|i| {
+            let template = Arc::clone(&template);
+            std::thread::spawn(move || {
+                let (src, dst) = setup_test_files(
+                    &format!("shared_{}", i),
+                    b"The quick brown fox jumps over the lazy dog",
+                    b"The quick brown cat jumps over the lazy dog",
+                );
+                let result = template.for_paths(&src, &dst).sync().unwrap();
+                verify_content(&dst, b"The quick brown fox jumps over the lazy dog");
+                cleanup_test_files(&src, &dst);
+                result.reused_bytes
+            })
+        })
+        .collect();
+    for handle in handles {
+        assert!(handle.join().unwrap() > 0);
+    }
+    assert_eq!(template.options().block_size, 4);
+}
+
+#[test]
+fn test_invalid_options_are_rejected() {
+    let (src, dst) = setup_test_files("validate", b"0123456789", b"0123456789");
+
+    let err = LocalSyncer::new(src.clone(), dst.clone())
+        .with_block_size(0)
+        .validate()
+        .unwrap_err();
+    assert!(matches!(err, SyncError::InvalidBlockSize(0)));
+    assert!(matches!(
+        LocalSyncer::new(src.clone(), dst.clone())
+            .with_block_size(usize::MAX)
+            .sync()
+            .unwrap_err(),
+        SyncError::InvalidBlockSize(_)
+    ));
+    assert!(matches!(
+        LocalSyncer::new(String::new(), dst.clone()).validate(),
+        Err(SyncError::EmptyPath("Source"))
+    ));
+    assert!(matches!(
+        LocalSyncer::new(src.clone(), dst.clone())
+            .with_checkpoint(true)
+            .validate(),
+        Err(SyncError::ConflictingOptions(_))
+    ));
+    assert!(
+        LocalSyncer::new(src.clone(), dst.clone())
+            .validate()
+            .is_ok()
+    );
+
+    cleanup_test_files(&src, &dst);
+}
+
+#[test]
+fn test_progress_callback_reports_phases() {
+    let mut content = vec![0u8; 600_000];
+    rand::rng().fill(&mut content[..]);
+    let (src, dst) = setup_test_files("progress", &content, &content[..400_000]);
+
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&reports);
+    let syncer = LocalSyncer::new(src.clone(), dst.clone())
+        .with_block_size(1024)
+        .on_progress(move |progress| {
+            assert_eq!(progress.file, Path::new("test_src_progress"));
+            sink.lock()
+                .unwrap()
+                .push((progress.phase, progress.bytes_done, progress.bytes_total));
+        });
+    syncer.sync().unwrap();
+
+    let reports = reports.lock().unwrap();
+    assert_eq!(reports.first(), Some(&(Phase::Signature, 0, 600_000)));
+    assert_eq!(reports.last(), Some(&(Phase::Complete, 600_000, 600_000)));
+    let transferred: Vec<u64> = reports
+        .iter()
+        .filter(|(phase, _, _)| *phase == Phase::Transfer)
+        .map(|(_, done, _)| *done)
+        .collect();
+    assert!(transferred.windows(2).all(|pair| pair[0] <= pair[1]));
+    assert_eq!(transferred.last(), Some(&600_000));
+    cleanup_test_files(&src, &dst);
+}
+
+#[test]
+fn test_cancel_stops_sync_and_removes_temp_file() {
+    let mut content = vec![0u8; 1_000_000];
+    rand::rng().fill(&mut content[..]);
+    let old_content = content[..500_000].to_vec();
+    let (src, dst) = setup_test_files("cancel", &content, &old_content);
+
+    // Cancel from the progress callback as soon as the first bytes are written
+    let token = CancelToken::new();
+    let trigger = token.clone();
+    let syncer = LocalSyncer::new(src.clone(), dst.clone())
+        .with_block_size(1024)
+        .with_cancel_token(token)
+        .on_progress(move |progress| {
+            if progress.phase == Phase::Transfer {
+                trigger.cancel();
+            }
+        });
+    let err = syncer.sync().unwrap_err();
+    assert!(matches!(err, SyncError::Cancelled));
+    verify_content(&dst, &old_content);
+    assert!(!Path::new("test_dst_cancel.tmp").exists());
+    cleanup_test_files(&src, &dst);
+}
+
+#[test]
+fn test_partial_data_is_kept_and_reused() {
+    let src_dir = "test_partial_src";
+    let dst_dir = "test_partial_dst";
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+    fs::create_dir_all(src_dir).unwrap();
+    fs::create_dir_all(dst_dir).unwrap();
+    let mut content = vec![0u8; 1_000_000];
+    rand::rng().fill(&mut content[..]);
+    let mut old_content = vec![0u8; 1_000_000];
+    rand::rng().fill(&mut old_content[..]);
+    fs::write(format!("{}/big.bin", src_dir), &content).unwrap();
+    let old_time = FileTime::from_unix_time(1_600_000_000, 0);
+
+    for partial_dir in [None, Some(PathBuf::from(".rsynx-partial"))] {
+        let partial = match &partial_dir {
+            Some(dir) => Path::new(dst_dir).join(dir).join("big.bin"),
+            None => Path::new(dst_dir).join("big.bin.partial"),
+        };
+        fs::write(format!("{}/big.bin", dst_dir), &old_content).unwrap();
+        filetime::set_file_mtime(format!("{}/big.bin", dst_dir), old_time).unwrap();
+        let token = CancelToken::new();
+        let trigger = token.clone();
+        let syncer = LocalSyncer::new(src_dir, dst_dir)
+            .with_delete_extraneous(true)
+            .with_partial(true)
+            .with_partial_dir(partial_dir.clone());
+        let err = syncer
+            .clone()
+            .with_cancel_token(token)
+            .on_progress(move |progress| {
+                if progress.phase == Phase::Transfer && progress.bytes_done >= 400_000 {
+                    trigger.cancel();
+                }
+            })
+            .sync()
+            .unwrap_err();
+        assert!(matches!(err, SyncError::Cancelled));
+        assert_eq!(
+            fs::read(format!("{}/big.bin", dst_dir)).unwrap(),
+            old_content
+        );
+        let kept = fs::read(&partial).unwrap();
+        assert!(kept.len() >= 400_000);
+        assert_eq!(kept, content[..kept.len()]);
+
+        // The next run picks the partial data up instead of deleting it as extraneous
+        let result = syncer.sync().unwrap();
+        assert!(result.reused_bytes >= kept.len() - 1024);
+        assert_eq!(fs::read(format!("{}/big.bin", dst_dir)).unwrap(), content);
+        assert!(!partial.exists());
+        assert_eq!(fs::read_dir(dst_dir).unwrap().count(), 1);
+    }
+
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+}
+
+#[test]
+fn test_unsupported_entries_are_counted() {
+    let src_dir = "test_unsupported_src";
+    let dst_dir = "test_unsupported_dst";
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+    fs::create_dir_all(src_dir).unwrap();
+    fs::write(format!("{}/file.txt", src_dir), b"regular").unwrap();
+    let _listener = std::os::unix::net::UnixListener::bind(format!("{}/sock", src_dir)).unwrap();
+
+    let result = LocalSyncer::new(src_dir, dst_dir).sync().unwrap();
+
+    assert_eq!(result.unsupported_files, 1);
+    assert_eq!(
+        fs::read(format!("{}/file.txt", dst_dir)).unwrap(),
+        b"regular"
+    );
+    assert!(!Path::new(&format!("{}/sock", dst_dir)).exists());
+
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+}
+
+#[test]
+fn test_atomic_sync_stages_until_complete() {
+    let src_dir = Path::new("test_sync_src_atomic");
+    let dst_dir = Path::new("test_sync_dst_atomic");
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+    fs::create_dir_all(src_dir.join("new")).unwrap();
+    fs::create_dir_all(dst_dir).unwrap();
+    fs::write(src_dir.join("changed.txt"), b"New content").unwrap();
+    fs::write(src_dir.join("new/added.txt"), b"Added").unwrap();
+    fs::write(dst_dir.join("changed.txt"), b"Old").unwrap();
+    fs::write(dst_dir.join("extra.txt"), b"Extra").unwrap();
+
+    // Cancelled after the first file: nothing reaches the destination
+    let token = CancelToken::new();
+    let cancel = token.clone();
+    let failed = LocalSyncer::new(src_dir, dst_dir)
+        .with_delete_extraneous(true)
+        .with_atomic(true)
+        .with_cancel_token(token)
+        .on_event(move |event| {
+            if matches!(event, SyncEvent::FileCompleted(_)) {
+                cancel.cancel();
+            }
+        })
+        .sync();
+    assert!(matches!(failed, Err(SyncError::Cancelled)));
+    assert_eq!(fs::read(dst_dir.join("changed.txt")).unwrap(), b"Old");
+    assert!(dst_dir.join("extra.txt").exists());
+    assert!(!dst_dir.join("new").exists());
+    assert!(!dst_dir.join(STAGING_DIR_NAME).exists());
+
+    let result = LocalSyncer::new(src_dir, dst_dir)
+        .with_delete_extraneous(true)
+        .with_atomic(true)
+        .sync()
+        .unwrap();
+    assert_eq!(result.created_files, 1);
+    assert_eq!(result.updated_files, 1);
+    assert_eq!(result.deleted_files, 1);
+    assert_eq!(
+        fs::read(dst_dir.join("changed.txt")).unwrap(),
+        b"New content"
+    );
+    assert_eq!(fs::read(dst_dir.join("new/added.txt")).unwrap(), b"Added");
+    assert!(!dst_dir.join("extra.txt").exists());
+    assert!(!dst_dir.join(STAGING_DIR_NAME).exists());
+
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+}
+
+#[test]
+fn test_transfer_order() {
+    let src_dir = Path::new("test_sync_src_order");
+    let dst_dir = Path::new("test_sync_dst_order");
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+    fs::create_dir_all(src_dir.join("sub")).unwrap();
+    fs::write(src_dir.join("medium.txt"), vec![b'm'; 200]).unwrap();
+    fs::write(src_dir.join("sub/large.txt"), vec![b'l'; 3000]).unwrap();
+    fs::write(src_dir.join("small.txt"), b"s").unwrap();
+    for (name, secs) in [("medium.txt", 3), ("sub/large.txt", 1), ("small.txt", 2)] {
+        filetime::set_file_mtime(
+            src_dir.join(name),
+            FileTime::from_unix_time(1_700_000_000 + secs, 0),
+        )
+        .unwrap();
+    }
+
+    let file_order = |order: TransferOrder| -> Vec<String> {
+        LocalSyncer::new(src_dir, dst_dir)
+            .with_order(order)
+            .plan()
+            .unwrap()
+            .iter()
+            .filter(|op| !op.is_dir)
+            .map(|op| op.destination.file_name().unwrap().to_string_lossy().into())
+            .collect()
+    };
+    assert_eq!(
+        file_order(TransferOrder::SmallestFirst),
+        ["small.txt", "medium.txt", "large.txt"]
+    );
+    assert_eq!(
+        file_order(TransferOrder::LargestFirst),
+        ["large.txt", "medium.txt", "small.txt"]
+    );
+    assert_eq!(
+        file_order(TransferOrder::NewestFirst),
+        ["medium.txt", "small.txt", "large.txt"]
+    );
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let result = LocalSyncer::new(src_dir, dst_dir)
+        .with_order(TransferOrder::SmallestFirst)
+        .on_event(move |event| {
+            if let SyncEvent::FileCompleted(record) = event {
+                let _ = sender.send(record.path.clone());
+            }
+        })
+        .sync()
+        .unwrap();
+    let completed: Vec<_> = receiver.try_iter().collect();
+    assert_eq!(result.created_files, 3);
+    assert_eq!(
+        completed,
+        [
+            dst_dir.join("small.txt"),
+            dst_dir.join("medium.txt"),
+            dst_dir.join("sub/large.txt")
+        ]
+    );
+    assert_eq!(fs::read(dst_dir.join("sub/large.txt")).unwrap().len(), 3000);
+
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+}
+
+#[test]
+fn test_preallocated_sync() {
+    let src_dir = Path::new("test_sync_src_preallocate");
+    let dst_dir = Path::new("test_sync_dst_preallocate");
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+    fs::create_dir_all(src_dir).unwrap();
+    fs::create_dir_all(dst_dir).unwrap();
+    let mut content = vec![0u8; 64 * 1024];
+    rand::rng().fill(&mut content[..]);
+    fs::write(src_dir.join("new.bin"), &content).unwrap();
+    fs::write(src_dir.join("changed.bin"), &content).unwrap();
+    fs::write(dst_dir.join("changed.bin"), &content[..40 * 1024]).unwrap();
+
+    let result = LocalSyncer::new(src_dir, dst_dir)
+        .with_preallocate(true)
+        .sync()
+        .unwrap();
+    assert_eq!(result.created_files, 1);
+    assert_eq!(result.updated_files, 1);
+    assert!(result.reused_bytes > 0);
+    for name in ["new.bin", "changed.bin"] {
+        assert_eq!(fs::read(dst_dir.join(name)).unwrap(), content);
+    }
+
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+}
+
+#[test]
+fn test_directory_delta_sync_with_prefetched_signatures() {
+    let src_dir = Path::new("test_sync_src_prefetch");
+    let dst_dir = Path::new("test_sync_dst_prefetch");
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+    fs::create_dir_all(src_dir).unwrap();
+    fs::create_dir_all(dst_dir).unwrap();
+    let mut rng = rand::rng();
+    for i in 0..4 {
+        let mut content = vec![0u8; 32 * 1024];
+        rng.fill(&mut content[..]);
+        fs::write(dst_dir.join(format!("{}.bin", i)), &content).unwrap();
+        content[i * 1000..i * 1000 + 100].fill(i as u8);
+        fs::write(src_dir.join(format!("{}.bin", i)), &content).unwrap();
+    }
+
+    for order in [TransferOrder::AsFound, TransferOrder::LargestFirst] {
+        let result = LocalSyncer::new(src_dir, dst_dir)
+            .with_order(order)
+            .with_ignore_times(true)
+            .sync()
+            .unwrap();
+        assert_eq!(result.updated_files, 4);
+        assert!(result.reused_bytes > 4 * 30 * 1024);
+        for i in 0..4 {
+            let name = format!("{}.bin", i);
+            assert_eq!(
+                fs::read(dst_dir.join(&name)).unwrap(),
+                fs::read(src_dir.join(&name)).unwrap()
+            );
+        }
+    }
+
+    let _ = fs::remove_dir_all(src_dir);
+    let _ = fs::remove_dir_all(dst_dir);
+}
+
+#[test]
+fn test_non_utf8_names() {
+    use rsynx::conflict::ConflictPolicy;
+    use rsynx::journal::{self, Journal};
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = Path::new("test_sync_non_utf8");
+    let _ = fs::remove_dir_all(dir);
+    let (src, dst) = (dir.join("src"), dir.join("dst"));
+    let latin1 = OsStr::from_bytes(b"caf\xe9.txt");
+    let newline = OsStr::from_bytes(b"two\nlines \xff%41");
+    fs::create_dir_all(src.join(newline)).unwrap();
+    fs::create_dir_all(&dst).unwrap();
+    fs::write(src.join(latin1), b"Latin-1 name").unwrap();
+    fs::write(src.join(newline).join(latin1), b"Nested").unwrap();
+    // Only differs from the name above in its invalid byte
+    fs::write(dst.join(OsStr::from_bytes(b"caf\xe8.txt")), b"Extraneous").unwrap();
+
+    let journal_path = dir.join("journal");
+    let sync = || {
+        LocalSyncer::new(&src, &dst)
+            .with_delete_extraneous(true)
+            .with_checkpoint(true)
+            .with_conflicts(Some(ConflictPolicy::Rename))
+            .with_journal(Some(Journal::open(&journal_path).unwrap()))
+            .sync()
+            .unwrap()
+    };
+    let result = sync();
+    assert_eq!(result.created_files, 2);
+    assert_eq!(result.deleted_files, 1);
+    assert_eq!(fs::read(dst.join(latin1)).unwrap(), b"Latin-1 name");
+    assert_eq!(fs::read(dst.join(newline).join(latin1)).unwrap(), b"Nested");
+
+    // The state file tells the destination edit apart, and its renamed copy isn't extraneous
+    fs::write(dst.join(newline).join(latin1), b"Destination edit").unwrap();
+    fs::write(src.join(newline).join(latin1), b"Source edit").unwrap();
+    assert_eq!(sync().conflicts, 1);
+    assert_eq!(sync().conflicts, 0);
+    let mut copy = latin1.to_os_string();
+    copy.push(".conflict-1");
+    assert_eq!(
+        fs::read(dst.join(newline).join(copy)).unwrap(),
+        b"Destination edit"
+    );
+
+    let entries = journal::read(fs::File::open(&journal_path).unwrap()).unwrap();
+    assert!(
+        entries
+            .iter()
+            .any(|entry| entry.path.ends_with(Path::new(newline).join(latin1)))
+    );
+
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_unicode_normalize() {
+    use rsynx::pathname::NameForm;
+
+    let dir = Path::new("test_unicode_normalize");
+    let _ = fs::remove_dir_all(dir);
+    let (src, dst) = (dir.join("src"), dir.join("dst"));
+    // Decomposed, as macOS stores them
+    fs::create_dir_all(src.join("Re\u{301}sume\u{301}")).unwrap();
+    fs::write(src.join("cafe\u{301}.txt"), b"New menu").unwrap();
+    fs::write(src.join("Re\u{301}sume\u{301}/nai\u{308}ve.txt"), b"Nested").unwrap();
+    // Composed, as synced earlier from Linux
+    fs::create_dir_all(dst.join("R\u{e9}sum\u{e9}")).unwrap();
+    fs::write(dst.join("caf\u{e9}.txt"), b"Last season's menu").unwrap();
+
+    let syncer = LocalSyncer::new(&src, &dst)
+        .with_delete_extraneous(true)
+        .with_unicode_normalize(Some(NameForm::Nfc));
+    let plan = syncer.plan().unwrap();
+    assert!(plan.iter().all(|op| op.action != PlanAction::Delete));
+    let result = syncer.sync().unwrap();
+    assert_eq!(result.deleted_files, 0);
+    assert_eq!(result.updated_files, 1);
+    assert_eq!(result.created_files, 1);
+
+    let names = |dir: &Path| {
+        let mut names: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        names
+    };
+    assert_eq!(names(&dst), ["R\u{e9}sum\u{e9}", "caf\u{e9}.txt"]);
+    assert_eq!(names(&dst.join("R\u{e9}sum\u{e9}")), ["na\u{ef}ve.txt"]);
+    assert_eq!(fs::read(dst.join("caf\u{e9}.txt")).unwrap(), b"New menu");
+
+    let _ = fs::remove_dir_all(dir);
+}
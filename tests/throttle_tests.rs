@@ -0,0 +1,102 @@
+use rsynx::local_sync::LocalSyncer;
+use rsynx::throttle::{DiskThrottled, SharedRateLimiter, Throttled, parse_rate};
+use std::{
+    fs,
+    io::{Read, Write},
+    time::Instant,
+};
+
+#[test]
+fn test_parse_rate_units() {
+    assert_eq!(parse_rate("500"), Some(500 * 1024));
+    assert_eq!(parse_rate("500k"), Some(500 * 1024));
+    assert_eq!(parse_rate("10M"), Some(10 * 1024 * 1024));
+    assert_eq!(parse_rate("1.5m"), Some(3 * 512 * 1024));
+    assert_eq!(parse_rate("2g"), Some(2 * 1024 * 1024 * 1024));
+    assert_eq!(parse_rate("64b"), Some(64));
+    assert_eq!(parse_rate("0"), Some(0));
+    assert_eq!(parse_rate("fast"), None);
+    assert_eq!(parse_rate("10x"), None);
+    assert_eq!(parse_rate("-1k"), None);
+    assert_eq!(parse_rate(""), None);
+}
+
+#[test]
+fn test_throttled_writer_paces_writes() {
+    let mut writer = Throttled::new(Vec::new(), Some(64 * 1024));
+    let start = Instant::now();
+    for _ in 0..4 {
+        writer.write_all(&[7u8; 4096]).unwrap();
+    }
+    assert!(start.elapsed().as_millis() >= 200);
+    assert_eq!(writer.into_inner().len(), 16384);
+
+    let mut unlimited = Throttled::new(Vec::new(), None);
+    let start = Instant::now();
+    unlimited.write_all(&[7u8; 1 << 20]).unwrap();
+    assert!(start.elapsed().as_millis() < 200);
+}
+
+#[test]
+fn test_local_sync_respects_bwlimit() {
+    let src = "test_bwlimit_src.bin";
+    let dst = "test_bwlimit_dst.bin";
+    let data: Vec<u8> = (0..32 * 1024).map(|i| (i % 251) as u8).collect();
+    fs::write(src, &data).unwrap();
+    let _ = fs::remove_file(dst);
+
+    let syncer = LocalSyncer::new(src, dst).with_bwlimit(Some(128 * 1024));
+    let start = Instant::now();
+    syncer.sync().unwrap();
+    assert!(start.elapsed().as_millis() >= 200);
+    assert_eq!(fs::read(dst).unwrap(), data);
+
+    // Rewriting an existing destination goes through the throttled reconstruction
+    fs::write(dst, &data[..16 * 1024]).unwrap();
+    let start = Instant::now();
+    syncer.sync().unwrap();
+    assert!(start.elapsed().as_millis() >= 200);
+    assert_eq!(fs::read(dst).unwrap(), data);
+
+    let _ = fs::remove_file(src);
+    let _ = fs::remove_file(dst);
+}
+
+#[test]
+fn test_shared_limiter_paces_reads_and_writes_together() {
+    let limiter = SharedRateLimiter::new(64 * 1024);
+    let mut reader = DiskThrottled::new(&[7u8; 8192][..], Some(limiter.clone()));
+    let mut writer = DiskThrottled::new(Vec::new(), Some(limiter));
+    let start = Instant::now();
+    let mut buffer = Vec::new();
+    reader.read_to_end(&mut buffer).unwrap();
+    writer.write_all(&buffer).unwrap();
+    assert!(start.elapsed().as_millis() >= 200);
+    assert_eq!(writer.into_inner(), buffer);
+}
+
+#[test]
+fn test_local_sync_respects_disk_limit() {
+    let src = "test_disk_limit_src.bin";
+    let dst = "test_disk_limit_dst.bin";
+    let data: Vec<u8> = (0..32 * 1024).map(|i| (i % 251) as u8).collect();
+    fs::write(src, &data).unwrap();
+    let _ = fs::remove_file(dst);
+
+    // Copying reads and writes 64 KiB in all
+    let syncer = LocalSyncer::new(src, dst).with_disk_limit(Some(128 * 1024));
+    let start = Instant::now();
+    syncer.sync().unwrap();
+    assert!(start.elapsed().as_millis() >= 400);
+    assert_eq!(fs::read(dst).unwrap(), data);
+
+    // Scanning the basis and source and rebuilding also count
+    fs::write(dst, &data[..16 * 1024]).unwrap();
+    let start = Instant::now();
+    syncer.sync().unwrap();
+    assert!(start.elapsed().as_millis() >= 400);
+    assert_eq!(fs::read(dst).unwrap(), data);
+
+    let _ = fs::remove_file(src);
+    let _ = fs::remove_file(dst);
+}
@@ -0,0 +1,348 @@
+#![cfg(feature = "webdav")]
+
+use rsynx::{
+    plan::PlanAction,
+    webdav::{
+        CHUNKED_THRESHOLD, WebDavLocation, WebDavSyncer, parse_webdav, percent_decode,
+        percent_encode, xml_elements,
+    },
+};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+#[test]
+fn test_parse_webdav() {
+    assert_eq!(
+        parse_webdav("davs://me@cloud.example.com/remote.php/dav/files/me/My Backup"),
+        Some(WebDavLocation {
+            url: "https://cloud.example.com/remote.php/dav/files/me/My%20Backup/".to_string(),
+            user: Some("me".to_string()),
+        })
+    );
+    assert_eq!(
+        parse_webdav("dav://localhost:8080").unwrap().url,
+        "http://localhost:8080/"
+    );
+    assert_eq!(parse_webdav("dav:///path"), None);
+    assert_eq!(parse_webdav("https://host/path"), None);
+}
+
+#[test]
+fn test_percent_encoding_round_trips() {
+    let name = "Résumé (final) #2.txt";
+    assert_eq!(
+        percent_encode(name),
+        "R%C3%A9sum%C3%A9%20%28final%29%20%232.txt"
+    );
+    assert_eq!(percent_decode(&percent_encode(name)), name);
+    assert_eq!(percent_decode("100%"), "100%");
+}
+
+#[test]
+fn test_xml_elements_ignore_namespace_prefixes() {
+    let xml = r#"<d:multistatus xmlns:d="DAV:"><d:response><d:href>/a</d:href>
+        <d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype>
+        </d:prop></d:propstat></d:response><D:response><D:href>/b</D:href></D:response>
+        <response><href>/c</href></response></d:multistatus>"#;
+    let responses = xml_elements(xml, "response");
+    assert_eq!(responses.len(), 3);
+    assert_eq!(xml_elements(responses[0], "collection"), vec![""]);
+    assert_eq!(xml_elements(responses[1], "href"), vec!["/b"]);
+    assert_eq!(xml_elements(responses[2], "href"), vec!["/c"]);
+}
+
+/// A file with its `mtime` and `sha256` properties, or a collection.
+#[derive(Debug, Clone)]
+enum Resource {
+    Collection,
+    File(Vec<u8>, BTreeMap<String, String>),
+}
+
+/// Resources of a fake WebDAV server by decoded path, and a log of requests.
+#[derive(Default)]
+struct Server {
+    resources: BTreeMap<String, Resource>,
+    log: Vec<String>,
+}
+
+fn serve(server: Arc<Mutex<Server>>) -> u16 {
+    server
+        .lock()
+        .unwrap()
+        .resources
+        .insert("/dav".to_string(), Resource::Collection);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let server = server.clone();
+            thread::spawn(move || handle(stream.unwrap(), &server));
+        }
+    });
+    port
+}
+
+fn read_body(reader: &mut impl BufRead, length: Option<usize>, chunked: bool) -> Vec<u8> {
+    if !chunked {
+        let mut body = vec![0; length.unwrap_or(0)];
+        reader.read_exact(&mut body).unwrap();
+        return body;
+    }
+    let mut body = Vec::new();
+    loop {
+        let mut size = String::new();
+        reader.read_line(&mut size).unwrap();
+        let size = usize::from_str_radix(size.trim(), 16).unwrap();
+        let mut chunk = vec![0; size + 2];
+        reader.read_exact(&mut chunk).unwrap();
+        if size == 0 {
+            return body;
+        }
+        body.extend_from_slice(&chunk[..size]);
+    }
+}
+
+fn propfind(resources: &BTreeMap<String, Resource>, path: &str) -> Option<String> {
+    let path = path.trim_end_matches('/');
+    if !matches!(resources.get(path), Some(Resource::Collection)) {
+        return None;
+    }
+    let mut xml = String::from(r#"<d:multistatus xmlns:d="DAV:" xmlns:r="urn:x-rsynx">"#);
+    for (name, resource) in resources.range(path.to_string()..) {
+        let Some(rest) = name.strip_prefix(path) else {
+            break;
+        };
+        if rest.matches('/').count() > 1 || (!rest.is_empty() && !rest.starts_with('/')) {
+            continue;
+        }
+        let href = name
+            .split('/')
+            .map(percent_encode)
+            .collect::<Vec<_>>()
+            .join("/");
+        let props = match resource {
+            Resource::Collection => "<d:resourcetype><d:collection/></d:resourcetype>".to_string(),
+            Resource::File(data, props) => {
+                let mut xml = format!(
+                    "<d:resourcetype/><d:getcontentlength>{}</d:getcontentlength>",
+                    data.len()
+                );
+                for (name, value) in props {
+                    xml.push_str(&format!("<r:{0}>{1}</r:{0}>", name, value));
+                }
+                xml
+            }
+        };
+        xml.push_str(&format!(
+            "<d:response><d:href>{}</d:href><d:propstat><d:prop>{}</d:prop></d:propstat></d:response>",
+            href, props
+        ));
+    }
+    xml.push_str("</d:multistatus>");
+    Some(xml)
+}
+
+fn handle(stream: TcpStream, server: &Mutex<Server>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut stream = stream;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap().to_string();
+        let path = percent_decode(parts.next().unwrap().trim_end_matches('/'));
+        let mut headers = Vec::new();
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            let (name, value) = header.split_once(':').unwrap();
+            headers.push((name.to_ascii_lowercase(), value.trim().to_string()));
+        }
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.clone())
+        };
+        // "user:secret"
+        assert_eq!(
+            header("authorization").as_deref(),
+            Some("Basic dXNlcjpzZWNyZXQ=")
+        );
+        let body = read_body(
+            &mut reader,
+            header("content-length").map(|len| len.parse().unwrap()),
+            header("transfer-encoding").is_some_and(|encoding| encoding == "chunked"),
+        );
+
+        let mut server = server.lock().unwrap();
+        server.log.push(format!("{} {}", method, path));
+        let resources = &mut server.resources;
+        let parent_exists = |path: &str| {
+            let parent = &path[..path.trim_end_matches('/').rfind('/').unwrap()];
+            matches!(resources.get(parent), Some(Resource::Collection))
+        };
+        let (status, response) = match method.as_str() {
+            "PROPFIND" => match propfind(resources, &path) {
+                Some(xml) => (207, xml),
+                None => (404, String::new()),
+            },
+            "MKCOL" if resources.contains_key(&path) => (405, String::new()),
+            "MKCOL" if parent_exists(&path) => {
+                resources.insert(path, Resource::Collection);
+                (201, String::new())
+            }
+            "PUT" if parent_exists(&path) => {
+                resources.insert(path, Resource::File(body, BTreeMap::new()));
+                (201, String::new())
+            }
+            "PROPPATCH" => match resources.get_mut(&path) {
+                Some(Resource::File(_, props)) => {
+                    let body = String::from_utf8(body).unwrap();
+                    for name in ["mtime", "sha256"] {
+                        let value = xml_elements(&body, name).pop().unwrap();
+                        props.insert(name.to_string(), value.to_string());
+                    }
+                    (207, String::new())
+                }
+                _ => (404, String::new()),
+            },
+            "MOVE" => {
+                let destination = header("destination").unwrap();
+                let destination = percent_decode(&destination[destination.find("/dav").unwrap()..]);
+                match resources.remove(&path) {
+                    Some(resource) => {
+                        resources.insert(destination, resource);
+                        (201, String::new())
+                    }
+                    None => (404, String::new()),
+                }
+            }
+            "DELETE" => {
+                let prefix = format!("{}/", path);
+                resources.retain(|name, _| name != &path && !name.starts_with(&prefix));
+                (204, String::new())
+            }
+            _ => (409, String::new()),
+        };
+        drop(server);
+        let reply = format!(
+            "HTTP/1.1 {} Status\r\nContent-Length: {}\r\n\r\n{}",
+            status,
+            response.len(),
+            response
+        );
+        stream.write_all(reply.as_bytes()).unwrap();
+    }
+}
+
+fn file(server: &Mutex<Server>, path: &str) -> Option<Vec<u8>> {
+    match server.lock().unwrap().resources.get(path) {
+        Some(Resource::File(data, _)) => Some(data.clone()),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_sync_to_fake_webdav_server() {
+    let server = Arc::new(Mutex::new(Server::default()));
+    let port = serve(server.clone());
+    let location = parse_webdav(&format!("dav://127.0.0.1:{}/dav/My Backup", port)).unwrap();
+
+    let dir = "test_webdav_sync";
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(format!("{}/sub", dir)).unwrap();
+    fs::write(format!("{}/small file.txt", dir), b"Small file").unwrap();
+    let large: Vec<u8> = (0..CHUNKED_THRESHOLD + 1000)
+        .map(|i| (i % 251) as u8)
+        .collect();
+    fs::write(format!("{}/sub/large.bin", dir), &large).unwrap();
+
+    let syncer = WebDavSyncer::new(dir, location)
+        .with_credentials("user", "secret")
+        .with_recursive(true)
+        .with_delete_extraneous(true);
+    let plan = syncer.plan().unwrap();
+    assert_eq!(
+        plan.iter().map(|op| op.action).collect::<Vec<_>>(),
+        vec![
+            PlanAction::Create,
+            PlanAction::Create,
+            PlanAction::Create,
+            PlanAction::Create
+        ]
+    );
+    assert!(
+        server
+            .lock()
+            .unwrap()
+            .log
+            .iter()
+            .all(|entry| entry.starts_with("PROPFIND"))
+    );
+
+    let result = syncer.execute(&plan).unwrap();
+    assert_eq!(result.created_files, 2);
+    assert_eq!(
+        file(&server, "/dav/My Backup/small file.txt").unwrap(),
+        b"Small file"
+    );
+    assert_eq!(
+        file(&server, "/dav/My Backup/sub/large.bin").unwrap(),
+        large
+    );
+
+    // Nothing is uploaded again while the files are unchanged
+    server.lock().unwrap().log.clear();
+    let result = syncer.sync().unwrap();
+    assert_eq!(result.skipped_files, 2);
+    assert!(
+        !server
+            .lock()
+            .unwrap()
+            .log
+            .iter()
+            .any(|entry| entry.starts_with("PUT"))
+    );
+
+    // Only the file whose recorded time no longer matches is sent again
+    filetime::set_file_mtime(
+        format!("{}/small file.txt", dir),
+        filetime::FileTime::from_unix_time(1_600_000_000, 0),
+    )
+    .unwrap();
+    let result = syncer.sync().unwrap();
+    assert_eq!((result.updated_files, result.skipped_files), (1, 1));
+
+    fs::write(format!("{}/small file.txt", dir), b"Small edit").unwrap();
+    fs::remove_dir_all(format!("{}/sub", dir)).unwrap();
+    let result = syncer.sync().unwrap();
+    assert_eq!(result.updated_files, 1);
+    assert_eq!(result.deleted_files, 1);
+    assert_eq!(
+        file(&server, "/dav/My Backup/small file.txt").unwrap(),
+        b"Small edit"
+    );
+    assert!(
+        !server
+            .lock()
+            .unwrap()
+            .resources
+            .keys()
+            .any(|name| name.starts_with("/dav/My Backup/sub") || name.ends_with(".rsynx-tmp"))
+    );
+
+    let _ = fs::remove_dir_all(dir);
+}